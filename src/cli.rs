@@ -24,6 +24,14 @@ struct Args {
     /// Output JSON file
     #[arg(long, value_name = "FILE")]
     output_json: Option<PathBuf>,
+
+    /// Output analysis report (Markdown) file
+    #[arg(long, value_name = "FILE")]
+    output_report: Option<PathBuf>,
+
+    /// Render the SPC output to a WAV file (uses the settings of --input-json if given)
+    #[arg(long, value_name = "FILE")]
+    render_wav: Option<PathBuf>,
 }
 
 #[cfg(windows)]
@@ -56,10 +64,15 @@ pub fn cli_main() -> Result<(), Box<dyn error::Error>> {
     console::attach_parent_or_alloc();
 
     let args = Args::parse();
-    let mut app = App::default();
+    // 前回の設定を読み込み
+    let mut app = App::with_preferences(load_preferences());
 
     // 出力が指定されてない
-    if args.output_smf.is_none() && args.output_json.is_none() {
+    if args.output_smf.is_none()
+        && args.output_json.is_none()
+        && args.output_report.is_none()
+        && args.render_wav.is_none()
+    {
         eprintln!("No output file specified.");
         return Ok(());
     }
@@ -71,6 +84,8 @@ pub fn cli_main() -> Result<(), Box<dyn error::Error>> {
         spc_file.into(),
         LoadedFile::SPCFile(*data),
     ))));
+    // 解析が終わるのを待つ
+    let _ = app.wait_background_operation();
 
     // JSONを開く
     if let Some(json_file) = &args.input_json {
@@ -83,13 +98,27 @@ pub fn cli_main() -> Result<(), Box<dyn error::Error>> {
 
     // MIDIを出力
     if let Some(output_smf) = &args.output_smf {
-        let smf = app.create_smf().expect("Failed to generate SMF");
+        let smf = app
+            .create_smf()
+            .map_err(|e| format!("Failed to generate SMF: {}", e))?;
         let writer = SMFWriter::from_smf(smf);
         writer
             .write_to_file(output_smf)
             .expect("Failed to write SMF");
     }
 
+    // WAVを出力
+    if let Some(render_wav) = &args.render_wav {
+        let (wav, duration_sec, peak_dbfs) = app.create_wav().ok_or("Failed to render WAV")?;
+        std::fs::write(render_wav, wav)?;
+        println!(
+            "Rendered {}: {:.2} sec, peak {:.1} dBFS",
+            render_wav.display(),
+            duration_sec,
+            peak_dbfs
+        );
+    }
+
     // JSONを出力
     if let Some(output_json) = &args.output_json {
         let json = app.create_json();
@@ -98,6 +127,11 @@ pub fn cli_main() -> Result<(), Box<dyn error::Error>> {
         serde_json::to_writer_pretty(writer, &json).expect("Faied to write json");
     }
 
+    // 解析レポートを出力
+    if let Some(output_report) = &args.output_report {
+        std::fs::write(output_report, app.create_report())?;
+    }
+
     // コンソールを破棄
     #[cfg(windows)]
     console::detach();
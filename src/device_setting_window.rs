@@ -1,14 +1,27 @@
+use crate::program::*;
 use crate::types::*;
 use crate::Message;
 use crate::SPC2MIDI2_TITLE_STR;
+use crate::VIRTUAL_MIDI_PORT_SUPPORTED;
 use cpal::traits::{DeviceTrait, HostTrait};
-use iced::widget::{column, combo_box, row, text};
+use iced::widget::{
+    button, checkbox, column, combo_box, pick_list, row, scrollable, slider, text, tooltip, Column,
+};
 use iced::{alignment, Element, Length};
 use iced_aw::number_input;
 use midir::MidiOutput;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 
+/// 設定ウィンドウのセクション
+pub const DEVICE_SETTING_SECTION_AUDIO: usize = 0;
+pub const DEVICE_SETTING_SECTION_MIDI_OUTPUT: usize = 1;
+pub const DEVICE_SETTING_SECTION_ANALYSIS: usize = 2;
+pub const DEVICE_SETTING_SECTION_DISPLAY: usize = 3;
+/// 設定ウィンドウのセクション名
+const DEVICE_SETTING_SECTION_TITLES: [&str; 4] = ["Audio", "MIDI Output", "Analysis", "Display"];
+
 #[derive(Debug)]
 pub struct DeviceSettingWindow {
     audio_out_device_name: Arc<RwLock<Option<String>>>,
@@ -16,6 +29,33 @@ pub struct DeviceSettingWindow {
     midi_out_port_name: Arc<RwLock<Option<String>>>,
     midi_ports_box: combo_box::State<String>,
     audio_output_latency_msec: Arc<AtomicUsize>,
+    use_virtual_midi_port: Arc<AtomicBool>,
+    ramp_in_on_play_start: Arc<AtomicBool>,
+    ramp_in_msec: Arc<AtomicUsize>,
+    device_switch_fade_msec: Arc<AtomicUsize>,
+    force_output_sample_rate: Arc<AtomicBool>,
+    output_sample_rate: Arc<AtomicU32>,
+    a4_pitch_hz: Arc<AtomicU32>,
+    indicator_smoothing: Arc<AtomicU32>,
+    spectrum_peak_labels: Arc<AtomicUsize>,
+    spectrum_peak_separation_hz: Arc<AtomicU32>,
+    cache_analysis: Arc<AtomicBool>,
+    lazy_source_decoding: Arc<AtomicBool>,
+    stretch_smf_export: Arc<AtomicBool>,
+    stretch_target_sec: Arc<AtomicU32>,
+    master_reverb_send: Arc<AtomicU8>,
+    stuck_note_timeout_sec: Arc<AtomicU32>,
+    export_preroll_msec: Arc<AtomicUsize>,
+    long_export_warning_sec: Arc<AtomicU32>,
+    program_velocity_calibration: Arc<RwLock<Vec<u8>>>,
+    source_parameter_template: Arc<RwLock<SourceParameterTemplate>>,
+    export_range_enabled: Arc<AtomicBool>,
+    export_range_start_sec: Arc<AtomicU32>,
+    export_range_end_sec: Arc<AtomicU32>,
+    /// セクションを展開しているか
+    pub showing_sections: [bool; 4],
+    /// ベロシティ補正率を編集中のプログラム
+    pub velocity_calibration_program: Program,
 }
 
 impl SPC2MIDI2Window for DeviceSettingWindow {
@@ -26,52 +66,356 @@ impl SPC2MIDI2Window for DeviceSettingWindow {
     fn view(&self) -> Element<'_, Message> {
         let audio_device_name = self.audio_out_device_name.read().unwrap();
         let midi_port_name = self.midi_out_port_name.read().unwrap();
-        let content = column![
-            column![
-                text("Audio Output Device"),
-                combo_box(
-                    &self.audio_out_devices_box,
-                    "Audio Output Device",
-                    audio_device_name.as_ref(),
-                    move |device_name| Message::AudioOutputDeviceSelected(device_name),
-                ),
-            ]
+        let mut content = Column::new()
             .spacing(10)
             .padding(10)
             .width(Length::Fill)
-            .align_x(alignment::Alignment::Start),
-            column![
-                text("MIDI Output Port"),
-                combo_box(
-                    &self.midi_ports_box,
-                    "MIDI Output Port",
-                    midi_port_name.as_ref(),
-                    move |port_name| Message::MIDIOutputPortSelected(port_name),
-                )
-            ]
-            .spacing(10)
-            .padding(10)
-            .width(Length::Fill)
-            .align_x(alignment::Alignment::Start),
-            row![
-                text("Audio Output Latency (msec)"),
-                number_input(
-                    &self.audio_output_latency_msec.load(Ordering::Relaxed),
-                    50..=1000,
-                    move |msec| Message::AudioLatencyMsecChanged(msec),
-                )
-                .step(10),
-            ]
-            .spacing(10)
-            .padding(10)
+            .align_x(alignment::Alignment::Start);
+
+        // オーディオ出力
+        content = content.push(self.section_header(DEVICE_SETTING_SECTION_AUDIO));
+        if self.showing_sections[DEVICE_SETTING_SECTION_AUDIO] {
+            content = content.push(
+                column![
+                    column![
+                        text("Audio Output Device"),
+                        combo_box(
+                            &self.audio_out_devices_box,
+                            "Audio Output Device",
+                            audio_device_name.as_ref(),
+                            move |device_name| Message::AudioOutputDeviceSelected(device_name),
+                        ),
+                    ]
+                    .spacing(10)
+                    .padding(10)
+                    .width(Length::Fill)
+                    .align_x(alignment::Alignment::Start),
+                    row![
+                        tooltip(
+                            text("Audio Output Latency (msec)"),
+                            "Increase if the playback stutters (applied from the next playback)",
+                            tooltip::Position::Top,
+                        ),
+                        number_input(
+                            &self.audio_output_latency_msec.load(Ordering::Relaxed),
+                            50..=1000,
+                            move |msec| Message::AudioLatencyMsecChanged(msec),
+                        )
+                        .step(10),
+                    ]
+                    .spacing(10)
+                    .padding(10)
+                    .width(Length::Fill)
+                    .align_y(alignment::Alignment::Center),
+                    row![
+                        tooltip(
+                            checkbox(self.ramp_in_on_play_start.load(Ordering::Relaxed))
+                                .label("Ramp In on Play Start")
+                                .on_toggle(|flag| Message::RampInOnPlayStartFlagToggled(flag)),
+                            "Start playback muted and fade in to avoid a pop",
+                            tooltip::Position::Top,
+                        ),
+                        text("Ramp Time (msec)"),
+                        number_input(
+                            &self.ramp_in_msec.load(Ordering::Relaxed),
+                            0..=5000,
+                            move |msec| Message::RampInMsecChanged(msec),
+                        )
+                        .step(50),
+                    ]
+                    .spacing(10)
+                    .padding(10)
+                    .width(Length::Fill)
+                    .align_y(alignment::Alignment::Center),
+                    row![
+                        tooltip(
+                            text("Device Switch Fade (msec)"),
+                            "Fade-in time when the output device is switched during playback (playback resumes from the same position)",
+                            tooltip::Position::Top,
+                        ),
+                        number_input(
+                            &self.device_switch_fade_msec.load(Ordering::Relaxed),
+                            0..=2000,
+                            move |msec| Message::DeviceSwitchFadeMsecChanged(msec),
+                        )
+                        .step(10),
+                    ]
+                    .spacing(10)
+                    .padding(10)
+                    .width(Length::Fill)
+                    .align_y(alignment::Alignment::Center),
+                    row![
+                        tooltip(
+                            checkbox(self.force_output_sample_rate.load(Ordering::Relaxed))
+                                .label("Force Output Sample Rate")
+                                .on_toggle(|flag| Message::ForceOutputSampleRateFlagToggled(flag)),
+                            "Use this rate instead of the device default if the device supports it (applied from the next playback)",
+                            tooltip::Position::Top,
+                        ),
+                        text("Rate (Hz)"),
+                        number_input(
+                            &self.output_sample_rate.load(Ordering::Relaxed),
+                            MIN_OUTPUT_SAMPLE_RATE..=MAX_OUTPUT_SAMPLE_RATE,
+                            move |rate| Message::OutputSampleRateChanged(rate),
+                        )
+                        .step(100),
+                    ]
+                    .spacing(10)
+                    .padding(10)
+                    .width(Length::Fill)
+                    .align_y(alignment::Alignment::Center),
+                ]
+                .width(Length::Fill),
+            );
+        }
+
+        // MIDI出力
+        content = content.push(self.section_header(DEVICE_SETTING_SECTION_MIDI_OUTPUT));
+        if self.showing_sections[DEVICE_SETTING_SECTION_MIDI_OUTPUT] {
+            content = content.push(
+                column![
+                    text("MIDI Output Port"),
+                    combo_box(
+                        &self.midi_ports_box,
+                        "MIDI Output Port",
+                        midi_port_name.as_ref(),
+                        move |port_name| Message::MIDIOutputPortSelected(port_name),
+                    ),
+                    tooltip(
+                        checkbox(self.use_virtual_midi_port.load(Ordering::Relaxed))
+                            .label("Create Virtual MIDI Port")
+                            .on_toggle_maybe(if VIRTUAL_MIDI_PORT_SUPPORTED {
+                                Some(|flag| Message::VirtualMIDIPortFlagToggled(flag))
+                            } else {
+                                None
+                            }),
+                        if VIRTUAL_MIDI_PORT_SUPPORTED {
+                            "Create a virtual MIDI output port so that DAWs can receive from this app"
+                        } else {
+                            "Virtual MIDI ports are not supported on this platform"
+                        },
+                        tooltip::Position::Top,
+                    ),
+                    row![
+                        tooltip(
+                            checkbox(self.stretch_smf_export.load(Ordering::Relaxed))
+                                .label("Stretch SMF Export To")
+                                .on_toggle(|flag| Message::StretchSMFExportFlagToggled(flag)),
+                            "Change the tempo of the saved SMF so that the output duration fits this length (changes tempo, not pitch)",
+                            tooltip::Position::Top,
+                        ),
+                        number_input(
+                            &self.stretch_target_sec.load(Ordering::Relaxed),
+                            MIN_STRETCH_TARGET_SEC..=MAX_STRETCH_TARGET_SEC,
+                            move |sec| Message::StretchTargetSecChanged(sec),
+                        )
+                        .step(1),
+                        text("sec"),
+                    ]
+                    .spacing(10)
+                    .align_y(alignment::Alignment::Center),
+                    row![
+                        tooltip(
+                            checkbox(self.export_range_enabled.load(Ordering::Relaxed))
+                                .label("Export Only Range")
+                                .on_toggle(|flag| Message::ExportRangeFlagToggled(flag)),
+                            "Save only this time range (from the start of the song) to SMF/WAV. Program changes and controllers sent before the start are restored at the beginning",
+                            tooltip::Position::Top,
+                        ),
+                        number_input(
+                            &f32::from_bits(self.export_range_start_sec.load(Ordering::Relaxed)),
+                            0.0..=MAX_EXPORT_RANGE_SEC,
+                            move |sec| Message::ExportRangeStartChanged(sec),
+                        )
+                        .step(0.1),
+                        text("-"),
+                        number_input(
+                            &f32::from_bits(self.export_range_end_sec.load(Ordering::Relaxed)),
+                            0.0..=MAX_EXPORT_RANGE_SEC,
+                            move |sec| Message::ExportRangeEndChanged(sec),
+                        )
+                        .step(0.1),
+                        text("sec"),
+                    ]
+                    .spacing(10)
+                    .align_y(alignment::Alignment::Center),
+                    row![
+                        tooltip(
+                            text("Master Reverb Send"),
+                            "Reverb send (CC#91) set on all channels at the start of playback and export. Per-source reverb sends are added on top of this value",
+                            tooltip::Position::Top,
+                        ),
+                        number_input(
+                            &self.master_reverb_send.load(Ordering::Relaxed),
+                            0..=127,
+                            move |send| Message::MasterReverbSendChanged(send),
+                        )
+                        .step(1),
+                    ]
+                    .spacing(10)
+                    .align_y(alignment::Alignment::Center),
+                    row![
+                        tooltip(
+                            text("Stuck Note Timeout (sec)"),
+                            "During playback, send a note-off to notes that keep sounding longer than this (0 disables). Held notes are also released when playback stops",
+                            tooltip::Position::Top,
+                        ),
+                        number_input(
+                            &self.stuck_note_timeout_sec.load(Ordering::Relaxed),
+                            0..=MAX_STUCK_NOTE_TIMEOUT_SEC,
+                            move |sec| Message::StuckNoteTimeoutChanged(sec),
+                        )
+                        .step(1),
+                    ]
+                    .spacing(10)
+                    .align_y(alignment::Alignment::Center),
+                    row![
+                        tooltip(
+                            text("Export Pre-roll (msec)"),
+                            "Run the emulation silently for this long before SMF/WAV/OGG export starts capturing, skipping the driver startup. Program and controller setup sent during the pre-roll is written at the start. The export range counts from the end of the pre-roll",
+                            tooltip::Position::Top,
+                        ),
+                        number_input(
+                            &self.export_preroll_msec.load(Ordering::Relaxed),
+                            0..=MAX_EXPORT_PREROLL_MSEC,
+                            move |msec| Message::ExportPrerollMsecChanged(msec),
+                        )
+                        .step(100),
+                    ]
+                    .spacing(10)
+                    .align_y(alignment::Alignment::Center),
+                    row![
+                        tooltip(
+                            text("Confirm Exports Longer Than (sec)"),
+                            "Ask with an estimated render time before exporting more than this length of the song (0 disables)",
+                            tooltip::Position::Top,
+                        ),
+                        number_input(
+                            &self.long_export_warning_sec.load(Ordering::Relaxed),
+                            0..=MAX_LONG_EXPORT_WARNING_SEC,
+                            move |sec| Message::LongExportWarningSecChanged(sec),
+                        )
+                        .step(60),
+                    ]
+                    .spacing(10)
+                    .align_y(alignment::Alignment::Center),
+                    self.program_velocity_calibration_editor(),
+                ]
+                .spacing(10)
+                .padding(10)
+                .width(Length::Fill)
+                .align_x(alignment::Alignment::Start),
+            );
+        }
+
+        // 解析
+        content = content.push(self.section_header(DEVICE_SETTING_SECTION_ANALYSIS));
+        if self.showing_sections[DEVICE_SETTING_SECTION_ANALYSIS] {
+            content = content.push(
+                column![
+                    row![
+                        tooltip(
+                            text("Reference Pitch A4 (Hz)"),
+                            "Tuning reference for center note estimation and Hz display (re-estimates all center notes)",
+                            tooltip::Position::Top,
+                        ),
+                        number_input(
+                            &f32::from_bits(self.a4_pitch_hz.load(Ordering::Relaxed)),
+                            MIN_A4_PITCH_HZ..=MAX_A4_PITCH_HZ,
+                            move |hz| Message::A4PitchChanged(hz),
+                        )
+                        .step(0.5),
+                    ]
+                    .spacing(10)
+                    .padding(10)
+                    .width(Length::Fill)
+                    .align_y(alignment::Alignment::Center),
+                    row![tooltip(
+                        checkbox(self.cache_analysis.load(Ordering::Relaxed))
+                            .label("Cache Analysis Results")
+                            .on_toggle(|flag| Message::CacheAnalysisFlagToggled(flag)),
+                        "Save the analysis next to the SPC file (<name>.analysis.json) and reuse it when the same SPC is opened again",
+                        tooltip::Position::Top,
+                    )]
+                    .spacing(10)
+                    .padding(10)
+                    .width(Length::Fill)
+                    .align_y(alignment::Alignment::Center),
+                    row![tooltip(
+                        checkbox(self.lazy_source_decoding.load(Ordering::Relaxed))
+                            .label("Decode Waveforms On Demand")
+                            .on_toggle(|flag| Message::LazySourceDecodingFlagToggled(flag)),
+                        "Keep only summary information of sources in memory and decode waveforms/spectra when a source window opens or a preview plays",
+                        tooltip::Position::Top,
+                    )]
+                    .spacing(10)
+                    .padding(10)
+                    .width(Length::Fill)
+                    .align_y(alignment::Alignment::Center),
+                    self.source_parameter_template_editor(),
+                ]
+                .width(Length::Fill),
+            );
+        }
+
+        // 表示
+        content = content.push(self.section_header(DEVICE_SETTING_SECTION_DISPLAY));
+        if self.showing_sections[DEVICE_SETTING_SECTION_DISPLAY] {
+            content = content.push(
+                row![
+                    tooltip(
+                        text("Indicator Smoothing"),
+                        "How slowly the channel indicators fall back after a peak (0 shows the raw values)",
+                        tooltip::Position::Top,
+                    ),
+                    number_input(
+                        &f32::from_bits(self.indicator_smoothing.load(Ordering::Relaxed)),
+                        0.0..=MAX_INDICATOR_SMOOTHING,
+                        move |smoothing| Message::IndicatorSmoothingChanged(smoothing),
+                    )
+                    .step(0.05),
+                ]
+                .spacing(10)
+                .padding(10)
+                .width(Length::Fill)
+                .align_y(alignment::Alignment::Center),
+            );
+            content = content.push(
+                row![
+                    tooltip(
+                        text("Spectrum Peak Labels"),
+                        "Number of peak frequencies labeled on the spectrum of the source window",
+                        tooltip::Position::Top,
+                    ),
+                    number_input(
+                        &self.spectrum_peak_labels.load(Ordering::Relaxed),
+                        0..=MAX_SPECTRUM_PEAK_LABELS,
+                        move |num_labels| Message::SpectrumPeakLabelsChanged(num_labels),
+                    )
+                    .step(1),
+                    tooltip(
+                        text("Min. Separation (Hz)"),
+                        "Labeled peaks are at least this far apart, so that labels spread over distinct partials",
+                        tooltip::Position::Top,
+                    ),
+                    number_input(
+                        &f32::from_bits(self.spectrum_peak_separation_hz.load(Ordering::Relaxed)),
+                        0.0..=MAX_SPECTRUM_PEAK_SEPARATION_HZ,
+                        move |hz| Message::SpectrumPeakSeparationChanged(hz),
+                    )
+                    .step(10.0),
+                ]
+                .spacing(10)
+                .padding(10)
+                .width(Length::Fill)
+                .align_y(alignment::Alignment::Center),
+            );
+        }
+
+        scrollable(content)
             .width(Length::Fill)
-            .align_y(alignment::Alignment::Center),
-        ]
-        .spacing(10)
-        .padding(10)
-        .width(Length::Fill)
-        .align_x(alignment::Alignment::Center);
-        content.into()
+            .height(Length::Fill)
+            .into()
     }
 }
 
@@ -80,6 +424,29 @@ impl DeviceSettingWindow {
         audio_out_device_name: Arc<RwLock<Option<String>>>,
         midi_out_port_name: Arc<RwLock<Option<String>>>,
         audio_output_latency_msec: Arc<AtomicUsize>,
+        use_virtual_midi_port: Arc<AtomicBool>,
+        ramp_in_on_play_start: Arc<AtomicBool>,
+        ramp_in_msec: Arc<AtomicUsize>,
+        device_switch_fade_msec: Arc<AtomicUsize>,
+        force_output_sample_rate: Arc<AtomicBool>,
+        output_sample_rate: Arc<AtomicU32>,
+        a4_pitch_hz: Arc<AtomicU32>,
+        indicator_smoothing: Arc<AtomicU32>,
+        spectrum_peak_labels: Arc<AtomicUsize>,
+        spectrum_peak_separation_hz: Arc<AtomicU32>,
+        cache_analysis: Arc<AtomicBool>,
+        lazy_source_decoding: Arc<AtomicBool>,
+        stretch_smf_export: Arc<AtomicBool>,
+        stretch_target_sec: Arc<AtomicU32>,
+        master_reverb_send: Arc<AtomicU8>,
+        stuck_note_timeout_sec: Arc<AtomicU32>,
+        export_preroll_msec: Arc<AtomicUsize>,
+        long_export_warning_sec: Arc<AtomicU32>,
+        program_velocity_calibration: Arc<RwLock<Vec<u8>>>,
+        source_parameter_template: Arc<RwLock<SourceParameterTemplate>>,
+        export_range_enabled: Arc<AtomicBool>,
+        export_range_start_sec: Arc<AtomicU32>,
+        export_range_end_sec: Arc<AtomicU32>,
     ) -> Self {
         let device_name_list: Vec<String> = cpal::default_host()
             .devices()
@@ -109,6 +476,268 @@ impl DeviceSettingWindow {
             midi_out_port_name: midi_out_port_name,
             midi_ports_box: combo_box::State::new(port_name_list),
             audio_output_latency_msec: audio_output_latency_msec,
+            use_virtual_midi_port: use_virtual_midi_port,
+            ramp_in_on_play_start: ramp_in_on_play_start,
+            ramp_in_msec: ramp_in_msec,
+            device_switch_fade_msec: device_switch_fade_msec,
+            force_output_sample_rate: force_output_sample_rate,
+            output_sample_rate: output_sample_rate,
+            a4_pitch_hz: a4_pitch_hz,
+            indicator_smoothing: indicator_smoothing,
+            spectrum_peak_labels: spectrum_peak_labels,
+            spectrum_peak_separation_hz: spectrum_peak_separation_hz,
+            cache_analysis: cache_analysis,
+            lazy_source_decoding: lazy_source_decoding,
+            stretch_smf_export: stretch_smf_export,
+            stretch_target_sec: stretch_target_sec,
+            master_reverb_send: master_reverb_send,
+            stuck_note_timeout_sec: stuck_note_timeout_sec,
+            export_preroll_msec: export_preroll_msec,
+            long_export_warning_sec: long_export_warning_sec,
+            program_velocity_calibration: program_velocity_calibration,
+            source_parameter_template: source_parameter_template,
+            export_range_enabled: export_range_enabled,
+            export_range_start_sec: export_range_start_sec,
+            export_range_end_sec: export_range_end_sec,
+            showing_sections: [true; 4],
+            velocity_calibration_program: Program::AcousticGrand,
         }
     }
+
+    // プログラムごとのベロシティ補正率の編集欄
+    fn program_velocity_calibration_editor(&self) -> Element<'_, Message> {
+        let program = self.velocity_calibration_program.clone();
+        let program_no = program.clone() as u8;
+        let percent = self
+            .program_velocity_calibration
+            .read()
+            .unwrap()
+            .get(program_no as usize)
+            .copied()
+            .unwrap_or(100);
+        let melodic_programs: Vec<Program> = Program::ALL
+            .iter()
+            .filter(|program| ((*program).clone() as u8) < 0x80)
+            .cloned()
+            .collect();
+        row![
+            tooltip(
+                text("Velocity Calibration (%)"),
+                "Per-program note-on velocity scale applied to SMF export when \"Calibrate Velocity by Program\" is enabled in the MIDI output configuration",
+                tooltip::Position::Top,
+            ),
+            pick_list(melodic_programs, Some(program), move |program| {
+                Message::ProgramVelocityCalibrationProgramSelected(program)
+            }),
+            number_input(
+                &percent,
+                MIN_PROGRAM_VELOCITY_PERCENT..=MAX_PROGRAM_VELOCITY_PERCENT,
+                move |percent| Message::ProgramVelocityCalibrationChanged(program_no, percent),
+            )
+            .step(5),
+            button(text("Reset")).on_press(Message::ProgramVelocityCalibrationReset),
+        ]
+        .spacing(10)
+        .align_y(alignment::Alignment::Center)
+        .into()
+    }
+
+    // 新しく開いたSPCの音源パラメータの既定値の編集欄
+    fn source_parameter_template_editor(&self) -> Element<'_, Message> {
+        let template = self.source_parameter_template.read().unwrap().clone();
+        let pan_law_template = template.clone();
+        let echo_template = template.clone();
+        column![
+            row![
+                tooltip(
+                    text("Default Source Parameters"),
+                    "Base values of the source parameters for newly opened SPC files. Program, center note and channels are still estimated per source",
+                    tooltip::Position::Top,
+                ),
+                button("Reset to Factory").on_press(Message::SourceParameterTemplateChanged(
+                    SourceParameterTemplate::default()
+                )),
+            ]
+            .spacing(10)
+            .align_y(alignment::Alignment::Center),
+            row![
+                text("Velocity"),
+                self.template_number_input(template.noteon_velocity, 1..=127, |t, velocity| {
+                    t.noteon_velocity = velocity
+                }),
+                tooltip(
+                    self.template_checkbox(
+                        "Scale by Key-On Volume",
+                        template.velocity_from_keyon_volume,
+                        |t, flag| t.velocity_from_keyon_volume = flag,
+                    ),
+                    "Scale the velocity by the SPC channel volume at key-on (Velocity is the maximum)",
+                    tooltip::Position::Top,
+                ),
+                tooltip(
+                    text("Pitch Bend Width"),
+                    "0 estimates the width from the pitch changes observed in the analysis",
+                    tooltip::Position::Top,
+                ),
+                self.template_number_input(
+                    template.pitch_bend_width,
+                    0..=MAX_PITCH_BEND_WIDTH,
+                    |t, width| t.pitch_bend_width = width,
+                ),
+            ]
+            .spacing(10)
+            .align_y(alignment::Alignment::Center),
+            row![
+                tooltip(
+                    text("Note Range"),
+                    "Notes outside this range are shifted by octaves into it (or clamped if the range is narrower than an octave)",
+                    tooltip::Position::Top,
+                ),
+                self.template_number_input(template.min_note, 0..=127, |t, note| {
+                    t.min_note = note
+                }),
+                text("-"),
+                self.template_number_input(template.max_note, 0..=127, |t, note| {
+                    t.max_note = note
+                }),
+            ]
+            .spacing(10)
+            .align_y(alignment::Alignment::Center),
+            row![
+                text("Pan"),
+                self.template_checkbox("Use SPC Value", template.auto_pan, |t, flag| {
+                    t.auto_pan = flag
+                }),
+                self.template_number_input(template.fixed_pan, 0..=127, |t, pan| {
+                    t.fixed_pan = pan
+                }),
+                tooltip(
+                    pick_list(PanLaw::ALL, Some(template.pan_law), move |pan_law| {
+                        Message::SourceParameterTemplateChanged(SourceParameterTemplate {
+                            pan_law: pan_law,
+                            ..pan_law_template.clone()
+                        })
+                    }),
+                    "Pan law of the target synth applied to the SPC pan (only when using the SPC value)",
+                    tooltip::Position::Top,
+                ),
+            ]
+            .spacing(10)
+            .align_y(alignment::Alignment::Center),
+            row![
+                text("Volume"),
+                self.template_checkbox("Use SPC Value", template.auto_volume, |t, flag| {
+                    t.auto_volume = flag
+                }),
+                self.template_number_input(template.fixed_volume, 0..=127, |t, volume| {
+                    t.fixed_volume = volume
+                }),
+            ]
+            .spacing(10)
+            .align_y(alignment::Alignment::Center),
+            row![
+                text("Effects"),
+                self.template_checkbox(
+                    "Echo as Reverb",
+                    template.echo_as_reverb_send,
+                    |t, flag| t.echo_as_reverb_send = flag,
+                ),
+                tooltip(
+                    slider(
+                        0..=MAX_ECHO_SEND_SCALE_PERCENT,
+                        template.echo_send_scale_percent,
+                        move |percent| {
+                            Message::SourceParameterTemplateChanged(SourceParameterTemplate {
+                                echo_send_scale_percent: percent,
+                                ..echo_template.clone()
+                            })
+                        }
+                    )
+                    .width(80),
+                    "Scale of the reverb send converted from echo",
+                    tooltip::Position::Top,
+                ),
+                text(format!("{:>3}%", template.echo_send_scale_percent)).width(40),
+                text("Reverb"),
+                self.template_number_input(template.fixed_reverb_send, 0..=127, |t, send| {
+                    t.fixed_reverb_send = send
+                }),
+                text("Chorus"),
+                self.template_number_input(template.chorus_send, 0..=127, |t, send| {
+                    t.chorus_send = send
+                }),
+            ]
+            .spacing(10)
+            .align_y(alignment::Alignment::Center),
+            row![
+                self.template_checkbox(
+                    "Envelope as Expression",
+                    template.envelope_as_expression,
+                    |t, flag| t.envelope_as_expression = flag,
+                ),
+                self.template_checkbox(
+                    "Update Parameters after NoteOn",
+                    template.update_parameter_after_noteon,
+                    |t, flag| t.update_parameter_after_noteon = flag,
+                ),
+                self.template_checkbox("Sustain Pedal", template.sustain_pedal, |t, flag| {
+                    t.sustain_pedal = flag
+                }),
+                self.template_checkbox("Link Duplicates", template.link_duplicates, |t, flag| {
+                    t.link_duplicates = flag
+                }),
+            ]
+            .spacing(10)
+            .align_y(alignment::Alignment::Center),
+        ]
+        .spacing(10)
+        .padding(10)
+        .width(Length::Fill)
+        .into()
+    }
+
+    // テンプレートの数値の項目を編集する入力欄
+    fn template_number_input<'a>(
+        &self,
+        value: u8,
+        range: RangeInclusive<u8>,
+        apply: fn(&mut SourceParameterTemplate, u8),
+    ) -> Element<'a, Message> {
+        let template = self.source_parameter_template.read().unwrap().clone();
+        number_input(&value, range, move |value| {
+            let mut template = template.clone();
+            apply(&mut template, value);
+            Message::SourceParameterTemplateChanged(template)
+        })
+        .step(1)
+        .into()
+    }
+
+    // テンプレートの真偽値の項目を切り替えるチェックボックス
+    fn template_checkbox<'a>(
+        &self,
+        label: &'a str,
+        value: bool,
+        apply: fn(&mut SourceParameterTemplate, bool),
+    ) -> Element<'a, Message> {
+        let template = self.source_parameter_template.read().unwrap().clone();
+        checkbox(value)
+            .label(label)
+            .on_toggle(move |flag| {
+                let mut template = template.clone();
+                apply(&mut template, flag);
+                Message::SourceParameterTemplateChanged(template)
+            })
+            .into()
+    }
+
+    // 折りたたみ可能なセクションの見出し
+    fn section_header(&self, section: usize) -> Element<'_, Message> {
+        row![checkbox(self.showing_sections[section])
+            .label(DEVICE_SETTING_SECTION_TITLES[section])
+            .on_toggle(move |flag| Message::DeviceSettingSectionToggled(section, flag))]
+        .width(Length::Fill)
+        .align_y(alignment::Alignment::Center)
+        .into()
+    }
 }
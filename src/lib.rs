@@ -2,7 +2,10 @@ pub mod cli;
 mod device_setting_window;
 mod main_window;
 mod midi_output_configuration_window;
+mod parameter_diff_window;
+mod parameter_log_window;
 mod program;
+mod register_inspector_window;
 mod source_estimation;
 mod srn_ch_routing_window;
 mod srn_window;
@@ -11,37 +14,48 @@ mod types;
 use crate::device_setting_window::*;
 use crate::main_window::*;
 use crate::midi_output_configuration_window::*;
+use crate::parameter_diff_window::*;
+use crate::parameter_log_window::*;
 use crate::program::*;
+use crate::register_inspector_window::*;
 use crate::source_estimation::*;
 use crate::srn_ch_routing_window::*;
 use crate::srn_window::*;
 use crate::types::*;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, PauseStreamError, PlayStreamError, Stream, StreamConfig};
+use cpal::{
+    Device, PauseStreamError, PlayStreamError, Stream, StreamConfig, SupportedStreamConfig,
+};
 use fixed_resample::ReadStatus;
 use iced::keyboard::key::Named;
-use iced::widget::{center, space};
+use iced::widget::{center, scrollable, space};
 use iced::{event, window, Subscription, Task, Theme};
 use midir::{MidiOutput, MidiOutputConnection};
-use rfd::AsyncFileDialog;
+use rfd::{AsyncFileDialog, AsyncMessageDialog, MessageButtons, MessageDialogResult, MessageLevel};
 use rimd::{
-    Event as MidiEvent, MetaEvent, MidiMessage, SMFFormat, SMFWriter, Track, TrackEvent, SMF,
+    Event as MidiEvent, MetaCommand, MetaEvent, MidiMessage, SMFFormat, SMFWriter, Track,
+    TrackEvent, SMF,
 };
 use samplerate::{convert, ConverterType};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::BTreeMap;
+use std::collections::VecDeque;
 use std::ffi::OsStr;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::BufWriter;
 use std::num::NonZero;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 use std::{cmp, io};
+use vorbis_rs::VorbisEncoderBuilder;
 
 use spc700::decoder::*;
 use spc700::mididsp::*;
@@ -51,14 +65,51 @@ use spc700::types::*;
 
 /// タイトル文字列
 const SPC2MIDI2_TITLE_STR: &'static str = "spc2midi-tsuu";
+/// 記号（♪▲▼→など）を含むフォントデータ（環境のデフォルトフォントに無い場合のフォールバック）
+pub const SYMBOL_FONT_BYTES: &'static [u8] =
+    include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/fonts/DejaVuSans.ttf"));
+/// 記号を表示するフォント
+const SYMBOL_FONT: iced::Font = iced::Font::with_name("DejaVu Sans");
 /// SPCの出力サンプリングレート
 const SPC_SAMPLING_RATE: u32 = 32000;
+/// レベルメーターの表示下限(dB)
+const LEVEL_METER_MIN_DB: f32 = -60.0;
+/// BRRブロックあたりのサンプル数
+const BRR_BLOCK_NUM_SAMPLES: usize = 16;
+/// SPCファイルのシグネチャ
+const SPC_FILE_SIGNATURE: &[u8] = b"SNES-SPC700 Sound File Data";
+/// SPCファイルの最小サイズ（ヘッダ・RAM・DSPレジスタ）
+const SPC_FILE_MIN_SIZE: usize = 0x10180;
+/// SPCファイル内のRAMの開始オフセット
+const SPC_FILE_RAM_OFFSET: usize = 0x100;
+/// SPCファイル内の拡張RAM（IPL ROM領域の裏のRAM）の開始オフセット
+const SPC_FILE_EXTRA_RAM_OFFSET: usize = 0x101C0;
+/// 拡張RAMのサイズ
+const SPC_EXTRA_RAM_SIZE: usize = 64;
+/// SPC700のRAMサイズ
+const SPC_RAM_SIZE: usize = 0x10000;
+/// IPL ROMが配置されるアドレス
+const SPC_IPL_ROM_ADDRESS: usize = 0xFFC0;
+/// コントロールレジスタのアドレス
+const SPC_CONTROL_REGISTER_ADDRESS: usize = 0xF1;
+/// DSPレジスタ：ノイズ有効フラグ(NON)
+const DSP_ADDRESS_NOISE_ON: u8 = 0x3D;
+/// DSPレジスタ：ピッチモジュレーション有効フラグ(PMON)
+const DSP_ADDRESS_PITCH_MOD_ON: u8 = 0x2D;
+/// DSPレジスタ：ADSR設定1
+const DSP_ADDRESS_V0ADSR1: u8 = 0x05;
+/// DSPレジスタ：ADSR設定2
+const DSP_ADDRESS_V0ADSR2: u8 = 0x06;
 /// PCM正規化定数
 const PCM_NORMALIZE_CONST: f32 = 1.0 / 32768.0;
 /// 64KHz周期のクロックサイクル SPCのクロック(1.024MHz)を64KHzで割って得られる = 1024000 / 64000
 const CLOCK_TICK_CYCLE_64KHZ: u32 = 16;
 /// 64kHz間隔に相当するナノ秒
 const CLOCK_TICK_CYCLE_64KHZ_NANOSEC: u64 = 15625;
+/// 鳴りっぱなしのノートを調べる間隔（64kHzティック数、100msec）
+const STUCK_NOTE_CHECK_INTERVAL_TICKS: u32 = 6400;
+/// 再生中のMIDI出力スレッドが音源パラメータを取り込み直す間隔（64kHzティック数、10msec）
+const SOURCE_PARAMETER_REFRESH_INTERVAL_TICKS: u32 = 640;
 /// MIDIメッセージ：ノートオン
 const MIDIMSG_NOTE_ON: u8 = 0x90;
 /// MIDIメッセージ：ノートオフ
@@ -67,8 +118,24 @@ const MIDIMSG_NOTE_OFF: u8 = 0x80;
 const MIDIMSG_PROGRAM_CHANGE: u8 = 0xC0;
 /// MIDIメッセージ：チャンネルモードメッセージ
 const MIDIMSG_MODE: u8 = 0xB0;
+/// MIDIメッセージ：ピッチベンド
+const MIDIMSG_PITCH_BEND: u8 = 0xE0;
 /// MIDIチェンネルモードメッセージ：オールサウンドオフ
 const MIDIMSG_MODE_ALL_SOUND_OFF: u8 = 0x78;
+/// MIDIコントロールチェンジ：サステインペダル
+const MIDIMSG_CC_SUSTAIN_PEDAL: u8 = 0x40;
+/// MIDIコントロールチェンジ：チャンネルボリューム
+const MIDIMSG_CC_VOLUME: u8 = 0x07;
+/// MIDIコントロールチェンジ：エクスプレッション
+const MIDIMSG_CC_EXPRESSION: u8 = 0x0B;
+/// MIDIコントロールチェンジ：パン
+const MIDIMSG_CC_PAN: u8 = 0x0A;
+/// MIDIコントロールチェンジ：リバーブセンド（エフェクト1デプス）
+const MIDIMSG_CC_REVERB_SEND: u8 = 0x5B;
+/// MIDIコントロールチェンジ：バンクセレクトMSB
+const MIDIMSG_CC_BANK_SELECT_MSB: u8 = 0x00;
+/// MIDIコントロールチェンジ：バンクセレクトLSB
+const MIDIMSG_CC_BANK_SELECT_LSB: u8 = 0x20;
 /// MIDI System Exclusive：GMシステムオン
 const MIDIMSG_SYSEX_GMLEVEL1_SYSTEM_ON: [u8; 6] = [0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7];
 /// MIDI System Exclusive：GMシステムオフ
@@ -83,12 +150,74 @@ const MIDIMSG_SYSEX_GS_RESET: [u8; 11] = [
 const MIDIMSG_SYSEX_XG_SYSTEM_ON: [u8; 9] = [0xF0, 0x43, 0x10, 0x4C, 0x00, 0x00, 0x7E, 0x00, 0xF7];
 /// MIDIをプレビューする際に使用するチャンネル
 const MIDI_PREVIEW_CHANNEL: u8 = 0;
+/// GMのドラムチャンネル
+const MIDI_DRUM_CHANNEL: u8 = 9;
 /// MIDIをプレビューする時間(msec)
 const MIDI_PREVIEW_DURATION_MSEC: u64 = 500;
 /// デフォルトの音源の分析時間(sec)
 const DEFAULT_ANALYZING_TIME_SEC: u32 = 120;
 /// 1オクターブに相当するノート(9bit小数部の固定小数)
 const OCTAVE_NOTE: u16 = 12 << 9;
+/// ボリューム自動調整で平均的なラウドネスの音源に設定するボリューム
+const BALANCED_REFERENCE_VOLUME: f32 = 100.0;
+/// 1セントに相当するノート(9bit小数部の固定小数)
+const CENT_NOTE: f32 = 512.0 / 100.0;
+/// 設定ファイル名
+const PREFERENCES_FILE_NAME: &'static str = "preferences.json";
+/// SMF出力の検証で出力する時間(msec)
+const VALIDATION_DURATION_MSEC: u64 = 30 * 1000;
+/// ループ検出で同じ時刻とみなす誤差（四分音符のティック数をこの値で割ったもの）
+const LOOP_DETECTION_TOLERANCE_DIVISOR: u64 = 48;
+/// 音源が見つからなかったときの対処の案内
+const NO_SOURCE_HINT: &str = "The song may start after a long silence: increase the Output Duration in the MIDI Output Configuration and press \"Re-analyze SRN\". Otherwise the file may be silent or broken.";
+/// 音色マッピングの特徴による照合で許すセンターノートの差（半音）
+const INSTRUMENT_MAPPING_NOTE_TOLERANCE: f32 = 12.0;
+/// 曲全体の波形概要の区間数
+const SONG_OVERVIEW_NUM_PEAKS: usize = 1000;
+/// 曲全体の波形概要のループ検出で同じ時刻とみなす誤差（1秒のサンプル数をこの値で割ったもの）
+const SONG_OVERVIEW_LOOP_TOLERANCE_DIVISOR: u32 = 50;
+/// 音源の波形サムネイルの区間数
+const SOURCE_THUMBNAIL_NUM_PEAKS: usize = 64;
+/// メインウィンドウのグリッド表示の列数
+const SOURCE_GRID_COLUMNS: usize = 6;
+/// メインウィンドウのグリッド表示の行の高さ（行間を含む）
+const SOURCE_GRID_ROW_HEIGHT: f32 = 100.0;
+/// グリッド表示に切り替えたときに波形サムネイルを作成しておく行数
+const SOURCE_GRID_INITIAL_ROWS: usize = 8;
+/// ノート名
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+/// オーディオ出力の状態判定でアンダーランを数える直近の時間(sec)
+const AUDIO_HEALTH_WINDOW_SEC: u64 = 10;
+/// 直近の時間内にこの回数以上アンダーランが発生したら頻繁とみなす
+const AUDIO_HEALTH_POOR_UNDERRUNS: usize = 3;
+/// MIDIの最大ビットレート(bps)
+const MIDI_MAX_BIT_RATE: f64 = 31_250.0;
+/// ドラムチャンネルで鳴らすクリックのノート（サイドスティック）
+const CLICK_TRACK_DRUM_NOTE: u8 = 37;
+/// ドラムチャンネル以外で鳴らすクリックのノート
+const CLICK_TRACK_TONAL_NOTE: u8 = 84;
+/// クリックのベロシティ
+const CLICK_TRACK_VELOCITY: u8 = 100;
+/// モノラル互換性に問題があるとみなす左右の相関係数
+const MONO_COMPATIBILITY_CORRELATION_THRESHOLD: f32 = 0.0;
+/// WAV出力でクリップが持続しているとみなす累計時間(msec)
+const WAV_CLIPPING_WARNING_MSEC: u64 = 100;
+/// ソフトリミッタが圧縮を始めるレベル（フルスケールに対する比）
+const SOFT_LIMITER_THRESHOLD: f32 = 0.8;
+/// 仮想MIDI出力ポートを作成できるか（midirはUnix系のみ対応）
+const VIRTUAL_MIDI_PORT_SUPPORTED: bool = cfg!(unix);
+/// ステレオペアとみなすのに必要な同時キーオン回数
+const STEREO_PAIR_MIN_KEYONS: usize = 2;
+/// ステレオペアとみなす波形の相関係数（絶対値）の下限
+const STEREO_PAIR_MIN_CORRELATION: f32 = 0.5;
+/// 片側に振り切っているとみなす左右ボリュームの比
+const HARD_PAN_VOLUME_RATIO: i32 = 8;
+/// DSPレジスタ：エコーボリューム（左）
+const DSP_ADDRESS_EVOLL: u8 = 0x2C;
+/// DSPレジスタ：エコーボリューム（右）
+const DSP_ADDRESS_EVOLR: u8 = 0x3C;
 
 #[derive(Debug, Clone)]
 pub enum Message {
@@ -102,21 +231,66 @@ pub enum Message {
     SRNWindowOpened(window::Id),
     OpenSRNChannelRoutingWindow(u8),
     SRNChannelRoutingWindowOpened(window::Id),
+    WindowRescaled(window::Id, f32),
+    AddSourceNumberChanged(u8),
+    MainWindowLayoutToggled,
+    SourceGridScrolled(scrollable::Viewport),
+    AddSourceRequested(u8),
+    OpenRegisterInspectorWindow,
+    AutoBalanceVolumeRequested,
+    EstimateAllSourcesRequested,
+    EstimateAllSourcesConfirmed(Option<bool>),
+    RegisterInspectorWindowOpened(window::Id),
+    InspectorRAMPageChanged(window::Id, u8),
+    InspectorPokeAddressChanged(window::Id, u8),
+    InspectorPokeValueChanged(window::Id, u8),
+    ParameterDiffWindowOpened(window::Id),
+    ParameterDiffEntryToggled(window::Id, usize, bool),
+    ParameterDiffApplied(window::Id, bool),
+    ParameterDiffCanceled(window::Id),
+    OpenParameterLogWindow,
+    ParameterLogWindowOpened(window::Id),
+    ParameterLogCleared,
+    InspectorPokeRequested(u8, u8),
     WindowClosed(window::Id),
     OpenFile,
+    ReopenFile,
+    ReopenFileConfirmed(bool),
     FileOpened(Result<(PathBuf, LoadedFile), Error>),
     SaveSMF,
     SMFSaved(Result<(), Error>),
     SaveJSON,
     JSONSaved(Result<(), Error>),
+    SaveReport,
+    ReportSaved(Result<(), Error>),
+    SaveSPC,
+    SPCSaved(Result<(), Error>),
+    ValidateSMF,
+    SMFPlaybackToggled,
+    LoadInstrumentMapping,
+    InstrumentMappingPicked(Option<PathBuf>),
+    PrintMIDINoteSummary,
+    NoteSummaryDurationChanged(u64),
+    MessageDialogClosed,
+    SaveWAV,
+    WAVSaved(Result<(), Error>),
+    SaveOGG,
+    OGGSaved(Result<(), Error>),
+    BackgroundOperationCancelRequested,
+    BackgroundOperationPolled,
     MenuSelected,
     EventOccurred(iced::Event),
     ReceivedSRNPlayStartRequest(u8),
     SRNPlayLoopFlagToggled(bool),
+    SRNSnapLoopPointFlagToggled(bool),
     SRNPlayVolumeChanged(u8),
     ReceivedPlayStartRequest,
     ReceivedPlayStopRequest,
+    SongSeekRequested(f32),
+    SongSeekPolled,
+    SongOverviewPolled,
     SRNChannelListFlagToggled(usize, bool),
+    DeviceSettingSectionToggled(usize, bool),
     SPCMuteFlagToggled(bool),
     MIDIMuteFlagToggled(bool),
     SRNMuteFlagToggled(u8, bool),
@@ -124,18 +298,26 @@ pub enum Message {
     ProgramSearchboxInputed(window::Id, String),
     ProgramSearchboxClosed(window::Id),
     SRNMIDIPreviewFlagToggled(bool),
+    SRNKeyboardPlayFlagToggled(u8, bool),
+    SRNKeyboardNoteOn(u8, u8),
+    SRNKeyboardNoteOff(u8, u8),
+    SRNKeyboardAllNotesOff(u8),
+    SRNSoloInSongToggled(u8, bool),
     ReceivedMIDIPreviewRequest(u8),
     CenterNoteIntChanged(u8, u8),
     CenterNoteFractionChanged(u8, f32),
     NoteOnVelocityChanged(u8, u8),
+    VelocityFromKeyOnVolumeFlagToggled(u8, bool),
     PitchBendWidthChanged(u8, u8),
     EnablePitchBendFlagToggled(u8, bool),
     AutoPanFlagToggled(u8, bool),
     FixedPanChanged(u8, u8),
+    PanLawChanged(u8, PanLaw),
     AutoVolumeFlagToggled(u8, bool),
     FixedVolumeChanged(u8, u8),
     EnvelopeAsExpressionFlagToggled(u8, bool),
     EchoAsReverbFlagToggled(u8, bool),
+    EchoSendScaleChanged(u8, u8),
     FixedReverbSendChanged(u8, u8),
     ChorusSendChanged(u8, u8),
     UpdateParameterAfterNoteOnFlagToggled(u8, bool),
@@ -143,21 +325,54 @@ pub enum Message {
     ChannelRoutingChanged(u8, u8, u8),
     ChannelRoutingReseted(u8),
     InstrumentNameChanged(u8, String),
+    LabelChanged(u8, String),
+    HideInIndicatorFlagToggled(u8, bool),
+    SustainPedalFlagToggled(u8, bool),
+    MinNoteChanged(u8, u8),
+    MaxNoteChanged(u8, u8),
+    LinkDuplicatesFlagToggled(u8, bool),
+    MergeStereoPairFlagToggled(u8, bool),
+    SRNKindOverrideSelected(u8, SourceKindOverride),
     SRNCenterNoteOctaveUpClicked(u8),
     SRNCenterNoteOctaveDownClicked(u8),
+    SRNCenterNoteSnapToSemitoneClicked(u8),
+    SRNCenterNoteSnapToCentClicked(u8),
     SRNNoteEstimationClicked(u8),
     ReceivedSourceParameterUpdate,
     AudioOutputDeviceSelected(String),
     MIDIOutputPortSelected(String),
+    VirtualMIDIPortFlagToggled(bool),
     MIDIOutputBpmChanged(f32),
     MIDIOutputTicksPerQuarterChanged(u16),
     MIDIVolumeCurveChanged(VolumeCurve),
     MIDISystemChanged(MIDISystem),
+    DrumKitChanged(DrumKit),
     MIDIOutputUpdatePeriodChanged(u8),
     MIDIOutputDurationChanged(u64),
     MIDIOutputSPC700ClockUpFactorChanged(u32),
     MIDIOutputSplitDrumIntoSeparateTracksChanged(bool),
     MIDIOutputTrimLeadingNonEventsPeriodChanged(bool),
+    MIDIOutputSourceMarkersFlagToggled(bool),
+    MIDIOutputEmbedSPCMetadataFlagToggled(bool),
+    MIDIOutputEmbedSPCCommentFlagToggled(bool),
+    MIDIOutputClickTrackFlagToggled(bool),
+    MIDIOutputMinNoteDurationChanged(u32),
+    MIDIOutputMaxPolyphonyChanged(u8),
+    AdvancedOptionsToggled(bool),
+    SplitSMFPerLoopFlagToggled(bool),
+    RoutePercussiveNotesFlagToggled(bool),
+    MinSourceKeyOnDurationChanged(u32),
+    ExcludedVoiceFlagToggled(u8, bool),
+    WAVBypassEchoChanged(bool),
+    WAVSoftLimiterFlagToggled(bool),
+    WAVStereoWidthChanged(u16),
+    WAVSampleRateChanged(WAVSampleRate),
+    WAVResampleQualityChanged(WAVResampleQuality),
+    FlattenVelocityFlagToggled(bool),
+    FlatVelocityChanged(u8),
+    CalibrateVelocityByProgramFlagToggled(bool),
+    TargetPitchBendWidthChanged(u8),
+    ClampPitchBendWidthFlagToggled(bool),
     MuteChannel(u8, bool),
     SoloChannel(u8),
     ReceivedBpmAnalyzeRequest,
@@ -166,6 +381,31 @@ pub enum Message {
     ReceivedSRNReanalyzeRequest,
     DisplaySourceIDTypeToggled,
     AudioLatencyMsecChanged(usize),
+    RampInOnPlayStartFlagToggled(bool),
+    RampInMsecChanged(usize),
+    DeviceSwitchFadeMsecChanged(usize),
+    ForceOutputSampleRateFlagToggled(bool),
+    OutputSampleRateChanged(u32),
+    A4PitchChanged(f32),
+    IndicatorSmoothingChanged(f32),
+    SpectrumPeakLabelsChanged(usize),
+    SpectrumPeakSeparationChanged(f32),
+    CacheAnalysisFlagToggled(bool),
+    LazySourceDecodingFlagToggled(bool),
+    StretchSMFExportFlagToggled(bool),
+    StretchTargetSecChanged(u32),
+    MasterReverbSendChanged(u8),
+    StuckNoteTimeoutChanged(u32),
+    ExportPrerollMsecChanged(usize),
+    LongExportWarningSecChanged(u32),
+    LongExportConfirmed(Box<Message>),
+    ProgramVelocityCalibrationProgramSelected(Program),
+    ProgramVelocityCalibrationChanged(u8, u8),
+    ProgramVelocityCalibrationReset,
+    SourceParameterTemplateChanged(SourceParameterTemplate),
+    ExportRangeFlagToggled(bool),
+    ExportRangeStartChanged(f32),
+    ExportRangeEndChanged(f32),
     Tick,
 }
 
@@ -175,6 +415,7 @@ pub struct App {
     windows: BTreeMap<window::Id, Box<dyn SPC2MIDI2Window>>,
     spc_file: Option<Box<SPCFile>>,
     spc_file_path: Option<PathBuf>,
+    spc_file_data: Option<Arc<Vec<u8>>>,
     source_infos: Arc<RwLock<BTreeMap<u8, SourceInformation>>>,
     source_parameter: Arc<RwLock<BTreeMap<u8, SourceParameter>>>,
     playback_status: Arc<RwLock<PlaybackStatus>>,
@@ -184,20 +425,89 @@ pub struct App {
     stream: Option<Stream>,
     stream_played_samples: Arc<AtomicUsize>,
     midi_output_bytes: Arc<AtomicUsize>,
+    /// 前回の表示更新以降のSPC（PCM）出力のピーク振幅（f32のビット列）
+    pcm_output_level: Arc<AtomicU32>,
+    /// MIDI出力から推定した出力振幅（f32のビット列）
+    midi_output_level: Arc<AtomicU32>,
+    stream_underruns: Arc<AtomicUsize>,
+    observed_underruns: usize,
+    recent_underrun_times: VecDeque<Instant>,
     stream_is_playing: Arc<AtomicBool>,
+    playback_mode: PlaybackMode,
     audio_output_latency_msec: Arc<AtomicUsize>,
+    ramp_in_on_play_start: Arc<AtomicBool>,
+    ramp_in_msec: Arc<AtomicUsize>,
+    device_switch_fade_msec: Arc<AtomicUsize>,
+    force_output_sample_rate: Arc<AtomicBool>,
+    output_sample_rate: Arc<AtomicU32>,
+    output_gain_target: Arc<AtomicU32>,
+    a4_pitch_hz: Arc<AtomicU32>,
+    indicator_smoothing: Arc<AtomicU32>,
+    spectrum_peak_labels: Arc<AtomicUsize>,
+    spectrum_peak_separation_hz: Arc<AtomicU32>,
+    cache_analysis: Arc<AtomicBool>,
+    lazy_source_decoding: Arc<AtomicBool>,
+    stretch_smf_export: Arc<AtomicBool>,
+    stretch_target_sec: Arc<AtomicU32>,
+    master_reverb_send: Arc<AtomicU8>,
+    stuck_note_timeout_sec: Arc<AtomicU32>,
+    export_preroll_msec: Arc<AtomicUsize>,
+    long_export_warning_sec: Arc<AtomicU32>,
+    /// 長時間の書き出しを確認済みか（次の書き出しで確認を省く）
+    long_export_confirmed: bool,
+    /// プログラムごとのベロシティ補正率(%)
+    program_velocity_calibration: Arc<RwLock<Vec<u8>>>,
+    /// 再生中にMIDI出力で鳴らしているノート
+    held_notes: Arc<Mutex<HeldNoteTracker>>,
+    source_parameter_template: Arc<RwLock<SourceParameterTemplate>>,
+    export_range_enabled: Arc<AtomicBool>,
+    export_range_start_sec: Arc<AtomicU32>,
+    export_range_end_sec: Arc<AtomicU32>,
     midi_out_conn: Option<Arc<Mutex<MidiOutputConnection>>>,
+    keyboard_held_notes: Vec<KeyboardHeldNote>,
+    /// 曲中でソロにしている音源番号とソロ開始前のチャンネルミュートフラグ
+    source_solo: Option<(u8, u8)>,
+    /// 差分確認中の読み込んだ設定
+    pending_import: Option<ExportInformation>,
+    /// 未保存の変更の確認用に、読み込み・JSON保存時点の音源パラメータを文字列にしたもの
+    saved_source_parameter: String,
+    use_virtual_midi_port: Arc<AtomicBool>,
     pcm_spc: Option<Arc<Mutex<Box<spc700::spc::SPC<spc700::sdsp::SDSP>>>>>,
     midi_spc: Option<Arc<Mutex<Box<spc700::spc::SPC<spc700::mididsp::MIDIDSP>>>>>,
     pcm_spc_on: Arc<AtomicBool>,
     midi_spc_on: Arc<AtomicBool>,
     midi_preview: Arc<AtomicBool>,
     preview_loop: Arc<AtomicBool>,
+    snap_loop_point: Arc<AtomicBool>,
     preview_volume: Arc<AtomicU8>,
     channel_mute_flags: Arc<AtomicU8>,
     audio_out_device_name: Arc<RwLock<Option<String>>>,
     midi_out_port_name: Arc<RwLock<Option<String>>>,
     display_source_id_type: Arc<RwLock<DisplaySourceIDType>>,
+    background_operation: Option<BackgroundOperation>,
+    /// 曲全体の波形概要の描画スレッドとそのキャンセルフラグ
+    song_overview_job: Option<thread::JoinHandle<Option<SongOverview>>>,
+    song_overview_cancel: Arc<AtomicBool>,
+    /// 再生位置の移動処理とそのキャンセルフラグ
+    song_seek_job: Option<SongSeekJob>,
+    song_seek_cancel: Arc<AtomicBool>,
+    /// 書き出したSMFを再生するスレッドとその停止フラグ
+    smf_playback_thread: Option<thread::JoinHandle<()>>,
+    smf_playback_stop: Arc<AtomicBool>,
+    /// 解析後に自動で適用する音色マッピングとそのファイルパス
+    instrument_mapping: Option<(PathBuf, InstrumentMapping)>,
+    operation_cancel: Arc<AtomicBool>,
+    operation_progress: Arc<AtomicUsize>,
+    wav_mono_compatibility: Option<MonoCompatibility>,
+    wav_clipped_msec: u64,
+    /// 書き出し中のOGGに付けたループ（開始サンプル, 長さ）
+    ogg_loop_samples: Option<(u64, u64)>,
+    /// SMF出力中に発音された、解析で見つからなかった音源番号
+    smf_unanalyzed_sources: Vec<u8>,
+    /// 音源パラメータの変更記録
+    parameter_log: Arc<RwLock<VecDeque<ParameterLogEntry>>>,
+    /// 設定の変更を設定ファイルに保存するか
+    persist_preferences: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -208,6 +518,528 @@ struct ExportInformation {
     midi_output_configure: MIDIOutputConfigure,
     /// 音源パラメータ割当
     source_parameter: BTreeMap<u8, SourceParameter>,
+    /// 音源ごとのセンターノート推定の信頼度（参考情報、読み込み時には使用しない）
+    #[serde(default)]
+    pitch_confidence: BTreeMap<u8, f32>,
+}
+
+/// SPCヘッダの文字列フィールドを終端のNULと空白を除いて取得
+fn spc_header_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string()
+}
+
+/// SMFのテキストイベントに入れられるよう、ASCII以外の文字を'?'に置き換え制御文字を取り除く
+fn sanitize_smf_text(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if c.is_ascii() { c } else { '?' })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// クリックトラックを出力するチャンネルを選ぶ（ドラムチャンネルが空いていれば優先、なければ空いている最後のチャンネル）
+fn find_click_channel(params: &BTreeMap<u8, SourceParameter>) -> Option<u8> {
+    let is_used = |midi_ch: u8| {
+        params
+            .values()
+            .any(|param| param.channel_routing.contains(&midi_ch))
+    };
+    if !is_used(MIDI_DRUM_CHANNEL) {
+        return Some(MIDI_DRUM_CHANNEL);
+    }
+    (0..16).rev().find(|&midi_ch| !is_used(midi_ch))
+}
+
+/// 推定したテンポの拍ごとにクリックを鳴らすトラックを作成（空いているチャンネルがなければNone）
+fn create_click_track(
+    config: &MIDIOutputConfigure,
+    params: &BTreeMap<u8, SourceParameter>,
+) -> Option<Track> {
+    let midi_ch = find_click_channel(params)?;
+    let ticks_per_beat = config.ticks_per_quarter as u64;
+    let note_ticks = (ticks_per_beat / 4).max(1);
+    let total_ticks = ((config.output_duration_msec as f64 / 60_000.0)
+        * config.beats_per_minute as f64
+        * ticks_per_beat as f64) as u64;
+
+    let mut events = vec![TrackEvent {
+        vtime: 0,
+        event: MidiEvent::Meta(MetaEvent::sequence_or_track_name("Click".to_string())),
+    }];
+    // ドラムチャンネル以外ではウッドブロックの音色で鳴らす
+    let note = if midi_ch == MIDI_DRUM_CHANNEL {
+        CLICK_TRACK_DRUM_NOTE
+    } else {
+        events.push(TrackEvent {
+            vtime: 0,
+            event: MidiEvent::Midi(MidiMessage::from_bytes(vec![
+                MIDIMSG_PROGRAM_CHANGE | midi_ch,
+                Program::Woodblock as u8,
+            ])),
+        });
+        CLICK_TRACK_TONAL_NOTE
+    };
+    let mut beat_ticks = 0;
+    let mut previous_ticks = 0;
+    while beat_ticks < total_ticks {
+        events.push(TrackEvent {
+            vtime: beat_ticks - previous_ticks,
+            event: MidiEvent::Midi(MidiMessage::from_bytes(vec![
+                MIDIMSG_NOTE_ON | midi_ch,
+                note,
+                CLICK_TRACK_VELOCITY,
+            ])),
+        });
+        events.push(TrackEvent {
+            vtime: note_ticks,
+            event: MidiEvent::Midi(MidiMessage::from_bytes(vec![
+                MIDIMSG_NOTE_OFF | midi_ch,
+                note,
+                0,
+            ])),
+        });
+        previous_ticks = beat_ticks + note_ticks;
+        beat_ticks += ticks_per_beat;
+    }
+
+    Some(Track {
+        copyright: None,
+        name: Some("Click".to_string()),
+        events: events,
+    })
+}
+
+/// SMFに埋め込むSPCの曲情報のテキストを作成（空のフィールドは出力しない）
+fn create_spc_metadata_texts(spc_file: &SPCFile) -> Vec<String> {
+    let mut texts = Vec::new();
+    for (key, bytes) in [
+        ("Title", &spc_file.header.music_title[..]),
+        ("Game", &spc_file.header.game_title[..]),
+        ("Artist", &spc_file.header.artist_name[..]),
+    ] {
+        let value = spc_header_text(bytes);
+        if !value.is_empty() {
+            texts.push(format!("{}: {}", key, value));
+        }
+    }
+    texts.push(format!(
+        "Tool: {} Ver.{}",
+        SPC2MIDI2_TITLE_STR,
+        env!("CARGO_PKG_VERSION")
+    ));
+    texts
+}
+
+/// 解析キャッシュに保存する1音源の解析結果（波形・スペクトルは保存しない）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedSource {
+    /// 音源ディレクトリエントリのアドレス
+    dir_address: usize,
+    /// チャンネルを使っているか？（8チャンネル分）
+    using_channel: [bool; 8],
+    /// キーオン時のピッチ
+    keyon_pitches: Vec<u16>,
+    /// 発音中のキーオン時ピッチからの最大変化量（半音単位）
+    max_pitch_deviation: f32,
+    /// ノイズ音源として発音されたか
+    uses_noise: bool,
+    /// ピッチモジュレーション有効で発音されたか
+    pitch_modulated: bool,
+    /// 採用しなかった他のディレクトリエントリのアドレス
+    #[serde(default)]
+    alternate_dir_addresses: Vec<usize>,
+    /// ステレオペアの相手の音源番号
+    #[serde(default)]
+    stereo_pair_partner: Option<u8>,
+}
+
+/// 音源解析の条件
+#[derive(Debug, Clone)]
+struct SourceAnalysisSettings {
+    /// 基準ピッチ(A4)の周波数(Hz)
+    a4_pitch_hz: f32,
+    /// 音源パラメータのテンプレート
+    template: SourceParameterTemplate,
+    /// 音源として扱う最小キーオン期間(ms)
+    min_source_keyon_msec: u32,
+    /// 解析から除外するボイス
+    excluded_voices: u8,
+    /// BPM推定でミュートするチャンネル
+    channel_mute_flags: u8,
+}
+
+/// 音源解析の結果
+#[derive(Debug, Clone)]
+struct SourceAnalysis {
+    /// 音源情報
+    infos: BTreeMap<u8, SourceInformation>,
+    /// 推定した音源パラメータ
+    params: BTreeMap<u8, SourceParameter>,
+    /// 推定したBPM
+    beats_per_minute: f32,
+    /// 解析キャッシュに保存する音源ごとの解析結果
+    cached_sources: BTreeMap<u8, CachedSource>,
+}
+
+/// 音源解析を行った目的
+enum AnalysisRequest {
+    /// SPCファイルを開く
+    FileOpen {
+        /// 開くファイルのパス
+        path: PathBuf,
+        /// 開くファイルのデータ
+        data: Vec<u8>,
+        /// 開くSPCファイル
+        spc_file: Box<SPCFile>,
+        /// 解析時間(sec)
+        analyze_duration_sec: u32,
+        /// 解析結果を保存するキャッシュのパスと解析条件
+        cache: Option<(PathBuf, AnalysisCache)>,
+    },
+    /// 開いているSPCファイルの再解析
+    Reanalyze {
+        /// 解析時間(sec)
+        analyze_duration_sec: u32,
+    },
+}
+
+/// SPCファイルの解析結果のキャッシュ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AnalysisCache {
+    /// SPCファイルの内容のハッシュ
+    content_hash: u64,
+    /// 解析時間(sec)
+    analyze_duration_sec: u32,
+    /// 解析時の最小キーオン期間(ms)
+    min_source_keyon_msec: u32,
+    /// 解析時の基準ピッチ(A4)の周波数(Hz)
+    a4_pitch_hz: f32,
+    /// 解析から除外したボイス
+    #[serde(default)]
+    excluded_voices: u8,
+    /// BPM推定でミュートしたチャンネル
+    #[serde(default)]
+    channel_mute_flags: u8,
+    /// 初期パラメータの作成に使ったテンプレート（記録の無い古いキャッシュはNone）
+    #[serde(default)]
+    source_parameter_template: Option<SourceParameterTemplate>,
+    /// 推定したBPM
+    beats_per_minute: f32,
+    /// 音源ごとの解析結果
+    sources: BTreeMap<u8, CachedSource>,
+    /// 推定した音源パラメータ
+    source_parameter: BTreeMap<u8, SourceParameter>,
+}
+
+impl AnalysisCache {
+    /// 同じ内容のSPCを同じ条件で解析した結果か
+    fn is_valid_for(&self, other: &AnalysisCache) -> bool {
+        self.content_hash == other.content_hash
+            && self.analyze_duration_sec == other.analyze_duration_sec
+            && self.min_source_keyon_msec == other.min_source_keyon_msec
+            && self.a4_pitch_hz == other.a4_pitch_hz
+            && self.excluded_voices == other.excluded_voices
+            && self.channel_mute_flags == other.channel_mute_flags
+            && self.source_parameter_template.is_some()
+            && self.source_parameter_template == other.source_parameter_template
+    }
+}
+
+/// SPCファイルの内容のハッシュ（実行環境によらず安定なFNV-1a）
+fn spc_content_hash(data: &[u8]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xCBF29CE484222325;
+    const FNV_PRIME: u64 = 0x100000001B3;
+    data.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// SPCファイルに対応する解析キャッシュのパス
+fn analysis_cache_path(spc_path: &PathBuf) -> PathBuf {
+    spc_path.with_extension("analysis.json")
+}
+
+/// 解析キャッシュの読み込み（読み込めなければNone）
+fn load_analysis_cache(path: &PathBuf) -> Option<AnalysisCache> {
+    let string = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<AnalysisCache>(&string) {
+        Ok(cache) => Some(cache),
+        Err(e) => {
+            eprintln!("ERROR: failed to load analysis cache: {:?}", e);
+            None
+        }
+    }
+}
+
+/// 解析キャッシュの保存
+fn save_analysis_cache(path: &PathBuf, cache: &AnalysisCache) -> Result<(), Error> {
+    let file = File::create(path).map_err(|e| Error::IoError(e.kind()))?;
+    serde_json::to_writer(BufWriter::new(file), cache)
+        .map_err(|_| Error::IoError(io::ErrorKind::InvalidData))
+}
+
+/// 音色マッピングの1項目（音源番号が一致するものを優先し、無ければ打楽器か・センターノートで照合）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InstrumentMappingEntry {
+    /// 対象の音源番号（省略時は特徴のみで照合）
+    #[serde(default)]
+    srn: Option<u8>,
+    /// 打楽器の音源に当てはめるか（省略時は区別しない）
+    #[serde(default)]
+    is_drum: Option<bool>,
+    /// 近い音源に当てはめるセンターノート（省略時は区別しない）
+    #[serde(default)]
+    center_note: Option<f32>,
+    /// 割り当てるプログラム
+    program: Program,
+    /// 割り当てるラベル（空なら変更しない）
+    #[serde(default)]
+    label: String,
+}
+
+/// 同じサウンドドライバの曲に共通して使う音色マッピング
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct InstrumentMapping {
+    entries: Vec<InstrumentMappingEntry>,
+}
+
+impl InstrumentMapping {
+    /// 音源に当てはめる項目を探す
+    fn find(&self, srn: u8, is_drum: bool, center_note: f32) -> Option<&InstrumentMappingEntry> {
+        // 音源番号が一致すればそれを使う
+        if let Some(entry) = self.entries.iter().find(|entry| entry.srn == Some(srn)) {
+            return Some(entry);
+        }
+        // 番号指定のない項目から、打楽器の区別が合いセンターノートが最も近いものを選ぶ
+        self.entries
+            .iter()
+            .filter(|entry| entry.srn.is_none())
+            .filter(|entry| entry.is_drum.map_or(true, |drum| drum == is_drum))
+            .filter_map(|entry| {
+                let distance = match entry.center_note {
+                    Some(note) => (note - center_note).abs(),
+                    None => INSTRUMENT_MAPPING_NOTE_TOLERANCE,
+                };
+                if distance <= INSTRUMENT_MAPPING_NOTE_TOLERANCE {
+                    Some((entry, distance))
+                } else {
+                    None
+                }
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(entry, _)| entry)
+    }
+
+    /// 音源パラメータにマッピングを適用し、当てはめた音源数を返す
+    fn apply(
+        &self,
+        infos: &BTreeMap<u8, SourceInformation>,
+        params: &mut BTreeMap<u8, SourceParameter>,
+    ) -> usize {
+        let mut num_applied = 0;
+        for (srn, param) in params.iter_mut() {
+            let is_drum = (param.program.clone() as u8) >= 0x80;
+            let center_note = param.center_note as f32 / 512.0;
+            if let Some(entry) = self.find(*srn, is_drum, center_note) {
+                let pitch_modulated = infos.get(srn).is_some_and(|info| info.pitch_modulated);
+                set_source_program(param, entry.program.clone(), pitch_modulated);
+                if !entry.label.is_empty() {
+                    param.label = entry.label.clone();
+                }
+                num_applied += 1;
+            }
+        }
+        num_applied
+    }
+}
+
+/// 音色マッピングファイルの読み込み
+fn load_instrument_mapping(path: &PathBuf) -> Result<InstrumentMapping, String> {
+    let string = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str::<InstrumentMapping>(&string).map_err(|e| e.to_string())
+}
+
+/// 読み込んだ設定と現在の設定の差分を項目ごとに列挙
+fn diff_export_information(
+    config: &MIDIOutputConfigure,
+    params: &BTreeMap<u8, SourceParameter>,
+    loaded: &ExportInformation,
+) -> Vec<ParameterDifference> {
+    let mut differences = vec![];
+    for (field, current, loaded) in diff_json_fields(config, &loaded.midi_output_configure) {
+        differences.push(ParameterDifference {
+            srn_no: None,
+            field: field,
+            current: Some(current),
+            loaded: loaded,
+            accepted: true,
+        });
+    }
+    for (srn_no, loaded_param) in loaded.source_parameter.iter() {
+        match params.get(srn_no) {
+            Some(param) => {
+                for (field, current, loaded) in diff_json_fields(param, loaded_param) {
+                    differences.push(ParameterDifference {
+                        srn_no: Some(*srn_no),
+                        field: field,
+                        current: Some(current),
+                        loaded: loaded,
+                        accepted: true,
+                    });
+                }
+            }
+            None => {
+                // 現在存在しない音源は丸ごと追加
+                differences.push(ParameterDifference {
+                    srn_no: Some(*srn_no),
+                    field: String::new(),
+                    current: None,
+                    loaded: serde_json::to_value(loaded_param).unwrap(),
+                    accepted: true,
+                });
+            }
+        }
+    }
+    differences
+}
+
+/// 2つの値をJSONオブジェクトとしてフィールドごとに比較し、異なるフィールド名と値の組を返す
+fn diff_json_fields<T: Serialize>(
+    current: &T,
+    loaded: &T,
+) -> Vec<(String, serde_json::Value, serde_json::Value)> {
+    let current = serde_json::to_value(current).unwrap();
+    let loaded = serde_json::to_value(loaded).unwrap();
+    let (current, loaded) = match (current.as_object(), loaded.as_object()) {
+        (Some(current), Some(loaded)) => (current, loaded),
+        _ => return vec![],
+    };
+    loaded
+        .iter()
+        .filter(|(field, value)| current.get(*field) != Some(*value))
+        .map(|(field, value)| {
+            (
+                field.clone(),
+                current.get(field).cloned().unwrap_or_default(),
+                value.clone(),
+            )
+        })
+        .collect()
+}
+
+/// JSONオブジェクトとして1フィールドだけ書き換える（変換できない場合は書き換えない）
+fn apply_json_field<T: Serialize + DeserializeOwned>(
+    target: &mut T,
+    field: &str,
+    value: &serde_json::Value,
+) {
+    let mut object = serde_json::to_value(&*target).unwrap();
+    if let Some(map) = object.as_object_mut() {
+        map.insert(field.to_string(), value.clone());
+    }
+    match serde_json::from_value(object) {
+        Ok(updated) => *target = updated,
+        Err(e) => eprintln!("ERROR: failed to apply field {}: {:?}", field, e),
+    }
+}
+
+/// 選択された差分のみを現在の設定に適用
+fn apply_parameter_differences(
+    config: &mut MIDIOutputConfigure,
+    params: &mut BTreeMap<u8, SourceParameter>,
+    loaded: &ExportInformation,
+    differences: &[ParameterDifference],
+) {
+    for difference in differences.iter().filter(|difference| difference.accepted) {
+        match difference.srn_no {
+            None => apply_json_field(config, &difference.field, &difference.loaded),
+            Some(srn_no) => {
+                if difference.field.is_empty() {
+                    if let Some(param) = loaded.source_parameter.get(&srn_no) {
+                        params.insert(srn_no, param.clone());
+                    }
+                } else if let Some(param) = params.get_mut(&srn_no) {
+                    apply_json_field(param, &difference.field, &difference.loaded);
+                }
+            }
+        }
+    }
+}
+
+/// バックグラウンド処理の結果
+enum BackgroundResult {
+    SMF(Option<SMF>, Vec<u8>),
+    SMFSections(Option<Vec<(String, SMF)>>, Vec<u8>),
+    WAV(Option<(Vec<u8>, MonoCompatibility, u64)>),
+    OGG(Option<(Vec<u8>, Option<(u64, u64)>)>),
+    Analysis(Result<Option<SourceAnalysis>, String>, AnalysisRequest),
+}
+
+/// レンダリングしたSPCの出力
+struct RenderedPCM {
+    /// インターリーブした16bitステレオPCM
+    pcm: Vec<i16>,
+    /// サンプリングレート(Hz)
+    sampling_rate: u32,
+    /// 曲のループ（出力先頭からの開始サンプル, 長さ）（検出できない・出力内に収まらない場合はNone）
+    loop_samples: Option<(u64, u64)>,
+    /// モノラル互換性
+    mono_compatibility: MonoCompatibility,
+    /// クリップした時間(msec)
+    clipped_msec: u64,
+}
+
+impl RenderedPCM {
+    /// チャンネル数
+    const NUM_CHANNELS: usize = 2;
+}
+
+/// WAV出力のモノラル互換性
+#[derive(Debug, Clone, Copy)]
+struct MonoCompatibility {
+    /// 左右チャンネルの相関係数（負の場合は位相の打ち消しが起こる）
+    correlation: f32,
+    /// モノラル化した時の左右平均に対するレベル変化(dB)
+    mono_level_db: f32,
+}
+
+/// 再生位置の移動処理
+struct SongSeekJob {
+    /// 移動先の時刻(sec)
+    time_sec: f32,
+    /// 移動が終わったら再生を始めるか
+    resume: bool,
+    /// 移動先まで空回ししたSPC（PCM出力用, MIDI出力用）を作るスレッド（キャンセルされた場合はNone）
+    handle: thread::JoinHandle<
+        Option<(
+            Box<spc700::spc::SPC<spc700::sdsp::SDSP>>,
+            Box<spc700::spc::SPC<spc700::mididsp::MIDIDSP>>,
+        )>,
+    >,
+}
+
+/// 実行中のバックグラウンド処理
+struct BackgroundOperation {
+    /// 保存時のデフォルトファイル名
+    default_file_name: String,
+    /// 処理スレッド
+    handle: thread::JoinHandle<BackgroundResult>,
+}
+
+/// キーボード演奏で押下中のノート
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct KeyboardHeldNote {
+    /// 音源番号
+    srn_no: u8,
+    /// キーに割り当てた基準からの半音数
+    key_offset: u8,
+    /// ノートオンしたチャンネル
+    channel: u8,
+    /// ノートオンしたノート番号
+    note: u8,
 }
 
 /// 読み込んだデータ
@@ -219,11 +1051,23 @@ pub enum LoadedFile {
 
 impl Default for App {
     fn default() -> Self {
+        Self::with_preferences(Preferences::default())
+    }
+}
+
+impl App {
+    /// 設定を指定して作成（設定ファイルは読み書きしない）
+    pub fn with_preferences(preferences: Preferences) -> Self {
         // 出力オーディオデバイスの初期設定
         let host = cpal::default_host();
+        let forced_sample_rate = if preferences.force_output_sample_rate {
+            Some(preferences.output_sample_rate)
+        } else {
+            None
+        };
         let (device, stream_config) = if let Some(device) = host.default_output_device() {
-            if let Ok(config) = device.default_output_config() {
-                (Some(device), Some(Into::<StreamConfig>::into(config)))
+            if let Some(config) = select_output_stream_config(&device, forced_sample_rate) {
+                (Some(device), Some(config))
             } else {
                 (None, None)
             }
@@ -231,30 +1075,20 @@ impl Default for App {
             (None, None)
         };
         // MIDIの初期接続設定
-        let (midi_out_port_name, midi_out_conn) =
-            if let Ok(midi_out) = MidiOutput::new(SPC2MIDI2_TITLE_STR) {
-                let midi_out_ports = midi_out.ports();
-                if midi_out_ports.len() > 0 {
-                    let default_midi_port_name = &midi_out_ports[0];
-                    let port_name = Some(midi_out.port_name(default_midi_port_name).unwrap());
-                    let midi_out_conn =
-                        match midi_out.connect(default_midi_port_name, SPC2MIDI2_TITLE_STR) {
-                            Ok(conn) => Some(Arc::new(Mutex::new(conn))),
-                            Err(_) => None,
-                        };
-                    (port_name, midi_out_conn)
-                } else {
-                    (None, None)
-                }
-            } else {
-                (None, None)
-            };
+        let use_virtual_midi_port =
+            preferences.use_virtual_midi_port && VIRTUAL_MIDI_PORT_SUPPORTED;
+        let (midi_out_port_name, midi_out_conn) = if use_virtual_midi_port {
+            connect_virtual_midi_output()
+        } else {
+            connect_default_midi_output()
+        };
         Self {
             theme: iced::Theme::Dark,
             main_window_id: window::Id::unique(),
             windows: BTreeMap::new(),
             spc_file: None,
             spc_file_path: None,
+            spc_file_data: None,
             source_infos: Arc::new(RwLock::new(BTreeMap::new())),
             source_parameter: Arc::new(RwLock::new(BTreeMap::new())),
             playback_status: Arc::new(RwLock::new(PlaybackStatus::new())),
@@ -264,15 +1098,113 @@ impl Default for App {
             stream: None,
             stream_played_samples: Arc::new(AtomicUsize::new(0)),
             midi_output_bytes: Arc::new(AtomicUsize::new(0)),
+            pcm_output_level: Arc::new(AtomicU32::new(0)),
+            midi_output_level: Arc::new(AtomicU32::new(0)),
+            stream_underruns: Arc::new(AtomicUsize::new(0)),
+            observed_underruns: 0,
+            recent_underrun_times: VecDeque::new(),
             stream_is_playing: Arc::new(AtomicBool::new(false)),
-            audio_output_latency_msec: Arc::new(AtomicUsize::new(200)),
+            playback_mode: PlaybackMode::None,
+            audio_output_latency_msec: Arc::new(AtomicUsize::new(
+                preferences.audio_output_latency_msec,
+            )),
+            ramp_in_on_play_start: Arc::new(AtomicBool::new(preferences.ramp_in_on_play_start)),
+            ramp_in_msec: Arc::new(AtomicUsize::new(preferences.ramp_in_msec)),
+            device_switch_fade_msec: Arc::new(AtomicUsize::new(
+                preferences.device_switch_fade_msec,
+            )),
+            force_output_sample_rate: Arc::new(AtomicBool::new(
+                preferences.force_output_sample_rate,
+            )),
+            output_sample_rate: Arc::new(AtomicU32::new(preferences.output_sample_rate)),
+            output_gain_target: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            a4_pitch_hz: Arc::new(AtomicU32::new(
+                sanitize_float_input(preferences.a4_pitch_hz, MIN_A4_PITCH_HZ..=MAX_A4_PITCH_HZ)
+                    .unwrap_or(DEFAULT_A4_PITCH_HZ)
+                    .to_bits(),
+            )),
+            indicator_smoothing: Arc::new(AtomicU32::new(
+                sanitize_float_input(
+                    preferences.indicator_smoothing,
+                    0.0..=MAX_INDICATOR_SMOOTHING,
+                )
+                .unwrap_or(DEFAULT_INDICATOR_SMOOTHING)
+                .to_bits(),
+            )),
+            spectrum_peak_labels: Arc::new(AtomicUsize::new(
+                preferences
+                    .spectrum_peak_labels
+                    .min(MAX_SPECTRUM_PEAK_LABELS),
+            )),
+            spectrum_peak_separation_hz: Arc::new(AtomicU32::new(
+                sanitize_float_input(
+                    preferences.spectrum_peak_separation_hz,
+                    0.0..=MAX_SPECTRUM_PEAK_SEPARATION_HZ,
+                )
+                .unwrap_or(DEFAULT_SPECTRUM_PEAK_SEPARATION_HZ)
+                .to_bits(),
+            )),
+            cache_analysis: Arc::new(AtomicBool::new(preferences.cache_analysis)),
+            lazy_source_decoding: Arc::new(AtomicBool::new(preferences.lazy_source_decoding)),
+            stretch_smf_export: Arc::new(AtomicBool::new(preferences.stretch_smf_export)),
+            stretch_target_sec: Arc::new(AtomicU32::new(
+                preferences
+                    .stretch_target_sec
+                    .clamp(MIN_STRETCH_TARGET_SEC, MAX_STRETCH_TARGET_SEC),
+            )),
+            master_reverb_send: Arc::new(AtomicU8::new(preferences.master_reverb_send.min(127))),
+            stuck_note_timeout_sec: Arc::new(AtomicU32::new(
+                preferences
+                    .stuck_note_timeout_sec
+                    .min(MAX_STUCK_NOTE_TIMEOUT_SEC),
+            )),
+            held_notes: Arc::new(Mutex::new(HeldNoteTracker::new())),
+            export_preroll_msec: Arc::new(AtomicUsize::new(
+                preferences.export_preroll_msec.min(MAX_EXPORT_PREROLL_MSEC),
+            )),
+            long_export_warning_sec: Arc::new(AtomicU32::new(
+                preferences
+                    .long_export_warning_sec
+                    .min(MAX_LONG_EXPORT_WARNING_SEC),
+            )),
+            long_export_confirmed: false,
+            program_velocity_calibration: Arc::new(RwLock::new(
+                if preferences.program_velocity_calibration.len() == 128 {
+                    preferences.program_velocity_calibration.clone()
+                } else {
+                    default_program_velocity_calibration()
+                },
+            )),
+            source_parameter_template: Arc::new(RwLock::new(
+                preferences.source_parameter_template.clone(),
+            )),
+            export_range_enabled: Arc::new(AtomicBool::new(preferences.export_range_enabled)),
+            export_range_start_sec: Arc::new(AtomicU32::new(
+                sanitize_float_input(
+                    preferences.export_range_start_sec,
+                    0.0..=MAX_EXPORT_RANGE_SEC,
+                )
+                .unwrap_or(0.0)
+                .to_bits(),
+            )),
+            export_range_end_sec: Arc::new(AtomicU32::new(
+                sanitize_float_input(preferences.export_range_end_sec, 0.0..=MAX_EXPORT_RANGE_SEC)
+                    .unwrap_or(0.0)
+                    .to_bits(),
+            )),
             midi_out_conn: midi_out_conn,
+            keyboard_held_notes: vec![],
+            source_solo: None,
+            pending_import: None,
+            saved_source_parameter: String::new(),
+            use_virtual_midi_port: Arc::new(AtomicBool::new(use_virtual_midi_port)),
             pcm_spc: None,
             midi_spc: None,
             pcm_spc_on: Arc::new(AtomicBool::new(true)),
             midi_spc_on: Arc::new(AtomicBool::new(true)),
             midi_preview: Arc::new(AtomicBool::new(true)),
             preview_loop: Arc::new(AtomicBool::new(true)),
+            snap_loop_point: Arc::new(AtomicBool::new(true)),
             preview_volume: Arc::new(AtomicU8::new(40)),
             channel_mute_flags: Arc::new(AtomicU8::new(0)),
             audio_out_device_name: Arc::new(RwLock::new(if let Some(device) = device {
@@ -289,16 +1221,40 @@ impl Default for App {
             })),
             midi_out_port_name: Arc::new(RwLock::new(midi_out_port_name)),
             display_source_id_type: Arc::new(RwLock::new(DisplaySourceIDType::StartAddress)),
+            background_operation: None,
+            song_overview_job: None,
+            song_overview_cancel: Arc::new(AtomicBool::new(false)),
+            song_seek_job: None,
+            song_seek_cancel: Arc::new(AtomicBool::new(false)),
+            smf_playback_thread: None,
+            smf_playback_stop: Arc::new(AtomicBool::new(false)),
+            instrument_mapping: preferences.instrument_mapping_path.and_then(|path| {
+                match load_instrument_mapping(&path) {
+                    Ok(mapping) => Some((path, mapping)),
+                    Err(e) => {
+                        eprintln!("ERROR: failed to load instrument mapping: {}", e);
+                        None
+                    }
+                }
+            }),
+            operation_cancel: Arc::new(AtomicBool::new(false)),
+            operation_progress: Arc::new(AtomicUsize::new(0)),
+            wav_mono_compatibility: None,
+            wav_clipped_msec: 0,
+            ogg_loop_samples: None,
+            smf_unanalyzed_sources: vec![],
+            parameter_log: Arc::new(RwLock::new(VecDeque::new())),
+            persist_preferences: false,
         }
     }
-}
 
-impl App {
     pub fn new() -> (Self, Task<Message>) {
-        (
-            App { ..App::default() },
-            Task::done(Message::OpenMainWindow),
-        )
+        // 前回の設定を読み込み、変更は設定ファイルに保存する
+        let app = App {
+            persist_preferences: true,
+            ..App::with_preferences(load_preferences())
+        };
+        (app, Task::done(Message::OpenMainWindow))
     }
 
     pub fn title(&self, id: window::Id) -> String {
@@ -309,6 +1265,20 @@ impl App {
     }
 
     pub fn update(&mut self, message: Message) -> Task<Message> {
+        let edited_srn_no = message.edited_source();
+        let previous_param = edited_srn_no
+            .and_then(|srn_no| self.source_parameter.read().unwrap().get(&srn_no).cloned());
+        let task = self.handle_message(message);
+        if let Some(srn_no) = edited_srn_no {
+            // 編集前後の差分を変更記録に残す
+            self.log_parameter_changes(srn_no, previous_param);
+            // 編集した音源の重複音源にパラメータを反映
+            self.sync_linked_duplicates(srn_no);
+        }
+        task
+    }
+
+    fn handle_message(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::OpenMainWindow => {
                 let (id, open) = window::open(window::Settings {
@@ -340,7 +1310,10 @@ impl App {
                 self.windows.insert(id, Box::new(window));
                 return open.map(Message::MainWindowOpened);
             }
-            Message::MainWindowOpened(_id) => {}
+            Message::MainWindowOpened(id) => {
+                return window::scale_factor(id)
+                    .map(move |factor| Message::WindowRescaled(id, factor));
+            }
             Message::OpenMIDIOutpoutConfigurationWindow => {
                 let (id, open) = window::open(window::Settings {
                     size: iced::Size::new(500.0, 600.0),
@@ -357,7 +1330,8 @@ impl App {
             Message::MIDIOutpoutConfigurationWindowOpened(_id) => {}
             Message::OpenDeviceSettingWindow => {
                 let (id, open) = window::open(window::Settings {
-                    size: iced::Size::new(500.0, 300.0),
+                    size: iced::Size::new(500.0, 600.0),
+                    min_size: Some(iced::Size::new(400.0, 200.0)),
                     ..Default::default()
                 });
                 self.windows.insert(
@@ -366,6 +1340,29 @@ impl App {
                         self.audio_out_device_name.clone(),
                         self.midi_out_port_name.clone(),
                         self.audio_output_latency_msec.clone(),
+                        self.use_virtual_midi_port.clone(),
+                        self.ramp_in_on_play_start.clone(),
+                        self.ramp_in_msec.clone(),
+                        self.device_switch_fade_msec.clone(),
+                        self.force_output_sample_rate.clone(),
+                        self.output_sample_rate.clone(),
+                        self.a4_pitch_hz.clone(),
+                        self.indicator_smoothing.clone(),
+                        self.spectrum_peak_labels.clone(),
+                        self.spectrum_peak_separation_hz.clone(),
+                        self.cache_analysis.clone(),
+                        self.lazy_source_decoding.clone(),
+                        self.stretch_smf_export.clone(),
+                        self.stretch_target_sec.clone(),
+                        self.master_reverb_send.clone(),
+                        self.stuck_note_timeout_sec.clone(),
+                        self.export_preroll_msec.clone(),
+                        self.long_export_warning_sec.clone(),
+                        self.program_velocity_calibration.clone(),
+                        self.source_parameter_template.clone(),
+                        self.export_range_enabled.clone(),
+                        self.export_range_start_sec.clone(),
+                        self.export_range_end_sec.clone(),
                     )),
                 );
                 return open.map(Message::DeviceWindowOpened);
@@ -376,8 +1373,7 @@ impl App {
                     size: iced::Size::new(800.0, 850.0),
                     ..Default::default()
                 });
-                let infos = self.source_infos.read().unwrap();
-                if let Some(source) = infos.get(&srn_no) {
+                if let Some(source) = self.decoded_source_info(srn_no) {
                     let window = SRNWindow::new(
                         id,
                         format!(
@@ -385,17 +1381,36 @@ impl App {
                             srn_no, source.start_address, source.end_address
                         ),
                         srn_no,
-                        source,
+                        &source,
                         self.source_parameter.clone(),
+                        self.midi_output_configure.clone(),
                         self.midi_preview.clone(),
                         self.preview_loop.clone(),
+                        self.snap_loop_point.clone(),
                         self.preview_volume.clone(),
+                        self.a4_pitch_hz.clone(),
+                        self.spectrum_peak_labels.clone(),
+                        self.spectrum_peak_separation_hz.clone(),
                     );
                     self.windows.insert(id, Box::new(window));
                     return open.map(Message::SRNWindowOpened);
                 }
             }
-            Message::SRNWindowOpened(_id) => {}
+            Message::SRNWindowOpened(id) => {
+                return window::scale_factor(id)
+                    .map(move |factor| Message::WindowRescaled(id, factor));
+            }
+            Message::WindowRescaled(id, factor) => {
+                // キャンバスを持つウィンドウに表示スケールを伝える
+                let scale = DisplayScale(factor);
+                if let Some(window) = self.windows.get_mut(&id) {
+                    if let Some(main_win) = window.as_any_mut().downcast_mut::<MainWindow>() {
+                        main_win.set_display_scale(scale);
+                    } else if let Some(srn_win) = window.as_any_mut().downcast_mut::<SRNWindow>() {
+                        srn_win.set_display_scale(scale);
+                    }
+                }
+            }
             Message::OpenSRNChannelRoutingWindow(srn_no) => {
                 let (id, open) = window::open(window::Settings {
                     size: iced::Size::new(350.0, 300.0),
@@ -414,10 +1429,193 @@ impl App {
                 }
             }
             Message::SRNChannelRoutingWindowOpened(_id) => {}
+            Message::MainWindowLayoutToggled => {
+                if let Some(window) = self.windows.get_mut(&self.main_window_id) {
+                    let main_win: &mut MainWindow =
+                        window.as_mut().as_any_mut().downcast_mut().unwrap();
+                    main_win.layout = match main_win.layout {
+                        MainWindowLayout::Table => MainWindowLayout::Mixer,
+                        MainWindowLayout::Mixer => MainWindowLayout::Grid,
+                        MainWindowLayout::Grid => MainWindowLayout::Table,
+                    };
+                    if main_win.layout == MainWindowLayout::Grid {
+                        // 先頭から画面に収まる程度の行のサムネイルを作成しておく
+                        self.update_source_thumbnails(0, SOURCE_GRID_INITIAL_ROWS);
+                    }
+                }
+            }
+            Message::SourceGridScrolled(viewport) => {
+                // 表示範囲に入った行のサムネイルを作成
+                let offset_y = viewport.absolute_offset().y;
+                let first_row = (offset_y / SOURCE_GRID_ROW_HEIGHT).floor().max(0.0) as usize;
+                let last_row = ((offset_y + viewport.bounds().height) / SOURCE_GRID_ROW_HEIGHT)
+                    .ceil()
+                    .max(0.0) as usize;
+                self.update_source_thumbnails(first_row, last_row - first_row + 1);
+            }
+            Message::AddSourceNumberChanged(srn_no) => {
+                if let Some(window) = self.windows.get_mut(&self.main_window_id) {
+                    let main_win: &mut MainWindow =
+                        window.as_mut().as_any_mut().downcast_mut().unwrap();
+                    main_win.add_srn_no = srn_no;
+                }
+            }
+            Message::AddSourceRequested(srn_no) => {
+                if let Err(description) = self.add_source(srn_no) {
+                    return Task::perform(
+                        show_message_dialog("Add Source".to_string(), description),
+                        |_| Message::MessageDialogClosed,
+                    );
+                }
+                return Task::perform(async {}, move |_| Message::ReceivedSourceParameterUpdate);
+            }
+            Message::OpenRegisterInspectorWindow => {
+                if let Some(spc_file) = &self.spc_file {
+                    let (id, open) = window::open(window::Settings {
+                        size: iced::Size::new(600.0, 800.0),
+                        ..Default::default()
+                    });
+                    let mut window =
+                        RegisterInspectorWindow::new(id, &spc_file.ram, &spc_file.dsp_register);
+                    if let Some(pcm_spc_ref) = &self.pcm_spc {
+                        let spc = pcm_spc_ref.lock().unwrap();
+                        read_dsp_registers(&spc.dsp, &mut window.dsp_register);
+                    }
+                    self.windows.insert(id, Box::new(window));
+                    return open.map(Message::RegisterInspectorWindowOpened);
+                }
+            }
+            Message::RegisterInspectorWindowOpened(_id) => {}
+            Message::InspectorRAMPageChanged(window_id, page) => {
+                if let Some(window) = self.windows.get_mut(&window_id) {
+                    let inspector_win: &mut RegisterInspectorWindow =
+                        window.as_mut().as_any_mut().downcast_mut().unwrap();
+                    inspector_win.ram_page = page;
+                }
+            }
+            Message::InspectorPokeAddressChanged(window_id, address) => {
+                if let Some(window) = self.windows.get_mut(&window_id) {
+                    let inspector_win: &mut RegisterInspectorWindow =
+                        window.as_mut().as_any_mut().downcast_mut().unwrap();
+                    inspector_win.poke_address = address;
+                }
+            }
+            Message::InspectorPokeValueChanged(window_id, value) => {
+                if let Some(window) = self.windows.get_mut(&window_id) {
+                    let inspector_win: &mut RegisterInspectorWindow =
+                        window.as_mut().as_any_mut().downcast_mut().unwrap();
+                    inspector_win.poke_value = value;
+                }
+            }
+            Message::InspectorPokeRequested(address, value) => {
+                // PCM側のDSPのみ書き換える
+                if let Some(pcm_spc_ref) = &self.pcm_spc {
+                    let mut spc = pcm_spc_ref.lock().unwrap();
+                    spc.dsp.write_register(&[0u8], address, value);
+                    for window in self.windows.values_mut() {
+                        if let Some(inspector_win) = window
+                            .as_mut()
+                            .as_any_mut()
+                            .downcast_mut::<RegisterInspectorWindow>()
+                        {
+                            read_dsp_registers(&spc.dsp, &mut inspector_win.dsp_register);
+                        }
+                    }
+                }
+            }
+            Message::ParameterDiffWindowOpened(_id) => {}
+            Message::OpenParameterLogWindow => {
+                let (id, open) = window::open(window::Settings {
+                    size: iced::Size::new(800.0, 500.0),
+                    ..Default::default()
+                });
+                self.windows.insert(
+                    id,
+                    Box::new(ParameterLogWindow::new(self.parameter_log.clone())),
+                );
+                return open.map(Message::ParameterLogWindowOpened);
+            }
+            Message::ParameterLogWindowOpened(_id) => {}
+            Message::ParameterLogCleared => {
+                self.parameter_log.write().unwrap().clear();
+            }
+            Message::ParameterDiffEntryToggled(window_id, index, flag) => {
+                if let Some(window) = self.windows.get_mut(&window_id) {
+                    let diff_win: &mut ParameterDiffWindow =
+                        window.as_mut().as_any_mut().downcast_mut().unwrap();
+                    if let Some(difference) = diff_win.differences.get_mut(index) {
+                        difference.accepted = flag;
+                    }
+                }
+            }
+            Message::ParameterDiffApplied(window_id, apply_all) => {
+                if let Some(mut window) = self.windows.remove(&window_id) {
+                    let diff_win: &mut ParameterDiffWindow =
+                        window.as_mut().as_any_mut().downcast_mut().unwrap();
+                    if let Some(json) = self.pending_import.take() {
+                        let mut config = self.midi_output_configure.write().unwrap();
+                        let mut params = self.source_parameter.write().unwrap();
+                        if apply_all {
+                            *config = json.midi_output_configure;
+                            // 丸ごと上書きすると設定済みのkeyを消してしまうので追記
+                            for (key, value) in json.source_parameter {
+                                params.insert(key, value);
+                            }
+                        } else {
+                            apply_parameter_differences(
+                                &mut config,
+                                &mut params,
+                                &json,
+                                &diff_win.differences,
+                            );
+                        }
+                    }
+                    return Task::batch([
+                        window::close(window_id),
+                        Task::perform(async {}, move |_| Message::ReceivedSourceParameterUpdate),
+                    ]);
+                }
+            }
+            Message::ParameterDiffCanceled(window_id) => {
+                if self.windows.remove(&window_id).is_some() {
+                    self.pending_import = None;
+                    return window::close(window_id);
+                }
+            }
             Message::WindowClosed(id) => {
                 if id == self.main_window_id {
+                    // 鳴ったままにならないよう消音を送ってから終了
+                    self.stop_smf_playback(true);
                     return iced::exit();
                 }
+                // 差分確認ウィンドウを閉じたら読み込んだ設定は破棄
+                if let Some(window) = self.windows.get_mut(&id) {
+                    if window
+                        .as_mut()
+                        .as_any_mut()
+                        .downcast_mut::<ParameterDiffWindow>()
+                        .is_some()
+                    {
+                        self.windows.remove(&id);
+                        self.pending_import = None;
+                    }
+                }
+                // キーボード演奏中の音源ウィンドウを閉じたらノートを止める
+                if let Some(window) = self.windows.get_mut(&id) {
+                    if let Some(srn_win) = window.as_mut().as_any_mut().downcast_mut::<SRNWindow>()
+                    {
+                        srn_win.keyboard_play = false;
+                        let srn_no = srn_win.srn_no();
+                        self.release_keyboard_notes(srn_no);
+                        // 曲中ソロも解除
+                        if self
+                            .source_solo
+                            .is_some_and(|(solo_srn, _)| solo_srn == srn_no)
+                        {
+                            self.release_source_solo();
+                        }
+                    }
+                }
             }
             Message::OpenFile => {
                 // 再生中の場合は止める
@@ -425,91 +1623,130 @@ impl App {
                     self.stream_play_stop().expect("Failed to stop play");
                 }
                 // すでに開いているメインウィンドウ以外を閉じる
-                let mut tasks = vec![];
-                for (id, _) in &self.windows {
-                    if *id != self.main_window_id {
-                        tasks.push(window::close(*id));
-                    }
-                }
+                let mut tasks = self.close_sub_windows();
                 tasks.push(Task::perform(open_file(), Message::FileOpened));
                 return Task::batch(tasks);
             }
+            Message::ReopenFile => {
+                if self.spc_file_path.is_some() {
+                    // 未保存の変更があれば破棄してよいか確認
+                    if self.has_unsaved_source_parameter() {
+                        return Task::perform(confirm_reopen_file(), Message::ReopenFileConfirmed);
+                    }
+                    return Task::perform(async {}, |_| Message::ReopenFileConfirmed(true));
+                }
+            }
+            Message::ReopenFileConfirmed(confirmed) => {
+                if let (true, Some(path)) = (confirmed, self.spc_file_path.clone()) {
+                    // 再生中の場合は止める
+                    if self.stream_is_playing.load(Ordering::Relaxed) {
+                        self.stream_play_stop().expect("Failed to stop play");
+                    }
+                    // ファイルを読み直して解析からやり直す
+                    let mut tasks = self.close_sub_windows();
+                    tasks.push(Task::perform(load_file(path), Message::FileOpened));
+                    return Task::batch(tasks);
+                }
+            }
             Message::FileOpened(result) => match result {
                 Ok((path, data)) => {
                     match data {
                         LoadedFile::SPCFile(data) => {
-                            if let Some(spc_file) = parse_spc_file(&data) {
-                                // 再生中の場合は止める
-                                if self.stream_is_playing.load(Ordering::Relaxed) {
-                                    self.stream_play_stop().expect("Failed to stop play");
-                                }
-                                self.spc_file = Some(Box::new(spc_file.clone()));
-                                self.analyze_sources(
-                                    if spc_file.header.duration > 0 {
-                                        spc_file.header.duration as u32
-                                    } else {
-                                        DEFAULT_ANALYZING_TIME_SEC
-                                    },
-                                    &spc_file.header.spc_register,
-                                    &spc_file.ram,
-                                    &spc_file.dsp_register,
-                                );
-                                // SPCを生成
-                                self.pcm_spc = Some(Arc::new(Mutex::new(Box::new({
-                                    let mut spc = SPC::new();
-                                    spc.initialize(
-                                        &spc_file.header.spc_register,
-                                        &spc_file.ram,
-                                        &spc_file.dsp_register,
-                                    );
-                                    spc
-                                }))));
-                                self.midi_spc = Some(Arc::new(Mutex::new(Box::new({
-                                    let mut spc = SPC::new();
-                                    spc.initialize(
-                                        &spc_file.header.spc_register,
-                                        &spc_file.ram,
-                                        &spc_file.dsp_register,
-                                    );
-                                    spc
-                                }))));
-                                // 再生サンプル数・MIDI出力サイズをリセット
-                                self.stream_played_samples.store(0, Ordering::Relaxed);
-                                self.midi_output_bytes.store(0, Ordering::Relaxed);
-                                // メインウィンドウの表示更新
-                                if let Some(window) = self.windows.get_mut(&self.main_window_id) {
-                                    let main_window: &mut MainWindow =
-                                        window.as_mut().as_any_mut().downcast_mut().unwrap();
-                                    // ウィンドウタイトルに開いたファイル名を追記
-                                    main_window.title = format!(
-                                        "{} - {}",
-                                        main_window.base_title,
-                                        path.file_name().unwrap().to_str().unwrap()
+                            let spc_file = match load_spc_file(&data) {
+                                Ok(spc_file) => spc_file,
+                                Err(e) => {
+                                    return Task::perform(
+                                        show_message_dialog(
+                                            "SPC File".to_string(),
+                                            format!("Failed to open the SPC file: {}", e),
+                                        ),
+                                        |_| Message::MessageDialogClosed,
                                     );
-                                    // 全てのSRNを表示
-                                    main_window.showing_channel_srn_list = [true; 8];
                                 }
-                                // 出力時間をSPCの情報を元に設定
-                                let mut config = self.midi_output_configure.write().unwrap();
-                                config.output_duration_msec = if spc_file.header.duration > 0 {
-                                    (spc_file.header.duration as u64) * 1000
-                                } else {
-                                    DEFAULT_OUTPUT_DURATION_MSEC
-                                };
-                                self.spc_file_path = Some(path);
+                            };
+                            // 別の解析・出力を実行中は開かない
+                            if self.background_operation.is_some() {
+                                return Task::perform(
+                                    show_message_dialog(
+                                        "SPC File".to_string(),
+                                        "Another operation is in progress. Open the file again after it finishes.".to_string(),
+                                    ),
+                                    |_| Message::MessageDialogClosed,
+                                );
+                            }
+                            // 再生中の場合は止める
+                            if self.stream_is_playing.load(Ordering::Relaxed) {
+                                self.stream_play_stop().expect("Failed to stop play");
                             }
+                            let spc_file = Box::new(spc_file);
+                            let analyze_duration_sec = if spc_file.header.duration > 0 {
+                                spc_file.header.duration as u32
+                            } else {
+                                DEFAULT_ANALYZING_TIME_SEC
+                            };
+                            let cache = if self.cache_analysis.load(Ordering::Relaxed) {
+                                // 同じ内容・同じ解析条件のキャッシュがあれば再利用
+                                let cache_path = analysis_cache_path(&path);
+                                let cache = self.create_analysis_cache(
+                                    spc_content_hash(&data),
+                                    analyze_duration_sec,
+                                );
+                                match load_analysis_cache(&cache_path) {
+                                    Some(cached) if cached.is_valid_for(&cache) => {
+                                        self.restore_analysis(cached, &spc_file.ram);
+                                        return self.open_analyzed_spc_file(
+                                            path,
+                                            data,
+                                            spc_file,
+                                            analyze_duration_sec,
+                                        );
+                                    }
+                                    _ => Some((cache_path, cache)),
+                                }
+                            } else {
+                                None
+                            };
+                            // 解析はバックグラウンドで行い、終わったら開く
+                            self.start_source_analysis(
+                                spc_file.clone(),
+                                AnalysisRequest::FileOpen {
+                                    path: path,
+                                    data: data,
+                                    spc_file: spc_file,
+                                    analyze_duration_sec: analyze_duration_sec,
+                                    cache: cache,
+                                },
+                            );
                         }
                         LoadedFile::JSONFile(data) => {
+                            // 確認中の設定を上書きしないよう、差分確認ウィンドウを閉じるまでは読み込まない
+                            if self.pending_import.is_some() {
+                                return Task::perform(
+                                    show_message_dialog(
+                                        "Parameter Import".to_string(),
+                                        "Another parameter file is awaiting review. Apply or cancel it before loading a new one.".to_string(),
+                                    ),
+                                    |_| Message::MessageDialogClosed,
+                                );
+                            }
                             match serde_json::from_str::<ExportInformation>(&data) {
                                 Ok(json) => {
-                                    // 読み込みに成功したら内部コンフィグとパラメータを更新
-                                    let mut config = self.midi_output_configure.write().unwrap();
-                                    let mut params = self.source_parameter.write().unwrap();
-                                    *config = json.midi_output_configure;
-                                    // 丸ごと上書きすると設定済みのkeyを消してしまうので追記
-                                    for (key, value) in json.source_parameter {
-                                        params.insert(key, value);
-                                    }
+                                    // 読み込みに成功したら現在の設定との差分を確認してから適用する
+                                    let differences = {
+                                        let config = self.midi_output_configure.read().unwrap();
+                                        let params = self.source_parameter.read().unwrap();
+                                        diff_export_information(&config, &params, &json)
+                                    };
+                                    let (id, open) = window::open(window::Settings {
+                                        size: iced::Size::new(900.0, 600.0),
+                                        ..Default::default()
+                                    });
+                                    self.windows.insert(
+                                        id,
+                                        Box::new(ParameterDiffWindow::new(id, differences)),
+                                    );
+                                    self.pending_import = Some(json);
+                                    return open.map(Message::ParameterDiffWindowOpened);
                                 }
                                 Err(e) => {
                                     eprintln!("ERROR: failed to load json file: {:?}", e);
@@ -523,19 +1760,89 @@ impl App {
                 }
             },
             Message::SaveSMF => {
-                if let Some(path) = &self.spc_file_path {
-                    if let Some(smf) = self.create_smf() {
-                        return Task::perform(
-                            save_smf(
-                                path.file_stem().unwrap().to_str().unwrap().to_owned() + ".mid",
-                                smf,
+                // 音源が無いと空のSMFになるので出力しない
+                if self.spc_file.is_some() && self.source_infos.read().unwrap().is_empty() {
+                    return Task::perform(
+                        show_message_dialog(
+                            "SMF Output".to_string(),
+                            format!(
+                                "No sources were detected, so the SMF would be empty.\n\n{}",
+                                NO_SOURCE_HINT
                             ),
-                            Message::SMFSaved,
+                        ),
+                        |_| Message::MessageDialogClosed,
+                    );
+                }
+                let range_msec = match self.export_range_msec() {
+                    Ok(range_msec) => range_msec,
+                    Err(e) => {
+                        return Task::perform(
+                            show_message_dialog("SMF Output".to_string(), e),
+                            |_| Message::MessageDialogClosed,
                         );
                     }
+                };
+                // 出力時間が長い場合は推定時間を示して確認してから出力
+                if let Some(task) = self.confirm_long_export(Message::SaveSMF, range_msec, true) {
+                    return task;
                 }
-            }
-            Message::SMFSaved(_result) => {}
+                if let (Some(path), Some(spc_file)) = (&self.spc_file_path, &self.spc_file) {
+                    let spc_file = spc_file.clone();
+                    let config = self.midi_output_configure.read().unwrap().clone();
+                    let params = self.source_parameter.read().unwrap().clone();
+                    let options = SMFExportOptions {
+                        stretch_target_msec: if self.stretch_smf_export.load(Ordering::Relaxed) {
+                            Some(self.stretch_target_sec.load(Ordering::Relaxed) as u64 * 1000)
+                        } else {
+                            None
+                        },
+                        master_reverb_send: self.master_reverb_send.load(Ordering::Relaxed),
+                        range_msec: range_msec,
+                        preroll_msec: self.export_preroll_msec.load(Ordering::Relaxed) as u64,
+                        velocity_calibration: self.export_velocity_calibration(&config),
+                    };
+                    let default_file_name =
+                        path.file_stem().unwrap().to_str().unwrap().to_owned() + ".mid";
+                    self.start_background_operation(default_file_name, move |cancel, progress| {
+                        // 解析で見つからずデフォルトのパラメータで出力された音源も受け取る
+                        let (smf, unanalyzed_sources) = match Self::build_smf(
+                            &spc_file, &config, &params, &options, &cancel, &progress,
+                        ) {
+                            Some((smf, unanalyzed_sources)) => (Some(smf), unanalyzed_sources),
+                            None => (None, vec![]),
+                        };
+                        // ループごとにファイルを分ける場合は分割
+                        if config.split_smf_per_loop {
+                            BackgroundResult::SMFSections(
+                                smf.map(split_smf_by_loop),
+                                unanalyzed_sources,
+                            )
+                        } else {
+                            BackgroundResult::SMF(smf, unanalyzed_sources)
+                        }
+                    });
+                }
+            }
+            Message::SMFSaved(result) => {
+                // 保存できたら解析で見つからなかった音源がないか確認
+                let unanalyzed_sources = std::mem::take(&mut self.smf_unanalyzed_sources);
+                if result.is_ok() && !unanalyzed_sources.is_empty() {
+                    return Task::perform(
+                        show_message_dialog(
+                            "SMF Output".to_string(),
+                            format!(
+                                "WARNING: The following sources (SRN) were played in the exported range but were not found by the analysis, so they were exported with default parameters:\n{}\n\nRe-analyze with a longer Song Duration, or add them with \"Add Source\" and configure them.",
+                                unanalyzed_sources
+                                    .iter()
+                                    .map(|srn_no| srn_no.to_string())
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ),
+                        ),
+                        |_| Message::MessageDialogClosed,
+                    );
+                }
+            }
             Message::SaveJSON => {
                 if let Some(path) = &self.spc_file_path {
                     return Task::perform(
@@ -547,7 +1854,217 @@ impl App {
                     );
                 }
             }
-            Message::JSONSaved(_result) => {}
+            Message::JSONSaved(result) => {
+                if result.is_ok() {
+                    self.saved_source_parameter = self.source_parameter_snapshot();
+                }
+            }
+            Message::SaveReport => {
+                if let Some(path) = &self.spc_file_path {
+                    return Task::perform(
+                        save_report(
+                            path.file_stem().unwrap().to_str().unwrap().to_owned() + ".md",
+                            self.create_report(),
+                        ),
+                        Message::ReportSaved,
+                    );
+                }
+            }
+            Message::ReportSaved(_result) => {}
+            Message::SaveSPC => {
+                if let (Some(path), Some(data)) = (&self.spc_file_path, &self.spc_file_data) {
+                    return Task::perform(
+                        save_spc(
+                            path.file_name().unwrap().to_str().unwrap().to_owned(),
+                            data.clone(),
+                        ),
+                        Message::SPCSaved,
+                    );
+                }
+            }
+            Message::SPCSaved(_result) => {}
+            Message::ValidateSMF => {
+                if let Some(result) = self.validate_smf() {
+                    return Task::perform(
+                        show_message_dialog("SMF Validation".to_string(), result),
+                        |_| Message::MessageDialogClosed,
+                    );
+                }
+            }
+            Message::SMFPlaybackToggled => {
+                self.toggle_smf_playback();
+            }
+            Message::LoadInstrumentMapping => {
+                return Task::perform(pick_instrument_mapping_file(), |path| {
+                    Message::InstrumentMappingPicked(path)
+                });
+            }
+            Message::InstrumentMappingPicked(path) => {
+                if let Some(path) = path {
+                    let description = match load_instrument_mapping(&path) {
+                        Ok(mapping) => {
+                            self.instrument_mapping = Some((path, mapping));
+                            self.persist_preferences();
+                            // 開いている曲にもすぐに適用
+                            let num_applied = self.apply_instrument_mapping();
+                            format!(
+                                "Loaded the instrument mapping. It is applied after each analysis.\n\n{} source(s) matched in the current song.",
+                                num_applied
+                            )
+                        }
+                        Err(e) => format!("Failed to load the instrument mapping: {}", e),
+                    };
+                    return Task::batch(vec![
+                        Task::perform(async {}, |_| Message::ReceivedSourceParameterUpdate),
+                        Task::perform(
+                            show_message_dialog("Instrument Mapping".to_string(), description),
+                            |_| Message::MessageDialogClosed,
+                        ),
+                    ]);
+                }
+            }
+            Message::PrintMIDINoteSummary => {
+                // コンソールの無い環境でも見られるようダイアログにも表示
+                if let Some(summary) = self.summarize_midi_notes() {
+                    println!("{}", summary);
+                    return Task::perform(
+                        show_message_dialog("MIDI Note Summary".to_string(), summary),
+                        |_| Message::MessageDialogClosed,
+                    );
+                }
+            }
+            Message::NoteSummaryDurationChanged(msec) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.note_summary_duration_msec = msec;
+            }
+            Message::MessageDialogClosed => {}
+            Message::SaveWAV => {
+                let range_msec = match self.export_range_msec() {
+                    Ok(range_msec) => range_msec,
+                    Err(e) => {
+                        return Task::perform(
+                            show_message_dialog("WAV Output".to_string(), e),
+                            |_| Message::MessageDialogClosed,
+                        );
+                    }
+                };
+                // 出力時間が長い場合は推定時間を示して確認してから出力
+                if let Some(task) = self.confirm_long_export(Message::SaveWAV, range_msec, false) {
+                    return task;
+                }
+                if let (Some(path), Some(spc_file)) = (&self.spc_file_path, &self.spc_file) {
+                    let spc_file = spc_file.clone();
+                    let config = self.midi_output_configure.read().unwrap().clone();
+                    let range_msec = preroll_render_range_msec(
+                        range_msec,
+                        config.output_duration_msec,
+                        self.export_preroll_msec.load(Ordering::Relaxed) as u64,
+                    );
+                    let default_file_name =
+                        path.file_stem().unwrap().to_str().unwrap().to_owned() + ".wav";
+                    self.start_background_operation(default_file_name, move |cancel, progress| {
+                        BackgroundResult::WAV(Self::render_wav(
+                            &spc_file, &config, range_msec, &cancel, &progress,
+                        ))
+                    });
+                }
+            }
+            Message::SaveOGG => {
+                let range_msec = match self.export_range_msec() {
+                    Ok(range_msec) => range_msec,
+                    Err(e) => {
+                        return Task::perform(
+                            show_message_dialog("OGG Output".to_string(), e),
+                            |_| Message::MessageDialogClosed,
+                        );
+                    }
+                };
+                // 出力時間が長い場合は推定時間を示して確認してから出力
+                if let Some(task) = self.confirm_long_export(Message::SaveOGG, range_msec, false) {
+                    return task;
+                }
+                if let (Some(path), Some(spc_file)) = (&self.spc_file_path, &self.spc_file) {
+                    let spc_file = spc_file.clone();
+                    let config = self.midi_output_configure.read().unwrap().clone();
+                    let range_msec = preroll_render_range_msec(
+                        range_msec,
+                        config.output_duration_msec,
+                        self.export_preroll_msec.load(Ordering::Relaxed) as u64,
+                    );
+                    let default_file_name =
+                        path.file_stem().unwrap().to_str().unwrap().to_owned() + ".ogg";
+                    self.start_background_operation(default_file_name, move |cancel, progress| {
+                        BackgroundResult::OGG(Self::render_ogg(
+                            &spc_file, &config, range_msec, &cancel, &progress,
+                        ))
+                    });
+                }
+            }
+            Message::BackgroundOperationCancelRequested => {
+                self.operation_cancel.store(true, Ordering::Relaxed);
+            }
+            Message::BackgroundOperationPolled => {
+                let progress = self.operation_progress.load(Ordering::Relaxed);
+                if let Some(window) = self.windows.get_mut(&self.main_window_id) {
+                    let main_win: &mut MainWindow =
+                        window.as_mut().as_any_mut().downcast_mut().unwrap();
+                    main_win.operation_progress = Some(progress as f32 / 1000.0);
+                }
+                // 終了していれば結果を取り出して保存へ
+                if self
+                    .background_operation
+                    .as_ref()
+                    .is_some_and(|operation| operation.handle.is_finished())
+                {
+                    let operation = self.background_operation.take().unwrap();
+                    return self.finish_background_operation(operation);
+                }
+            }
+            Message::WAVSaved(result) => {
+                // 保存できたらモノラル互換性とクリップに問題がないか確認
+                let clipped_msec = std::mem::take(&mut self.wav_clipped_msec);
+                if let (Ok(_), Some(mono_compatibility)) =
+                    (result, self.wav_mono_compatibility.take())
+                {
+                    let mut warnings = vec![];
+                    if mono_compatibility.correlation < MONO_COMPATIBILITY_CORRELATION_THRESHOLD {
+                        warnings.push(format!(
+                            "WARNING: Phase cancellation detected when summed to mono.\nL/R correlation: {:+.2}\nMono level change: {:+.1} dB\nNarrowing the stereo width may help.",
+                            mono_compatibility.correlation, mono_compatibility.mono_level_db
+                        ));
+                    }
+                    if clipped_msec >= WAV_CLIPPING_WARNING_MSEC {
+                        warnings.push(format!(
+                            "WARNING: The output clipped for {} msec in total (e.g. by runaway echo feedback).\nEnabling the soft limiter or bypassing the echo may help.",
+                            clipped_msec
+                        ));
+                    }
+                    if !warnings.is_empty() {
+                        return Task::perform(
+                            show_message_dialog("WAV Output".to_string(), warnings.join("\n\n")),
+                            |_| Message::MessageDialogClosed,
+                        );
+                    }
+                }
+            }
+            Message::OGGSaved(result) => {
+                // 保存できたら付けたループタグ（またはループが無いこと）を通知
+                let loop_samples = self.ogg_loop_samples.take();
+                if result.is_ok() {
+                    let description = if let Some((loop_start, loop_length)) = loop_samples {
+                        format!(
+                            "Saved with loop tags.\nLOOPSTART: {}\nLOOPLENGTH: {}",
+                            loop_start, loop_length
+                        )
+                    } else {
+                        "WARNING: No loop was detected within the output, so the file was saved without loop tags.\nTry a longer output duration that covers at least two loops.".to_string()
+                    };
+                    return Task::perform(
+                        show_message_dialog("OGG Output".to_string(), description),
+                        |_| Message::MessageDialogClosed,
+                    );
+                }
+            }
             Message::MenuSelected => {}
             Message::EventOccurred(event) => match event {
                 iced::event::Event::Window(event) => {
@@ -555,6 +2072,13 @@ impl App {
                         return Task::perform(load_file(path), Message::FileOpened);
                     }
                 }
+                iced::event::Event::Keyboard(iced::keyboard::Event::KeyPressed {
+                    key: iced::keyboard::Key::Character(c),
+                    modifiers,
+                    ..
+                }) if modifiers.control() && c.as_str() == "r" => {
+                    return Task::perform(async {}, move |_| Message::ReopenFile);
+                }
                 iced::event::Event::Keyboard(iced::keyboard::Event::KeyReleased {
                     key: iced::keyboard::Key::Named(Named::F4),
                     ..
@@ -570,11 +2094,13 @@ impl App {
                 _ => {}
             },
             Message::ReceivedSRNPlayStartRequest(srn_no) => {
-                if self.stream_is_playing.load(Ordering::Relaxed) {
-                    // 再生中の場合は止める
+                // 同じ音源のプレビュー中なら停止、曲の再生中は一時停止してプレビューに切り替え
+                let current = self.playback_mode();
+                let next = current.toggled(PlaybackMode::Preview(srn_no));
+                if current != PlaybackMode::None {
                     self.stream_play_stop().expect("Failed to stop play");
-                } else {
-                    // 新規再生処理
+                }
+                if next != PlaybackMode::None {
                     if let Err(_) = self.srn_play_start(srn_no) {
                         eprintln!("[{}] Faild to start playback", SPC2MIDI2_TITLE_STR);
                     }
@@ -583,26 +2109,82 @@ impl App {
             Message::SRNPlayLoopFlagToggled(flag) => {
                 self.preview_loop.store(flag, Ordering::Relaxed);
             }
+            Message::SRNSnapLoopPointFlagToggled(flag) => {
+                self.snap_loop_point.store(flag, Ordering::Relaxed);
+            }
             Message::SRNPlayVolumeChanged(volume) => {
                 self.preview_volume.store(volume, Ordering::Relaxed);
             }
             Message::SRNMIDIPreviewFlagToggled(flag) => {
                 self.midi_preview.store(flag, Ordering::Relaxed);
             }
+            Message::SRNKeyboardPlayFlagToggled(srn_no, flag) => {
+                for window in self.windows.values_mut() {
+                    if let Some(srn_win) = window.as_mut().as_any_mut().downcast_mut::<SRNWindow>()
+                    {
+                        if srn_win.srn_no() == srn_no {
+                            srn_win.keyboard_play = flag;
+                        }
+                    }
+                }
+                if !flag {
+                    self.release_keyboard_notes(srn_no);
+                }
+            }
+            Message::SRNSoloInSongToggled(srn_no, flag) => {
+                if flag {
+                    // ソロ開始前のミュート状態を覚えておく（別の音源のソロからの切り替えでは引き継ぐ）
+                    let saved_flags = match self.source_solo {
+                        Some((_, saved_flags)) => saved_flags,
+                        None => self.channel_mute_flags.load(Ordering::Relaxed),
+                    };
+                    self.source_solo = Some((srn_no, saved_flags));
+                    self.update_source_solo();
+                } else if self
+                    .source_solo
+                    .is_some_and(|(solo_srn, _)| solo_srn == srn_no)
+                {
+                    self.release_source_solo();
+                }
+                for window in self.windows.values_mut() {
+                    if let Some(srn_win) = window.as_mut().as_any_mut().downcast_mut::<SRNWindow>()
+                    {
+                        srn_win.solo_in_song = flag && srn_win.srn_no() == srn_no;
+                    }
+                }
+            }
+            Message::SRNKeyboardNoteOn(srn_no, key_offset) => {
+                self.keyboard_note_on(srn_no, key_offset);
+            }
+            Message::SRNKeyboardNoteOff(srn_no, key_offset) => {
+                self.keyboard_note_off(srn_no, key_offset);
+            }
+            Message::SRNKeyboardAllNotesOff(srn_no) => {
+                self.release_keyboard_notes(srn_no);
+            }
             Message::ReceivedPlayStartRequest => {
-                if self.stream_is_playing.load(Ordering::Relaxed) {
-                    // 再生中の場合は止める
+                // 再生位置の移動中は移動後に再生するかを切り替える
+                if let Some(job) = self.song_seek_job.as_mut() {
+                    job.resume = !job.resume;
+                    return Task::none();
+                }
+                // 曲の再生中なら一時停止、プレビュー中はプレビューを止めて曲の再生を開始
+                let current = self.playback_mode();
+                let next = current.toggled(PlaybackMode::Song);
+                if current != PlaybackMode::None {
                     self.stream_play_stop().expect("Failed to stop play");
-                } else {
-                    // 再生開始
-                    if let Err(_) = self.play_start() {
+                }
+                if next != PlaybackMode::None {
+                    if let Err(_) = self.play_start(self.play_start_ramp_in_msec()) {
                         eprintln!("[{}] Faild to start playback", SPC2MIDI2_TITLE_STR);
                     }
                 }
             }
             Message::ReceivedPlayStopRequest => {
-                // 再生中の場合は止める
-                if self.stream_is_playing.load(Ordering::Relaxed) {
+                // 移動中の再生位置は捨てる
+                self.cancel_song_seek();
+                // 曲の再生中の場合は止める（プレビューは止めない）
+                if self.playback_mode() == PlaybackMode::Song {
                     self.stream_play_stop().expect("Failed to stop play");
                 }
                 // DSPをリセット
@@ -630,6 +2212,37 @@ impl App {
                 self.stream_played_samples.store(0, Ordering::Relaxed);
                 self.midi_output_bytes.store(0, Ordering::Relaxed);
             }
+            Message::SongSeekRequested(time_sec) => {
+                self.seek_song(time_sec);
+            }
+            Message::SongSeekPolled => {
+                // 移動先まで空回しし終わっていれば再生用のSPCを置き換える
+                if self
+                    .song_seek_job
+                    .as_ref()
+                    .is_some_and(|job| job.handle.is_finished())
+                {
+                    let job = self.song_seek_job.take().unwrap();
+                    self.finish_song_seek(job);
+                }
+            }
+            Message::SongOverviewPolled => {
+                // 描画が終わっていれば結果をメインウィンドウに渡す
+                if self
+                    .song_overview_job
+                    .as_ref()
+                    .is_some_and(|job| job.is_finished())
+                {
+                    let job = self.song_overview_job.take().unwrap();
+                    if let Ok(Some(overview)) = job.join() {
+                        if let Some(window) = self.windows.get_mut(&self.main_window_id) {
+                            let main_win: &mut MainWindow =
+                                window.as_mut().as_any_mut().downcast_mut().unwrap();
+                            main_win.song_overview = Some(Arc::new(overview));
+                        }
+                    }
+                }
+            }
             Message::SRNChannelListFlagToggled(spc_ch, flag) => {
                 if let Some(window) = self.windows.get_mut(&self.main_window_id) {
                     let main_win: &mut MainWindow =
@@ -637,6 +2250,17 @@ impl App {
                     main_win.showing_channel_srn_list[spc_ch] = flag;
                 }
             }
+            Message::DeviceSettingSectionToggled(section, flag) => {
+                for window in self.windows.values_mut() {
+                    if let Some(device_win) = window
+                        .as_mut()
+                        .as_any_mut()
+                        .downcast_mut::<DeviceSettingWindow>()
+                    {
+                        device_win.showing_sections[section] = flag;
+                    }
+                }
+            }
             Message::SPCMuteFlagToggled(flag) => {
                 if let Some(pcm_spc_ref) = &self.pcm_spc {
                     let pcm_spc = pcm_spc_ref.clone();
@@ -661,7 +2285,11 @@ impl App {
                     spc.dsp.write_register(
                         &[0u8],
                         DSP_ADDRESS_CHANNEL_MUTE,
-                        if flag { flags } else { 0xFF },
+                        if flag {
+                            self.midi_channel_mute_flags(flags)
+                        } else {
+                            0xFF
+                        },
                     );
                     // フラグ書き換え
                     self.midi_spc_on.clone().store(flag, Ordering::Relaxed);
@@ -681,21 +2309,15 @@ impl App {
                 }
             }
             Message::ProgramSelected(srn_no, program, window_id) => {
+                let pitch_modulated = self
+                    .source_infos
+                    .read()
+                    .unwrap()
+                    .get(&srn_no)
+                    .is_some_and(|info| info.pitch_modulated);
                 let mut params = self.source_parameter.write().unwrap();
                 if let Some(param) = params.get_mut(&srn_no) {
-                    // 出力チャンネルを設定前がドラムであればSPCと同じに、設定後にドラムであれば9に
-                    let prev_is_drum = (param.program.clone() as u8) >= 0x80;
-                    let curr_is_drum = (program.clone() as u8) >= 0x80;
-                    if prev_is_drum && !curr_is_drum {
-                        for ch in 0..8 {
-                            param.channel_routing[ch] = ch as u8;
-                        }
-                    } else if !prev_is_drum && curr_is_drum {
-                        for ch in 0..8 {
-                            param.channel_routing[ch] = 9u8;
-                        }
-                    }
-                    param.program = program.clone();
+                    set_source_program(param, program.clone(), pitch_modulated);
                 }
                 let mut tasks = vec![];
                 // SRNウィンドウからの呼び出しの場合
@@ -731,7 +2353,8 @@ impl App {
             Message::CenterNoteIntChanged(srn_no, note) => {
                 let mut params = self.source_parameter.write().unwrap();
                 if let Some(param) = params.get_mut(&srn_no) {
-                    param.center_note = (param.center_note & 0x01FF) | ((note as u16) << 9);
+                    let fraction = (param.center_note & 0x01FF) as f32 / 512.0;
+                    param.center_note = set_center_note(note, fraction);
                 }
                 let mut tasks = vec![];
                 if self.midi_preview.load(Ordering::Relaxed) {
@@ -746,9 +2369,11 @@ impl App {
             }
             Message::CenterNoteFractionChanged(srn_no, fraction) => {
                 let mut params = self.source_parameter.write().unwrap();
-                if let Some(param) = params.get_mut(&srn_no) {
-                    let clamped_fraction = f32::round(fraction * 512.0).clamp(0.0, 511.0) as u16;
-                    param.center_note = (param.center_note & 0xFE00) | clamped_fraction;
+                if let (Some(param), Some(fraction)) = (
+                    params.get_mut(&srn_no),
+                    sanitize_float_input(fraction, 0.0..=1.0),
+                ) {
+                    param.center_note = set_center_note((param.center_note >> 9) as u8, fraction);
                     return Task::perform(async {}, move |_| {
                         Message::ReceivedSourceParameterUpdate
                     });
@@ -770,6 +2395,12 @@ impl App {
                 }));
                 return Task::batch(tasks);
             }
+            Message::VelocityFromKeyOnVolumeFlagToggled(srn_no, flag) => {
+                let mut params = self.source_parameter.write().unwrap();
+                if let Some(param) = params.get_mut(&srn_no) {
+                    param.velocity_from_keyon_volume = flag;
+                }
+            }
             Message::PitchBendWidthChanged(srn_no, width) => {
                 let mut params = self.source_parameter.write().unwrap();
                 if let Some(param) = params.get_mut(&srn_no) {
@@ -806,6 +2437,12 @@ impl App {
                     });
                 }
             }
+            Message::PanLawChanged(srn_no, pan_law) => {
+                let mut params = self.source_parameter.write().unwrap();
+                if let Some(param) = params.get_mut(&srn_no) {
+                    param.pan_law = pan_law;
+                }
+            }
             Message::AutoVolumeFlagToggled(srn_no, flag) => {
                 let mut params = self.source_parameter.write().unwrap();
                 if let Some(param) = params.get_mut(&srn_no) {
@@ -866,6 +2503,113 @@ impl App {
                     });
                 }
             }
+            Message::LabelChanged(srn_no, label) => {
+                let mut params = self.source_parameter.write().unwrap();
+                if let Some(param) = params.get_mut(&srn_no) {
+                    param.label = label;
+                }
+            }
+            Message::HideInIndicatorFlagToggled(srn_no, flag) => {
+                let mut params = self.source_parameter.write().unwrap();
+                if let Some(param) = params.get_mut(&srn_no) {
+                    param.hide_in_indicator = flag;
+                }
+            }
+            Message::LinkDuplicatesFlagToggled(srn_no, flag) => {
+                let infos = self.source_infos.read().unwrap();
+                let mut params = self.source_parameter.write().unwrap();
+                if let Some(param) = params.get_mut(&srn_no) {
+                    param.link_duplicates = flag;
+                }
+                // リンク解除は重複音源側にも反映（リンクは編集後の同期で反映される）
+                if !flag {
+                    if let Some(info) = infos.get(&srn_no) {
+                        for duplicate in info.duplicates.iter() {
+                            if let Some(param) = params.get_mut(duplicate) {
+                                param.link_duplicates = false;
+                            }
+                        }
+                    }
+                }
+            }
+            Message::MergeStereoPairFlagToggled(srn_no, flag) => {
+                let infos = self.source_infos.read().unwrap();
+                let mut params = self.source_parameter.write().unwrap();
+                if let Some(partner) = infos.get(&srn_no).and_then(|info| info.stereo_pair_partner)
+                {
+                    // 番号の小さい側を中央に定位させて残し、相手をミュートして1音源にする
+                    // 統合前の設定は記録しておき、解除したときに戻す
+                    for (pair_srn_no, is_kept) in
+                        [(srn_no.min(partner), true), (srn_no.max(partner), false)]
+                    {
+                        let Some(param) = params.get_mut(&pair_srn_no) else {
+                            continue;
+                        };
+                        if param.merge_stereo_pair == flag {
+                            continue;
+                        }
+                        param.merge_stereo_pair = flag;
+                        if flag {
+                            param.pre_merge_state = Some(StereoPairMergeBackup {
+                                auto_pan: param.auto_pan,
+                                fixed_pan: param.fixed_pan,
+                                mute: param.mute,
+                            });
+                            if is_kept {
+                                param.auto_pan = false;
+                                param.fixed_pan = 64;
+                            } else {
+                                param.mute = true;
+                            }
+                        } else if let Some(backup) = param.pre_merge_state.take() {
+                            param.auto_pan = backup.auto_pan;
+                            param.fixed_pan = backup.fixed_pan;
+                            param.mute = backup.mute;
+                        }
+                    }
+                    return Task::perform(async {}, move |_| {
+                        Message::ReceivedSourceParameterUpdate
+                    });
+                }
+            }
+            Message::SRNKindOverrideSelected(srn_no, kind) => {
+                let info = self.decoded_source_info(srn_no);
+                let a4_pitch_hz = self.a4_pitch_hz();
+                let mut params = self.source_parameter.write().unwrap();
+                if let (Some(param), Some(info)) = (params.get_mut(&srn_no), info) {
+                    param.force_tonal = kind == SourceKindOverride::Tonal;
+                    param.force_drum = kind == SourceKindOverride::Drum;
+                    // 上書き指定（自動の場合は解析の判定）に合わせてドラム判定に依存する設定を変える
+                    let (detected_drum, _) = estimate_drum_and_note(&info, a4_pitch_hz);
+                    let is_drum = param.resolve_drum(detected_drum);
+                    apply_drum_classification(param, &info, is_drum);
+                    return Task::perform(async {}, move |_| {
+                        Message::ReceivedSourceParameterUpdate
+                    });
+                }
+            }
+            Message::SustainPedalFlagToggled(srn_no, flag) => {
+                let mut params = self.source_parameter.write().unwrap();
+                if let Some(param) = params.get_mut(&srn_no) {
+                    param.sustain_pedal = flag;
+                }
+            }
+            Message::MinNoteChanged(srn_no, note) => {
+                let mut params = self.source_parameter.write().unwrap();
+                if let Some(param) = params.get_mut(&srn_no) {
+                    // 上限を下回らないよう上限も合わせる
+                    param.min_note = note.min(127);
+                    param.max_note = param.max_note.max(param.min_note);
+                }
+            }
+            Message::MaxNoteChanged(srn_no, note) => {
+                let mut params = self.source_parameter.write().unwrap();
+                if let Some(param) = params.get_mut(&srn_no) {
+                    // 下限を上回らないよう下限も合わせる
+                    param.max_note = note.min(127);
+                    param.min_note = param.min_note.min(param.max_note);
+                }
+            }
             Message::EnvelopeAsExpressionFlagToggled(srn_no, flag) => {
                 let mut params = self.source_parameter.write().unwrap();
                 if let Some(param) = params.get_mut(&srn_no) {
@@ -884,6 +2628,12 @@ impl App {
                     });
                 }
             }
+            Message::EchoSendScaleChanged(srn_no, percent) => {
+                let mut params = self.source_parameter.write().unwrap();
+                if let Some(param) = params.get_mut(&srn_no) {
+                    param.echo_send_scale_percent = percent.min(MAX_ECHO_SEND_SCALE_PERCENT);
+                }
+            }
             Message::FixedReverbSendChanged(srn_no, send) => {
                 let mut params = self.source_parameter.write().unwrap();
                 if let Some(param) = params.get_mut(&srn_no) {
@@ -930,6 +2680,24 @@ impl App {
                     }
                 }
             }
+            Message::SRNCenterNoteSnapToSemitoneClicked(srn_no) => {
+                let mut params = self.source_parameter.write().unwrap();
+                if let Some(param) = params.get_mut(&srn_no) {
+                    param.center_note = snap_center_note(param.center_note, 512.0);
+                    return Task::perform(async {}, move |_| {
+                        Message::ReceivedSourceParameterUpdate
+                    });
+                }
+            }
+            Message::SRNCenterNoteSnapToCentClicked(srn_no) => {
+                let mut params = self.source_parameter.write().unwrap();
+                if let Some(param) = params.get_mut(&srn_no) {
+                    param.center_note = snap_center_note(param.center_note, CENT_NOTE);
+                    return Task::perform(async {}, move |_| {
+                        Message::ReceivedSourceParameterUpdate
+                    });
+                }
+            }
             Message::SRNCenterNoteOctaveDownClicked(srn_no) => {
                 let mut params = self.source_parameter.write().unwrap();
                 if let Some(param) = params.get_mut(&srn_no) {
@@ -949,12 +2717,12 @@ impl App {
                 }
             }
             Message::SRNNoteEstimationClicked(srn_no) => {
+                let info = self.decoded_source_info(srn_no);
                 let mut params = self.source_parameter.write().unwrap();
-                let infos = self.source_infos.read().unwrap();
                 if let Some(param) = params.get_mut(&srn_no) {
-                    if let Some(info) = infos.get(&srn_no) {
-                        let (_, center_note) = estimate_drum_and_note(&info);
-                        param.center_note = f32::round(center_note * 512.0) as u16;
+                    if let Some(info) = info {
+                        let (_, center_note) = estimate_drum_and_note(&info, self.a4_pitch_hz());
+                        param.center_note = center_note_from_float(center_note);
                         return Task::perform(async {}, move |_| {
                             Message::ReceivedSourceParameterUpdate
                         });
@@ -968,8 +2736,13 @@ impl App {
                 self.preview_midi_sound(srn_no);
             }
             Message::AudioOutputDeviceSelected(device_name) => {
-                let mut audio_out_device_name = self.audio_out_device_name.write().unwrap();
-                *audio_out_device_name = Some(device_name.clone());
+                // 曲の再生中は一旦止めて、新しいデバイスで同じ位置から再開する
+                // （SPCの状態と再生サンプル数は停止しても保持される）
+                let resume_playback = self.playback_mode() == PlaybackMode::Song;
+                if self.playback_mode() != PlaybackMode::None {
+                    self.stream_play_stop().expect("Failed to stop play");
+                }
+                *self.audio_out_device_name.write().unwrap() = Some(device_name.clone());
                 // オーディオ出力デバイスを再構築
                 let devices = cpal::default_host()
                     .devices()
@@ -978,9 +2751,11 @@ impl App {
                     .filter(|d| d.supports_output())
                     .find(|d| device_name.starts_with(d.description().unwrap().name()))
                 {
-                    if let Ok(config) = device.default_output_config() {
+                    if let Some(config) =
+                        select_output_stream_config(&device, self.forced_output_sample_rate())
+                    {
                         self.stream_device = Some(device);
-                        self.stream_config = Some(config.into());
+                        self.stream_config = Some(config);
                     } else {
                         self.stream_device = None;
                         self.stream_config = None;
@@ -989,8 +2764,18 @@ impl App {
                     self.stream_device = None;
                     self.stream_config = None;
                 }
+                if resume_playback {
+                    let fade_msec = self.device_switch_fade_msec.load(Ordering::Relaxed);
+                    if let Err(_) = self.play_start(Some(fade_msec)) {
+                        eprintln!("[{}] Faild to start playback", SPC2MIDI2_TITLE_STR);
+                    }
+                }
             }
             Message::MIDIOutputPortSelected(port_name) => {
+                // 既存ポートを選択したら仮想ポートの使用をやめる
+                if self.use_virtual_midi_port.swap(false, Ordering::Relaxed) {
+                    self.persist_preferences();
+                }
                 let mut midi_out_port_name = self.midi_out_port_name.write().unwrap();
                 *midi_out_port_name = Some(port_name.clone());
                 // MIDI出力ポートを再接続
@@ -1014,9 +2799,27 @@ impl App {
                     None
                 };
             }
+            Message::VirtualMIDIPortFlagToggled(flag) => {
+                // 接続を閉じてからポートを作り直す
+                self.midi_out_conn = None;
+                let (port_name, midi_out_conn) = if flag {
+                    connect_virtual_midi_output()
+                } else {
+                    connect_default_midi_output()
+                };
+                *self.midi_out_port_name.write().unwrap() = port_name;
+                self.midi_out_conn = midi_out_conn;
+                self.use_virtual_midi_port.store(flag, Ordering::Relaxed);
+                self.persist_preferences();
+            }
             Message::MIDIOutputBpmChanged(bpm) => {
-                let mut config = self.midi_output_configure.write().unwrap();
-                config.beats_per_minute = Self::round_bpm(bpm);
+                if let Some(bpm) = sanitize_float_input(
+                    bpm,
+                    (MIN_BEATS_PER_MINUTE as f32)..=(MAX_BEATS_PER_MINUTE as f32),
+                ) {
+                    let mut config = self.midi_output_configure.write().unwrap();
+                    config.beats_per_minute = Self::round_bpm(bpm);
+                }
             }
             Message::MIDIOutputTicksPerQuarterChanged(ticks) => {
                 let mut config = self.midi_output_configure.write().unwrap();
@@ -1054,6 +2857,12 @@ impl App {
                     }
                 }
                 config.midi_system = system;
+                self.send_drum_kit_select(&config);
+            }
+            Message::DrumKitChanged(kit) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.drum_kit = kit;
+                self.send_drum_kit_select(&config);
             }
             Message::MIDIOutputUpdatePeriodChanged(period) => {
                 let mut config = self.midi_output_configure.write().unwrap();
@@ -1063,7 +2872,8 @@ impl App {
             }
             Message::MIDIOutputDurationChanged(duration) => {
                 let mut config = self.midi_output_configure.write().unwrap();
-                config.output_duration_msec = duration;
+                config.output_duration_msec =
+                    duration.clamp(MIN_OUTPUT_DURATION_MSEC, MAX_OUTPUT_DURATION_MSEC);
             }
             Message::MIDIOutputSPC700ClockUpFactorChanged(factor) => {
                 let mut config = self.midi_output_configure.write().unwrap();
@@ -1077,32 +2887,155 @@ impl App {
                 let mut config = self.midi_output_configure.write().unwrap();
                 config.trim_leading_nonevents_period = flag;
             }
-            Message::MuteChannel(ch, flag) => {
-                if let (Some(pcm_spc_ref), Some(midi_spc_ref)) = (&self.pcm_spc, &self.midi_spc) {
-                    let (pcm_spc, midi_spc) = (pcm_spc_ref.clone(), midi_spc_ref.clone());
-                    let flags = self.channel_mute_flags.load(Ordering::Relaxed);
-                    let new_flags = if flag {
-                        flags | (1 << ch)
-                    } else {
-                        flags & !(1 << ch)
-                    };
-                    let midi_on = self.midi_spc_on.load(Ordering::Relaxed);
-                    let mut midi_spc = midi_spc.lock().unwrap();
-                    midi_spc.dsp.write_register(
-                        &[0u8],
-                        DSP_ADDRESS_CHANNEL_MUTE,
-                        if midi_on { new_flags } else { 0xFF },
-                    );
-                    let pcm_on = self.pcm_spc_on.load(Ordering::Relaxed);
-                    let mut pcm_spc = pcm_spc.lock().unwrap();
-                    pcm_spc.dsp.write_register(
-                        &[0u8],
-                        DSP_ADDRESS_CHANNEL_MUTE,
-                        if pcm_on { new_flags } else { 0xFF },
-                    );
-                    self.channel_mute_flags.store(new_flags, Ordering::Relaxed);
-                    if flag {
-                        // ミュートの場合は音を止める
+            Message::MIDIOutputSourceMarkersFlagToggled(flag) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.insert_source_markers = flag;
+            }
+            Message::MIDIOutputEmbedSPCMetadataFlagToggled(flag) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.embed_spc_metadata = flag;
+            }
+            Message::MIDIOutputEmbedSPCCommentFlagToggled(flag) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.embed_spc_comment_as_lyric = flag;
+            }
+            Message::MIDIOutputClickTrackFlagToggled(flag) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.insert_click_track = flag;
+            }
+            Message::AdvancedOptionsToggled(flag) => {
+                for window in self.windows.values_mut() {
+                    if let Some(config_win) = window
+                        .as_mut()
+                        .as_any_mut()
+                        .downcast_mut::<MIDIOutputConfigurationWindow>()
+                    {
+                        config_win.show_advanced_options = flag;
+                    }
+                }
+            }
+            Message::SplitSMFPerLoopFlagToggled(flag) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.split_smf_per_loop = flag;
+            }
+            Message::RoutePercussiveNotesFlagToggled(flag) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.route_percussive_notes = flag;
+            }
+            Message::MinSourceKeyOnDurationChanged(msec) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.min_source_keyon_msec = msec;
+            }
+            Message::ExcludedVoiceFlagToggled(ch, flag) => {
+                {
+                    let mut config = self.midi_output_configure.write().unwrap();
+                    if flag {
+                        config.excluded_voices |= 1 << ch;
+                    } else {
+                        config.excluded_voices &= !(1 << ch);
+                    }
+                }
+                // 再生中のMIDI出力にも反映
+                if let Some(midi_spc_ref) = &self.midi_spc {
+                    let midi_spc = midi_spc_ref.clone();
+                    let flags = self.channel_mute_flags.load(Ordering::Relaxed);
+                    let midi_on = self.midi_spc_on.load(Ordering::Relaxed);
+                    midi_spc.lock().unwrap().dsp.write_register(
+                        &[0u8],
+                        DSP_ADDRESS_CHANNEL_MUTE,
+                        if midi_on {
+                            self.midi_channel_mute_flags(flags)
+                        } else {
+                            0xFF
+                        },
+                    );
+                    if flag {
+                        self.stop_midi_channel_sound(ch);
+                    }
+                }
+            }
+            Message::MIDIOutputMinNoteDurationChanged(msec) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.min_note_duration_msec = msec;
+            }
+            Message::MIDIOutputMaxPolyphonyChanged(polyphony) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.max_polyphony_per_channel = polyphony.min(MAX_POLYPHONY_PER_CHANNEL);
+            }
+            Message::WAVBypassEchoChanged(flag) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.wav_bypass_echo = flag;
+            }
+            Message::WAVSoftLimiterFlagToggled(flag) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.wav_soft_limiter = flag;
+            }
+            Message::FlattenVelocityFlagToggled(flag) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.flatten_velocity = flag;
+            }
+            Message::FlatVelocityChanged(velocity) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.flat_velocity = velocity;
+            }
+            Message::CalibrateVelocityByProgramFlagToggled(flag) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.calibrate_velocity_by_program = flag;
+            }
+            Message::WAVSampleRateChanged(rate) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.wav_sample_rate = rate;
+            }
+            Message::WAVResampleQualityChanged(quality) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.wav_resample_quality = quality;
+            }
+            Message::WAVStereoWidthChanged(width) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.wav_stereo_width_percent = width;
+            }
+            Message::TargetPitchBendWidthChanged(width) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.target_pitch_bend_width = width;
+                // 再生にかかわることなのでパラメータ反映
+                return Task::perform(async {}, move |_| Message::ReceivedSourceParameterUpdate);
+            }
+            Message::ClampPitchBendWidthFlagToggled(flag) => {
+                let mut config = self.midi_output_configure.write().unwrap();
+                config.clamp_pitch_bend_width = flag;
+                // 再生にかかわることなのでパラメータ反映
+                return Task::perform(async {}, move |_| Message::ReceivedSourceParameterUpdate);
+            }
+            Message::MuteChannel(ch, flag) => {
+                if let (Some(pcm_spc_ref), Some(midi_spc_ref)) = (&self.pcm_spc, &self.midi_spc) {
+                    let (pcm_spc, midi_spc) = (pcm_spc_ref.clone(), midi_spc_ref.clone());
+                    let flags = self.channel_mute_flags.load(Ordering::Relaxed);
+                    let new_flags = if flag {
+                        flags | (1 << ch)
+                    } else {
+                        flags & !(1 << ch)
+                    };
+                    let midi_on = self.midi_spc_on.load(Ordering::Relaxed);
+                    let mut midi_spc = midi_spc.lock().unwrap();
+                    midi_spc.dsp.write_register(
+                        &[0u8],
+                        DSP_ADDRESS_CHANNEL_MUTE,
+                        if midi_on {
+                            self.midi_channel_mute_flags(new_flags)
+                        } else {
+                            0xFF
+                        },
+                    );
+                    let pcm_on = self.pcm_spc_on.load(Ordering::Relaxed);
+                    let mut pcm_spc = pcm_spc.lock().unwrap();
+                    pcm_spc.dsp.write_register(
+                        &[0u8],
+                        DSP_ADDRESS_CHANNEL_MUTE,
+                        if pcm_on { new_flags } else { 0xFF },
+                    );
+                    self.channel_mute_flags.store(new_flags, Ordering::Relaxed);
+                    if flag {
+                        // ミュートの場合は音を止める
                         self.stop_midi_channel_sound(ch);
                     }
                 }
@@ -1132,7 +3065,11 @@ impl App {
                     midi_spc.dsp.write_register(
                         &[0u8],
                         DSP_ADDRESS_CHANNEL_MUTE,
-                        if midi_on { new_flags } else { 0xFF },
+                        if midi_on {
+                            self.midi_channel_mute_flags(new_flags)
+                        } else {
+                            0xFF
+                        },
                     );
                     // ミュートの場合は音を止める
                     if new_flags != 0 {
@@ -1179,12 +3116,13 @@ impl App {
                     (config.output_duration_msec as f32 / 1000.0).round() as u32
                 };
                 if let Some(spc_file) = &self.spc_file {
-                    let spc_file = Box::new(spc_file.clone());
-                    self.analyze_sources(
-                        output_duration,
-                        &spc_file.header.spc_register,
-                        &spc_file.ram,
-                        &spc_file.dsp_register,
+                    // 解析はバックグラウンドで行い、終わったら反映する
+                    let spc_file = spc_file.clone();
+                    self.start_source_analysis(
+                        spc_file,
+                        AnalysisRequest::Reanalyze {
+                            analyze_duration_sec: output_duration,
+                        },
                     );
                 }
             }
@@ -1196,11 +3134,209 @@ impl App {
                     };
                 }
             }
+            Message::AutoBalanceVolumeRequested => {
+                let infos = self.source_infos.read().unwrap();
+                let mut params = self.source_parameter.write().unwrap();
+                balance_source_volumes(&infos, &mut params);
+                return Task::perform(async {}, move |_| Message::ReceivedSourceParameterUpdate);
+            }
+            Message::EstimateAllSourcesRequested => {
+                if !self.source_infos.read().unwrap().is_empty() {
+                    return Task::perform(
+                        confirm_estimate_all_sources(),
+                        Message::EstimateAllSourcesConfirmed,
+                    );
+                }
+            }
+            Message::EstimateAllSourcesConfirmed(reset_programs) => {
+                // キャンセル時は何もしない
+                if let Some(reset_programs) = reset_programs {
+                    let infos = self.decoded_source_infos();
+                    let mut params = self.source_parameter.write().unwrap();
+                    reestimate_source_parameters(
+                        &infos,
+                        &mut params,
+                        self.a4_pitch_hz(),
+                        reset_programs,
+                    );
+                    return Task::perform(async {}, move |_| {
+                        Message::ReceivedSourceParameterUpdate
+                    });
+                }
+            }
             Message::AudioLatencyMsecChanged(msec) => {
                 self.audio_output_latency_msec
                     .store(msec, Ordering::Relaxed);
+                // 次回起動時にも使えるよう保存（再生中の場合は次の再生開始時から反映）
+                self.persist_preferences();
+            }
+            Message::CacheAnalysisFlagToggled(flag) => {
+                self.cache_analysis.store(flag, Ordering::Relaxed);
+                self.persist_preferences();
+            }
+            Message::LazySourceDecodingFlagToggled(flag) => {
+                self.lazy_source_decoding.store(flag, Ordering::Relaxed);
+                self.persist_preferences();
+                // 有効にしたら波形を解放し、無効にしたら常駐させ直す
+                if flag {
+                    self.release_source_signals();
+                } else {
+                    let decoded = self.decoded_source_infos();
+                    *self.source_infos.write().unwrap() = decoded;
+                }
+            }
+            Message::StretchSMFExportFlagToggled(flag) => {
+                self.stretch_smf_export.store(flag, Ordering::Relaxed);
+                self.persist_preferences();
+            }
+            Message::StretchTargetSecChanged(sec) => {
+                self.stretch_target_sec.store(sec, Ordering::Relaxed);
+                self.persist_preferences();
+            }
+            Message::ExportRangeFlagToggled(flag) => {
+                self.export_range_enabled.store(flag, Ordering::Relaxed);
+                self.persist_preferences();
+            }
+            Message::ExportRangeStartChanged(sec) => {
+                if let Some(sec) = sanitize_float_input(sec, 0.0..=MAX_EXPORT_RANGE_SEC) {
+                    self.export_range_start_sec
+                        .store(sec.to_bits(), Ordering::Relaxed);
+                    self.persist_preferences();
+                }
+            }
+            Message::ExportRangeEndChanged(sec) => {
+                if let Some(sec) = sanitize_float_input(sec, 0.0..=MAX_EXPORT_RANGE_SEC) {
+                    self.export_range_end_sec
+                        .store(sec.to_bits(), Ordering::Relaxed);
+                    self.persist_preferences();
+                }
+            }
+            Message::MasterReverbSendChanged(send) => {
+                self.master_reverb_send
+                    .store(send.min(127), Ordering::Relaxed);
+                self.persist_preferences();
+            }
+            Message::StuckNoteTimeoutChanged(sec) => {
+                self.stuck_note_timeout_sec
+                    .store(sec.min(MAX_STUCK_NOTE_TIMEOUT_SEC), Ordering::Relaxed);
+                self.persist_preferences();
+            }
+            Message::ExportPrerollMsecChanged(msec) => {
+                self.export_preroll_msec
+                    .store(msec.min(MAX_EXPORT_PREROLL_MSEC), Ordering::Relaxed);
+                self.persist_preferences();
+            }
+            Message::LongExportWarningSecChanged(sec) => {
+                self.long_export_warning_sec
+                    .store(sec.min(MAX_LONG_EXPORT_WARNING_SEC), Ordering::Relaxed);
+                self.persist_preferences();
+            }
+            Message::LongExportConfirmed(message) => {
+                self.long_export_confirmed = true;
+                return self.update(*message);
+            }
+            Message::ProgramVelocityCalibrationProgramSelected(program) => {
+                for window in self.windows.values_mut() {
+                    if let Some(device_win) = window
+                        .as_mut()
+                        .as_any_mut()
+                        .downcast_mut::<DeviceSettingWindow>()
+                    {
+                        device_win.velocity_calibration_program = program;
+                    }
+                }
+            }
+            Message::ProgramVelocityCalibrationChanged(program, percent) => {
+                if let Some(value) = self
+                    .program_velocity_calibration
+                    .write()
+                    .unwrap()
+                    .get_mut(program as usize)
+                {
+                    *value =
+                        percent.clamp(MIN_PROGRAM_VELOCITY_PERCENT, MAX_PROGRAM_VELOCITY_PERCENT);
+                }
+                self.persist_preferences();
+            }
+            Message::ProgramVelocityCalibrationReset => {
+                *self.program_velocity_calibration.write().unwrap() =
+                    default_program_velocity_calibration();
+                self.persist_preferences();
+            }
+            Message::SourceParameterTemplateChanged(mut template) => {
+                template.min_note = template.min_note.min(127);
+                template.max_note = template.max_note.clamp(template.min_note, 127);
+                *self.source_parameter_template.write().unwrap() = template;
+                self.persist_preferences();
+            }
+            Message::RampInOnPlayStartFlagToggled(flag) => {
+                self.ramp_in_on_play_start.store(flag, Ordering::Relaxed);
+                self.persist_preferences();
+            }
+            Message::ForceOutputSampleRateFlagToggled(flag) => {
+                self.force_output_sample_rate.store(flag, Ordering::Relaxed);
+                self.reselect_stream_config();
+                self.persist_preferences();
+            }
+            Message::OutputSampleRateChanged(rate) => {
+                self.output_sample_rate.store(rate, Ordering::Relaxed);
+                self.reselect_stream_config();
+                self.persist_preferences();
+            }
+            Message::A4PitchChanged(hz) => {
+                if let Some(hz) = sanitize_float_input(hz, MIN_A4_PITCH_HZ..=MAX_A4_PITCH_HZ) {
+                    self.a4_pitch_hz.store(hz.to_bits(), Ordering::Relaxed);
+                    self.persist_preferences();
+                    // 新しい基準ピッチで全音源のセンターノートを推定し直す
+                    let infos = self.decoded_source_infos();
+                    let mut params = self.source_parameter.write().unwrap();
+                    for (srn_no, param) in params.iter_mut() {
+                        if let Some(info) = infos.get(srn_no) {
+                            let (_, center_note) = estimate_drum_and_note(info, hz);
+                            param.center_note = center_note_from_float(center_note);
+                        }
+                    }
+                    return Task::perform(async {}, move |_| {
+                        Message::ReceivedSourceParameterUpdate
+                    });
+                }
+            }
+            Message::IndicatorSmoothingChanged(smoothing) => {
+                if let Some(smoothing) =
+                    sanitize_float_input(smoothing, 0.0..=MAX_INDICATOR_SMOOTHING)
+                {
+                    self.indicator_smoothing
+                        .store(smoothing.to_bits(), Ordering::Relaxed);
+                    self.persist_preferences();
+                }
+            }
+            Message::SpectrumPeakLabelsChanged(num_labels) => {
+                self.spectrum_peak_labels
+                    .store(num_labels.min(MAX_SPECTRUM_PEAK_LABELS), Ordering::Relaxed);
+                self.persist_preferences();
+            }
+            Message::SpectrumPeakSeparationChanged(hz) => {
+                if let Some(hz) = sanitize_float_input(hz, 0.0..=MAX_SPECTRUM_PEAK_SEPARATION_HZ) {
+                    self.spectrum_peak_separation_hz
+                        .store(hz.to_bits(), Ordering::Relaxed);
+                    self.persist_preferences();
+                }
+            }
+            Message::RampInMsecChanged(msec) => {
+                self.ramp_in_msec.store(msec, Ordering::Relaxed);
+                self.persist_preferences();
+            }
+            Message::DeviceSwitchFadeMsecChanged(msec) => {
+                self.device_switch_fade_msec.store(msec, Ordering::Relaxed);
+                self.persist_preferences();
             }
             Message::Tick => {
+                // ワンショットのプレビューがストリーム側で終了していたらストリームを破棄
+                if !self.stream_is_playing.load(Ordering::Relaxed) {
+                    self.stream_play_stop().expect("Failed to stop play");
+                    return Task::none();
+                }
+
                 // 再生情報取得
                 if let Some(midi_spc_ref) = &self.midi_spc {
                     let midi_spc = midi_spc_ref.clone();
@@ -1209,9 +3345,42 @@ impl App {
                     *status = read_playback_status(&spc.dsp);
                 }
 
+                // 曲中ソロの音源を鳴らしているボイスを追従
+                if self.source_solo.is_some() {
+                    self.update_source_solo();
+                }
+
+                // インスペクタのDSPレジスタ表示更新
+                if let Some(pcm_spc_ref) = &self.pcm_spc {
+                    let spc = pcm_spc_ref.lock().unwrap();
+                    for window in self.windows.values_mut() {
+                        if let Some(inspector_win) = window
+                            .as_mut()
+                            .as_any_mut()
+                            .downcast_mut::<RegisterInspectorWindow>()
+                        {
+                            read_dsp_registers(&spc.dsp, &mut inspector_win.dsp_register);
+                        }
+                    }
+                }
+                // インスペクタのRAM表示更新（表示中のページだけ写す）
+                if let Some(midi_spc_ref) = &self.midi_spc {
+                    let spc = midi_spc_ref.lock().unwrap();
+                    for window in self.windows.values_mut() {
+                        if let Some(inspector_win) = window
+                            .as_mut()
+                            .as_any_mut()
+                            .downcast_mut::<RegisterInspectorWindow>()
+                        {
+                            inspector_win.update_ram_page(&spc.ram);
+                        }
+                    }
+                }
+
                 // 再生情報更新
                 if let Some(window) = self.windows.get_mut(&self.main_window_id) {
                     let status = self.playback_status.read().unwrap();
+                    let params = self.source_parameter.read().unwrap();
                     let main_win: &mut MainWindow =
                         window.as_mut().as_any_mut().downcast_mut().unwrap();
                     let played_samples = self.stream_played_samples.load(Ordering::Relaxed);
@@ -1219,20 +3388,66 @@ impl App {
                     let playback_time = played_samples as f32
                         / self.stream_config.as_ref().unwrap().sample_rate as f32;
                     main_win.playback_time_sec = playback_time;
+                    main_win.beat_indicator.playback_time_sec = playback_time;
+                    main_win.beat_indicator.beats_per_minute =
+                        self.midi_output_configure.read().unwrap().beats_per_minute;
                     main_win.midi_bit_rate = if playback_time > 0.0 {
                         (midi_output_bytes as f32 * 10.0) / playback_time // スタート・ストップビットの2bitを加えて1バイト当たり10bit送るとする
                     } else {
                         0.0
                     };
+                    // 新たに発生したアンダーランを記録し、直近の発生回数から状態を判定
+                    let now = Instant::now();
+                    let underruns = self.stream_underruns.load(Ordering::Relaxed);
+                    for _ in self.observed_underruns..underruns {
+                        self.recent_underrun_times.push_back(now);
+                    }
+                    self.observed_underruns = underruns;
+                    while let Some(time) = self.recent_underrun_times.front() {
+                        if now.duration_since(*time).as_secs() < AUDIO_HEALTH_WINDOW_SEC {
+                            break;
+                        }
+                        self.recent_underrun_times.pop_front();
+                    }
+                    main_win.recent_underruns = self.recent_underrun_times.len();
+                    main_win.audio_health = audio_health(main_win.recent_underruns);
+                    let smoothing =
+                        f32::from_bits(self.indicator_smoothing.load(Ordering::Relaxed));
+                    // PCMはピークを読み出してリセット、MIDIは現在の推定値を読む
+                    let pcm_level =
+                        f32::from_bits(self.pcm_output_level.swap(0, Ordering::Relaxed));
+                    let midi_level = f32::from_bits(self.midi_output_level.load(Ordering::Relaxed));
+                    main_win
+                        .pcm_level_indicator
+                        .set_value(amplitude_to_meter_db(pcm_level), smoothing);
+                    main_win
+                        .midi_level_indicator
+                        .set_value(amplitude_to_meter_db(midi_level), smoothing);
                     for ch in 0..8 {
-                        main_win.expression_indicator[ch].value = status.envelope[ch] as f32;
-                        main_win.pitch_indicator[ch].value = if status.pitch[ch] > 0 {
-                            12.0 * (f32::log2(status.pitch[ch] as f32) - 12.0)
-                        } else {
-                            0.0
-                        };
-                        main_win.volume_indicator[ch][0].value = status.volume[ch][0] as f32;
-                        main_win.volume_indicator[ch][1].value = status.volume[ch][1] as f32;
+                        // 非表示指定の音源が鳴っているチャンネルはインジケータを更新しない
+                        if let Some(param) = params.get(&status.srn_no[ch]) {
+                            if param.hide_in_indicator {
+                                main_win.expression_indicator[ch].set_value(0.0, 0.0);
+                                main_win.pitch_indicator[ch].set_value(0.0, 0.0);
+                                main_win.volume_indicator[ch][0].set_value(0.0, 0.0);
+                                main_win.volume_indicator[ch][1].set_value(0.0, 0.0);
+                                continue;
+                            }
+                        }
+                        main_win.expression_indicator[ch]
+                            .set_value(status.envelope[ch] as f32, smoothing);
+                        main_win.pitch_indicator[ch].set_value(
+                            if status.pitch[ch] > 0 {
+                                12.0 * (f32::log2(status.pitch[ch] as f32) - 12.0)
+                            } else {
+                                0.0
+                            },
+                            smoothing,
+                        );
+                        main_win.volume_indicator[ch][0]
+                            .set_value(status.volume[ch][0] as f32, smoothing);
+                        main_win.volume_indicator[ch][1]
+                            .set_value(status.volume[ch][1] as f32, smoothing);
                     }
                 }
             }
@@ -1253,16 +3468,46 @@ impl App {
     }
 
     pub fn subscription(&self) -> Subscription<Message> {
-        if self.stream_is_playing.load(Ordering::Relaxed) {
+        // バックグラウンド処理中は終了を監視
+        let operation_poll = if self.background_operation.is_some() {
+            iced::time::every(iced::time::Duration::from_millis(100))
+                .map(|_| Message::BackgroundOperationPolled)
+        } else {
+            Subscription::none()
+        };
+        // 曲全体の波形描画中は終了を監視
+        let overview_poll = if self.song_overview_job.is_some() {
+            iced::time::every(iced::time::Duration::from_millis(200))
+                .map(|_| Message::SongOverviewPolled)
+        } else {
+            Subscription::none()
+        };
+        // 再生位置の移動中は終了を監視
+        let seek_poll = if self.song_seek_job.is_some() {
+            iced::time::every(iced::time::Duration::from_millis(50))
+                .map(|_| Message::SongSeekPolled)
+        } else {
+            Subscription::none()
+        };
+        // ストリーム側で再生が終了した場合もストリームを破棄するまでティックを続ける
+        if self.stream.is_some() {
             Subscription::batch(vec![
                 iced::time::every(iced::time::Duration::from_millis(10)).map(|_| Message::Tick),
                 window::close_events().map(Message::WindowClosed),
                 event::listen().map(Message::EventOccurred),
+                event::listen_with(window_rescaled_message),
+                operation_poll,
+                overview_poll,
+                seek_poll,
             ])
         } else {
             Subscription::batch(vec![
                 window::close_events().map(Message::WindowClosed),
                 event::listen().map(Message::EventOccurred),
+                event::listen_with(window_rescaled_message),
+                operation_poll,
+                overview_poll,
+                seek_poll,
             ])
         }
     }
@@ -1322,21 +3567,38 @@ impl App {
         Self::round_bpm(estimate_bpm(&onset_signal, 64_000.0))
     }
 
-    /// 音源ソースの解析
+    /// 現在の設定から音源解析の条件を作成
+    fn source_analysis_settings(&self) -> SourceAnalysisSettings {
+        let config = self.midi_output_configure.read().unwrap();
+        SourceAnalysisSettings {
+            a4_pitch_hz: self.a4_pitch_hz(),
+            template: self.source_parameter_template.read().unwrap().clone(),
+            min_source_keyon_msec: config.min_source_keyon_msec,
+            excluded_voices: config.excluded_voices,
+            channel_mute_flags: self.channel_mute_flags.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 音源ソースの解析（RAMイメージが不正な場合はエラー、キャンセルされた場合はNoneを返す）
     fn analyze_sources(
-        &mut self,
+        settings: &SourceAnalysisSettings,
         analyze_duration_sec: u32,
         register: &SPCRegister,
         ram: &[u8],
         dsp_register: &[u8; 128],
-    ) {
+        cancel: &AtomicBool,
+        progress: &AtomicUsize,
+    ) -> Result<Option<SourceAnalysis>, String> {
+        validate_spc_ram(ram)?;
         let analyze_duration_64khz_ticks = analyze_duration_sec * 64000;
-
-        // 音源情報を作り直す
-        let mut infos = self.source_infos.write().unwrap();
-        *infos = BTreeMap::new();
-        let mut params = self.source_parameter.write().unwrap();
-        *params = BTreeMap::new();
+        let mut cached_sources = BTreeMap::new();
+        let min_keyon_64khz_ticks = settings.min_source_keyon_msec * 64;
+        let excluded_voices = settings.excluded_voices;
+        // BPM推定ではミュート中のチャンネルも除く
+        let bpm_mute_flags = settings.channel_mute_flags | excluded_voices;
+        let mut infos = BTreeMap::new();
+        let mut params = BTreeMap::new();
+        progress.store(0, Ordering::Relaxed);
 
         // 一定期間シミュレートし、サンプルソース番号とそれに紐づく開始アドレスとキーオンされたチャンネルを取得
         let mut midispc: Box<spc700::spc::SPC<spc700::mididsp::MIDIDSP>> = Box::new({
@@ -1347,120 +3609,582 @@ impl App {
         let mut cycle_count = 0;
         let mut tick64khz_count = 0;
         let mut start_address_map = BTreeMap::new();
+        let mut alternate_dir_map: BTreeMap<u8, Vec<usize>> = BTreeMap::new();
         let mut using_channel_map = BTreeMap::new();
+        let mut keyon_pitch_map: BTreeMap<u8, Vec<u16>> = BTreeMap::new();
+        let mut pitch_deviation_map: BTreeMap<u8, f32> = BTreeMap::new();
+        let mut noise_sources = vec![];
+        let mut pitch_modulated_sources = vec![];
+        // （左に振り切った音源番号, 右に振り切った音源番号）ごとの同時キーオン回数
+        let mut stereo_keyon_map: BTreeMap<(u8, u8), usize> = BTreeMap::new();
+        // チャンネルごとの発音中のサンプル番号とキーオン時ピッチ
+        let mut channel_keyon: [Option<(u8, u16)>; 8] = [None; 8];
+        // チャンネルごとの発音中のサンプル番号とキーオン時刻、音源ごとの最長キーオン期間
+        let mut channel_keyon_tick: [Option<(u8, u32)>; 8] = [None; 8];
+        let mut max_keyon_ticks_map: BTreeMap<u8, u32> = BTreeMap::new();
+        // BPM推定用のオンセット信号
+        let mut onset_signal = vec![];
+        let mut update_max_keyon_ticks = |keyon_tick: Option<(u8, u32)>, tick: u32| {
+            if let Some((sample_source, start_tick)) = keyon_tick {
+                let max_ticks = max_keyon_ticks_map.entry(sample_source).or_insert(0);
+                *max_ticks = (*max_ticks).max(tick - start_tick);
+            }
+        };
         while tick64khz_count < analyze_duration_64khz_ticks {
             cycle_count += midispc.execute_step() as u32;
             // キーオンが打たれていた時のサンプル番号を取得
             // DSPを動かすとキーオンフラグが落ちることがあるので64kHzティック前に調べる
-            let keyon = midispc.dsp.read_register(ram, DSP_ADDRESS_KON);
+            // 除外したボイスのキーオンは無視
+            let keyon = midispc.dsp.read_register(ram, DSP_ADDRESS_KON) & !excluded_voices;
             if keyon != 0 {
                 let brr_dir_base_address =
-                    (midispc.dsp.read_register(ram, DSP_ADDRESS_DIR) as u16) << 8;
+                    (midispc.dsp.read_register(ram, DSP_ADDRESS_DIR) as usize) << 8;
+                let noise_on = midispc.dsp.read_register(ram, DSP_ADDRESS_NOISE_ON);
+                // ボイス0はピッチモジュレーションできないので除く
+                let pitch_mod_on = midispc.dsp.read_register(ram, DSP_ADDRESS_PITCH_MOD_ON) & 0xFE;
+                // 同時にキーオンされたボイスの（音源番号, 片側に振り切っていればその向き）
+                let mut keyon_pan_sides: [Option<(u8, bool)>; 8] = [None; 8];
                 for ch in 0..8 {
                     if (keyon >> ch) & 1 != 0 {
                         let sample_source = midispc
                             .dsp
                             .read_register(ram, (ch << 4) | DSP_ADDRESS_V0SRCN);
-                        let dir_address =
-                            (brr_dir_base_address + 4 * (sample_source as u16)) as usize;
-                        start_address_map.insert(sample_source, dir_address);
+                        let dir_address = brr_dir_base_address + 4 * (sample_source as usize);
+                        // 曲中でDIRが変わり同じ音源番号が別のディレクトリエントリを指す場合は
+                        // 最後に発音されたエントリを採用し、それ以外のアドレスを警告用に記録する
+                        if let Some(previous) = start_address_map.insert(sample_source, dir_address)
+                        {
+                            if previous != dir_address {
+                                let alternates =
+                                    alternate_dir_map.entry(sample_source).or_insert(vec![]);
+                                if !alternates.contains(&previous) {
+                                    alternates.push(previous);
+                                }
+                                alternates.retain(|address| *address != dir_address);
+                            }
+                        }
                         using_channel_map
                             .entry(sample_source)
                             .and_modify(|keyon_ch| *keyon_ch |= 1 << ch)
                             .or_insert(1 << ch);
+                        let pitch_high = midispc
+                            .dsp
+                            .read_register(ram, (ch << 4) | DSP_ADDRESS_V0PITCHH);
+                        let pitch_low = midispc
+                            .dsp
+                            .read_register(ram, (ch << 4) | DSP_ADDRESS_V0PITCHL);
+                        let pitch = ((pitch_high as u16) << 8) | (pitch_low as u16);
+                        keyon_pitch_map
+                            .entry(sample_source)
+                            .or_default()
+                            .push(pitch);
+                        channel_keyon[ch as usize] = Some((sample_source, pitch));
+                        let lvol = midispc
+                            .dsp
+                            .read_register(ram, (ch << 4) | DSP_ADDRESS_V0VOLL)
+                            as i8;
+                        let rvol = midispc
+                            .dsp
+                            .read_register(ram, (ch << 4) | DSP_ADDRESS_V0VOLR)
+                            as i8;
+                        keyon_pan_sides[ch as usize] =
+                            hard_pan_side(lvol, rvol).map(|left| (sample_source, left));
+                        // 発音中に再度キーオンされた場合はそこまでをキーオン期間とする
+                        update_max_keyon_ticks(channel_keyon_tick[ch as usize], tick64khz_count);
+                        channel_keyon_tick[ch as usize] = Some((sample_source, tick64khz_count));
+                        // ノイズ有効でキーオンされた音源
+                        if (noise_on >> ch) & 1 != 0 && !noise_sources.contains(&sample_source) {
+                            noise_sources.push(sample_source);
+                        }
+                        // ピッチモジュレーション有効でキーオンされた音源
+                        if (pitch_mod_on >> ch) & 1 != 0
+                            && !pitch_modulated_sources.contains(&sample_source)
+                        {
+                            pitch_modulated_sources.push(sample_source);
+                        }
+                    }
+                }
+                // 左右に振り切った別の音源が同時にキーオンされていればステレオペアの候補
+                for left in keyon_pan_sides.iter().flatten().filter(|(_, left)| *left) {
+                    for right in keyon_pan_sides.iter().flatten().filter(|(_, left)| !*left) {
+                        if left.0 != right.0 {
+                            *stereo_keyon_map.entry((left.0, right.0)).or_insert(0) += 1;
+                        }
                     }
                 }
             }
             // 64kHzティック処理
             if cycle_count >= CLOCK_TICK_CYCLE_64KHZ {
+                // ノートオンされていた音のボリュームの和をオンセット信号とする
+                let noteon = midispc.dsp.read_register(ram, DSP_ADDRESS_NOTEON);
+                let mut onset = 0.0;
+                for ch in 0..8 {
+                    if ((bpm_mute_flags >> ch) & 0x1) == 0 && ((noteon >> ch) & 0x1) != 0 {
+                        let lvol = midispc
+                            .dsp
+                            .read_register(ram, (ch << 4) | DSP_ADDRESS_V0VOLL)
+                            as f32;
+                        let rvol = midispc
+                            .dsp
+                            .read_register(ram, (ch << 4) | DSP_ADDRESS_V0VOLR)
+                            as f32;
+                        onset += lvol.abs() + rvol.abs();
+                    }
+                }
+                onset_signal.push(onset);
                 midispc.clock_tick_64k_hz();
                 cycle_count -= CLOCK_TICK_CYCLE_64KHZ;
                 tick64khz_count += 1;
+                // 1秒毎に進捗を更新
+                if tick64khz_count % 64000 == 0 {
+                    progress.store(
+                        (1000 * tick64khz_count as usize) / analyze_duration_64khz_ticks as usize,
+                        Ordering::Relaxed,
+                    );
+                    if cancel.load(Ordering::Relaxed) {
+                        return Ok(None);
+                    }
+                }
+                // 1msec毎に発音中のピッチ変化量を調べる
+                if tick64khz_count % 64 == 0 {
+                    let noteon = midispc.dsp.read_register(ram, DSP_ADDRESS_NOTEON);
+                    for ch in 0..8 {
+                        if (noteon >> ch) & 1 == 0 {
+                            channel_keyon[ch as usize] = None;
+                            update_max_keyon_ticks(
+                                channel_keyon_tick[ch as usize].take(),
+                                tick64khz_count,
+                            );
+                        }
+                        if let Some((sample_source, keyon_pitch)) = channel_keyon[ch as usize] {
+                            let pitch_high = midispc
+                                .dsp
+                                .read_register(ram, (ch << 4) | DSP_ADDRESS_V0PITCHH);
+                            let pitch_low = midispc
+                                .dsp
+                                .read_register(ram, (ch << 4) | DSP_ADDRESS_V0PITCHL);
+                            let pitch = ((pitch_high as u16) << 8) | (pitch_low as u16);
+                            if pitch > 0 && keyon_pitch > 0 {
+                                let deviation =
+                                    (12.0 * f32::log2(pitch as f32 / keyon_pitch as f32)).abs();
+                                let max_deviation =
+                                    pitch_deviation_map.entry(sample_source).or_insert(0.0);
+                                *max_deviation = max_deviation.max(deviation);
+                            }
+                        }
+                    }
+                }
             }
         }
 
-        // BPM（テンポ）推定
-        {
-            let channel_mute_flags = self.channel_mute_flags.load(Ordering::Relaxed);
-            let bpm = Self::estimate_bpm(
-                analyze_duration_sec,
-                channel_mute_flags,
-                register,
-                ram,
-                dsp_register,
-            );
-            let mut config = self.midi_output_configure.write().unwrap();
-            config.beats_per_minute = Self::round_bpm(bpm);
+        // 解析終了時に発音中のキーオンを打ち切り、短いキーオンしかない音源を除く
+        for keyon_tick in channel_keyon_tick.iter_mut() {
+            update_max_keyon_ticks(keyon_tick.take(), tick64khz_count);
         }
+        if min_keyon_64khz_ticks > 0 {
+            start_address_map.retain(|srn, _| {
+                max_keyon_ticks_map
+                    .get(srn)
+                    .is_some_and(|ticks| *ticks >= min_keyon_64khz_ticks)
+            });
+        }
+
+        // BPM（テンポ）推定
+        let beats_per_minute = Self::round_bpm(estimate_bpm(&onset_signal, 64_000.0));
 
         // 波形情報の読み込み
         for (srn, dir_address) in start_address_map.iter() {
-            let mut decoder = Decoder::new();
-            let mut signal = Vec::new();
-            decoder.keyon(ram, *dir_address);
-            // 原音ピッチで終端までデコード
-            loop {
-                let pcm = decoder.process(ram, 0x1000) as f32;
-                signal.push(pcm * PCM_NORMALIZE_CONST);
-                // 最後のブロックはデコードしない（ループを繋ぐため）
-                if decoder.end {
-                    break;
+            let using_channel_flags = using_channel_map
+                .get(srn)
+                .ok_or(format!("SRN {} has no key-on channel", srn))?;
+            let using_channel: [bool; 8] =
+                std::array::from_fn(|ch| ((using_channel_flags >> ch) & 1) != 0);
+            // ディレクトリエントリが無効な場合はスキップ
+            let mut source_info = match decode_source(
+                ram,
+                *dir_address,
+                using_channel,
+                keyon_pitch_map.get(srn).cloned().unwrap_or_default(),
+            ) {
+                Some(info) => info,
+                None => {
+                    eprintln!(
+                        "WARNING: SRN {} has invalid directory entry at 0x{:X}, skipped",
+                        srn, dir_address
+                    );
+                    continue;
                 }
-            }
-            // データ追記
-            let start_address =
-                make_u16_from_u8(&ram[(*dir_address + 0)..(*dir_address + 2)]) as usize;
-            let loop_address =
-                make_u16_from_u8(&ram[(*dir_address + 2)..(*dir_address + 4)]) as usize;
-            let using_channel_flags = using_channel_map.get(srn).unwrap();
-            let using_channel: [bool; 8] = (0..8)
-                .into_iter()
-                .map(|ch| ((using_channel_flags >> ch) & 1) != 0)
-                .collect::<Vec<bool>>()
-                .try_into()
-                .unwrap();
-            let source_info = SourceInformation {
-                signal: signal.clone(),
-                power_spectrum: compute_power_spectrum(&signal),
-                start_address: start_address,
-                end_address: start_address + (signal.len() * 9) / 16,
-                loop_start_sample: ((loop_address - start_address) * 16) / 9,
-                using_channel: using_channel,
             };
+            source_info.max_pitch_deviation = pitch_deviation_map.get(srn).cloned().unwrap_or(0.0);
+            source_info.uses_noise = noise_sources.contains(srn);
+            source_info.pitch_modulated = pitch_modulated_sources.contains(srn);
+            source_info.detected_drum =
+                estimate_drum_and_note(&source_info, settings.a4_pitch_hz).0;
+            source_info.alternate_dir_addresses =
+                alternate_dir_map.get(srn).cloned().unwrap_or_default();
+            if !source_info.alternate_dir_addresses.is_empty() {
+                eprintln!(
+                    "WARNING: SRN {} is played with multiple directory entries (using 0x{:X})",
+                    srn, dir_address
+                );
+            }
+            cached_sources.insert(
+                *srn,
+                CachedSource {
+                    dir_address: *dir_address,
+                    using_channel: using_channel,
+                    keyon_pitches: keyon_pitch_map.remove(srn).unwrap_or_default(),
+                    max_pitch_deviation: source_info.max_pitch_deviation,
+                    uses_noise: source_info.uses_noise,
+                    pitch_modulated: source_info.pitch_modulated,
+                    alternate_dir_addresses: source_info.alternate_dir_addresses.clone(),
+                    stereo_pair_partner: None,
+                },
+            );
             infos.insert(*srn, source_info.clone());
-            // ドラム音とピッチの推定
-            let (is_drum, center_note) = estimate_drum_and_note(&source_info);
             params.insert(
                 *srn,
-                SourceParameter {
-                    mute: false,
-                    program: if is_drum {
-                        Program::AcousticBassDrum
-                    } else {
-                        Program::AcousticGrand
-                    },
-                    center_note: f32::round(center_note * 512.0) as u16,
-                    noteon_velocity: 100,
-                    pitch_bend_width: 12,
-                    envelope_as_expression: false,
-                    auto_pan: true,
-                    fixed_pan: 64,
-                    auto_volume: true,
-                    fixed_volume: 100,
-                    fixed_reverb_send: 0,
-                    chorus_send: 0,
-                    enable_pitch_bend: !is_drum,
-                    echo_as_reverb_send: false,
-                    update_parameter_after_noteon: true,
-                    channel_routing: if is_drum {
-                        [9; 8]
-                    } else {
-                        [0, 1, 2, 3, 4, 5, 6, 7]
-                    },
-                    channel_mute: [false; 8],
-                    instrument_name: "".to_string(),
-                },
+                create_default_source_parameter(
+                    &source_info,
+                    settings.a4_pitch_hz,
+                    &settings.template,
+                ),
+            );
+        }
+
+        // 同じ波形の音源を検出
+        detect_duplicate_sources(&mut infos);
+
+        // ステレオペアを検出
+        detect_stereo_pairs(&mut infos, &stereo_keyon_map);
+        for (srn, cached_source) in cached_sources.iter_mut() {
+            cached_source.stereo_pair_partner =
+                infos.get(srn).and_then(|info| info.stereo_pair_partner);
+        }
+
+        Ok(Some(SourceAnalysis {
+            infos: infos,
+            params: params,
+            beats_per_minute: beats_per_minute,
+            cached_sources: cached_sources,
+        }))
+    }
+
+    /// 音源解析の結果を音源情報とパラメータに反映
+    fn apply_source_analysis(&mut self, analysis: SourceAnalysis) {
+        *self.source_infos.write().unwrap() = analysis.infos;
+        *self.source_parameter.write().unwrap() = analysis.params;
+        let mut config = self.midi_output_configure.write().unwrap();
+        config.beats_per_minute = analysis.beats_per_minute;
+    }
+
+    /// 音源解析をバックグラウンドで開始
+    fn start_source_analysis(&mut self, spc_file: Box<SPCFile>, request: AnalysisRequest) {
+        let settings = self.source_analysis_settings();
+        let analyze_duration_sec = match &request {
+            AnalysisRequest::FileOpen {
+                analyze_duration_sec,
+                ..
+            } => *analyze_duration_sec,
+            AnalysisRequest::Reanalyze {
+                analyze_duration_sec,
+            } => *analyze_duration_sec,
+        };
+        self.start_background_operation(String::new(), move |cancel, progress| {
+            BackgroundResult::Analysis(
+                Self::analyze_sources(
+                    &settings,
+                    analyze_duration_sec,
+                    &spc_file.header.spc_register,
+                    &spc_file.ram,
+                    &spc_file.dsp_register,
+                    &cancel,
+                    &progress,
+                ),
+                request,
+            )
+        });
+    }
+
+    /// バックグラウンドの音源解析の結果を反映
+    fn finish_source_analysis(
+        &mut self,
+        result: Result<Option<SourceAnalysis>, String>,
+        request: AnalysisRequest,
+    ) -> Task<Message> {
+        let analysis = match result {
+            Ok(Some(analysis)) => analysis,
+            // キャンセルされた場合は何も変えない
+            Ok(None) => return Task::none(),
+            Err(e) => {
+                // 解析できなかったファイルは開かない
+                let (title, description) = match request {
+                    AnalysisRequest::FileOpen { .. } => {
+                        ("SPC File", format!("Failed to analyze the SPC file: {}", e))
+                    }
+                    AnalysisRequest::Reanalyze { .. } => (
+                        "Source Analysis",
+                        format!("Failed to analyze the sources: {}", e),
+                    ),
+                };
+                return Task::perform(show_message_dialog(title.to_string(), description), |_| {
+                    Message::MessageDialogClosed
+                });
+            }
+        };
+        match request {
+            AnalysisRequest::FileOpen {
+                path,
+                data,
+                spc_file,
+                analyze_duration_sec,
+                cache,
+            } => {
+                let cached_sources = analysis.cached_sources.clone();
+                self.apply_source_analysis(analysis);
+                // マッピングは解析し直した音源にだけ適用し、適用後のパラメータをキャッシュする
+                self.apply_instrument_mapping();
+                if let Some((cache_path, mut cache)) = cache {
+                    cache.sources = cached_sources;
+                    cache.beats_per_minute =
+                        self.midi_output_configure.read().unwrap().beats_per_minute;
+                    cache.source_parameter = self.source_parameter.read().unwrap().clone();
+                    if let Err(e) = save_analysis_cache(&cache_path, &cache) {
+                        eprintln!("ERROR: failed to save analysis cache: {:?}", e);
+                    }
+                }
+                self.open_analyzed_spc_file(path, data, spc_file, analyze_duration_sec)
+            }
+            AnalysisRequest::Reanalyze {
+                analyze_duration_sec,
+            } => {
+                self.apply_source_analysis(analysis);
+                self.release_source_signals();
+                self.apply_instrument_mapping();
+                self.notify_if_no_source(analyze_duration_sec)
+            }
+        }
+    }
+
+    /// 解析を終えたSPCファイルを開く
+    fn open_analyzed_spc_file(
+        &mut self,
+        path: PathBuf,
+        data: Vec<u8>,
+        spc_file: Box<SPCFile>,
+        analyze_duration_sec: u32,
+    ) -> Task<Message> {
+        // 解析中に再生を始めていれば止める
+        if self.stream_is_playing.load(Ordering::Relaxed) {
+            self.stream_play_stop().expect("Failed to stop play");
+        }
+        // 前のファイルでの再生位置の移動・SMFの再生は止める
+        self.cancel_song_seek();
+        self.stop_smf_playback(false);
+        self.spc_file = Some(spc_file.clone());
+        self.spc_file_data = Some(Arc::new(data));
+        self.release_source_signals();
+        // SPCを生成
+        self.pcm_spc = Some(Arc::new(Mutex::new(Box::new({
+            let mut spc = SPC::new();
+            spc.initialize(
+                &spc_file.header.spc_register,
+                &spc_file.ram,
+                &spc_file.dsp_register,
+            );
+            spc
+        }))));
+        self.midi_spc = Some(Arc::new(Mutex::new(Box::new({
+            let mut spc = SPC::new();
+            spc.initialize(
+                &spc_file.header.spc_register,
+                &spc_file.ram,
+                &spc_file.dsp_register,
             );
+            spc
+        }))));
+        // 再生サンプル数・MIDI出力サイズをリセット
+        self.stream_played_samples.store(0, Ordering::Relaxed);
+        self.midi_output_bytes.store(0, Ordering::Relaxed);
+        // メインウィンドウの表示更新
+        if let Some(window) = self.windows.get_mut(&self.main_window_id) {
+            let main_window: &mut MainWindow = window.as_mut().as_any_mut().downcast_mut().unwrap();
+            // ウィンドウタイトルに開いたファイル名を追記
+            main_window.title = format!(
+                "{} - {}",
+                main_window.base_title,
+                path.file_name().unwrap().to_str().unwrap()
+            );
+            // 全てのSRNを表示
+            main_window.showing_channel_srn_list = [true; 8];
+            main_window.song_overview = None;
+        }
+        // 出力時間をSPCの情報を元に設定
+        self.midi_output_configure
+            .write()
+            .unwrap()
+            .output_duration_msec = if spc_file.header.duration > 0 {
+            (spc_file.header.duration as u64) * 1000
+        } else {
+            DEFAULT_OUTPUT_DURATION_MSEC
+        };
+        self.spc_file_path = Some(path);
+        self.saved_source_parameter = self.source_parameter_snapshot();
+        // シークバーに表示する曲全体の波形を描画
+        self.start_song_overview_render();
+        // 音源が見つからなければ理由の候補を知らせる
+        self.notify_if_no_source(analyze_duration_sec)
+    }
+
+    /// 現在の解析条件で空の解析キャッシュを作成
+    fn create_analysis_cache(&self, content_hash: u64, analyze_duration_sec: u32) -> AnalysisCache {
+        let settings = self.source_analysis_settings();
+        AnalysisCache {
+            content_hash: content_hash,
+            analyze_duration_sec: analyze_duration_sec,
+            min_source_keyon_msec: settings.min_source_keyon_msec,
+            a4_pitch_hz: settings.a4_pitch_hz,
+            excluded_voices: settings.excluded_voices,
+            channel_mute_flags: settings.channel_mute_flags,
+            source_parameter_template: Some(settings.template),
+            beats_per_minute: DEFAULT_MIDI_BPM,
+            sources: BTreeMap::new(),
+            source_parameter: BTreeMap::new(),
+        }
+    }
+
+    /// キャッシュした解析結果から音源情報とパラメータを復元（波形・スペクトルはRAMから再計算）
+    fn restore_analysis(&mut self, cache: AnalysisCache, ram: &[u8]) {
+        let a4_pitch_hz = self.a4_pitch_hz();
+        let mut infos = self.source_infos.write().unwrap();
+        *infos = BTreeMap::new();
+        for (srn, source) in cache.sources.into_iter() {
+            if let Some(mut source_info) = decode_source(
+                ram,
+                source.dir_address,
+                source.using_channel,
+                source.keyon_pitches,
+            ) {
+                source_info.max_pitch_deviation = source.max_pitch_deviation;
+                source_info.uses_noise = source.uses_noise;
+                source_info.pitch_modulated = source.pitch_modulated;
+                source_info.alternate_dir_addresses = source.alternate_dir_addresses;
+                source_info.stereo_pair_partner = source.stereo_pair_partner;
+                source_info.detected_drum = estimate_drum_and_note(&source_info, a4_pitch_hz).0;
+                infos.insert(srn, source_info);
+            }
+        }
+        detect_duplicate_sources(&mut infos);
+        *self.source_parameter.write().unwrap() = cache.source_parameter;
+        let mut config = self.midi_output_configure.write().unwrap();
+        config.beats_per_minute = cache.beats_per_minute;
+    }
+
+    // 音源パラメータの変更箇所を記録（上限を超えたら古いものから捨てる）
+    fn log_parameter_changes(&mut self, srn_no: u8, previous: Option<SourceParameter>) {
+        let current = self.source_parameter.read().unwrap().get(&srn_no).cloned();
+        if let (Some(previous), Some(current)) = (previous, current) {
+            let mut log = self.parameter_log.write().unwrap();
+            for (field, old, new) in diff_json_fields(&previous, &current) {
+                log.push_back(ParameterLogEntry {
+                    srn_no: srn_no,
+                    field: field,
+                    old: old,
+                    new: new,
+                    applied: false,
+                });
+            }
+            while log.len() > MAX_PARAMETER_LOG_ENTRIES {
+                log.pop_front();
+            }
+        }
+    }
+
+    // 重複音源のリンクが有効であれば編集した音源のパラメータを重複音源にコピー
+    fn sync_linked_duplicates(&mut self, srn_no: u8) {
+        let infos = self.source_infos.read().unwrap();
+        let mut params = self.source_parameter.write().unwrap();
+        if let (Some(info), Some(param)) = (infos.get(&srn_no), params.get(&srn_no).cloned()) {
+            if !param.link_duplicates {
+                return;
+            }
+            for duplicate in info.duplicates.iter() {
+                if let Some(duplicate_param) = params.get_mut(duplicate) {
+                    // ラベルは音源ごとに残す
+                    let label = std::mem::take(&mut duplicate_param.label);
+                    *duplicate_param = param.clone();
+                    duplicate_param.label = label;
+                }
+            }
+        }
+    }
+
+    // 解析で検出されなかった音源をSRN指定で追加
+    fn add_source(&mut self, srn_no: u8) -> Result<(), String> {
+        let spc_file = self
+            .spc_file
+            .as_ref()
+            .ok_or("No SPC file is loaded".to_string())?;
+        if self.source_infos.read().unwrap().contains_key(&srn_no) {
+            return Err(format!("SRN {} already exists", srn_no));
+        }
+        let ram = &spc_file.ram;
+        let brr_dir_base_address = (spc_file.dsp_register[DSP_ADDRESS_DIR as usize] as usize) << 8;
+        let dir_address = brr_dir_base_address + 4 * (srn_no as usize);
+        // 発音チャンネルは不明なので全チャンネルで使われうるとする
+        let mut source_info = decode_source(ram, dir_address, [true; 8], vec![]).ok_or(format!(
+            "SRN {} has invalid directory entry at 0x{:X}",
+            srn_no, dir_address
+        ))?;
+        source_info.detected_drum = estimate_drum_and_note(&source_info, self.a4_pitch_hz()).0;
+        let param = create_default_source_parameter(
+            &source_info,
+            self.a4_pitch_hz(),
+            &self.source_parameter_template.read().unwrap(),
+        );
+        // 重複検出には全音源の波形が必要
+        let mut infos = self.decoded_source_infos();
+        infos.insert(srn_no, source_info);
+        detect_duplicate_sources(&mut infos);
+        *self.source_infos.write().unwrap() = infos;
+        self.source_parameter.write().unwrap().insert(srn_no, param);
+        self.release_source_signals();
+        Ok(())
+    }
+
+    // 音源情報を取得（波形を解放している場合はRAMからデコードし直す）
+    fn decoded_source_info(&self, srn_no: u8) -> Option<SourceInformation> {
+        let mut info = self.source_infos.read().unwrap().get(&srn_no).cloned()?;
+        if info.signal.is_empty() {
+            if let Some(spc_file) = &self.spc_file {
+                if let Some(decoded) =
+                    decode_source(&spc_file.ram, info.dir_address, [true; 8], vec![])
+                {
+                    info.signal = decoded.signal;
+                    info.power_spectrum = decoded.power_spectrum;
+                }
+            }
+        }
+        Some(info)
+    }
+
+    // 波形をデコードした全音源情報を取得
+    fn decoded_source_infos(&self) -> BTreeMap<u8, SourceInformation> {
+        let srns: Vec<u8> = self.source_infos.read().unwrap().keys().cloned().collect();
+        srns.into_iter()
+            .filter_map(|srn| self.decoded_source_info(srn).map(|info| (srn, info)))
+            .collect()
+    }
+
+    // 必要時デコードが有効なら常駐している波形・スペクトルを解放
+    fn release_source_signals(&self) {
+        if !self.lazy_source_decoding.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut infos = self.source_infos.write().unwrap();
+        for info in infos.values_mut() {
+            info.signal = Vec::new();
+            info.power_spectrum = Vec::new();
         }
     }
 
@@ -1468,19 +4192,23 @@ impl App {
     fn find_first_midi_event_time(
         config: &MIDIOutputConfigure,
         spc: &mut spc700::spc::SPC<spc700::mididsp::MIDIDSP>,
+        preroll_nanosec: u64,
     ) -> u64 {
         let spc_64k_hz_cycle = config.spc_clockup_factor * CLOCK_TICK_CYCLE_64KHZ;
         let mut first_event_time_nanosec = 0;
         let mut cycle_count = 0;
 
-        while first_event_time_nanosec < config.output_duration_msec * 1000_000 {
+        while first_event_time_nanosec < preroll_nanosec + config.output_duration_msec * 1000_000 {
             // 64kHzタイマーティックするまで処理
             while cycle_count < spc_64k_hz_cycle {
                 cycle_count += spc.execute_step() as u32;
             }
             cycle_count -= spc_64k_hz_cycle;
+            // プリロール中のイベントは数えない
             if let Some(_) = spc.clock_tick_64k_hz() {
-                return first_event_time_nanosec;
+                if first_event_time_nanosec >= preroll_nanosec {
+                    return first_event_time_nanosec;
+                }
             }
             // 先に時間を進めると最初のイベントを見逃すのでここで時間を増加
             first_event_time_nanosec += CLOCK_TICK_CYCLE_64KHZ_NANOSEC;
@@ -1490,11 +4218,17 @@ impl App {
     }
 
     // トラックに指定時間分のMIDIイベントを出力
+    // 出力中にノートオンされたパラメータの無い（解析で見つからなかった）音源番号を記録する
+    #[allow(clippy::too_many_arguments)]
     fn dump_midi_events_to_track(
         config: &MIDIOutputConfigure,
-        first_event_time_nanosec: u64,
+        source_params: &BTreeMap<u8, SourceParameter>,
+        options: &SMFExportOptions,
+        start_time_nanosec: u64,
+        cancel: &AtomicBool,
         spc: &mut spc700::spc::SPC<spc700::mididsp::MIDIDSP>,
         track: &mut rimd::Track,
+        unanalyzed_sources: &mut Vec<u8>,
     ) {
         // ナノ秒当たりのティック数
         let ticks_per_nanosec =
@@ -1502,22 +4236,68 @@ impl App {
         let spc_64k_hz_cycle = config.spc_clockup_factor * CLOCK_TICK_CYCLE_64KHZ;
         let mut previous_elapsed_ticks = 0;
         let mut cycle_count = 0;
+        let mut velocity_scaler = KeyOnVelocityScaler::new();
+        let mut echo_send_scaler = EchoSendScaler::new(options.master_reverb_send);
+        // 一律のベロシティで出力する場合は補正しない
+        let mut velocity_calibrator = options
+            .velocity_calibration
+            .filter(|_| !config.flatten_velocity)
+            .map(ProgramVelocityCalibrator::new);
+        let mut pan_law_mapper = PanLawMapper::new();
+        let mut note_range_folder = NoteRangeFolder::new();
+        let mut sustain_inserter = SustainPedalInserter::new();
+        let mut source_marker = SourceMarkerInserter::new();
+        let mut percussive_router = PercussiveNoteRouter::new();
+        let mut polyphony_limiter = PolyphonyLimiter::new(config.max_polyphony_per_channel);
 
-        // 最初のイベント発生時刻まで空回し
+        // 出力開始時刻まで空回し
+        // 途中から出力する場合に備えて、それまでに送られたチャンネルの状態を記録しておく
+        let mut state_recorder = ChannelStateRecorder::new();
         let mut total_elapsed_time_nanosec = 0;
-        while total_elapsed_time_nanosec < first_event_time_nanosec {
+        while total_elapsed_time_nanosec < start_time_nanosec {
             // 64kHzタイマーティックするまで処理
             while cycle_count < spc_64k_hz_cycle {
                 cycle_count += spc.execute_step() as u32;
             }
             cycle_count -= spc_64k_hz_cycle;
-            let _ = spc.clock_tick_64k_hz();
+            if let Some(out) = spc.clock_tick_64k_hz() {
+                echo_send_scaler.update(&spc.dsp, source_params);
+                pan_law_mapper.update(&spc.dsp, source_params);
+                for i in 0..out.num_messages {
+                    let msg = out.messages[i];
+                    let mut data = msg.data[..msg.length].to_vec();
+                    echo_send_scaler.apply(&mut data);
+                    pan_law_mapper.apply(&mut data);
+                    state_recorder.record(&data);
+                    // 出力開始時点のプログラムを追跡しておく
+                    if let Some(calibrator) = velocity_calibrator.as_mut() {
+                        calibrator.apply(&mut data);
+                    }
+                }
+            }
             // clock_tick_64k_hz実行後に64KHz周期がすぎるので、ここで時間を増加
             total_elapsed_time_nanosec += CLOCK_TICK_CYCLE_64KHZ_NANOSEC;
         }
 
+        // 出力開始時点のチャンネルの状態を復元
+        // 以降の最初のメッセージはランニングステータスが崩れないようステータスバイトを付ける
+        let running_status = state_recorder.status_byte;
+        let restore_messages = state_recorder.restore_messages();
+        let mut force_status = !restore_messages.is_empty();
+        for data in restore_messages {
+            track.events.push(TrackEvent {
+                vtime: 0,
+                event: MidiEvent::Midi(MidiMessage::from_bytes(data)),
+            });
+        }
+
         total_elapsed_time_nanosec = 0;
+        let mut previous_noteon = spc.dsp.read_register(&[0u8], DSP_ADDRESS_NOTEON);
         while total_elapsed_time_nanosec < config.output_duration_msec * 1000_000 {
+            // キャンセルされたら出力を打ち切る
+            if cancel.load(Ordering::Relaxed) {
+                return;
+            }
             // 64kHzタイマーティックするまで処理
             while cycle_count < spc_64k_hz_cycle {
                 cycle_count += spc.execute_step() as u32;
@@ -1531,20 +4311,90 @@ impl App {
                 let total_elapsed_ticks =
                     ((total_elapsed_time_nanosec as f64) * ticks_per_nanosec).round() as u64;
                 let delta_ticks = total_elapsed_ticks - previous_elapsed_ticks;
+                // 新たにノートオンされたチャンネルの音源番号を調べる
+                let noteon = spc.dsp.read_register(&[0u8], DSP_ADDRESS_NOTEON);
+                let keyon = noteon & !previous_noteon;
+                for ch in (0..8).filter(|ch| (keyon >> ch) & 1 != 0) {
+                    let srn_no = spc
+                        .dsp
+                        .read_register(&[0u8], DSP_ADDRESS_V0SRCN | ((ch as u8) << 4));
+                    if !source_params.contains_key(&srn_no) && !unanalyzed_sources.contains(&srn_no)
+                    {
+                        unanalyzed_sources.push(srn_no);
+                    }
+                }
+                previous_noteon = noteon;
+                velocity_scaler.update(&spc.dsp, source_params, config.flattened_velocity());
+                echo_send_scaler.update(&spc.dsp, source_params);
+                pan_law_mapper.update(&spc.dsp, source_params);
+                note_range_folder.update(&spc.dsp, source_params);
+                sustain_inserter.update(&spc.dsp, source_params);
+                if config.route_percussive_notes {
+                    percussive_router.update(&spc.dsp, source_params);
+                }
+                if config.insert_source_markers {
+                    source_marker.update(&spc.dsp, source_params);
+                }
                 // メッセージ追記
+                let mut vtime = delta_ticks;
                 for i in 0..out.num_messages {
                     let msg = out.messages[i];
-                    track.events.push(TrackEvent {
-                        vtime: if i == 0 { delta_ticks } else { 0 },
-                        event: MidiEvent::Midi(MidiMessage::from_bytes(
-                            msg.data[..msg.length].to_vec(),
-                        )),
-                    });
+                    let mut data = msg.data[..msg.length].to_vec();
+                    if force_status && !data.is_empty() {
+                        if (data[0] & 0x80) == 0 {
+                            data.insert(0, running_status);
+                        }
+                        force_status = false;
+                    }
+                    velocity_scaler.apply(&mut data);
+                    if let Some(calibrator) = velocity_calibrator.as_mut() {
+                        calibrator.apply(&mut data);
+                    }
+                    echo_send_scaler.apply(&mut data);
+                    pan_law_mapper.apply(&mut data);
+                    note_range_folder.apply(&mut data);
+                    if config.route_percussive_notes {
+                        percussive_router.apply(&mut data);
+                    }
+                    for data in polyphony_limiter
+                        .process(data)
+                        .into_iter()
+                        .flat_map(|data| {
+                            sustain_inserter
+                                .process(&data)
+                                .unwrap_or_else(|| vec![data])
+                        })
+                    {
+                        // 音源の最初のノートオンの直前にマーカーを挿入
+                        if config.insert_source_markers {
+                            if let Some(marker) = source_marker.process(&data, source_params) {
+                                track.events.push(TrackEvent {
+                                    vtime: vtime,
+                                    event: MidiEvent::Meta(MetaEvent::marker_text(marker)),
+                                });
+                                vtime = 0;
+                            }
+                        }
+                        track.events.push(TrackEvent {
+                            vtime: vtime,
+                            event: MidiEvent::Midi(MidiMessage::from_bytes(data)),
+                        });
+                        vtime = 0;
+                    }
                 }
-                previous_elapsed_ticks = total_elapsed_ticks;
+                // 何も出力しなかった場合は差分ティックを次のイベントに持ち越す
+                previous_elapsed_ticks = total_elapsed_ticks - vtime;
             }
         }
 
+        // 踏まれたままのサステインペダルを離す
+        for data in sustain_inserter.release_all() {
+            track.events.push(TrackEvent {
+                vtime: 0,
+                event: MidiEvent::Midi(MidiMessage::from_bytes(data)),
+            });
+        }
+
         // 終端でノートオフが漏れているチャンネルにノートオフを送信
         {
             /// MIDIメッセージ：ノートオン
@@ -1594,911 +4444,7067 @@ impl App {
                 });
             }
         }
+
+        // 同じティックでの再発音を聞こえるように並べ替え
+        separate_retriggered_notes(track);
+
+        // 短いノートを除去
+        if config.min_note_duration_msec > 0 {
+            let min_duration_nanosec = config.min_note_duration_msec as f64 * 1000_000.0;
+            let min_ticks = (min_duration_nanosec * ticks_per_nanosec).round() as u64;
+            remove_short_notes(track, min_ticks);
+        }
     }
 
-    // SMFを作成
-    pub fn create_smf(&self) -> Option<SMF> {
-        if let Some(spc_file) = &self.spc_file {
-            let config = self.midi_output_configure.read().unwrap();
-            let params = self.source_parameter.read().unwrap();
+    // SMFを作成（出力範囲の指定が不正な場合は全体を出力）
+    pub fn create_smf(&self) -> Result<SMF, String> {
+        let range_msec = self.export_range_msec().unwrap_or(None);
+        let config = self.midi_output_configure.read().unwrap();
+        self.create_smf_with_config(&config, range_msec)
+    }
 
-            let mut smf = SMF {
-                format: SMFFormat::MultiTrack,
-                tracks: Vec::new(),
-                division: config.ticks_per_quarter as i16,
+    // 出力時間が閾値を超える書き出しであれば確認ダイアログのタスクを返す（確認済み・不要ならNone）
+    fn confirm_long_export(
+        &mut self,
+        message: Message,
+        range_msec: Option<(u64, u64)>,
+        is_smf: bool,
+    ) -> Option<Task<Message>> {
+        if std::mem::take(&mut self.long_export_confirmed) {
+            return None;
+        }
+        let spc_file = self.spc_file.as_ref()?;
+        let config = self.midi_output_configure.read().unwrap().clone();
+        let duration_msec = match range_msec {
+            Some((start_msec, end_msec)) => end_msec - start_msec,
+            None => config.output_duration_msec,
+        } + self.export_preroll_msec.load(Ordering::Relaxed) as u64;
+        let threshold_sec = self.long_export_warning_sec.load(Ordering::Relaxed);
+        if threshold_sec == 0 || duration_msec <= threshold_sec as u64 * 1000 {
+            return None;
+        }
+        // SMFはMIDIチャンネル（トラック）ごとにエミュレーションし直す
+        let num_passes = if is_smf {
+            let params = self.source_parameter.read().unwrap();
+            let num_channels = (0..16u8)
+                .filter(|ch| {
+                    params
+                        .values()
+                        .any(|param| param.channel_routing.contains(ch))
+                })
+                .count();
+            let num_drum_tracks = if config.split_drum_into_separate_tracks {
+                params
+                    .values()
+                    .filter(|param| (param.program.clone() as u8) >= 0x80)
+                    .count()
+            } else {
+                0
             };
+            num_channels + num_drum_tracks
+        } else {
+            1
+        };
+        let render_sec = measure_emulation_sec_per_song_sec(spc_file, &config)
+            * (duration_msec as f32 / 1000.0)
+            * num_passes.max(1) as f32;
+        Some(Task::perform(
+            confirm_long_export(duration_msec, render_sec),
+            move |confirmed| {
+                if confirmed {
+                    Message::LongExportConfirmed(Box::new(message.clone()))
+                } else {
+                    Message::MessageDialogClosed
+                }
+            },
+        ))
+    }
 
-            // SPCの作成
-            let mut spc: spc700::spc::SPC<spc700::mididsp::MIDIDSP> = SPC::new();
+    // 指定した出力設定・範囲でSMFを作成
+    fn create_smf_with_config(
+        &self,
+        config: &MIDIOutputConfigure,
+        range_msec: Option<(u64, u64)>,
+    ) -> Result<SMF, String> {
+        let spc_file = self
+            .spc_file
+            .as_ref()
+            .ok_or("No SPC file is loaded".to_string())?;
+        validate_spc_ram(&spc_file.ram)?;
+        let params = self.source_parameter.read().unwrap();
+        let options = SMFExportOptions {
+            stretch_target_msec: None,
+            master_reverb_send: self.master_reverb_send.load(Ordering::Relaxed),
+            range_msec: range_msec,
+            preroll_msec: self.export_preroll_msec.load(Ordering::Relaxed) as u64,
+            velocity_calibration: self.export_velocity_calibration(config),
+        };
+        Self::build_smf(
+            spc_file,
+            config,
+            &params,
+            &options,
+            &AtomicBool::new(false),
+            &AtomicUsize::new(0),
+        )
+        .map(|(smf, _)| smf)
+        .ok_or("SMF generation was cancelled".to_string())
+    }
 
-            smf.tracks.push(Track {
-                copyright: None,
-                name: Some(String::from_utf8_lossy(&spc_file.header.music_title).to_string()),
-                events: Vec::new(),
-            });
+    // 出力に使うプログラムごとのベロシティ補正率（補正が無効ならNone）
+    fn export_velocity_calibration(&self, config: &MIDIOutputConfigure) -> Option<[u8; 128]> {
+        if !config.calibrate_velocity_by_program {
+            return None;
+        }
+        let table = self.program_velocity_calibration.read().unwrap();
+        let mut percent = [100u8; 128];
+        for (dst, src) in percent.iter_mut().zip(table.iter()) {
+            *dst = (*src).clamp(MIN_PROGRAM_VELOCITY_PERCENT, MAX_PROGRAM_VELOCITY_PERCENT);
+        }
+        Some(percent)
+    }
 
-            // メタイベントの設定
-            // MIDIシステムの設定
-            let sysex_msg = match config.midi_system {
-                MIDISystem::NONE => None,
-                MIDISystem::GMLevel1 => Some(MIDIMSG_SYSEX_GMLEVEL1_SYSTEM_ON.to_vec()),
-                MIDISystem::GMLevel2 => Some(MIDIMSG_SYSEX_GMLEVEL2_SYSTEM_ON.to_vec()),
-                MIDISystem::GS => Some(MIDIMSG_SYSEX_GS_RESET.to_vec()),
-                MIDISystem::XG => Some(MIDIMSG_SYSEX_XG_SYSTEM_ON.to_vec()),
-            };
-            if let Some(mut sysex) = sysex_msg {
-                // System Exclusiveのサイズを付加
-                sysex.insert(1, sysex.len() as u8 - 1u8);
+    // 出力範囲の指定を検証して返す（範囲を指定しない場合はNone）
+    fn export_range_msec(&self) -> Result<Option<(u64, u64)>, String> {
+        if !self.export_range_enabled.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let start_sec = f32::from_bits(self.export_range_start_sec.load(Ordering::Relaxed));
+        let end_sec = f32::from_bits(self.export_range_end_sec.load(Ordering::Relaxed));
+        let start_msec = (start_sec * 1000.0).round() as u64;
+        let end_msec = (end_sec * 1000.0).round() as u64;
+        let duration_msec = self
+            .midi_output_configure
+            .read()
+            .unwrap()
+            .output_duration_msec;
+        if start_msec < end_msec && end_msec <= duration_msec {
+            Ok(Some((start_msec, end_msec)))
+        } else {
+            Err(format!(
+                "The export range ({:.1} - {:.1} sec) is invalid: the start must be before the end, and the end must not exceed the Output Duration ({:.1} sec).",
+                start_sec,
+                end_sec,
+                duration_msec as f32 / 1000.0
+            ))
+        }
+    }
+
+    // SMFと出力範囲で鳴った解析で見つからなかった音源番号を作成（キャンセルされた場合はNone、進捗は1000分率で通知）
+    fn build_smf(
+        spc_file: &SPCFile,
+        config: &MIDIOutputConfigure,
+        params: &BTreeMap<u8, SourceParameter>,
+        options: &SMFExportOptions,
+        cancel: &AtomicBool,
+        progress: &AtomicUsize,
+    ) -> Option<(SMF, Vec<u8>)> {
+        let master_reverb_send = options.master_reverb_send;
+        // 範囲を指定した場合はその長さだけ出力する
+        let mut ranged_config = config.clone();
+        if let Some((start_msec, end_msec)) = options.range_msec {
+            ranged_config.output_duration_msec = end_msec - start_msec;
+        }
+        let config = &ranged_config;
+
+        let mut smf = SMF {
+            format: SMFFormat::MultiTrack,
+            tracks: Vec::new(),
+            division: config.ticks_per_quarter as i16,
+        };
+
+        // SPCの作成
+        let mut spc: spc700::spc::SPC<spc700::mididsp::MIDIDSP> = SPC::new();
+
+        smf.tracks.push(Track {
+            copyright: None,
+            name: Some(String::from_utf8_lossy(&spc_file.header.music_title).to_string()),
+            events: Vec::new(),
+        });
+
+        // メタイベントの設定
+        // MIDIシステムの設定
+        let sysex_msg = match config.midi_system {
+            MIDISystem::NONE => None,
+            MIDISystem::GMLevel1 => Some(MIDIMSG_SYSEX_GMLEVEL1_SYSTEM_ON.to_vec()),
+            MIDISystem::GMLevel2 => Some(MIDIMSG_SYSEX_GMLEVEL2_SYSTEM_ON.to_vec()),
+            MIDISystem::GS => Some(MIDIMSG_SYSEX_GS_RESET.to_vec()),
+            MIDISystem::XG => Some(MIDIMSG_SYSEX_XG_SYSTEM_ON.to_vec()),
+        };
+        if let Some(mut sysex) = sysex_msg {
+            // System Exclusiveのサイズを付加
+            sysex.insert(1, sysex.len() as u8 - 1u8);
+            smf.tracks[0].events.push(TrackEvent {
+                vtime: 0,
+                event: MidiEvent::Midi(MidiMessage::from_bytes(sysex)),
+            });
+        }
+        // ドラムキットの選択
+        for msg in drum_kit_select_messages(&config.midi_system, config.drum_kit) {
+            smf.tracks[0].events.push(TrackEvent {
+                vtime: 0,
+                event: MidiEvent::Midi(MidiMessage::from_bytes(msg)),
+            });
+        }
+        // テンポ
+        let quarter_usec = stretched_quarter_usec(
+            config.beats_per_minute,
+            config.output_duration_msec,
+            options.stretch_target_msec,
+        );
+        smf.tracks[0].events.push(TrackEvent {
+            vtime: 0,
+            event: MidiEvent::Meta(MetaEvent::tempo_setting(quarter_usec)),
+        });
+        // SPCの曲情報
+        if config.embed_spc_metadata {
+            for text in create_spc_metadata_texts(spc_file) {
                 smf.tracks[0].events.push(TrackEvent {
                     vtime: 0,
-                    event: MidiEvent::Midi(MidiMessage::from_bytes(sysex)),
+                    event: MidiEvent::Meta(MetaEvent::text_event(text)),
                 });
             }
-            // テンポ
-            let quarter_usec = (60_000_000.0 / config.beats_per_minute) as u32;
-            smf.tracks[0].events.push(TrackEvent {
-                vtime: 0,
-                event: MidiEvent::Meta(MetaEvent::tempo_setting(quarter_usec)),
-            });
+        }
+        // SPCのコメントを歌詞として先頭に置く
+        if config.embed_spc_comment_as_lyric {
+            let comment = sanitize_smf_text(&spc_header_text(&spc_file.header.comment));
+            if !comment.is_empty() {
+                smf.tracks[0].events.push(TrackEvent {
+                    vtime: 0,
+                    event: MidiEvent::Meta(MetaEvent::lyric_text(comment)),
+                });
+            }
+        }
 
-            // トラック全体で発生する最初のイベント時刻を探索
-            let first_event_time_nanosec = if config.trim_leading_nonevents_period {
-                // SPC初期化・パラメータ設定
-                spc.initialize(
-                    &spc_file.header.spc_register,
-                    &spc_file.ram,
-                    &spc_file.dsp_register,
-                );
-                apply_source_parameter(&mut spc, &config, &params, &spc_file.ram);
+        // トラック全体で発生する最初のイベント時刻を探索
+        // 範囲を指定した場合は先頭の無音区間の除去より優先する
+        // プリロール中に送られたプログラム・コントローラの設定は出力開始時に復元される
+        let preroll_nanosec = options.preroll_msec * 1000_000;
+        let start_time_nanosec = if let Some((start_msec, _)) = options.range_msec {
+            preroll_nanosec + start_msec * 1000_000
+        } else if config.trim_leading_nonevents_period {
+            // SPC初期化・パラメータ設定
+            spc.initialize(
+                &spc_file.header.spc_register,
+                &spc_file.ram,
+                &spc_file.dsp_register,
+            );
+            apply_source_parameter(&mut spc, config, params, &spc_file.ram);
+            spc.dsp
+                .write_register(&[0u8], DSP_ADDRESS_CHANNEL_MUTE, config.excluded_voices);
 
-                Self::find_first_midi_event_time(&config, &mut spc)
-            } else {
-                0
-            };
+            Self::find_first_midi_event_time(config, &mut spc, preroll_nanosec)
+        } else {
+            preroll_nanosec
+        };
 
-            // MIDIチャンネルごとに出力
-            for midi_ch in 0..16 {
-                // ドラム音色をトラックに分ける場合はいったんスキップ
-                if midi_ch == 9 && config.split_drum_into_separate_tracks {
-                    continue;
-                }
+        // 進捗計算のための出力トラック数
+        let num_drum_tracks = if config.split_drum_into_separate_tracks {
+            params
+                .values()
+                .filter(|param| (param.program.clone() as u8) >= 0x80)
+                .count()
+        } else {
+            0
+        };
+        let num_total_tracks = 16 + num_drum_tracks;
+        let mut num_processed_tracks = 0;
+        let mut unanalyzed_sources = vec![];
+        progress.store(0, Ordering::Relaxed);
 
-                let mut track = Track {
-                    copyright: None,
-                    name: None,
-                    events: Vec::new(),
-                };
+        // MIDIチャンネルごとに出力
+        for midi_ch in 0..16 {
+            num_processed_tracks += 1;
+            progress.store(
+                (1000 * num_processed_tracks) / num_total_tracks,
+                Ordering::Relaxed,
+            );
+            if cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+            // ドラム音色をトラックに分ける場合はいったんスキップ
+            if midi_ch == 9 && config.split_drum_into_separate_tracks {
+                continue;
+            }
 
-                // SPC初期化
-                spc.initialize(
-                    &spc_file.header.spc_register,
-                    &spc_file.ram,
-                    &spc_file.dsp_register,
-                );
+            let mut track = Track {
+                copyright: None,
+                name: None,
+                events: Vec::new(),
+            };
 
-                // パラメータ適用
-                apply_source_parameter(&mut spc, &config, &params, &spc_file.ram);
+            // SPC初期化
+            spc.initialize(
+                &spc_file.header.spc_register,
+                &spc_file.ram,
+                &spc_file.dsp_register,
+            );
 
-                // 出力先チャンネルがmidi_ch以外になっているルーティングをミュート
-                let mut track_names = vec![];
-                let mut exist_routing_in_track = false;
-                for (srn_no, param) in params.iter() {
-                    let mut exist_routing = false;
-                    for ch in 0..8 {
-                        if param.channel_routing[ch] != midi_ch {
-                            let value = 0x80 | ((ch << 4) as u8) | param.channel_routing[ch];
-                            spc.dsp
-                                .write_register(&[0u8], DSP_ADDRESS_SRN_TARGET, *srn_no);
-                            spc.dsp
-                                .write_register(&[0u8], DSP_ADDRESS_SRN_CHANNEL_ROUTING, value);
-                        } else {
-                            exist_routing = true;
-                        }
-                    }
-                    if exist_routing {
-                        exist_routing_in_track = true;
-                        if param.instrument_name != "" {
-                            track_names.push(param.instrument_name.clone());
-                        }
-                    }
-                }
+            // パラメータ適用
+            apply_source_parameter(&mut spc, config, params, &spc_file.ram);
+            spc.dsp
+                .write_register(&[0u8], DSP_ADDRESS_CHANNEL_MUTE, config.excluded_voices);
 
-                // トラックに出力
-                if exist_routing_in_track {
-                    // トラックに含まれる名前を連結してメタイベントに登録
-                    if track_names.len() > 0 {
-                        track.events.push(TrackEvent {
-                            vtime: 0,
-                            event: MidiEvent::Meta(MetaEvent::sequence_or_track_name(
-                                track_names.join("/"),
-                            )),
-                        });
+            // 出力先チャンネルがmidi_ch以外になっているルーティングをミュート
+            let mut track_names = vec![];
+            let mut exist_routing_in_track = false;
+            for (srn_no, param) in params.iter() {
+                let mut exist_routing = false;
+                for ch in 0..8 {
+                    if param.channel_routing[ch] != midi_ch {
+                        let value = 0x80 | ((ch << 4) as u8) | param.channel_routing[ch];
+                        spc.dsp
+                            .write_register(&[0u8], DSP_ADDRESS_SRN_TARGET, *srn_no);
+                        spc.dsp
+                            .write_register(&[0u8], DSP_ADDRESS_SRN_CHANNEL_ROUTING, value);
+                    } else {
+                        exist_routing = true;
                     }
-                    Self::dump_midi_events_to_track(
-                        &config,
-                        first_event_time_nanosec,
-                        &mut spc,
-                        &mut track,
-                    );
-                    if track.events.len() > 0 {
-                        smf.tracks.push(track);
+                }
+                if exist_routing {
+                    exist_routing_in_track = true;
+                    if param.instrument_name != "" {
+                        track_names.push(param.instrument_name.clone());
                     }
                 }
             }
 
-            // ドラム音色をサンプル単位でトラックに分割
-            if config.split_drum_into_separate_tracks {
-                for (srn_no, param) in params.iter() {
-                    if (param.program.clone() as u8) >= 0x80 {
-                        let mut track = Track {
-                            copyright: None,
-                            name: None,
-                            events: Vec::new(),
-                        };
-
-                        // SPC初期化
-                        spc.initialize(
-                            &spc_file.header.spc_register,
-                            &spc_file.ram,
-                            &spc_file.dsp_register,
-                        );
+            // トラックに出力
+            if exist_routing_in_track {
+                // トラックに含まれる名前を連結してメタイベントに登録
+                if track_names.len() > 0 {
+                    track.events.push(TrackEvent {
+                        vtime: 0,
+                        event: MidiEvent::Meta(MetaEvent::sequence_or_track_name(
+                            track_names.join("/"),
+                        )),
+                    });
+                }
+                Self::dump_midi_events_to_track(
+                    config,
+                    params,
+                    options,
+                    start_time_nanosec,
+                    cancel,
+                    &mut spc,
+                    &mut track,
+                    &mut unanalyzed_sources,
+                );
+                if !track.events.is_empty() {
+                    // 出力開始時にマスターリバーブセンドを設定
+                    track
+                        .events
+                        .insert(0, master_reverb_send_event(midi_ch, master_reverb_send));
+                    smf.tracks.push(track);
+                }
+            }
+        }
 
-                        // パラメータ適用
-                        apply_source_parameter(&mut spc, &config, &params, &spc_file.ram);
+        // ドラム音色をサンプル単位でトラックに分割
+        if config.split_drum_into_separate_tracks {
+            for (srn_no, param) in params.iter() {
+                if (param.program.clone() as u8) >= 0x80 {
+                    num_processed_tracks += 1;
+                    progress.store(
+                        (1000 * num_processed_tracks) / num_total_tracks,
+                        Ordering::Relaxed,
+                    );
+                    if cancel.load(Ordering::Relaxed) {
+                        return None;
+                    }
+                    let mut track = Track {
+                        copyright: None,
+                        name: None,
+                        events: Vec::new(),
+                    };
 
-                        // srn_no以外を全てミュート
-                        for (another_srn_no, _) in params.iter() {
-                            if another_srn_no != srn_no {
-                                spc.dsp.write_register(
-                                    &[0u8],
-                                    DSP_ADDRESS_SRN_TARGET,
-                                    *another_srn_no,
-                                );
-                                spc.dsp.write_register(&[0u8], DSP_ADDRESS_SRN_FLAG, 0x80);
-                            }
-                        }
+                    // SPC初期化
+                    spc.initialize(
+                        &spc_file.header.spc_register,
+                        &spc_file.ram,
+                        &spc_file.dsp_register,
+                    );
 
-                        // トラック名があれば追加
-                        if param.instrument_name != "" {
-                            track.events.push(TrackEvent {
-                                vtime: 0,
-                                event: MidiEvent::Meta(MetaEvent::sequence_or_track_name(
-                                    param.instrument_name.clone(),
-                                )),
-                            });
+                    // パラメータ適用
+                    apply_source_parameter(&mut spc, config, params, &spc_file.ram);
+                    spc.dsp.write_register(
+                        &[0u8],
+                        DSP_ADDRESS_CHANNEL_MUTE,
+                        config.excluded_voices,
+                    );
+
+                    // srn_no以外を全てミュート
+                    for (another_srn_no, _) in params.iter() {
+                        if another_srn_no != srn_no {
+                            spc.dsp.write_register(
+                                &[0u8],
+                                DSP_ADDRESS_SRN_TARGET,
+                                *another_srn_no,
+                            );
+                            spc.dsp.write_register(&[0u8], DSP_ADDRESS_SRN_FLAG, 0x80);
                         }
+                    }
+
+                    // トラック名があれば追加
+                    if param.instrument_name != "" {
+                        track.events.push(TrackEvent {
+                            vtime: 0,
+                            event: MidiEvent::Meta(MetaEvent::sequence_or_track_name(
+                                param.instrument_name.clone(),
+                            )),
+                        });
+                    }
 
-                        // トラック生成
-                        Self::dump_midi_events_to_track(
-                            &config,
-                            first_event_time_nanosec,
-                            &mut spc,
-                            &mut track,
+                    // トラック生成
+                    Self::dump_midi_events_to_track(
+                        config,
+                        params,
+                        options,
+                        start_time_nanosec,
+                        cancel,
+                        &mut spc,
+                        &mut track,
+                        &mut unanalyzed_sources,
+                    );
+                    if !track.events.is_empty() {
+                        // 出力開始時にマスターリバーブセンドを設定
+                        track.events.insert(
+                            0,
+                            master_reverb_send_event(MIDI_DRUM_CHANNEL, master_reverb_send),
                         );
-                        if track.events.len() > 0 {
-                            smf.tracks.push(track);
-                        }
+                        smf.tracks.push(track);
                     }
                 }
             }
+        }
 
-            Some(smf)
-        } else {
-            None
+        // クリックトラック
+        if config.insert_click_track {
+            if let Some(track) = create_click_track(config, params) {
+                smf.tracks.push(track);
+            }
         }
-    }
 
-    // JSON生成
-    pub fn create_json(&self) -> serde_json::Value {
-        let config = self.midi_output_configure.read().unwrap();
-        let params = self.source_parameter.read().unwrap();
-        json!(ExportInformation {
-            tool_information: format!("{} Ver.{}", SPC2MIDI2_TITLE_STR, env!("CARGO_PKG_VERSION")),
-            midi_output_configure: config.clone(),
-            source_parameter: params.clone(),
-        })
-    }
+        if cancel.load(Ordering::Relaxed) {
+            return None;
+        }
 
-    // 再生開始
-    fn play_start(&mut self) -> Result<(), PlayStreamError> {
-        const NUM_CHANNELS: usize = 2;
+        unanalyzed_sources.sort();
+        Some((smf, unanalyzed_sources))
+    }
 
-        // SPCの参照をクローン
-        let (pcm_spc, midi_spc) =
-            if let (Some(pcm_spc_ref), Some(midi_spc_ref)) = (&self.pcm_spc, &self.midi_spc) {
-                (pcm_spc_ref.clone(), midi_spc_ref.clone())
-            } else {
-                return Ok(());
-            };
+    // 短縮した出力時間でSMFを作成し、出力されるメッセージの統計をまとめる
+    pub fn validate_smf(&self) -> Option<String> {
+        let config = {
+            let mut config = self.midi_output_configure.read().unwrap().clone();
+            config.output_duration_msec = config.output_duration_msec.min(VALIDATION_DURATION_MSEC);
+            config
+        };
+        let smf = self.create_smf_with_config(&config, None).ok()?;
 
-        // オーディオデバイスとMIDI出力ポートの存在確認
-        if self.stream_device.is_none() || self.stream_config.is_none() {
-            return Err(PlayStreamError::DeviceNotAvailable);
+        // ティックあたりの秒数
+        let sec_per_tick =
+            60.0 / (config.beats_per_minute as f64 * config.ticks_per_quarter as f64);
+        let mut num_events = 0;
+        let mut num_notes = 0;
+        let mut total_bytes = 0;
+        let mut used_channels = [false; 16];
+        // 1秒ごとの出力バイト数
+        let mut bytes_per_sec = vec![0usize; (config.output_duration_msec / 1000) as usize + 1];
+        for track in smf.tracks.iter() {
+            let mut elapsed_ticks = 0;
+            let mut status_byte = 0;
+            for e in track.events.iter() {
+                elapsed_ticks += e.vtime;
+                if let MidiEvent::Midi(msg) = &e.event {
+                    if msg.data.is_empty() || msg.data[0] == 0xF0 {
+                        continue;
+                    }
+                    // ランニングステータスの更新
+                    if (msg.data[0] & 0x80) != 0 {
+                        status_byte = msg.data[0];
+                    }
+                    num_events += 1;
+                    total_bytes += msg.data.len();
+                    used_channels[(status_byte & 0xF) as usize] = true;
+                    if (status_byte & 0xF0) == MIDIMSG_NOTE_ON && msg.data[msg.data.len() - 1] > 0 {
+                        num_notes += 1;
+                    }
+                    let sec = ((elapsed_ticks as f64 * sec_per_tick) as usize)
+                        .min(bytes_per_sec.len() - 1);
+                    bytes_per_sec[sec] += msg.data.len();
+                }
+            }
         }
-        let stream_device = self.stream_device.clone().unwrap();
-        let stream_config = self.stream_config.clone().unwrap();
 
-        let midi_out_conn = if let Some(midi_out_conn_ref) = &self.midi_out_conn {
-            midi_out_conn_ref.clone()
-        } else {
-            return Err(PlayStreamError::DeviceNotAvailable);
-        };
+        // スタート・ストップビットの2bitを加えて1バイト当たり10bit送るとする
+        let duration_sec = (config.output_duration_msec as f64 / 1000.0).max(1.0);
+        let average_bit_rate = (total_bytes as f64 * 10.0) / duration_sec;
+        let peak_bit_rate = *bytes_per_sec.iter().max().unwrap_or(&0) as f64 * 10.0;
+        let channels: Vec<_> = (0..16)
+            .filter(|&ch| used_channels[ch])
+            .map(|ch| ch.to_string())
+            .collect();
 
-        // リサンプラ初期化 32k -> デバイスの出力レート変換となるように
-        let latency = self.audio_output_latency_msec.load(Ordering::Relaxed) as f64 / 1000.0;
-        let (mut prod, mut cons) = fixed_resample::resampling_channel::<f32, NUM_CHANNELS>(
-            NonZero::new(NUM_CHANNELS).unwrap(),
-            SPC_SAMPLING_RATE,
-            stream_config.sample_rate,
-            fixed_resample::ResamplingChannelConfig {
-                latency_seconds: latency,
-                capacity_seconds: 3.0 * latency, // 2倍程度確保するべしとあるが、余裕を見て3倍
-                overflow_autocorrect_percent_threshold: None, // capacityを大きくするとOverflowと判定されやすくなるため判定を無効に
-                ..Default::default()
+        let mut result = format!(
+            "Validated the first {:.1} sec\n\nTracks: {}\nEvents: {}\nNotes: {}\nAverage Bit Rate: {:.2} kbps\nPeak Bit Rate: {:.2} kbps\nChannels: {}\n",
+            config.output_duration_msec as f32 / 1000.0,
+            smf.tracks.len(),
+            num_events,
+            num_notes,
+            average_bit_rate / 1000.0,
+            peak_bit_rate / 1000.0,
+            if channels.is_empty() {
+                "None".to_string()
+            } else {
+                channels.join(", ")
             },
         );
-
-        // SPCのミュートフラグ取得・設定
-        {
-            let flags = self.channel_mute_flags.load(Ordering::Relaxed);
-            let pcm_on = self.pcm_spc_on.load(Ordering::Relaxed);
-            let midi_on = self.midi_spc_on.load(Ordering::Relaxed);
-            let mut pcm_spc = pcm_spc.lock().unwrap();
-            let mut midi_spc = midi_spc.lock().unwrap();
-            pcm_spc.dsp.write_register(
-                &[0u8],
-                DSP_ADDRESS_CHANNEL_MUTE,
-                if pcm_on { flags } else { 0xFF },
-            );
-            midi_spc.dsp.write_register(
-                &[0u8],
-                DSP_ADDRESS_CHANNEL_MUTE,
-                if midi_on { flags } else { 0xFF },
-            );
+        // 設定ミスの可能性がある場合の警告
+        if num_notes == 0 {
+            result += "\nWARNING: No notes are emitted.";
         }
+        if channels.len() == 1 && self.source_parameter.read().unwrap().len() > 1 {
+            result += "\nWARNING: All sources are routed to a single channel.";
+        }
+        if peak_bit_rate > MIDI_MAX_BIT_RATE {
+            result += "\nWARNING: Peak bit rate exceeds the MIDI bandwidth (31.25 kbps).";
+        }
+        result += &self.pitch_bend_width_warnings();
+        result += &self.directory_change_warnings();
 
-        // SPCにパラメータ適用
-        self.apply_source_parameter();
-
-        // 再生済みサンプル数・MIDI出力サイズ
-        let played_samples = self.stream_played_samples.clone();
-        let midi_output_bytes = self.midi_output_bytes.clone();
+        Some(result)
+    }
 
-        // 再生ストリーム作成
-        let mut spc_cycle_count = 0;
-        let stream = match stream_device.build_output_stream(
-            &stream_config,
-            move |buffer: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let mut progress = played_samples.load(Ordering::Relaxed);
-                let buffer_num_samples = buffer.len() / NUM_CHANNELS;
+    // 短い出力時間でSMFを作成し、チャンネルごとのノート（ノート名・時刻・長さ）を一覧にする
+    pub fn summarize_midi_notes(&self) -> Option<String> {
+        let config = {
+            let mut config = self.midi_output_configure.read().unwrap().clone();
+            config.output_duration_msec = config
+                .output_duration_msec
+                .min(config.note_summary_duration_msec);
+            config
+        };
+        let smf = self.create_smf_with_config(&config, None).ok()?;
 
-                // バッファを出力サンプルで埋める
-                buffer.fill(0.0);
-                let mut buffer_progress = 0;
-                while buffer_progress < buffer_num_samples {
-                    // 入力キューがいっぱいになるまで出力計算
-                    if let Ok(mut spc) = pcm_spc.lock() {
-                        let nsamples = prod.available_frames();
-                        for _ in 0..nsamples {
-                            spc_cycle_count += spc.execute_step() as u32;
-                            if spc_cycle_count >= CLOCK_TICK_CYCLE_64KHZ {
-                                spc_cycle_count -= CLOCK_TICK_CYCLE_64KHZ;
-                                // PCM出力
-                                if let Some(pcm) = spc.clock_tick_64k_hz() {
-                                    prod.push_interleaved(&[
-                                        (pcm[0] as f32) * PCM_NORMALIZE_CONST,
-                                        (pcm[1] as f32) * PCM_NORMALIZE_CONST,
-                                    ]);
-                                }
+        // ティックあたりの秒数
+        let sec_per_tick =
+            60.0 / (config.beats_per_minute as f64 * config.ticks_per_quarter as f64);
+        // チャンネルごとの（開始時刻, ノート番号, 長さ, ベロシティ）
+        let mut channel_notes: BTreeMap<u8, Vec<(f64, u8, f64, u8)>> = BTreeMap::new();
+        for track in smf.tracks.iter() {
+            let mut elapsed_ticks = 0;
+            let mut status_byte = 0;
+            // 発音中のノート（チャンネル, ノート番号, 開始ティック, ベロシティ）
+            let mut noteons: Vec<(u8, u8, u64, u8)> = vec![];
+            for e in track.events.iter() {
+                elapsed_ticks += e.vtime;
+                if let MidiEvent::Midi(msg) = &e.event {
+                    if msg.data.is_empty() || msg.data[0] == 0xF0 {
+                        continue;
+                    }
+                    // ランニングステータスの更新
+                    if (msg.data[0] & 0x80) != 0 {
+                        status_byte = msg.data[0];
+                    }
+                    let data = &msg.data[msg.data.len().saturating_sub(2)..];
+                    if data.len() < 2 {
+                        continue;
+                    }
+                    let (ch, note, velocity) = (status_byte & 0xF, data[0], data[1]);
+                    match status_byte & 0xF0 {
+                        MIDIMSG_NOTE_ON if velocity > 0 => {
+                            noteons.push((ch, note, elapsed_ticks, velocity));
+                        }
+                        MIDIMSG_NOTE_ON | MIDIMSG_NOTE_OFF => {
+                            if let Some(pos) = noteons.iter().position(|n| n.0 == ch && n.1 == note)
+                            {
+                                let (_, _, start_ticks, velocity) = noteons.remove(pos);
+                                channel_notes.entry(ch).or_default().push((
+                                    start_ticks as f64 * sec_per_tick,
+                                    note,
+                                    (elapsed_ticks - start_ticks) as f64 * sec_per_tick,
+                                    velocity,
+                                ));
                             }
                         }
+                        _ => {}
                     }
-
-                    // リサンプラー出力を取り出してバッファに書き出し
-                    let num_outputs = (buffer_num_samples - buffer_progress)
-                        .min(cons.available_frames())
-                        .max(0);
-                    let status = cons.read_interleaved(
-                        &mut buffer[buffer_progress * NUM_CHANNELS
-                            ..(buffer_progress + num_outputs) * NUM_CHANNELS],
-                    );
-                    if let ReadStatus::UnderflowOccurred { .. } = status {
-                        eprintln!("input stream fell behind: try increasing channel latency");
-                    }
-
-                    buffer_progress += num_outputs;
                 }
+            }
+        }
 
-                // 再生サンプル数増加
-                progress += buffer_num_samples;
-                played_samples.store(progress, Ordering::Relaxed);
-            },
-            |err| eprintln!("[{}] {err}", SPC2MIDI2_TITLE_STR),
-            None,
-        ) {
-            Ok(stream) => stream,
-            Err(_) => return Err(PlayStreamError::DeviceNotAvailable),
-        };
+        let mut summary = format!(
+            "MIDI note summary (first {:.1} sec)\n",
+            config.output_duration_msec as f64 / 1000.0
+        );
+        for (ch, notes) in channel_notes.iter_mut() {
+            notes.sort_by(|a, b| a.0.total_cmp(&b.0));
+            summary += &format!("Ch {:2} ({} notes)\n", ch + 1, notes.len());
+            for (start, note, duration, velocity) in notes.iter() {
+                summary += &format!(
+                    "  {:8.3}s  {:<4} {:6.3}s  vel {:3}\n",
+                    start,
+                    format!(
+                        "{}{}",
+                        NOTE_NAMES[(note % 12) as usize],
+                        (*note as i32) / 12 - 1
+                    ),
+                    duration,
+                    velocity
+                );
+            }
+        }
 
-        // MIDI再生スレッド生成
-        let is_playing = self.stream_is_playing.clone();
-        let midi_output_configure = self.midi_output_configure.clone();
-        let mut midi_cycle_count = 0;
-        let _midi_thread = thread::spawn(move || {
-            let interval = Duration::from_nanos(CLOCK_TICK_CYCLE_64KHZ_NANOSEC);
-            let mut next = Instant::now();
-            while is_playing.load(Ordering::Relaxed) {
-                {
-                    let mut midispc = midi_spc.lock().unwrap();
-                    let mut midi_bytes = midi_output_bytes.load(Ordering::Relaxed);
-                    // 64kHzのサイクル数
-                    let spc_64k_hz_cycle = if let Ok(config) = midi_output_configure.read() {
-                        config.spc_clockup_factor * CLOCK_TICK_CYCLE_64KHZ
+        Some(summary)
+    }
+
+    // ピッチベンド幅が出力先シンセの最大値や発音中のピッチ変化に合わない音源の警告
+    fn pitch_bend_width_warnings(&self) -> String {
+        let config = self.midi_output_configure.read().unwrap();
+        let infos = self.source_infos.read().unwrap();
+        let params = self.source_parameter.read().unwrap();
+        let mut warnings = String::new();
+        for (srn, param) in params
+            .iter()
+            .filter(|(_, p)| !p.mute && p.enable_pitch_bend)
+        {
+            if param.pitch_bend_width > config.target_pitch_bend_width {
+                warnings += &format!(
+                    "\nWARNING: SRN {} pitch bend width ({}) exceeds the target synth max ({}){}.",
+                    srn,
+                    param.pitch_bend_width,
+                    config.target_pitch_bend_width,
+                    if config.clamp_pitch_bend_width {
+                        " and is clamped"
                     } else {
-                        CLOCK_TICK_CYCLE_64KHZ
-                    };
-                    // 64kHzタイマーティックするまで処理
-                    while midi_cycle_count < spc_64k_hz_cycle {
-                        midi_cycle_count += midispc.execute_step() as u32;
-                    }
-                    midi_cycle_count -= spc_64k_hz_cycle;
-                    // MIDI出力
-                    if let Some(msgs) = midispc.clock_tick_64k_hz() {
-                        // MIDI出力のロック
-                        let mut conn_out = midi_out_conn.lock().unwrap();
-                        for i in 0..msgs.num_messages {
-                            let msg = msgs.messages[i];
-                            conn_out.send(&msg.data[..msg.length]).unwrap();
-                            midi_bytes += msg.length;
-                        }
+                        ""
                     }
-                    midi_output_bytes.store(midi_bytes, Ordering::Relaxed);
-                }
-                // ビジーループで待つ
-                next += interval;
-                while Instant::now() < next {
-                    thread::yield_now();
+                );
+            }
+            if let Some(info) = infos.get(srn) {
+                let required = required_pitch_bend_width(info);
+                if required > param.pitch_bend_width {
+                    warnings += &format!(
+                        "\nWARNING: SRN {} bends up to {:.1} semitones; pitch bend width {} or more is suggested.",
+                        srn, info.max_pitch_deviation, required
+                    );
                 }
             }
-        });
+        }
+        warnings
+    }
 
-        // 再生開始
-        self.stream_is_playing.store(true, Ordering::Relaxed);
-        stream.play()?;
-        self.stream = Some(stream);
+    // 曲中のDIR変更で複数のディレクトリエントリから発音された音源の警告
+    fn directory_change_warnings(&self) -> String {
+        let infos = self.source_infos.read().unwrap();
+        let mut warnings = String::new();
+        for (srn, info) in infos
+            .iter()
+            .filter(|(_, info)| !info.alternate_dir_addresses.is_empty())
+        {
+            let addresses: Vec<_> = info
+                .alternate_dir_addresses
+                .iter()
+                .map(|address| format!("0x{:04X}", address))
+                .collect();
+            warnings += &format!(
+                "\nWARNING: SRN {} is also played from directory entries {} (the sample directory changes during the song); only the entry at 0x{:04X} is used.",
+                srn,
+                addresses.join(", "),
+                info.dir_address
+            );
+        }
+        warnings
+    }
 
-        Ok(())
+    // WAVを作成（SPCの出力を設定したレートに変換し、ステレオ・16bitで出力）
+    // （WAV, 長さ(sec), ピークレベル(dBFS)）を返す
+    pub fn create_wav(&self) -> Option<(Vec<u8>, f32, f32)> {
+        let spc_file = self.spc_file.as_ref()?;
+        let config = self.midi_output_configure.read().unwrap();
+        let range_msec = preroll_render_range_msec(
+            self.export_range_msec().unwrap_or(None),
+            config.output_duration_msec,
+            self.export_preroll_msec.load(Ordering::Relaxed) as u64,
+        );
+        let rendered = Self::render_pcm(
+            spc_file,
+            &config,
+            range_msec,
+            &AtomicBool::new(false),
+            &AtomicUsize::new(0),
+        )?;
+        let num_frames = rendered.pcm.len() / RenderedPCM::NUM_CHANNELS;
+        Some((
+            encode_wav(
+                &rendered.pcm,
+                rendered.sampling_rate,
+                RenderedPCM::NUM_CHANNELS as u16,
+            ),
+            num_frames as f32 / rendered.sampling_rate as f32,
+            pcm_peak_dbfs(&rendered.pcm),
+        ))
     }
 
-    // プレビュー再生開始
-    fn srn_play_start(&mut self, srn_no: u8) -> Result<(), PlayStreamError> {
-        // 再生対象の音源をコピー
-        let infos = self.source_infos.read().unwrap();
-        let source = if let Some(srn) = infos.get(&srn_no) {
-            srn.clone()
-        } else {
-            return Ok(());
-        };
+    // WAVをレンダリング（キャンセルされた場合はNone、進捗は1000分率で通知）
+    // 範囲を指定した場合はその区間だけを出力する
+    fn render_wav(
+        spc_file: &SPCFile,
+        config: &MIDIOutputConfigure,
+        range_msec: Option<(u64, u64)>,
+        cancel: &AtomicBool,
+        progress: &AtomicUsize,
+    ) -> Option<(Vec<u8>, MonoCompatibility, u64)> {
+        let rendered = Self::render_pcm(spc_file, config, range_msec, cancel, progress)?;
+        Some((
+            encode_wav(
+                &rendered.pcm,
+                rendered.sampling_rate,
+                RenderedPCM::NUM_CHANNELS as u16,
+            ),
+            rendered.mono_compatibility,
+            rendered.clipped_msec,
+        ))
+    }
 
-        // オーディオデバイスの存在確認
-        if self.stream_device.is_none() || self.stream_config.is_none() {
-            return Err(PlayStreamError::DeviceNotAvailable);
+    // OGGをレンダリング（キャンセル・エンコード失敗時はNone）
+    // 曲のループが検出できればLOOPSTART/LOOPLENGTHを付け、そのサンプル位置も返す
+    fn render_ogg(
+        spc_file: &SPCFile,
+        config: &MIDIOutputConfigure,
+        range_msec: Option<(u64, u64)>,
+        cancel: &AtomicBool,
+        progress: &AtomicUsize,
+    ) -> Option<(Vec<u8>, Option<(u64, u64)>)> {
+        let rendered = Self::render_pcm(spc_file, config, range_msec, cancel, progress)?;
+        match encode_ogg(
+            &rendered.pcm,
+            rendered.sampling_rate,
+            RenderedPCM::NUM_CHANNELS,
+            rendered.loop_samples,
+        ) {
+            Ok(ogg) => Some((ogg, rendered.loop_samples)),
+            Err(e) => {
+                eprintln!("[{}] Failed to encode OGG: {}", SPC2MIDI2_TITLE_STR, e);
+                None
+            }
         }
-        let stream_device = self.stream_device.clone().unwrap();
-        let stream_config = self.stream_config.clone().unwrap();
+    }
 
-        let num_channels = stream_config.channels as usize;
-        let is_playing = self.stream_is_playing.clone();
-        let loop_start_sample = f64::round(
-            (source.loop_start_sample * stream_config.sample_rate as usize) as f64
-                / SPC_SAMPLING_RATE as f64,
-        ) as usize;
+    // SPCの出力をレンダリングし、ステレオ・16bitのPCMを作成（キャンセルされた場合はNone、進捗は1000分率で通知）
+    // 範囲を指定した場合はその区間だけを出力する
+    fn render_pcm(
+        spc_file: &SPCFile,
+        config: &MIDIOutputConfigure,
+        range_msec: Option<(u64, u64)>,
+        cancel: &AtomicBool,
+        progress: &AtomicUsize,
+    ) -> Option<RenderedPCM> {
+        const NUM_CHANNELS: usize = RenderedPCM::NUM_CHANNELS;
+        let mut spc: Box<spc700::spc::SPC<spc700::sdsp::SDSP>> = Box::new({
+            let mut spc = SPC::new();
+            spc.initialize(
+                &spc_file.header.spc_register,
+                &spc_file.ram,
+                &spc_file.dsp_register,
+            );
+            spc
+        });
 
-        // 出力先デバイスのレートに合わせてレート変換
-        let resampled_pcm = convert(
-            SPC_SAMPLING_RATE,
-            stream_config.sample_rate,
-            1,
-            ConverterType::SincBestQuality,
-            &source.signal,
-        )
-        .unwrap();
-        let resampled_len = resampled_pcm.len();
+        let (start_msec, end_msec) = range_msec.unwrap_or((0, config.output_duration_msec));
+        let num_samples = ((end_msec * SPC_SAMPLING_RATE as u64) / 1000) as usize;
+        let mut pcm = Vec::with_capacity(num_samples * NUM_CHANNELS);
+        // ループ検出用のキーオン（サンプル位置, チャンネルと音源番号）
+        let mut keyons: Vec<(u64, u32)> = vec![];
+        let mut previous_keyon = 0;
+        let mut cycle_count = 0;
+        progress.store(0, Ordering::Relaxed);
+        while pcm.len() < num_samples * NUM_CHANNELS {
+            // 1秒ごとにキャンセル確認と進捗更新
+            if pcm.len() % (NUM_CHANNELS * SPC_SAMPLING_RATE as usize) == 0 {
+                if cancel.load(Ordering::Relaxed) {
+                    return None;
+                }
+                progress.store(
+                    (1000 * pcm.len()) / (num_samples * NUM_CHANNELS),
+                    Ordering::Relaxed,
+                );
+            }
+            cycle_count += spc.execute_step() as u32;
+            // DSPを動かすとキーオンフラグが落ちることがあるので64kHzティック前に調べる
+            let keyon = spc.dsp.read_register(&spc_file.ram, DSP_ADDRESS_KON);
+            for ch in 0..8 {
+                if ((keyon & !previous_keyon) >> ch) & 1 != 0 {
+                    let srn_no = spc
+                        .dsp
+                        .read_register(&spc_file.ram, (ch << 4) | DSP_ADDRESS_V0SRCN);
+                    keyons.push((
+                        (pcm.len() / NUM_CHANNELS) as u64,
+                        ((ch as u32) << 8) | srn_no as u32,
+                    ));
+                }
+            }
+            previous_keyon = keyon;
+            if cycle_count >= CLOCK_TICK_CYCLE_64KHZ {
+                cycle_count -= CLOCK_TICK_CYCLE_64KHZ;
+                // エコーを除く場合は（ドライバが書き換えることがあるので）ティック毎にエコーボリュームを0にする
+                if config.wav_bypass_echo {
+                    spc.dsp.write_register(&[0u8], DSP_ADDRESS_EVOLL, 0);
+                    spc.dsp.write_register(&[0u8], DSP_ADDRESS_EVOLR, 0);
+                }
+                if let Some(out) = spc.clock_tick_64k_hz() {
+                    pcm.push(out[0] as i32);
+                    pcm.push(out[1] as i32);
+                }
+            }
+        }
 
-        // 音源はモノラルなので出力チャンネル数分コピー
-        let mut output = vec![0.0f32; resampled_len * num_channels];
-        for smpl in 0..resampled_len {
-            for ch in 0..num_channels {
-                output[ch as usize + num_channels * smpl] = resampled_pcm[smpl];
+        // 範囲の開始より前を取り除く
+        let num_skip_samples = ((start_msec * SPC_SAMPLING_RATE as u64) / 1000) as usize;
+        pcm.drain(..(num_skip_samples * NUM_CHANNELS).min(pcm.len()));
+
+        // クリップ時間を測ってから16bitに収める（桁あふれで折り返さないよう飽和させる）
+        let clipped_frames = count_clipped_frames(&pcm, NUM_CHANNELS);
+        let clipped_msec = (clipped_frames as u64 * 1000) / SPC_SAMPLING_RATE as u64;
+        let mut pcm = limit_pcm(&pcm, config.wav_soft_limiter);
+
+        // ミッド・サイド処理でステレオ幅を調整
+        if config.wav_stereo_width_percent != 100 {
+            let width = config.wav_stereo_width_percent as f32 / 100.0;
+            for frame in pcm.chunks_exact_mut(NUM_CHANNELS) {
+                let mid = (frame[0] as f32 + frame[1] as f32) * 0.5;
+                let side = (frame[0] as f32 - frame[1] as f32) * 0.5 * width;
+                frame[0] = (mid + side).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+                frame[1] = (mid - side).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
             }
         }
-        // ループ開始位置は出力サンプル数で上限をかける
-        let loop_start_progress = cmp::min(num_channels * loop_start_sample, output.len() - 1);
 
-        // ループフラグ・プレビューボリューム
-        let preview_loop = self.preview_loop.clone();
-        let preview_volume = self.preview_volume.clone();
-        const PREVIEW_VOLUME_NORMALIZE_VALUE: f32 = 1.0 / 128.0;
+        // 出力レートに変換（SPCのレートで描画してから高品質にダウン/アップサンプル）
+        let sample_rate = config.wav_sample_rate.hz();
+        let pcm = if sample_rate != SPC_SAMPLING_RATE {
+            if cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+            resample_pcm(
+                &pcm,
+                SPC_SAMPLING_RATE,
+                sample_rate,
+                NUM_CHANNELS,
+                &config.wav_resample_quality,
+            )?
+        } else {
+            pcm
+        };
 
-        // 再生サンプル数（ワンショットのプレビュー再生なので再生サンプルはselfに保持しない）
-        let mut progress = 0;
+        // ループ位置を出力のサンプル位置に変換
+        let tolerance = (SPC_SAMPLING_RATE / SONG_OVERVIEW_LOOP_TOLERANCE_DIVISOR) as u64;
+        let loop_samples = detect_loop(&keyons, tolerance).and_then(|spc_loop| {
+            loop_samples_in_output(
+                spc_loop,
+                num_skip_samples as u64,
+                sample_rate,
+                (pcm.len() / NUM_CHANNELS) as u64,
+            )
+        });
 
-        // 再生ストリーム作成
-        let stream = match stream_device.build_output_stream(
-            &stream_config,
-            move |buffer: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                // 一旦バッファを無音で埋める
-                buffer.fill(0.0);
-                // バッファにコピー
-                let num_copy_samples = cmp::min(output.len() - progress, buffer.len());
-                // 出力ボリュームを適用しながらバッファにコピー
-                let volume =
-                    preview_volume.load(Ordering::Relaxed) as f32 * PREVIEW_VOLUME_NORMALIZE_VALUE;
-                for smpl in 0..num_copy_samples {
-                    buffer[smpl] = volume * output[progress + smpl];
+        let mono_compatibility = measure_mono_compatibility(&pcm);
+        Some(RenderedPCM {
+            pcm: pcm,
+            sampling_rate: sample_rate,
+            loop_samples: loop_samples,
+            mono_compatibility: mono_compatibility,
+            clipped_msec: clipped_msec,
+        })
+    }
+
+    // JSON生成
+    pub fn create_json(&self) -> serde_json::Value {
+        let config = self.midi_output_configure.read().unwrap();
+        let params = self.source_parameter.read().unwrap();
+        let infos = self.source_infos.read().unwrap();
+        json!(ExportInformation {
+            tool_information: format!("{} Ver.{}", SPC2MIDI2_TITLE_STR, env!("CARGO_PKG_VERSION")),
+            midi_output_configure: config.clone(),
+            source_parameter: params.clone(),
+            pitch_confidence: infos
+                .iter()
+                .map(|(srn_no, info)| (*srn_no, info.pitch_confidence))
+                .collect(),
+        })
+    }
+
+    // 解析結果のレポート（Markdown）生成
+    pub fn create_report(&self) -> String {
+        let config = self.midi_output_configure.read().unwrap();
+        let params = self.source_parameter.read().unwrap();
+        let infos = self.source_infos.read().unwrap();
+        let mut report = String::new();
+
+        // 曲全体の情報
+        let title = if let Some(spc_file) = &self.spc_file {
+            String::from_utf8_lossy(&spc_file.header.music_title)
+                .trim_end_matches('\0')
+                .to_string()
+        } else {
+            "".to_string()
+        };
+        report += &format!("# {} Analysis Report\n\n", title);
+        report += &format!(
+            "- Tool: {} Ver.{}\n",
+            SPC2MIDI2_TITLE_STR,
+            env!("CARGO_PKG_VERSION")
+        );
+        if let Some(path) = &self.spc_file_path {
+            report += &format!("- File: {}\n", path.display());
+        }
+        report += &format!(
+            "- Output Duration: {:.1} sec\n",
+            config.output_duration_msec as f32 / 1000.0
+        );
+        // 曲のループ区間（描画済みの波形概要があれば流用し、なければ求める）
+        let main_window_overview = self
+            .windows
+            .get(&self.main_window_id)
+            .and_then(|window| window.as_ref().as_any().downcast_ref::<MainWindow>())
+            .and_then(|main_win| main_win.song_overview.clone());
+        let loop_region = match main_window_overview {
+            Some(overview) => overview.loop_region,
+            None => self.spc_file.as_ref().and_then(|spc_file| {
+                render_song_overview(
+                    spc_file,
+                    config.output_duration_msec,
+                    &AtomicBool::new(false),
+                )
+                .and_then(|overview| overview.loop_region)
+            }),
+        };
+        report += &match loop_region {
+            Some((loop_start, loop_end)) => {
+                format!("- Song Loop: {:.2} - {:.2} sec\n", loop_start, loop_end)
+            }
+            None => "- Song Loop: N/A (not detected)\n".to_string(),
+        };
+        report += &format!("- Tempo: {:.2} BPM\n", config.beats_per_minute);
+
+        // 音色がドラム以外のキーオン時のノートから調を推定
+        let mut pitch_class_histogram = [0.0f32; 12];
+        for (srn_no, info) in infos.iter() {
+            if let Some(param) = params.get(srn_no) {
+                if (param.program.clone() as u8) >= 0x80 {
+                    continue;
                 }
-                progress += num_copy_samples;
-                // 端点に来た時の処理
-                if progress >= output.len() {
-                    if preview_loop.load(Ordering::Relaxed) {
-                        // ループしながらバッファがいっぱいになるまでコピー
-                        let mut buffer_pos = num_copy_samples;
-                        progress = loop_start_progress;
-                        while buffer_pos < buffer.len() {
-                            let num_copy_samples =
-                                cmp::min(output.len() - progress, buffer.len() - buffer_pos);
-                            // 出力ボリュームを適用しながらバッファにコピー
-                            let volume = preview_volume.load(Ordering::Relaxed) as f32
-                                * PREVIEW_VOLUME_NORMALIZE_VALUE;
-                            for smpl in 0..num_copy_samples {
-                                buffer[buffer_pos + smpl] = volume * output[progress + smpl];
-                            }
-                            buffer_pos += num_copy_samples;
-                            progress += num_copy_samples;
-                            if progress >= output.len() {
-                                progress = loop_start_progress;
-                            }
-                        }
-                    } else {
-                        // 再生終了
-                        is_playing.store(false, Ordering::Relaxed);
-                    }
+                for pitch in info.keyon_pitches.iter().filter(|&&p| p > 0) {
+                    let note =
+                        param.center_note as f32 / 512.0 + 12.0 * (f32::log2(*pitch as f32) - 12.0);
+                    pitch_class_histogram[(note.round() as i32).rem_euclid(12) as usize] += 1.0;
                 }
-            },
-            |err| eprintln!("[{}] {err}", SPC2MIDI2_TITLE_STR),
-            None,
-        ) {
-            Ok(stream) => stream,
-            Err(_) => return Err(PlayStreamError::DeviceNotAvailable),
+            }
+        }
+        report += &match estimate_key(&pitch_class_histogram) {
+            Some((tonic, major)) => format!(
+                "- Estimated Key: {} {}\n",
+                NOTE_NAMES[tonic as usize],
+                if major { "Major" } else { "Minor" }
+            ),
+            None => "- Estimated Key: N/A\n".to_string(),
         };
 
-        // 再生開始
-        self.stream_is_playing.store(true, Ordering::Relaxed);
-        stream.play()?;
-        self.stream = Some(stream);
-
-        Ok(())
-    }
+        // 再生済みであればMIDIビットレートを記載
+        let played_samples = self.stream_played_samples.load(Ordering::Relaxed);
+        let midi_output_bytes = self.midi_output_bytes.load(Ordering::Relaxed);
+        if let (Some(stream_config), true) = (&self.stream_config, played_samples > 0) {
+            let playback_time = played_samples as f32 / stream_config.sample_rate as f32;
+            report += &format!(
+                "- MIDI Bit Rate: {:.2} kbps\n",
+                (midi_output_bytes as f32 * 10.0) / playback_time / 1000.0
+            );
+        } else {
+            report += "- MIDI Bit Rate: N/A (not played yet)\n";
+        }
 
-    // MIDIの全ての音を止める
-    fn stop_midi_all_sound(&mut self) {
-        if let Some(midi_out_conn_ref) = &self.midi_out_conn {
-            let midi_out_conn = midi_out_conn_ref.clone();
-            let mut conn_out = midi_out_conn.lock().unwrap();
-            for ch in 0..16 {
-                conn_out
-                    .send(&[MIDIMSG_MODE | ch, MIDIMSG_MODE_ALL_SOUND_OFF, 0])
-                    .unwrap();
+        // 音源ごとの情報
+        report += "\n## Sources\n\n";
+        report += "| SRN | Address | Loop Start | Detected | Program | Center Note | Confidence | Channels | Key-Ons |\n";
+        report += "|---:|---|---:|---|---|---:|---:|---|---:|\n";
+        for (srn_no, info) in infos.iter() {
+            if let Some(param) = params.get(srn_no) {
+                let channels: Vec<_> = (0..8)
+                    .filter(|&ch| info.using_channel[ch])
+                    .map(|ch| ch.to_string())
+                    .collect();
+                report += &format!(
+                    "| {} | 0x{:04X} - 0x{:04X} | {} | {} | {} | {:.2} | {:.0}% | {} | {} |\n",
+                    srn_no,
+                    info.start_address,
+                    info.end_address,
+                    info.loop_start_sample,
+                    match param.kind_override() {
+                        SourceKindOverride::Auto if info.detected_drum => "Drum",
+                        SourceKindOverride::Auto => "Tonal",
+                        SourceKindOverride::Tonal => "Tonal (forced)",
+                        SourceKindOverride::Drum => "Drum (forced)",
+                    },
+                    if param.instrument_name != "" {
+                        format!("{} ({})", param.program, param.instrument_name)
+                    } else {
+                        param.program.to_string()
+                    },
+                    param.center_note as f32 / 512.0,
+                    info.pitch_confidence * 100.0,
+                    channels.join(", "),
+                    info.keyon_pitches.len(),
+                );
             }
         }
+
+        report
     }
 
-    // MIDIの特定チャンネルの音を止める
-    fn stop_midi_channel_sound(&mut self, ch: u8) {
-        if let Some(midi_out_conn_ref) = &self.midi_out_conn {
-            let midi_out_conn = midi_out_conn_ref.clone();
-            let mut conn_out = midi_out_conn.lock().unwrap();
-            // ATENSION! MIDIVoiceは0..7chにある前提
-            conn_out
-                .send(&[MIDIMSG_MODE | ch, MIDIMSG_MODE_ALL_SOUND_OFF, 0])
-                .unwrap();
+    // バックグラウンド処理を開始（実行中の処理があれば何もしない）
+    fn start_background_operation<F>(&mut self, default_file_name: String, operation: F)
+    where
+        F: FnOnce(Arc<AtomicBool>, Arc<AtomicUsize>) -> BackgroundResult + Send + 'static,
+    {
+        if self.background_operation.is_some() {
+            return;
         }
+        self.operation_cancel.store(false, Ordering::Relaxed);
+        self.operation_progress.store(0, Ordering::Relaxed);
+        let cancel = self.operation_cancel.clone();
+        let progress = self.operation_progress.clone();
+        self.background_operation = Some(BackgroundOperation {
+            default_file_name: default_file_name,
+            handle: thread::spawn(move || operation(cancel, progress)),
+        });
     }
 
-    // 再生停止
-    fn stream_play_stop(&mut self) -> Result<(), PauseStreamError> {
-        if let Some(stream) = &self.stream {
-            self.stream_is_playing.store(false, Ordering::Relaxed);
-            stream.pause()?;
-            self.stream = None;
+    // バックグラウンド処理の結果を取り出して保存・反映へ（終わっていなければ終わるまで待つ）
+    fn finish_background_operation(&mut self, operation: BackgroundOperation) -> Task<Message> {
+        if let Some(window) = self.windows.get_mut(&self.main_window_id) {
+            let main_win: &mut MainWindow = window.as_mut().as_any_mut().downcast_mut().unwrap();
+            main_win.operation_progress = None;
+        }
+        // キャンセルされた場合は結果がNoneになっている
+        match operation.handle.join() {
+            Ok(BackgroundResult::SMF(Some(smf), unanalyzed_sources)) => {
+                self.smf_unanalyzed_sources = unanalyzed_sources;
+                Task::perform(
+                    save_smf(operation.default_file_name, smf),
+                    Message::SMFSaved,
+                )
+            }
+            Ok(BackgroundResult::SMFSections(Some(sections), unanalyzed_sources)) => {
+                if sections.is_empty() {
+                    return Task::perform(
+                        show_message_dialog(
+                            "Split SMF per Loop".to_string(),
+                            "No loop was detected. Try a longer output duration that covers at least two loops.".to_string(),
+                        ),
+                        |_| Message::MessageDialogClosed,
+                    );
+                }
+                self.smf_unanalyzed_sources = unanalyzed_sources;
+                Task::perform(
+                    save_smf_sections(operation.default_file_name, sections),
+                    Message::SMFSaved,
+                )
+            }
+            Ok(BackgroundResult::WAV(Some((wav, mono_compatibility, clipped_msec)))) => {
+                self.wav_mono_compatibility = Some(mono_compatibility);
+                self.wav_clipped_msec = clipped_msec;
+                Task::perform(
+                    save_wav(operation.default_file_name, wav),
+                    Message::WAVSaved,
+                )
+            }
+            Ok(BackgroundResult::OGG(Some((ogg, loop_samples)))) => {
+                self.ogg_loop_samples = loop_samples;
+                Task::perform(
+                    save_ogg(operation.default_file_name, ogg),
+                    Message::OGGSaved,
+                )
+            }
+            Ok(BackgroundResult::Analysis(result, request)) => {
+                self.finish_source_analysis(result, request)
+            }
+            _ => Task::none(),
         }
-        self.stop_midi_all_sound();
-        Ok(())
     }
 
-    // MIDI楽器音をプレビュー
-    fn preview_midi_sound(&self, srn_no: u8) {
-        // 再生時のパラメータ設定
-        let params = self.source_parameter.read().unwrap();
-        let param = params.get(&srn_no).unwrap();
-        let program = param.program.clone() as u8;
-        let velocity = param.noteon_velocity;
-        let note = (param.center_note >> 9) as u8;
-
-        // MIDI出力の作成
-        let midi_out_conn = if let Some(midi_out_conn_ref) = &self.midi_out_conn {
-            midi_out_conn_ref.clone()
-        } else {
-            // TODO: エラーにした方が良い
-            return;
-        };
-        let mut conn_out = midi_out_conn.lock().unwrap();
+    /// 実行中のバックグラウンド処理が終わるまで待って結果を反映（CLIなど同期的に処理する場合）
+    pub fn wait_background_operation(&mut self) -> Task<Message> {
+        match self.background_operation.take() {
+            Some(operation) => self.finish_background_operation(operation),
+            None => Task::none(),
+        }
+    }
 
-        // ノートオン
-        if program < 0x80 {
-            conn_out
-                .send(&[MIDIMSG_PROGRAM_CHANGE | MIDI_PREVIEW_CHANNEL, program])
-                .unwrap();
-            conn_out
-                .send(&[MIDIMSG_NOTE_ON | MIDI_PREVIEW_CHANNEL, note, velocity])
-                .unwrap();
+    // 読み込んでいる音色マッピングを音源パラメータに適用し、当てはめた音源数を返す
+    fn apply_instrument_mapping(&mut self) -> usize {
+        if let Some((_, mapping)) = &self.instrument_mapping {
+            mapping.apply(
+                &self.source_infos.read().unwrap(),
+                &mut self.source_parameter.write().unwrap(),
+            )
         } else {
-            // ドラム音色
-            conn_out
-                .send(&[MIDIMSG_NOTE_ON | 0x9, program - 0x80, velocity])
-                .unwrap();
+            0
         }
+    }
 
-        // プレビュー時間流す
-        thread::sleep(Duration::from_millis(MIDI_PREVIEW_DURATION_MSEC));
-
-        // ノートオフ
-        if program < 0x80 {
-            conn_out
-                .send(&[MIDIMSG_NOTE_OFF | MIDI_PREVIEW_CHANNEL, note, 0])
-                .unwrap();
-        } else {
-            // ドラム音色
-            conn_out
-                .send(&[MIDIMSG_NOTE_OFF | 0x9, program - 0x80, 0])
-                .unwrap();
+    // 解析で音源が見つからなかったかをメインウィンドウに反映し、見つからなければ知らせる
+    fn notify_if_no_source(&mut self, analyze_duration_sec: u32) -> Task<Message> {
+        let no_source = self.source_infos.read().unwrap().is_empty();
+        if let Some(window) = self.windows.get_mut(&self.main_window_id) {
+            let main_win: &mut MainWindow = window.as_mut().as_any_mut().downcast_mut().unwrap();
+            main_win.no_source_detected = no_source;
+        }
+        if !no_source {
+            return Task::none();
         }
+        Task::perform(
+            show_message_dialog(
+                "No Sources Detected".to_string(),
+                format!(
+                    "No key-on was found in the first {} seconds of the song.\n\n{}",
+                    analyze_duration_sec, NO_SOURCE_HINT
+                ),
+            ),
+            |_| Message::MessageDialogClosed,
+        )
     }
 
-    // 音源パラメータをDSPに適用
-    fn apply_source_parameter(&mut self) {
-        if let Some(midi_spc_ref) = &self.midi_spc {
-            let midi_spc = midi_spc_ref.clone();
-            let config = self.midi_output_configure.read().unwrap();
-            let params = self.source_parameter.read().unwrap();
-            let mut midispc = midi_spc.lock().unwrap();
-            apply_source_parameter(
-                &mut midispc,
+    // 書き出したSMFをMIDI出力ポートで再生（再生中なら停止）
+    // ライブ再生との違いからSMFへの変換で生じたタイミングのずれを確認できる
+    fn toggle_smf_playback(&mut self) {
+        if self
+            .smf_playback_thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+        {
+            self.stop_smf_playback(false);
+            return;
+        }
+        let (spc_file, midi_out_conn) = match (&self.spc_file, &self.midi_out_conn) {
+            (Some(spc_file), Some(midi_out_conn)) => (spc_file.clone(), midi_out_conn.clone()),
+            _ => return,
+        };
+        // ライブ再生とMIDI出力が重ならないよう止める
+        if self.playback_mode() != PlaybackMode::None {
+            self.stream_play_stop().expect("Failed to stop play");
+        }
+        let config = self.midi_output_configure.read().unwrap().clone();
+        let params = self.source_parameter.read().unwrap().clone();
+        let options = SMFExportOptions {
+            stretch_target_msec: None,
+            master_reverb_send: self.master_reverb_send.load(Ordering::Relaxed),
+            range_msec: self.export_range_msec().unwrap_or(None),
+            preroll_msec: self.export_preroll_msec.load(Ordering::Relaxed) as u64,
+            velocity_calibration: self.export_velocity_calibration(&config),
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        self.smf_playback_stop = stop.clone();
+        self.smf_playback_thread = Some(thread::spawn(move || {
+            // 停止された場合はSMFの作成も打ち切る
+            let smf = match Self::build_smf(
+                &spc_file,
                 &config,
                 &params,
-                &self.spc_file.as_ref().unwrap().ram,
-            );
+                &options,
+                &stop,
+                &AtomicUsize::new(0),
+            ) {
+                Some((smf, _)) => smf,
+                None => return,
+            };
+            let start = Instant::now();
+            for (usec, data) in smf_to_timed_messages(smf) {
+                let due = start + Duration::from_micros(usec);
+                // 停止を確認しながら送信時刻まで待つ
+                while Instant::now() < due {
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    thread::sleep((due - Instant::now()).min(Duration::from_millis(1)));
+                }
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                // ポートが失われるなどで送れなくなったら再生をやめる（ロック中にパニックしない）
+                if midi_out_conn.lock().unwrap().send(&data).is_err() {
+                    break;
+                }
+            }
+            let mut conn_out = midi_out_conn.lock().unwrap();
+            for ch in 0..16 {
+                let _ = conn_out.send(&[MIDIMSG_MODE | ch, MIDIMSG_MODE_ALL_SOUND_OFF, 0]);
+            }
+        }));
+    }
+
+    // SMFの再生を停止（waitがtrueなら全音消音を送り終えるまで待つ）
+    fn stop_smf_playback(&mut self, wait: bool) {
+        self.smf_playback_stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.smf_playback_thread.take() {
+            if wait {
+                let _ = thread.join();
+            }
         }
     }
-}
 
-/// 音源パラメータをDSPに適用
+    // 曲全体の波形概要をバックグラウンドで描画（描画中のものはキャンセル）
+    fn start_song_overview_render(&mut self) {
+        self.song_overview_cancel.store(true, Ordering::Relaxed);
+        if let Some(spc_file) = &self.spc_file {
+            let spc_file = spc_file.clone();
+            let duration_msec = self
+                .midi_output_configure
+                .read()
+                .unwrap()
+                .output_duration_msec;
+            let cancel = Arc::new(AtomicBool::new(false));
+            self.song_overview_cancel = cancel.clone();
+            self.song_overview_job = Some(thread::spawn(move || {
+                render_song_overview(&spc_file, duration_msec, &cancel)
+            }));
+        }
+    }
+
+    // 曲の再生位置を移動（指定時刻まで空回ししたSPCをバックグラウンドで作成する）
+    fn seek_song(&mut self, time_sec: f32) {
+        let spc_file = if let Some(spc_file) = &self.spc_file {
+            spc_file.clone()
+        } else {
+            return;
+        };
+        // 移動中に再度移動した場合も、最初の移動前に再生していれば再開する
+        let mut resume = self.song_seek_job.as_ref().is_some_and(|job| job.resume);
+        self.cancel_song_seek();
+        if self.playback_mode() == PlaybackMode::Song {
+            self.stream_play_stop().expect("Failed to stop play");
+            resume = true;
+        }
+        let time_sec = time_sec.max(0.0);
+        let config = self.midi_output_configure.read().unwrap().clone();
+        let params = self.source_parameter.read().unwrap().clone();
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.song_seek_cancel = cancel.clone();
+        let handle = thread::spawn(move || {
+            let mut pcm_spc: Box<spc700::spc::SPC<spc700::sdsp::SDSP>> = Box::new(SPC::new());
+            pcm_spc.initialize(
+                &spc_file.header.spc_register,
+                &spc_file.ram,
+                &spc_file.dsp_register,
+            );
+            let num_samples = (time_sec * SPC_SAMPLING_RATE as f32) as u64;
+            let mut cycle_count = 0;
+            let mut sample_count = 0;
+            while sample_count < num_samples {
+                cycle_count += pcm_spc.execute_step() as u32;
+                if cycle_count >= CLOCK_TICK_CYCLE_64KHZ {
+                    cycle_count -= CLOCK_TICK_CYCLE_64KHZ;
+                    if let Some(_) = pcm_spc.clock_tick_64k_hz() {
+                        sample_count += 1;
+                        if sample_count % SPC_SAMPLING_RATE as u64 == 0
+                            && cancel.load(Ordering::Relaxed)
+                        {
+                            return None;
+                        }
+                    }
+                }
+            }
+            let mut midi_spc: Box<spc700::spc::SPC<spc700::mididsp::MIDIDSP>> =
+                Box::new(SPC::new());
+            midi_spc.initialize(
+                &spc_file.header.spc_register,
+                &spc_file.ram,
+                &spc_file.dsp_register,
+            );
+            // 空回し中のMIDIの状態が再生時と同じになるようパラメータを先に適用
+            apply_source_parameter(&mut midi_spc, &config, &params, &spc_file.ram);
+            let spc_64k_hz_cycle = config.spc_clockup_factor * CLOCK_TICK_CYCLE_64KHZ;
+            let num_ticks = (time_sec * 64000.0) as u64;
+            let mut cycle_count = 0;
+            for tick in 0..num_ticks {
+                while cycle_count < spc_64k_hz_cycle {
+                    cycle_count += midi_spc.execute_step() as u32;
+                }
+                cycle_count -= spc_64k_hz_cycle;
+                let _ = midi_spc.clock_tick_64k_hz();
+                if tick % 64000 == 0 && cancel.load(Ordering::Relaxed) {
+                    return None;
+                }
+            }
+            Some((pcm_spc, midi_spc))
+        });
+        self.song_seek_job = Some(SongSeekJob {
+            time_sec: time_sec,
+            resume: resume,
+            handle: handle,
+        });
+        // 再生時刻の表示を移動先に合わせる
+        if let Some(window) = self.windows.get_mut(&self.main_window_id) {
+            let main_win: &mut MainWindow = window.as_mut().as_any_mut().downcast_mut().unwrap();
+            main_win.playback_time_sec = time_sec;
+            main_win.beat_indicator.playback_time_sec = time_sec;
+        }
+    }
+
+    // 再生位置の移動を反映（空回ししたSPCに置き換え、移動前に再生していれば再開）
+    fn finish_song_seek(&mut self, job: SongSeekJob) {
+        let (pcm_spc, midi_spc) = match job.handle.join() {
+            Ok(Some(spcs)) => spcs,
+            _ => return,
+        };
+        if let Some(pcm_spc_ref) = &self.pcm_spc {
+            *pcm_spc_ref.lock().unwrap() = pcm_spc;
+        }
+        if let Some(midi_spc_ref) = &self.midi_spc {
+            *midi_spc_ref.lock().unwrap() = midi_spc;
+            // 記録済みの変更はDSPに反映された
+            for entry in self.parameter_log.write().unwrap().iter_mut() {
+                entry.applied = true;
+            }
+        }
+        if let Some(stream_config) = &self.stream_config {
+            self.stream_played_samples.store(
+                (job.time_sec * stream_config.sample_rate as f32) as usize,
+                Ordering::Relaxed,
+            );
+        }
+        if job.resume {
+            if let Err(_) = self.play_start(self.play_start_ramp_in_msec()) {
+                eprintln!("[{}] Faild to start playback", SPC2MIDI2_TITLE_STR);
+            }
+        }
+    }
+
+    // 再生開始時のフェードイン時間（フェードインしない設定ならNone）
+    fn play_start_ramp_in_msec(&self) -> Option<usize> {
+        if self.ramp_in_on_play_start.load(Ordering::Relaxed) {
+            Some(self.ramp_in_msec.load(Ordering::Relaxed))
+        } else {
+            None
+        }
+    }
+
+    // 移動中の再生位置の移動を取りやめる
+    fn cancel_song_seek(&mut self) {
+        self.song_seek_cancel.store(true, Ordering::Relaxed);
+        self.song_seek_job = None;
+    }
+
+    // 保存する設定を作成
+    fn create_preferences(&self) -> Preferences {
+        Preferences {
+            audio_output_latency_msec: self.audio_output_latency_msec.load(Ordering::Relaxed),
+            use_virtual_midi_port: self.use_virtual_midi_port.load(Ordering::Relaxed),
+            ramp_in_on_play_start: self.ramp_in_on_play_start.load(Ordering::Relaxed),
+            ramp_in_msec: self.ramp_in_msec.load(Ordering::Relaxed),
+            device_switch_fade_msec: self.device_switch_fade_msec.load(Ordering::Relaxed),
+            force_output_sample_rate: self.force_output_sample_rate.load(Ordering::Relaxed),
+            output_sample_rate: self.output_sample_rate.load(Ordering::Relaxed),
+            a4_pitch_hz: self.a4_pitch_hz(),
+            indicator_smoothing: f32::from_bits(self.indicator_smoothing.load(Ordering::Relaxed)),
+            spectrum_peak_labels: self.spectrum_peak_labels.load(Ordering::Relaxed),
+            spectrum_peak_separation_hz: f32::from_bits(
+                self.spectrum_peak_separation_hz.load(Ordering::Relaxed),
+            ),
+            cache_analysis: self.cache_analysis.load(Ordering::Relaxed),
+            lazy_source_decoding: self.lazy_source_decoding.load(Ordering::Relaxed),
+            stretch_smf_export: self.stretch_smf_export.load(Ordering::Relaxed),
+            stretch_target_sec: self.stretch_target_sec.load(Ordering::Relaxed),
+            master_reverb_send: self.master_reverb_send.load(Ordering::Relaxed),
+            stuck_note_timeout_sec: self.stuck_note_timeout_sec.load(Ordering::Relaxed),
+            export_preroll_msec: self.export_preroll_msec.load(Ordering::Relaxed),
+            long_export_warning_sec: self.long_export_warning_sec.load(Ordering::Relaxed),
+            program_velocity_calibration: self.program_velocity_calibration.read().unwrap().clone(),
+            source_parameter_template: self.source_parameter_template.read().unwrap().clone(),
+            export_range_enabled: self.export_range_enabled.load(Ordering::Relaxed),
+            export_range_start_sec: f32::from_bits(
+                self.export_range_start_sec.load(Ordering::Relaxed),
+            ),
+            export_range_end_sec: f32::from_bits(self.export_range_end_sec.load(Ordering::Relaxed)),
+            instrument_mapping_path: self
+                .instrument_mapping
+                .as_ref()
+                .map(|(path, _)| path.clone()),
+        }
+    }
+
+    // 次回起動時にも使えるよう設定を保存（設定ファイルから起動していなければ保存しない）
+    fn persist_preferences(&self) {
+        if !self.persist_preferences {
+            return;
+        }
+        if let Err(e) = save_preferences(&self.create_preferences()) {
+            eprintln!("ERROR: failed to save preferences: {:?}", e);
+        }
+    }
+
+    // ノート推定・周波数表示の基準ピッチ(A4)の周波数
+    fn a4_pitch_hz(&self) -> f32 {
+        f32::from_bits(self.a4_pitch_hz.load(Ordering::Relaxed))
+    }
+
+    // 強制するオーディオ出力のサンプリングレート（強制しない場合はNone）
+    fn forced_output_sample_rate(&self) -> Option<u32> {
+        if self.force_output_sample_rate.load(Ordering::Relaxed) {
+            Some(self.output_sample_rate.load(Ordering::Relaxed))
+        } else {
+            None
+        }
+    }
+
+    // 現在の出力デバイスの出力設定を選び直す（次の再生から適用）
+    fn reselect_stream_config(&mut self) {
+        if let Some(device) = &self.stream_device {
+            self.stream_config =
+                select_output_stream_config(device, self.forced_output_sample_rate());
+        }
+    }
+
+    // 再生開始（ramp_in_msecを指定した場合は無音からフェードイン）
+    fn play_start(&mut self, ramp_in_msec: Option<usize>) -> Result<(), PlayStreamError> {
+        const NUM_CHANNELS: usize = 2;
+
+        // SMFの再生とMIDI出力が重ならないよう止める
+        self.stop_smf_playback(false);
+
+        // SPCの参照をクローン
+        let (pcm_spc, midi_spc) =
+            if let (Some(pcm_spc_ref), Some(midi_spc_ref)) = (&self.pcm_spc, &self.midi_spc) {
+                (pcm_spc_ref.clone(), midi_spc_ref.clone())
+            } else {
+                return Ok(());
+            };
+
+        // オーディオデバイスとMIDI出力ポートの存在確認
+        if self.stream_device.is_none() || self.stream_config.is_none() {
+            return Err(PlayStreamError::DeviceNotAvailable);
+        }
+        let stream_device = self.stream_device.clone().unwrap();
+        let stream_config = self.stream_config.clone().unwrap();
+
+        let midi_out_conn = if let Some(midi_out_conn_ref) = &self.midi_out_conn {
+            midi_out_conn_ref.clone()
+        } else {
+            return Err(PlayStreamError::DeviceNotAvailable);
+        };
+
+        // リサンプラ初期化 32k -> デバイスの出力レート変換となるように
+        let latency = self.audio_output_latency_msec.load(Ordering::Relaxed) as f64 / 1000.0;
+        let (mut prod, mut cons) = fixed_resample::resampling_channel::<f32, NUM_CHANNELS>(
+            NonZero::new(NUM_CHANNELS).unwrap(),
+            SPC_SAMPLING_RATE,
+            stream_config.sample_rate,
+            fixed_resample::ResamplingChannelConfig {
+                latency_seconds: latency,
+                capacity_seconds: 3.0 * latency, // 2倍程度確保するべしとあるが、余裕を見て3倍
+                overflow_autocorrect_percent_threshold: None, // capacityを大きくするとOverflowと判定されやすくなるため判定を無効に
+                ..Default::default()
+            },
+        );
+
+        // SPCのミュートフラグ取得・設定
+        {
+            let flags = self.channel_mute_flags.load(Ordering::Relaxed);
+            let pcm_on = self.pcm_spc_on.load(Ordering::Relaxed);
+            let midi_on = self.midi_spc_on.load(Ordering::Relaxed);
+            let mut pcm_spc = pcm_spc.lock().unwrap();
+            let mut midi_spc = midi_spc.lock().unwrap();
+            pcm_spc.dsp.write_register(
+                &[0u8],
+                DSP_ADDRESS_CHANNEL_MUTE,
+                if pcm_on { flags } else { 0xFF },
+            );
+            midi_spc.dsp.write_register(
+                &[0u8],
+                DSP_ADDRESS_CHANNEL_MUTE,
+                if midi_on {
+                    self.midi_channel_mute_flags(flags)
+                } else {
+                    0xFF
+                },
+            );
+        }
+
+        // SPCにパラメータ適用
+        self.apply_source_parameter();
+
+        // 再生済みサンプル数・MIDI出力サイズ
+        let played_samples = self.stream_played_samples.clone();
+        let midi_output_bytes = self.midi_output_bytes.clone();
+
+        // 出力レベル
+        let pcm_output_level = self.pcm_output_level.clone();
+        let midi_output_level = self.midi_output_level.clone();
+        pcm_output_level.store(0, Ordering::Relaxed);
+        midi_output_level.store(0, Ordering::Relaxed);
+
+        // アンダーラン回数は再生開始ごとに数え直す
+        let underruns = self.stream_underruns.clone();
+        underruns.store(0, Ordering::Relaxed);
+        self.observed_underruns = 0;
+        self.recent_underrun_times.clear();
+
+        // 出力ゲイン（フェードイン時は無音から目標ゲインに向けて1サンプルずつ近づける）
+        let gain_target = self.output_gain_target.clone();
+        let ramp_in_samples = ramp_in_msec.unwrap_or(0) * stream_config.sample_rate as usize / 1000;
+        let gain_step = 1.0 / ramp_in_samples.max(1) as f32;
+        let mut gain = if ramp_in_msec.is_some() {
+            0.0
+        } else {
+            f32::from_bits(gain_target.load(Ordering::Relaxed))
+        };
+
+        // 再生ストリーム作成
+        let mut spc_cycle_count = 0;
+        let stream = match stream_device.build_output_stream(
+            &stream_config,
+            move |buffer: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                let mut progress = played_samples.load(Ordering::Relaxed);
+                let buffer_num_samples = buffer.len() / NUM_CHANNELS;
+
+                // バッファを出力サンプルで埋める
+                buffer.fill(0.0);
+                let mut buffer_progress = 0;
+                while buffer_progress < buffer_num_samples {
+                    // 入力キューがいっぱいになるまで出力計算
+                    if let Ok(mut spc) = pcm_spc.lock() {
+                        let nsamples = prod.available_frames();
+                        for _ in 0..nsamples {
+                            spc_cycle_count += spc.execute_step() as u32;
+                            if spc_cycle_count >= CLOCK_TICK_CYCLE_64KHZ {
+                                spc_cycle_count -= CLOCK_TICK_CYCLE_64KHZ;
+                                // PCM出力
+                                if let Some(pcm) = spc.clock_tick_64k_hz() {
+                                    prod.push_interleaved(&[
+                                        (pcm[0] as f32) * PCM_NORMALIZE_CONST,
+                                        (pcm[1] as f32) * PCM_NORMALIZE_CONST,
+                                    ]);
+                                }
+                            }
+                        }
+                    }
+
+                    // リサンプラー出力を取り出してバッファに書き出し
+                    let num_outputs = (buffer_num_samples - buffer_progress)
+                        .min(cons.available_frames())
+                        .max(0);
+                    let status = cons.read_interleaved(
+                        &mut buffer[buffer_progress * NUM_CHANNELS
+                            ..(buffer_progress + num_outputs) * NUM_CHANNELS],
+                    );
+                    if let ReadStatus::UnderflowOccurred { .. } = status {
+                        eprintln!("input stream fell behind: try increasing channel latency");
+                        underruns.fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    buffer_progress += num_outputs;
+                }
+
+                // 出力ゲインを目標値に近づけながら適用
+                let target = f32::from_bits(gain_target.load(Ordering::Relaxed));
+                for frame in buffer.chunks_mut(NUM_CHANNELS) {
+                    gain = if gain < target {
+                        (gain + gain_step).min(target)
+                    } else {
+                        (gain - gain_step).max(target)
+                    };
+                    for smpl in frame.iter_mut() {
+                        *smpl *= gain;
+                    }
+                }
+
+                // 出力のピークを記録（非負のf32はビット列の大小関係が値と一致する）
+                let peak = buffer.iter().fold(0.0f32, |acc, x| acc.max(x.abs()));
+                pcm_output_level.fetch_max(peak.to_bits(), Ordering::Relaxed);
+
+                // 再生サンプル数増加
+                progress += buffer_num_samples;
+                played_samples.store(progress, Ordering::Relaxed);
+            },
+            |err| eprintln!("[{}] {err}", SPC2MIDI2_TITLE_STR),
+            None,
+        ) {
+            Ok(stream) => stream,
+            Err(_) => return Err(PlayStreamError::DeviceNotAvailable),
+        };
+
+        // MIDI再生スレッド生成
+        let is_playing = self.stream_is_playing.clone();
+        let midi_output_configure = self.midi_output_configure.clone();
+        let source_parameter = self.source_parameter.clone();
+        let master_reverb_send = self.master_reverb_send.load(Ordering::Relaxed);
+        // 音源パラメータはスナップショットを使い、一定間隔で取り込み直す
+        let mut params = self.source_parameter.read().unwrap().clone();
+        let mut params_refresh_ticks = 0;
+        let mut velocity_scaler = KeyOnVelocityScaler::new();
+        let mut echo_send_scaler = EchoSendScaler::new(master_reverb_send);
+        let mut pan_law_mapper = PanLawMapper::new();
+        let mut note_range_folder = NoteRangeFolder::new();
+        let mut sustain_inserter = SustainPedalInserter::new();
+        let mut percussive_router = PercussiveNoteRouter::new();
+        let mut level_estimator = MIDIOutputLevelEstimator::new();
+        let held_notes = self.held_notes.clone();
+        *held_notes.lock().unwrap() = HeldNoteTracker::new();
+        let stuck_note_timeout_sec = self.stuck_note_timeout_sec.clone();
+        let mut stuck_note_check_ticks = 0;
+        let mut midi_cycle_count = 0;
+        let _midi_thread = thread::spawn(move || {
+            // 再生開始時に全チャンネルへマスターリバーブセンドを設定
+            {
+                let mut conn_out = midi_out_conn.lock().unwrap();
+                for ch in 0..16 {
+                    let msg = [
+                        MIDIMSG_MODE | ch,
+                        MIDIMSG_CC_REVERB_SEND,
+                        master_reverb_send,
+                    ];
+                    conn_out.send(&msg).unwrap();
+                }
+            }
+            let interval = Duration::from_nanos(CLOCK_TICK_CYCLE_64KHZ_NANOSEC);
+            let mut next = Instant::now();
+            while is_playing.load(Ordering::Relaxed) {
+                {
+                    let mut midispc = midi_spc.lock().unwrap();
+                    let mut midi_bytes = midi_output_bytes.load(Ordering::Relaxed);
+                    // 64kHzのサイクル数
+                    let (spc_64k_hz_cycle, flat_velocity, route_percussive_notes) =
+                        if let Ok(config) = midi_output_configure.read() {
+                            (
+                                config.spc_clockup_factor * CLOCK_TICK_CYCLE_64KHZ,
+                                config.flattened_velocity(),
+                                config.route_percussive_notes,
+                            )
+                        } else {
+                            (CLOCK_TICK_CYCLE_64KHZ, None, false)
+                        };
+                    // 64kHzタイマーティックするまで処理
+                    while midi_cycle_count < spc_64k_hz_cycle {
+                        midi_cycle_count += midispc.execute_step() as u32;
+                    }
+                    midi_cycle_count -= spc_64k_hz_cycle;
+                    // UIでの変更を取り込む（書き込み中であれば次の機会に回す）
+                    params_refresh_ticks += 1;
+                    if params_refresh_ticks >= SOURCE_PARAMETER_REFRESH_INTERVAL_TICKS {
+                        if let Ok(latest) = source_parameter.try_read() {
+                            params.clone_from(&latest);
+                            params_refresh_ticks = 0;
+                        }
+                    }
+                    // MIDI出力
+                    if let Some(msgs) = midispc.clock_tick_64k_hz() {
+                        velocity_scaler.update(&midispc.dsp, &params, flat_velocity);
+                        echo_send_scaler.update(&midispc.dsp, &params);
+                        pan_law_mapper.update(&midispc.dsp, &params);
+                        note_range_folder.update(&midispc.dsp, &params);
+                        sustain_inserter.update(&midispc.dsp, &params);
+                        if route_percussive_notes {
+                            percussive_router.update(&midispc.dsp, &params);
+                        }
+                        // MIDI出力のロック
+                        let mut conn_out = midi_out_conn.lock().unwrap();
+                        let mut held = held_notes.lock().unwrap();
+                        let mut send = |data: &[u8]| {
+                            let restored = held.restore_status(data);
+                            let data = restored.as_deref().unwrap_or(data);
+                            conn_out.send(data).unwrap();
+                            midi_bytes += data.len();
+                            level_estimator.apply(data);
+                            held.apply(data, Instant::now());
+                        };
+                        for i in 0..msgs.num_messages {
+                            // メッセージのコピーをその場で書き換える
+                            let mut msg = msgs.messages[i];
+                            let data = &mut msg.data[..msg.length];
+                            velocity_scaler.apply(data);
+                            echo_send_scaler.apply(data);
+                            pan_law_mapper.apply(data);
+                            note_range_folder.apply(data);
+                            // 振り替えはステータスバイトを補うため、有効なときだけ確保する
+                            let routed = route_percussive_notes.then(|| {
+                                let mut data = data.to_vec();
+                                percussive_router.apply(&mut data);
+                                data
+                            });
+                            let data = routed.as_deref().unwrap_or(data);
+                            match sustain_inserter.process(data) {
+                                Some(msgs) => msgs.iter().for_each(|data| send(data)),
+                                None => send(data),
+                            }
+                        }
+                        midi_output_level
+                            .store(level_estimator.level().to_bits(), Ordering::Relaxed);
+                    }
+                    // 一定間隔で最大時間を超えて鳴り続けているノートを止める
+                    // 出力中のメッセージ列に割り込むため、このスレッドから送る
+                    stuck_note_check_ticks += 1;
+                    if stuck_note_check_ticks >= STUCK_NOTE_CHECK_INTERVAL_TICKS {
+                        stuck_note_check_ticks = 0;
+                        let timeout_sec = stuck_note_timeout_sec.load(Ordering::Relaxed);
+                        if timeout_sec > 0 {
+                            let mut conn_out = midi_out_conn.lock().unwrap();
+                            let mut held = held_notes.lock().unwrap();
+                            for data in held.take_stuck_note_offs(
+                                Instant::now(),
+                                Duration::from_secs(timeout_sec as u64),
+                            ) {
+                                conn_out.send(&data).unwrap();
+                                midi_bytes += data.len();
+                            }
+                        }
+                    }
+                    midi_output_bytes.store(midi_bytes, Ordering::Relaxed);
+                }
+                // ビジーループで待つ
+                next += interval;
+                while Instant::now() < next {
+                    thread::yield_now();
+                }
+            }
+            midi_output_level.store(0, Ordering::Relaxed);
+            // 踏まれたままのサステインペダルを離す
+            let mut conn_out = midi_out_conn.lock().unwrap();
+            for data in sustain_inserter.release_all() {
+                conn_out.send(&data).unwrap();
+            }
+        });
+
+        // 再生開始
+        self.stream_is_playing.store(true, Ordering::Relaxed);
+        stream.play()?;
+        self.stream = Some(stream);
+        self.playback_mode = PlaybackMode::Song;
+
+        Ok(())
+    }
+
+    // プレビュー再生開始
+    fn srn_play_start(&mut self, srn_no: u8) -> Result<(), PlayStreamError> {
+        // 再生対象の音源をコピー
+        let source = if let Some(srn) = self.decoded_source_info(srn_no) {
+            srn
+        } else {
+            return Ok(());
+        };
+
+        // オーディオデバイスの存在確認
+        if self.stream_device.is_none() || self.stream_config.is_none() {
+            return Err(PlayStreamError::DeviceNotAvailable);
+        }
+        let stream_device = self.stream_device.clone().unwrap();
+        let stream_config = self.stream_config.clone().unwrap();
+
+        let num_channels = stream_config.channels as usize;
+        let is_playing = self.stream_is_playing.clone();
+        // ループ開始位置（必要ならBRRブロック境界に丸める）
+        let source_loop_start_sample = if self.snap_loop_point.load(Ordering::Relaxed) {
+            snap_to_brr_block_boundary(source.loop_start_sample)
+        } else {
+            source.loop_start_sample
+        };
+        let loop_start_sample = f64::round(
+            (source_loop_start_sample * stream_config.sample_rate as usize) as f64
+                / SPC_SAMPLING_RATE as f64,
+        ) as usize;
+
+        // 出力先デバイスのレートに合わせてレート変換
+        let resampled_pcm = convert(
+            SPC_SAMPLING_RATE,
+            stream_config.sample_rate,
+            1,
+            ConverterType::SincBestQuality,
+            &source.signal,
+        )
+        .unwrap();
+        let resampled_len = resampled_pcm.len();
+
+        // 音源はモノラルなので出力チャンネル数分コピー
+        let mut output = vec![0.0f32; resampled_len * num_channels];
+        for smpl in 0..resampled_len {
+            for ch in 0..num_channels {
+                output[ch as usize + num_channels * smpl] = resampled_pcm[smpl];
+            }
+        }
+        // ループ開始位置は出力サンプル数で上限をかける
+        let loop_start_progress = cmp::min(num_channels * loop_start_sample, output.len() - 1);
+
+        // ループフラグ・プレビューボリューム
+        let preview_loop = self.preview_loop.clone();
+        let preview_volume = self.preview_volume.clone();
+        const PREVIEW_VOLUME_NORMALIZE_VALUE: f32 = 1.0 / 128.0;
+
+        // 再生サンプル数（ワンショットのプレビュー再生なので再生サンプルはselfに保持しない）
+        let mut progress = 0;
+
+        // 再生ストリーム作成
+        let stream = match stream_device.build_output_stream(
+            &stream_config,
+            move |buffer: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                // 一旦バッファを無音で埋める
+                buffer.fill(0.0);
+                // バッファにコピー
+                let num_copy_samples = cmp::min(output.len() - progress, buffer.len());
+                // 出力ボリュームを適用しながらバッファにコピー
+                let volume =
+                    preview_volume.load(Ordering::Relaxed) as f32 * PREVIEW_VOLUME_NORMALIZE_VALUE;
+                for smpl in 0..num_copy_samples {
+                    buffer[smpl] = volume * output[progress + smpl];
+                }
+                progress += num_copy_samples;
+                // 端点に来た時の処理
+                if progress >= output.len() {
+                    if preview_loop.load(Ordering::Relaxed) {
+                        // ループしながらバッファがいっぱいになるまでコピー
+                        let mut buffer_pos = num_copy_samples;
+                        progress = loop_start_progress;
+                        while buffer_pos < buffer.len() {
+                            let num_copy_samples =
+                                cmp::min(output.len() - progress, buffer.len() - buffer_pos);
+                            // 出力ボリュームを適用しながらバッファにコピー
+                            let volume = preview_volume.load(Ordering::Relaxed) as f32
+                                * PREVIEW_VOLUME_NORMALIZE_VALUE;
+                            for smpl in 0..num_copy_samples {
+                                buffer[buffer_pos + smpl] = volume * output[progress + smpl];
+                            }
+                            buffer_pos += num_copy_samples;
+                            progress += num_copy_samples;
+                            if progress >= output.len() {
+                                progress = loop_start_progress;
+                            }
+                        }
+                    } else {
+                        // 再生終了
+                        is_playing.store(false, Ordering::Relaxed);
+                    }
+                }
+            },
+            |err| eprintln!("[{}] {err}", SPC2MIDI2_TITLE_STR),
+            None,
+        ) {
+            Ok(stream) => stream,
+            Err(_) => return Err(PlayStreamError::DeviceNotAvailable),
+        };
+
+        // 再生開始
+        self.stream_is_playing.store(true, Ordering::Relaxed);
+        stream.play()?;
+        self.stream = Some(stream);
+        self.playback_mode = PlaybackMode::Preview(srn_no);
+
+        Ok(())
+    }
+
+    // 現在の再生モード（ワンショットのプレビューはストリーム側で終了するためフラグも確認）
+    fn playback_mode(&self) -> PlaybackMode {
+        if self.stream_is_playing.load(Ordering::Relaxed) {
+            self.playback_mode
+        } else {
+            PlaybackMode::None
+        }
+    }
+
+    // MIDIの全ての音を止める
+    fn stop_midi_all_sound(&mut self) {
+        if let Some(midi_out_conn_ref) = &self.midi_out_conn {
+            let midi_out_conn = midi_out_conn_ref.clone();
+            let mut conn_out = midi_out_conn.lock().unwrap();
+            for ch in 0..16 {
+                conn_out
+                    .send(&[MIDIMSG_MODE | ch, MIDIMSG_MODE_ALL_SOUND_OFF, 0])
+                    .unwrap();
+            }
+        }
+    }
+
+    // MIDI側のDSPに設定するチャンネルミュートフラグ（除外したボイスは常にミュート）
+    fn midi_channel_mute_flags(&self, flags: u8) -> u8 {
+        flags | self.midi_output_configure.read().unwrap().excluded_voices
+    }
+
+    // DSPのチャンネルミュートフラグを設定（新たにミュートしたチャンネルのMIDIの音は止める）
+    fn set_channel_mute_flags(&mut self, new_flags: u8) {
+        let flags = self.channel_mute_flags.load(Ordering::Relaxed);
+        if new_flags == flags {
+            return;
+        }
+        if let (Some(pcm_spc_ref), Some(midi_spc_ref)) = (&self.pcm_spc, &self.midi_spc) {
+            let (pcm_spc, midi_spc) = (pcm_spc_ref.clone(), midi_spc_ref.clone());
+            let pcm_on = self.pcm_spc_on.load(Ordering::Relaxed);
+            pcm_spc.lock().unwrap().dsp.write_register(
+                &[0u8],
+                DSP_ADDRESS_CHANNEL_MUTE,
+                if pcm_on { new_flags } else { 0xFF },
+            );
+            let midi_on = self.midi_spc_on.load(Ordering::Relaxed);
+            midi_spc.lock().unwrap().dsp.write_register(
+                &[0u8],
+                DSP_ADDRESS_CHANNEL_MUTE,
+                if midi_on {
+                    self.midi_channel_mute_flags(new_flags)
+                } else {
+                    0xFF
+                },
+            );
+        }
+        for ch in 0..8 {
+            if (new_flags & !flags) & (1 << ch) != 0 {
+                self.stop_midi_channel_sound(ch);
+            }
+        }
+        self.channel_mute_flags.store(new_flags, Ordering::Relaxed);
+    }
+
+    // 曲中ソロの音源を鳴らしているボイス以外をミュート
+    fn update_source_solo(&mut self) {
+        if let Some((srn_no, _)) = self.source_solo {
+            let status = self.playback_status.read().unwrap();
+            let mut new_flags = 0xFF;
+            for ch in 0..8 {
+                if status.srn_no[ch] == srn_no {
+                    new_flags &= !(1 << ch);
+                }
+            }
+            drop(status);
+            self.set_channel_mute_flags(new_flags);
+        }
+    }
+
+    // 曲中ソロを解除してソロ開始前のミュート状態に戻す
+    fn release_source_solo(&mut self) {
+        if let Some((_, saved_flags)) = self.source_solo.take() {
+            self.set_channel_mute_flags(saved_flags);
+        }
+    }
+
+    // MIDIの特定チャンネルの音を止める
+    fn stop_midi_channel_sound(&mut self, ch: u8) {
+        if let Some(midi_out_conn_ref) = &self.midi_out_conn {
+            let midi_out_conn = midi_out_conn_ref.clone();
+            let mut conn_out = midi_out_conn.lock().unwrap();
+            // ATENSION! MIDIVoiceは0..7chにある前提
+            conn_out
+                .send(&[MIDIMSG_MODE | ch, MIDIMSG_MODE_ALL_SOUND_OFF, 0])
+                .unwrap();
+        }
+    }
+
+    // 再生停止
+    fn stream_play_stop(&mut self) -> Result<(), PauseStreamError> {
+        if let Some(stream) = &self.stream {
+            self.stream_is_playing.store(false, Ordering::Relaxed);
+            stream.pause()?;
+            self.stream = None;
+        }
+        self.playback_mode = PlaybackMode::None;
+        self.stop_midi_all_sound();
+        self.release_held_notes();
+        Ok(())
+    }
+
+    // 鳴っているノートにノートオフを送る（再生スレッドの停止後に呼ぶ）
+    fn release_held_notes(&mut self) {
+        let notes = self
+            .held_notes
+            .lock()
+            .unwrap()
+            .take_notes(Instant::now(), None);
+        if let Some(midi_out_conn_ref) = &self.midi_out_conn {
+            let mut conn_out = midi_out_conn_ref.lock().unwrap();
+            for (ch, note) in notes {
+                conn_out.send(&[MIDIMSG_NOTE_OFF | ch, note, 0]).unwrap();
+            }
+        }
+    }
+
+    // MIDI楽器音をプレビュー
+    fn preview_midi_sound(&self, srn_no: u8) {
+        // 再生時のパラメータ設定
+        let params = self.source_parameter.read().unwrap();
+        let param = params.get(&srn_no).unwrap();
+        let program = param.program.clone() as u8;
+        let velocity = param.noteon_velocity;
+        let note = (param.center_note >> 9) as u8;
+
+        // MIDI出力の作成
+        let midi_out_conn = if let Some(midi_out_conn_ref) = &self.midi_out_conn {
+            midi_out_conn_ref.clone()
+        } else {
+            // TODO: エラーにした方が良い
+            return;
+        };
+        let mut conn_out = midi_out_conn.lock().unwrap();
+
+        // ノートオン
+        if program < 0x80 {
+            conn_out
+                .send(&[MIDIMSG_PROGRAM_CHANGE | MIDI_PREVIEW_CHANNEL, program])
+                .unwrap();
+            conn_out
+                .send(&[MIDIMSG_NOTE_ON | MIDI_PREVIEW_CHANNEL, note, velocity])
+                .unwrap();
+        } else {
+            // ドラム音色
+            conn_out
+                .send(&[MIDIMSG_NOTE_ON | 0x9, program - 0x80, velocity])
+                .unwrap();
+        }
+
+        // プレビュー時間流す
+        thread::sleep(Duration::from_millis(MIDI_PREVIEW_DURATION_MSEC));
+
+        // ノートオフ
+        if program < 0x80 {
+            conn_out
+                .send(&[MIDIMSG_NOTE_OFF | MIDI_PREVIEW_CHANNEL, note, 0])
+                .unwrap();
+        } else {
+            // ドラム音色
+            conn_out
+                .send(&[MIDIMSG_NOTE_OFF | 0x9, program - 0x80, 0])
+                .unwrap();
+        }
+    }
+
+    // グリッド表示の指定した行の音源の波形サムネイルを作成（作成済みで波形が変わっていなければそのまま）
+    fn update_source_thumbnails(&mut self, first_row: usize, num_rows: usize) {
+        let main_window_id = self.main_window_id;
+        let (srns, missing): (Vec<u8>, Vec<u8>) = {
+            let infos = self.source_infos.read().unwrap();
+            let thumbnails = match self.windows.get_mut(&main_window_id) {
+                Some(window) => {
+                    let main_win: &mut MainWindow =
+                        window.as_mut().as_any_mut().downcast_mut().unwrap();
+                    &main_win.thumbnails
+                }
+                None => return,
+            };
+            let missing = infos
+                .iter()
+                .skip(first_row * SOURCE_GRID_COLUMNS)
+                .take(num_rows * SOURCE_GRID_COLUMNS)
+                .filter(|(srn, info)| {
+                    !thumbnails
+                        .get(srn)
+                        .is_some_and(|(start_address, _)| *start_address == info.start_address)
+                })
+                .map(|(srn, _)| *srn)
+                .collect();
+            (infos.keys().cloned().collect(), missing)
+        };
+        // 波形を解放している場合もあるのでデコードし直した波形から作る
+        let created: Vec<_> = missing
+            .into_iter()
+            .filter_map(|srn| self.decoded_source_info(srn))
+            .filter(|info| !info.signal.is_empty())
+            .map(|info| {
+                (
+                    info.start_address,
+                    Arc::new(thumbnail_peaks(&info.signal, SOURCE_THUMBNAIL_NUM_PEAKS)),
+                )
+            })
+            .collect();
+        if let Some(window) = self.windows.get_mut(&main_window_id) {
+            let main_win: &mut MainWindow = window.as_mut().as_any_mut().downcast_mut().unwrap();
+            // 解析し直して無くなった音源のサムネイルは捨てる
+            main_win.thumbnails.retain(|srn, _| srns.contains(srn));
+            for (srn, thumbnail) in created {
+                main_win.thumbnails.insert(srn, thumbnail);
+            }
+        }
+    }
+
+    // メインウィンドウ以外のウィンドウを閉じるタスク
+    fn close_sub_windows(&self) -> Vec<Task<Message>> {
+        self.windows
+            .keys()
+            .filter(|id| **id != self.main_window_id)
+            .map(|id| window::close(*id))
+            .collect()
+    }
+
+    // 音源パラメータを比較用の文字列にしたもの
+    fn source_parameter_snapshot(&self) -> String {
+        serde_json::to_string(&*self.source_parameter.read().unwrap()).unwrap_or_default()
+    }
+
+    // 読み込み・JSON保存の後に音源パラメータが変更されているか
+    fn has_unsaved_source_parameter(&self) -> bool {
+        self.source_parameter_snapshot() != self.saved_source_parameter
+    }
+
+    // 接続中のMIDI出力にドラムキットの選択を送信
+    fn send_drum_kit_select(&self, config: &MIDIOutputConfigure) {
+        if let Some(midi_out_conn_ref) = &self.midi_out_conn {
+            let mut conn_out = midi_out_conn_ref.lock().unwrap();
+            for msg in drum_kit_select_messages(&config.midi_system, config.drum_kit) {
+                let _ = conn_out.send(&msg);
+            }
+        }
+    }
+
+    // キーボード演奏のノートオン（キーリピートなど押下中のキーは無視）
+    fn keyboard_note_on(&mut self, srn_no: u8, key_offset: u8) {
+        if self
+            .keyboard_held_notes
+            .iter()
+            .any(|held| held.srn_no == srn_no && held.key_offset == key_offset)
+        {
+            return;
+        }
+        let midi_out_conn = if let Some(midi_out_conn_ref) = &self.midi_out_conn {
+            midi_out_conn_ref.clone()
+        } else {
+            return;
+        };
+
+        // 中心ノートのオクターブのCを基準にキーを割り当てる
+        let params = self.source_parameter.read().unwrap();
+        let param = params.get(&srn_no).unwrap();
+        let program = param.program.clone() as u8;
+        let velocity = param.noteon_velocity;
+        let (channel, note) = if program < 0x80 {
+            let base_note = ((param.center_note >> 9) as u8 / 12) * 12;
+            (
+                MIDI_PREVIEW_CHANNEL,
+                cmp::min(base_note as u32 + key_offset as u32, 127) as u8,
+            )
+        } else {
+            // ドラム音色はキーによらず音色のノートを鳴らす
+            (0x9, program - 0x80)
+        };
+
+        let mut conn_out = midi_out_conn.lock().unwrap();
+        if program < 0x80 {
+            let _ = conn_out.send(&[MIDIMSG_PROGRAM_CHANGE | channel, program]);
+        }
+        let _ = conn_out.send(&[MIDIMSG_NOTE_ON | channel, note, velocity]);
+        self.keyboard_held_notes.push(KeyboardHeldNote {
+            srn_no: srn_no,
+            key_offset: key_offset,
+            channel: channel,
+            note: note,
+        });
+    }
+
+    // キーボード演奏のノートオフ（ノートオン時のチャンネル・ノートで止める）
+    fn keyboard_note_off(&mut self, srn_no: u8, key_offset: u8) {
+        let released: Vec<_> = self
+            .keyboard_held_notes
+            .iter()
+            .filter(|held| held.srn_no == srn_no && held.key_offset == key_offset)
+            .copied()
+            .collect();
+        self.send_keyboard_note_offs(&released);
+    }
+
+    // 音源のキーボード演奏で押下中のノートを全て止める
+    fn release_keyboard_notes(&mut self, srn_no: u8) {
+        let released: Vec<_> = self
+            .keyboard_held_notes
+            .iter()
+            .filter(|held| held.srn_no == srn_no)
+            .copied()
+            .collect();
+        self.send_keyboard_note_offs(&released);
+    }
+
+    // キーボード演奏のノートオフを送って押下中のノートから除く
+    fn send_keyboard_note_offs(&mut self, released: &[KeyboardHeldNote]) {
+        self.keyboard_held_notes
+            .retain(|held| !released.contains(held));
+        if let Some(midi_out_conn_ref) = &self.midi_out_conn {
+            let mut conn_out = midi_out_conn_ref.lock().unwrap();
+            for held in released {
+                let _ = conn_out.send(&[MIDIMSG_NOTE_OFF | held.channel, held.note, 0]);
+            }
+        }
+    }
+
+    // 音源パラメータをDSPに適用
+    fn apply_source_parameter(&mut self) {
+        if let (Some(midi_spc_ref), Some(spc_file)) = (&self.midi_spc, &self.spc_file) {
+            let midi_spc = midi_spc_ref.clone();
+            let config = self.midi_output_configure.read().unwrap();
+            let params = self.source_parameter.read().unwrap();
+            let mut midispc = midi_spc.lock().unwrap();
+            apply_source_parameter(&mut midispc, &config, &params, &spc_file.ram);
+            // 記録済みの変更はDSPに反映された
+            for entry in self.parameter_log.write().unwrap().iter_mut() {
+                entry.applied = true;
+            }
+        }
+    }
+}
+
+/// 音源パラメータをDSPに適用
 fn apply_source_parameter(
     spc: &mut spc700::spc::SPC<spc700::mididsp::MIDIDSP>,
     config: &MIDIOutputConfigure,
     source_params: &BTreeMap<u8, SourceParameter>,
     ram: &[u8],
 ) {
-    // 音源に依存するパラメータ
-    for (srn_no, param) in source_params.iter() {
-        spc.dsp.write_register(ram, DSP_ADDRESS_SRN_TARGET, *srn_no);
-        let mut flag = 0;
-        if param.mute {
-            flag |= 0x80;
+    // 音源に依存するパラメータ
+    for (srn_no, param) in source_params.iter() {
+        spc.dsp.write_register(ram, DSP_ADDRESS_SRN_TARGET, *srn_no);
+        let mut flag = 0;
+        if param.mute {
+            flag |= 0x80;
+        }
+        if param.envelope_as_expression {
+            flag |= 0x40;
+        }
+        if param.update_parameter_after_noteon {
+            flag |= 0x20;
+        }
+        spc.dsp.write_register(ram, DSP_ADDRESS_SRN_FLAG, flag);
+        spc.dsp
+            .write_register(ram, DSP_ADDRESS_SRN_PROGRAM, param.program.clone() as u8);
+        spc.dsp
+            .write_register(ram, DSP_ADDRESS_SRN_NOTEON_VELOCITY, param.noteon_velocity);
+        spc.dsp.write_register(
+            ram,
+            DSP_ADDRESS_SRN_CENTER_NOTE_HIGH,
+            ((param.center_note >> 8) & 0xFF) as u8,
+        );
+        spc.dsp.write_register(
+            ram,
+            DSP_ADDRESS_SRN_CENTER_NOTE_LOW,
+            ((param.center_note >> 0) & 0xFF) as u8,
+        );
+        spc.dsp.write_register(
+            ram,
+            DSP_ADDRESS_SRN_VOLUME,
+            if param.auto_volume { 0x80 } else { 0x00 } | param.fixed_volume,
+        );
+        spc.dsp.write_register(
+            ram,
+            DSP_ADDRESS_SRN_PAN,
+            if param.auto_pan { 0x80 } else { 0x00 } | param.fixed_pan,
+        );
+        // 出力先シンセの最大幅に制限する場合はRPNとベンド値の両方がその幅に収まる
+        let pitch_bend_width = if config.clamp_pitch_bend_width {
+            cmp::min(param.pitch_bend_width, config.target_pitch_bend_width)
+        } else {
+            param.pitch_bend_width
+        };
+        spc.dsp.write_register(
+            ram,
+            DSP_ADDRESS_SRN_PITCHBEND_SENSITIVITY,
+            if param.enable_pitch_bend { 0x80 } else { 0x00 } | pitch_bend_width,
+        );
+        spc.dsp.write_register(
+            ram,
+            DSP_ADDRESS_SRN_REVERB_SEND,
+            if param.echo_as_reverb_send {
+                0x80
+            } else {
+                0x00
+            } | param.fixed_reverb_send,
+        );
+        spc.dsp
+            .write_register(ram, DSP_ADDRESS_SRN_CHORUS_SEND, param.chorus_send);
+        for ch in 0..8 {
+            let value = if param.channel_mute[ch] { 0x80 } else { 0x00 }
+                | (ch << 4) as u8
+                | param.channel_routing[ch];
+            spc.dsp
+                .write_register(ram, DSP_ADDRESS_SRN_CHANNEL_ROUTING, value);
+        }
+    }
+    // 音源に依存しないパラメータ
+    spc.dsp.write_register(
+        ram,
+        DSP_ADDRESS_PLAYBACK_PARAMETER_UPDATE_PERIOD,
+        config.playback_parameter_update_period,
+    );
+    let mut flag = 0;
+    match config.volume_curve {
+        VolumeCurve::SquareRoot => flag |= 0x00,
+        VolumeCurve::Log => flag |= 0x40,
+        VolumeCurve::Linear => flag |= 0x80,
+    }
+    spc.dsp
+        .write_register(ram, DSP_ADDRESS_CONFIGURE_FLAG, flag);
+}
+
+/// 出力されたMIDIメッセージを、そのMIDIチャンネルに出力しているSPCのボイスに対応付ける
+/// 同じMIDIチャンネルに複数のボイスが出力している場合は、ティック内のメッセージがボイス番号順に出力されるものとみなす
+#[derive(Debug, Default)]
+struct VoiceChannelMap {
+    /// ボイスごとの出力先MIDIチャンネル（パラメータの無い音源のボイスはNone）
+    midi_ch: [Option<u8>; 8],
+    /// ノートオンフラグ
+    noteon: u8,
+    /// 今回のティックで新たにキーオンされたボイス
+    keyon: u8,
+    /// 今回のティックでMIDIチャンネルごとに対応付けたメッセージ数
+    assigned: [usize; 16],
+}
+
+impl VoiceChannelMap {
+    /// ティック直後のDSPの状態からボイスごとの出力先を更新
+    fn update(
+        &mut self,
+        dsp: &spc700::mididsp::MIDIDSP,
+        source_params: &BTreeMap<u8, SourceParameter>,
+    ) {
+        let noteon = dsp.read_register(&[0u8], DSP_ADDRESS_NOTEON);
+        self.keyon = noteon & !self.noteon;
+        self.noteon = noteon;
+        for ch in 0..8 {
+            let srn_no = dsp.read_register(&[0u8], DSP_ADDRESS_V0SRCN | ((ch as u8) << 4));
+            self.midi_ch[ch] = source_params
+                .get(&srn_no)
+                .map(|param| param.channel_routing[ch] & 0xF);
+        }
+        self.assigned = [0; 16];
+    }
+
+    /// 候補のボイスのうちMIDIチャンネルに出力しているものを順に対応付ける
+    /// 候補が足りなければ最後のボイスに対応付ける
+    fn next_voice_in(&mut self, midi_ch: u8, candidates: u8) -> Option<usize> {
+        let mut voices =
+            (0..8).filter(|&ch| (candidates >> ch) & 1 != 0 && self.midi_ch[ch] == Some(midi_ch));
+        let count = self.assigned[midi_ch as usize];
+        let voice = voices.clone().nth(count).or_else(|| voices.next_back())?;
+        self.assigned[midi_ch as usize] = count + 1;
+        Some(voice)
+    }
+
+    /// ノートオンを出力したボイスを返す（新たにキーオンされたボイスを優先）
+    fn next_keyon_voice(&mut self, midi_ch: u8) -> Option<usize> {
+        self.next_voice_in(midi_ch, self.keyon)
+            .or_else(|| self.next_voice_in(midi_ch, self.noteon))
+    }
+
+    /// コントロールチェンジ等を出力したボイスを返す（発音中のボイスを優先）
+    fn next_voice(&mut self, midi_ch: u8) -> Option<usize> {
+        self.next_voice_in(midi_ch, self.noteon)
+            .or_else(|| self.next_voice_in(midi_ch, 0xFF))
+    }
+}
+
+/// キーオン時のチャンネルボリュームからノートオンベロシティを決める
+/// 一律のベロシティが指定された場合は全てのノートオンベロシティを置き換える
+struct KeyOnVelocityScaler {
+    /// ランニングステータス
+    status_byte: u8,
+    /// ボイスと出力先MIDIチャンネルの対応
+    voices: VoiceChannelMap,
+    /// 一律のベロシティ
+    flat_velocity: Option<u8>,
+    /// ボイスごとの置き換え後ベロシティ
+    velocity: [Option<u8>; 8],
+}
+
+impl KeyOnVelocityScaler {
+    fn new() -> Self {
+        Self {
+            status_byte: 0,
+            voices: VoiceChannelMap::default(),
+            flat_velocity: None,
+            velocity: [None; 8],
+        }
+    }
+
+    /// ティック直後のDSPの状態からボイスごとのベロシティを計算
+    fn update(
+        &mut self,
+        dsp: &spc700::mididsp::MIDIDSP,
+        source_params: &BTreeMap<u8, SourceParameter>,
+        flat_velocity: Option<u8>,
+    ) {
+        self.voices.update(dsp, source_params);
+        self.flat_velocity = flat_velocity;
+        self.velocity = [None; 8];
+        if flat_velocity.is_some() {
+            return;
+        }
+        for ch in (0..8).filter(|ch| (self.voices.noteon >> ch) & 1 != 0) {
+            let ch_nibble = (ch as u8) << 4;
+            let srn_no = dsp.read_register(&[0u8], DSP_ADDRESS_V0SRCN | ch_nibble);
+            if let Some(param) = source_params.get(&srn_no) {
+                if !param.velocity_from_keyon_volume {
+                    continue;
+                }
+                // エンベロープの影響を受けないボリュームレジスタの値を使う
+                let lvol = dsp.read_register(&[0u8], DSP_ADDRESS_V0VOLL | ch_nibble) as i8;
+                let rvol = dsp.read_register(&[0u8], DSP_ADDRESS_V0VOLR | ch_nibble) as i8;
+                let volume = cmp::max(lvol.unsigned_abs(), rvol.unsigned_abs()).min(127);
+                let velocity = (param.noteon_velocity as u32 * volume as u32) / 127;
+                self.velocity[ch] = Some(velocity.clamp(1, 127) as u8);
+            }
+        }
+    }
+
+    /// ノートオンメッセージであればベロシティを置き換え
+    fn apply(&mut self, data: &mut [u8]) {
+        if data.len() < 2 {
+            return;
+        }
+        // ランニングステータスの更新
+        if (data[0] & 0x80) != 0 {
+            self.status_byte = data[0];
+        }
+        let velocity_index = data.len() - 1;
+        if (self.status_byte & 0xF0) == MIDIMSG_NOTE_ON && data[velocity_index] > 0 {
+            let velocity = self.flat_velocity.or_else(|| {
+                self.voices
+                    .next_keyon_voice(self.status_byte & 0xF)
+                    .and_then(|voice| self.velocity[voice])
+            });
+            if let Some(velocity) = velocity {
+                data[velocity_index] = velocity;
+            }
+        }
+    }
+}
+
+/// チャンネルごとに現在のプログラムを追跡し、プログラムごとの補正率でノートオンベロシティを補正する
+/// 楽器ごとの音量感の違いを揃えるためのもので、ドラムチャンネルは補正しない
+struct ProgramVelocityCalibrator {
+    /// ランニングステータス
+    status_byte: u8,
+    /// MIDIチャンネルごとの現在のプログラム
+    program: [u8; 16],
+    /// プログラムごとの補正率(%)
+    percent: [u8; 128],
+}
+
+impl ProgramVelocityCalibrator {
+    fn new(percent: [u8; 128]) -> Self {
+        Self {
+            status_byte: 0,
+            program: [0; 16],
+            percent: percent,
+        }
+    }
+
+    /// プログラムチェンジを記録し、ノートオンであればベロシティを補正
+    fn apply(&mut self, data: &mut [u8]) {
+        if data.is_empty() {
+            return;
+        }
+        // システムメッセージはランニングステータスに影響させない
+        if data[0] >= 0xF0 {
+            return;
+        }
+        // ランニングステータスの更新
+        if (data[0] & 0x80) != 0 {
+            self.status_byte = data[0];
+        }
+        let ch = (self.status_byte & 0xF) as usize;
+        let last_index = data.len() - 1;
+        match self.status_byte & 0xF0 {
+            MIDIMSG_PROGRAM_CHANGE => {
+                self.program[ch] = data[last_index] & 0x7F;
+            }
+            MIDIMSG_NOTE_ON if data.len() >= 2 && data[last_index] > 0 => {
+                if ch == MIDI_DRUM_CHANNEL as usize {
+                    return;
+                }
+                let percent = self.percent[self.program[ch] as usize] as u32;
+                let velocity = (data[last_index] as u32 * percent + 50) / 100;
+                data[last_index] = velocity.clamp(1, 127) as u8;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// エコーから変換したリバーブセンド（CC#91）を音源ごとの倍率でスケーリングし、マスターセンドを加算する
+struct EchoSendScaler {
+    /// ランニングステータス
+    status_byte: u8,
+    /// ボイスと出力先MIDIチャンネルの対応
+    voices: VoiceChannelMap,
+    /// ボイスごとの倍率(%)
+    scale_percent: [Option<u8>; 8],
+    /// 加算するマスターリバーブセンド
+    master_send: u8,
+}
+
+impl EchoSendScaler {
+    fn new(master_send: u8) -> Self {
+        Self {
+            status_byte: 0,
+            voices: VoiceChannelMap::default(),
+            scale_percent: [None; 8],
+            master_send: master_send,
+        }
+    }
+
+    /// ティック直後のDSPの状態からボイスごとの倍率を設定
+    fn update(
+        &mut self,
+        dsp: &spc700::mididsp::MIDIDSP,
+        source_params: &BTreeMap<u8, SourceParameter>,
+    ) {
+        self.voices.update(dsp, source_params);
+        self.scale_percent = [None; 8];
+        for ch in 0..8 {
+            let ch_nibble = (ch as u8) << 4;
+            let srn_no = dsp.read_register(&[0u8], DSP_ADDRESS_V0SRCN | ch_nibble);
+            if let Some(param) = source_params.get(&srn_no) {
+                if param.echo_as_reverb_send
+                    && param.echo_send_scale_percent != DEFAULT_ECHO_SEND_SCALE_PERCENT
+                {
+                    self.scale_percent[ch] = Some(param.echo_send_scale_percent);
+                }
+            }
+        }
+    }
+
+    /// リバーブセンドのコントロールチェンジであれば値をスケーリングしてマスターセンドを加算
+    fn apply(&mut self, data: &mut [u8]) {
+        if data.len() < 2 {
+            return;
+        }
+        // ランニングステータスの更新
+        if (data[0] & 0x80) != 0 {
+            self.status_byte = data[0];
+        }
+        let value_index = data.len() - 1;
+        if (self.status_byte & 0xF0) == MIDIMSG_MODE
+            && data[value_index - 1] == MIDIMSG_CC_REVERB_SEND
+        {
+            let mut value = data[value_index] as u32;
+            let voice = self.voices.next_voice(self.status_byte & 0xF);
+            if let Some(percent) = voice.and_then(|voice| self.scale_percent[voice]) {
+                value = (value * percent as u32) / 100;
+            }
+            data[value_index] = (value + self.master_send as u32).min(127) as u8;
+        }
+    }
+}
+
+/// 出力開始前に送られたチャンネルの状態（コントロールチェンジ・プログラムチェンジ・ピッチベンド）を記録する
+struct ChannelStateRecorder {
+    /// ランニングステータス
+    status_byte: u8,
+    /// 最後に送られた順の（ステータスバイト, コントロール番号, ステータスバイト付きのメッセージ）
+    messages: Vec<(u8, u8, Vec<u8>)>,
+}
+
+impl ChannelStateRecorder {
+    fn new() -> Self {
+        Self {
+            status_byte: 0,
+            messages: vec![],
+        }
+    }
+
+    /// メッセージが状態を変えるものであれば最新の値として記録
+    fn record(&mut self, data: &[u8]) {
+        if data.is_empty() || data[0] == 0xF0 {
+            return;
+        }
+        // ランニングステータスの更新（省略されている場合は補う）
+        let data = if (data[0] & 0x80) != 0 {
+            self.status_byte = data[0];
+            data.to_vec()
+        } else {
+            [&[self.status_byte][..], data].concat()
+        };
+        let key = match self.status_byte & 0xF0 {
+            MIDIMSG_MODE if data.len() == 3 => data[1],
+            MIDIMSG_PROGRAM_CHANGE | MIDIMSG_PITCH_BEND => 0,
+            _ => return,
+        };
+        let status_byte = self.status_byte;
+        self.messages
+            .retain(|(status, k, _)| !(*status == status_byte && *k == key));
+        self.messages.push((status_byte, key, data));
+    }
+
+    /// 記録した状態を復元するメッセージ列を返す
+    /// RPNの設定順が崩れないようコントロールチェンジは送られた順のまま先に送り、バンクセレクトの後にプログラムチェンジを送る
+    fn restore_messages(self) -> Vec<Vec<u8>> {
+        let mut messages = self.messages;
+        messages.sort_by_key(|(status, _, _)| match status & 0xF0 {
+            MIDIMSG_MODE => 0,
+            MIDIMSG_PROGRAM_CHANGE => 1,
+            _ => 2,
+        });
+        messages.into_iter().map(|(_, _, data)| data).collect()
+    }
+}
+
+/// ウィンドウの表示スケールの変化（別のモニタへの移動など）をメッセージにする
+fn window_rescaled_message(
+    event: iced::Event,
+    _status: event::Status,
+    id: window::Id,
+) -> Option<Message> {
+    match event {
+        iced::Event::Window(window::Event::Rescaled(factor)) => {
+            Some(Message::WindowRescaled(id, factor))
+        }
+        _ => None,
+    }
+}
+
+/// 直近のアンダーランの回数からオーディオ出力の状態を判定
+fn audio_health(recent_underruns: usize) -> AudioHealth {
+    if recent_underruns == 0 {
+        AudioHealth::Good
+    } else if recent_underruns < AUDIO_HEALTH_POOR_UNDERRUNS {
+        AudioHealth::Degraded
+    } else {
+        AudioHealth::Poor
+    }
+}
+
+/// 出力開始時に送るマスターリバーブセンド（CC#91）のイベントを作成
+fn master_reverb_send_event(midi_ch: u8, send: u8) -> TrackEvent {
+    TrackEvent {
+        vtime: 0,
+        event: MidiEvent::Midi(MidiMessage::from_bytes(vec![
+            MIDIMSG_MODE | midi_ch,
+            MIDIMSG_CC_REVERB_SEND,
+            send.min(127),
+        ])),
+    }
+}
+
+/// 振幅をレベルメーター表示用のdB値に変換（メーターの下限で打ち切る）
+fn amplitude_to_meter_db(amplitude: f32) -> f32 {
+    (20.0 * f32::log10(amplitude.max(1.0e-6))).max(LEVEL_METER_MIN_DB)
+}
+
+/// サンプル位置を最も近いBRRブロック（16サンプル）境界に丸める
+fn snap_to_brr_block_boundary(sample: usize) -> usize {
+    ((sample + BRR_BLOCK_NUM_SAMPLES / 2) / BRR_BLOCK_NUM_SAMPLES) * BRR_BLOCK_NUM_SAMPLES
+}
+
+/// 信号を区間に分け、区間ごとの（最小値, 最大値）に間引く
+fn thumbnail_peaks(signal: &[f32], num_peaks: usize) -> Vec<(f32, f32)> {
+    if signal.is_empty() || num_peaks == 0 {
+        return vec![];
+    }
+    let chunk_size = signal.len().div_ceil(num_peaks);
+    signal
+        .chunks(chunk_size)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .fold((0.0f32, 0.0f32), |(min, max), &x| (min.min(x), max.max(x)))
+        })
+        .collect()
+}
+
+/// ドラムチャンネルでドラムキットを選択するメッセージ列を作成（GM2/GS/XG以外は空）
+fn drum_kit_select_messages(system: &MIDISystem, kit: DrumKit) -> Vec<Vec<u8>> {
+    let bank_msb = match system {
+        MIDISystem::GMLevel2 => 0x78,
+        MIDISystem::GS => 0x00,
+        MIDISystem::XG => 0x7F,
+        MIDISystem::NONE | MIDISystem::GMLevel1 => return Vec::new(),
+    };
+    vec![
+        vec![
+            MIDIMSG_MODE | MIDI_DRUM_CHANNEL,
+            MIDIMSG_CC_BANK_SELECT_MSB,
+            bank_msb,
+        ],
+        vec![
+            MIDIMSG_MODE | MIDI_DRUM_CHANNEL,
+            MIDIMSG_CC_BANK_SELECT_LSB,
+            0x00,
+        ],
+        vec![MIDIMSG_PROGRAM_CHANGE | MIDI_DRUM_CHANNEL, kit.program()],
+    ]
+}
+
+/// SPCの値から出力したパン（CC#10）を音源ごとのパンローで変換する
+struct PanLawMapper {
+    /// ランニングステータス
+    status_byte: u8,
+    /// ボイスと出力先MIDIチャンネルの対応
+    voices: VoiceChannelMap,
+    /// ボイスごとのパンロー
+    pan_law: [Option<PanLaw>; 8],
+}
+
+impl PanLawMapper {
+    fn new() -> Self {
+        Self {
+            status_byte: 0,
+            voices: VoiceChannelMap::default(),
+            pan_law: [None; 8],
+        }
+    }
+
+    /// ティック直後のDSPの状態からボイスごとのパンローを設定
+    fn update(
+        &mut self,
+        dsp: &spc700::mididsp::MIDIDSP,
+        source_params: &BTreeMap<u8, SourceParameter>,
+    ) {
+        self.voices.update(dsp, source_params);
+        self.pan_law = [None; 8];
+        for ch in 0..8 {
+            let ch_nibble = (ch as u8) << 4;
+            let srn_no = dsp.read_register(&[0u8], DSP_ADDRESS_V0SRCN | ch_nibble);
+            if let Some(param) = source_params.get(&srn_no) {
+                if param.auto_pan && param.pan_law != PanLaw::Linear {
+                    self.pan_law[ch] = Some(param.pan_law);
+                }
+            }
+        }
+    }
+
+    /// パンのコントロールチェンジであれば値を変換
+    fn apply(&mut self, data: &mut [u8]) {
+        if data.len() < 2 {
+            return;
+        }
+        // ランニングステータスの更新
+        if (data[0] & 0x80) != 0 {
+            self.status_byte = data[0];
+        }
+        let value_index = data.len() - 1;
+        if (self.status_byte & 0xF0) == MIDIMSG_MODE && data[value_index - 1] == MIDIMSG_CC_PAN {
+            let voice = self.voices.next_voice(self.status_byte & 0xF);
+            if let Some(pan_law) = voice.and_then(|voice| self.pan_law[voice]) {
+                data[value_index] = pan_law.map(data[value_index]);
+            }
+        }
+    }
+}
+
+/// 音色がドラム以外の音源のキーオンを発音ごとに判定し、打楽器的なものをドラムチャンネルに振り替える
+struct PercussiveNoteRouter {
+    /// 入力側のランニングステータス
+    status_byte: u8,
+    /// 前回ティック時のノートオンフラグ
+    previous_noteon: u8,
+    /// MIDIチャンネルごとの今回のキーオンの振り替え先ドラムノート
+    keyon_drum_note: [Option<u8>; 16],
+    /// 振り替え中のノート（元のチャンネル, 元のノート番号, ドラムノート）
+    routed_notes: Vec<(u8, u8, u8)>,
+}
+
+impl PercussiveNoteRouter {
+    /// 打楽器とみなす最小のアタックレート
+    const MIN_ATTACK_RATE: u8 = 0xE;
+    /// 打楽器とみなす最小のサステインレート（サステイン区間ですぐに消える）
+    const MIN_SUSTAIN_RATE: u8 = 0x14;
+    /// 打楽器とみなす最大のサステインレベル
+    const MAX_SUSTAIN_LEVEL: u8 = 3;
+    /// 低音（バスドラム）とみなす最大のピッチ
+    const MAX_KICK_PITCH: u16 = 0x0800;
+    /// 中音（スネア）とみなす最大のピッチ
+    const MAX_SNARE_PITCH: u16 = 0x1800;
+    /// GMドラムのノート番号（バスドラム・スネア・クローズハイハット）
+    const GM_DRUM_KICK: u8 = 36;
+    const GM_DRUM_SNARE: u8 = 38;
+    const GM_DRUM_CLOSED_HIHAT: u8 = 42;
+
+    fn new() -> Self {
+        Self {
+            status_byte: 0,
+            previous_noteon: 0,
+            keyon_drum_note: [None; 16],
+            routed_notes: vec![],
+        }
+    }
+
+    /// キーオン時のDSPの状態から打楽器的な発音か判定し、振り替え先のドラムノートを返す
+    fn classify_keyon(dsp: &spc700::mididsp::MIDIDSP, ch: usize) -> Option<u8> {
+        let ch_nibble = (ch as u8) << 4;
+        // ノイズはハイハットとみなす
+        if (dsp.read_register(&[0u8], DSP_ADDRESS_NOISE_ON) >> ch) & 1 != 0 {
+            return Some(Self::GM_DRUM_CLOSED_HIHAT);
+        }
+        // 立ち上がりが速く、すぐに減衰して消えるエンベロープを打楽器とみなす（GAINの場合は判定しない）
+        let adsr1 = dsp.read_register(&[0u8], DSP_ADDRESS_V0ADSR1 | ch_nibble);
+        let adsr2 = dsp.read_register(&[0u8], DSP_ADDRESS_V0ADSR2 | ch_nibble);
+        if (adsr1 & 0x80) == 0
+            || (adsr1 & 0xF) < Self::MIN_ATTACK_RATE
+            || (adsr2 >> 5) > Self::MAX_SUSTAIN_LEVEL
+            || (adsr2 & 0x1F) < Self::MIN_SUSTAIN_RATE
+        {
+            return None;
+        }
+        // ピッチの高さでドラムの種類を決める
+        let pitch = ((dsp.read_register(&[0u8], DSP_ADDRESS_V0PITCHH | ch_nibble) as u16 & 0x3F)
+            << 8)
+            | dsp.read_register(&[0u8], DSP_ADDRESS_V0PITCHL | ch_nibble) as u16;
+        Some(if pitch <= Self::MAX_KICK_PITCH {
+            Self::GM_DRUM_KICK
+        } else if pitch <= Self::MAX_SNARE_PITCH {
+            Self::GM_DRUM_SNARE
+        } else {
+            Self::GM_DRUM_CLOSED_HIHAT
+        })
+    }
+
+    /// ティック直後のDSPの状態から新たにキーオンされた発音を判定
+    fn update(
+        &mut self,
+        dsp: &spc700::mididsp::MIDIDSP,
+        source_params: &BTreeMap<u8, SourceParameter>,
+    ) {
+        let noteon = dsp.read_register(&[0u8], DSP_ADDRESS_NOTEON);
+        let keyon = noteon & !self.previous_noteon;
+        self.keyon_drum_note = [None; 16];
+        for ch in (0..8).filter(|ch| (keyon >> ch) & 1 != 0) {
+            let ch_nibble = (ch as u8) << 4;
+            let srn_no = dsp.read_register(&[0u8], DSP_ADDRESS_V0SRCN | ch_nibble);
+            if let Some(param) = source_params.get(&srn_no) {
+                let midi_ch = param.channel_routing[ch] & 0xF;
+                // 音階のある音源と指定された音源は振り替えない
+                if (param.program.clone() as u8) < 0x80
+                    && midi_ch != MIDI_DRUM_CHANNEL
+                    && !param.force_tonal
+                {
+                    self.keyon_drum_note[midi_ch as usize] = Self::classify_keyon(dsp, ch);
+                }
+            }
+        }
+        self.previous_noteon = noteon;
+    }
+
+    /// 振り替え対象のノートオン・ノートオフをドラムチャンネルに書き換え
+    /// 書き換えるとランニングステータスが崩れるため、チャンネルメッセージは常にステータスバイトを付ける
+    fn apply(&mut self, data: &mut Vec<u8>) {
+        if data.is_empty() || data[0] == 0xF0 {
+            return;
+        }
+        if (data[0] & 0x80) != 0 {
+            self.status_byte = data[0];
+        } else {
+            data.insert(0, self.status_byte);
+        }
+        if data.len() != 3 {
+            return;
+        }
+        let (ch, note, velocity) = (self.status_byte & 0xF, data[1], data[2]);
+        match self.status_byte & 0xF0 {
+            MIDIMSG_NOTE_ON if velocity > 0 => {
+                if let Some(drum_note) = self.keyon_drum_note[ch as usize].take() {
+                    self.routed_notes.push((ch, note, drum_note));
+                    data[0] = MIDIMSG_NOTE_ON | MIDI_DRUM_CHANNEL;
+                    data[1] = drum_note;
+                }
+            }
+            MIDIMSG_NOTE_ON | MIDIMSG_NOTE_OFF => {
+                if let Some(pos) = self
+                    .routed_notes
+                    .iter()
+                    .position(|&(c, n, _)| c == ch && n == note)
+                {
+                    let (_, _, drum_note) = self.routed_notes.remove(pos);
+                    data[0] = MIDIMSG_NOTE_OFF | MIDI_DRUM_CHANNEL;
+                    data[1] = drum_note;
+                    data[2] = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// ノート番号を範囲内に収める
+/// 範囲が1オクターブ以上あればオクターブ単位で折り返し、狭ければ範囲の端に丸める
+fn fold_note_into_range(note: u8, min_note: u8, max_note: u8) -> u8 {
+    if max_note < min_note || (min_note..=max_note).contains(&note) {
+        return note;
+    }
+    if max_note - min_note < 11 {
+        return note.clamp(min_note, max_note);
+    }
+    let mut note = note;
+    while note < min_note {
+        note += 12;
+    }
+    while note > max_note {
+        note -= 12;
+    }
+    note
+}
+
+/// 音源ごとのノート範囲外のノートを範囲内に折り返す
+struct NoteRangeFolder {
+    /// ランニングステータス
+    status_byte: u8,
+    /// ボイスと出力先MIDIチャンネルの対応
+    voices: VoiceChannelMap,
+    /// ボイスごとのノート範囲
+    note_range: [Option<(u8, u8)>; 8],
+    /// 折り返し中のノート（チャンネル, 元のノート番号, 出力したノート番号）
+    folded_notes: Vec<(u8, u8, u8)>,
+}
+
+impl NoteRangeFolder {
+    fn new() -> Self {
+        Self {
+            status_byte: 0,
+            voices: VoiceChannelMap::default(),
+            note_range: [None; 8],
+            folded_notes: vec![],
+        }
+    }
+
+    /// ティック直後のDSPの状態からボイスごとのノート範囲を設定
+    fn update(
+        &mut self,
+        dsp: &spc700::mididsp::MIDIDSP,
+        source_params: &BTreeMap<u8, SourceParameter>,
+    ) {
+        self.voices.update(dsp, source_params);
+        self.note_range = [None; 8];
+        for ch in 0..8 {
+            let ch_nibble = (ch as u8) << 4;
+            let srn_no = dsp.read_register(&[0u8], DSP_ADDRESS_V0SRCN | ch_nibble);
+            if let Some(param) = source_params.get(&srn_no) {
+                if param.min_note > 0 || param.max_note < 127 {
+                    self.note_range[ch] = Some((param.min_note, param.max_note));
+                }
+            }
+        }
+    }
+
+    /// ノートオンであればノートを範囲内に折り返し、対応するノートオフも同じノートに書き換え
+    fn apply(&mut self, data: &mut [u8]) {
+        if data.is_empty() {
+            return;
+        }
+        // ランニングステータスの更新（省略されている場合は先頭がノート番号）
+        let note_index = if (data[0] & 0x80) != 0 {
+            self.status_byte = data[0];
+            1
+        } else {
+            0
+        };
+        if data.len() != note_index + 2 {
+            return;
+        }
+        let ch = self.status_byte & 0xF;
+        let (note, velocity) = (data[note_index], data[note_index + 1]);
+        match self.status_byte & 0xF0 {
+            MIDIMSG_NOTE_ON if velocity > 0 => {
+                let voice = self.voices.next_keyon_voice(ch);
+                if let Some((min_note, max_note)) = voice.and_then(|voice| self.note_range[voice]) {
+                    let folded = fold_note_into_range(note, min_note, max_note);
+                    if folded != note {
+                        self.folded_notes.push((ch, note, folded));
+                        data[note_index] = folded;
+                    }
+                }
+            }
+            MIDIMSG_NOTE_ON | MIDIMSG_NOTE_OFF => {
+                if let Some(pos) = self
+                    .folded_notes
+                    .iter()
+                    .position(|&(c, n, _)| c == ch && n == note)
+                {
+                    let (_, _, folded) = self.folded_notes.remove(pos);
+                    data[note_index] = folded;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// レガート音源のノート境界にサステインペダル（CC#64）を挿入する
+/// ペダルは次のノートオンまで踏まれたままになるため、ノートの重なりが増える
+struct SustainPedalInserter {
+    /// 入力側のランニングステータス
+    status_byte: u8,
+    /// ペダルメッセージ挿入後、ステータスバイトを省略せずに出力するか
+    force_status: bool,
+    /// MIDIチャンネルごとのペダル挿入有効フラグ
+    enabled: [bool; 16],
+    /// MIDIチャンネルごとのペダルが踏まれているか
+    pedal_on: [bool; 16],
+}
+
+impl SustainPedalInserter {
+    fn new() -> Self {
+        Self {
+            status_byte: 0,
+            force_status: false,
+            enabled: [false; 16],
+            pedal_on: [false; 16],
+        }
+    }
+
+    /// ティック直後のDSPの状態からペダルを挿入するMIDIチャンネルを決める
+    fn update(
+        &mut self,
+        dsp: &spc700::mididsp::MIDIDSP,
+        source_params: &BTreeMap<u8, SourceParameter>,
+    ) {
+        let noteon = dsp.read_register(&[0u8], DSP_ADDRESS_NOTEON);
+        self.enabled = [false; 16];
+        for ch in (0..8).filter(|ch| (noteon >> ch) & 1 != 0) {
+            let ch_nibble = (ch as u8) << 4;
+            let srn_no = dsp.read_register(&[0u8], DSP_ADDRESS_V0SRCN | ch_nibble);
+            if let Some(param) = source_params.get(&srn_no) {
+                if param.sustain_pedal {
+                    self.enabled[(param.channel_routing[ch] & 0xF) as usize] = true;
+                }
+            }
+        }
+    }
+
+    /// メッセージを処理し、ペダル操作を含めた出力メッセージ列を返す
+    /// メッセージを書き換える必要が無ければNoneを返す（そのまま出力する）
+    fn process(&mut self, data: &[u8]) -> Option<Vec<Vec<u8>>> {
+        // システムメッセージはそのまま出力
+        if data.len() < 2 || data[0] >= 0xF0 {
+            return None;
+        }
+        // ランニングステータスの更新
+        let has_status = (data[0] & 0x80) != 0;
+        if has_status {
+            self.status_byte = data[0];
+            self.force_status = false;
+        }
+        let ch = self.status_byte & 0xF;
+        let velocity = data[data.len() - 1];
+        if (self.status_byte & 0xF0) != MIDIMSG_NOTE_ON
+            || velocity == 0
+            || !self.enabled[ch as usize]
+        {
+            if !has_status && self.force_status {
+                return Some(vec![[&[self.status_byte][..], data].concat()]);
+            }
+            return None;
+        }
+        // 前のノートのペダルを離してからノートオンし、再度ペダルを踏む
+        let mut msgs = vec![];
+        if self.pedal_on[ch as usize] {
+            msgs.push(vec![MIDIMSG_MODE | ch, MIDIMSG_CC_SUSTAIN_PEDAL, 0]);
+        }
+        if has_status {
+            msgs.push(data.to_vec());
+        } else {
+            msgs.push([&[self.status_byte][..], data].concat());
+        }
+        msgs.push(vec![MIDIMSG_MODE | ch, MIDIMSG_CC_SUSTAIN_PEDAL, 127]);
+        self.pedal_on[ch as usize] = true;
+        // 以降のランニングステータスはペダルのステータスと解釈されるため補う
+        self.force_status = true;
+        Some(msgs)
+    }
+
+    /// 踏まれたままのペダルを全て離すメッセージ列を返す
+    fn release_all(&mut self) -> Vec<Vec<u8>> {
+        let mut msgs = vec![];
+        for ch in 0..16 {
+            if self.pedal_on[ch as usize] {
+                msgs.push(vec![MIDIMSG_MODE | ch, MIDIMSG_CC_SUSTAIN_PEDAL, 0]);
+                self.pedal_on[ch as usize] = false;
+            }
+        }
+        if !msgs.is_empty() {
+            self.force_status = true;
+        }
+        msgs
+    }
+}
+
+/// 送出したMIDIメッセージから鳴っているノートとそのノートオン時刻を追跡する
+/// ノートオン・オフの対応が崩れて外部シンセで鳴りっぱなしになったノートを止めるために使う
+#[derive(Debug)]
+struct HeldNoteTracker {
+    /// 入力側のランニングステータス
+    status_byte: u8,
+    /// ノートオフ割り込み後、ステータスバイトを省略せずに出力するか
+    force_status: bool,
+    /// MIDIチャンネル・ノートごとのノートオン時刻（鳴っていなければNone）
+    noteon_time: [[Option<Instant>; 128]; 16],
+}
+
+impl HeldNoteTracker {
+    fn new() -> Self {
+        Self {
+            status_byte: 0,
+            force_status: false,
+            noteon_time: [[None; 128]; 16],
+        }
+    }
+
+    /// ノートオフ割り込み後のランニングステータスのメッセージにステータスバイトを補う
+    /// 補う必要が無ければNoneを返す
+    fn restore_status(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        if !self.force_status || data.is_empty() {
+            return None;
+        }
+        self.force_status = false;
+        if (data[0] & 0x80) == 0 {
+            return Some([&[self.status_byte][..], data].concat());
+        }
+        None
+    }
+
+    /// 最大時間を超えて鳴っているノートのノートオフを返す
+    /// 割り込んだノートオフ以降のランニングステータスは補って出力する
+    fn take_stuck_note_offs(&mut self, now: Instant, max_duration: Duration) -> Vec<Vec<u8>> {
+        let msgs: Vec<_> = self
+            .take_notes(now, Some(max_duration))
+            .into_iter()
+            .map(|(ch, note)| vec![MIDIMSG_NOTE_OFF | ch, note, 0])
+            .collect();
+        if !msgs.is_empty() {
+            self.force_status = true;
+        }
+        msgs
+    }
+
+    /// 送出したメッセージで状態を更新
+    fn apply(&mut self, data: &[u8], now: Instant) {
+        if data.is_empty() || data[0] >= 0xF0 {
+            return;
+        }
+        let body = if (data[0] & 0x80) != 0 {
+            self.status_byte = data[0];
+            &data[1..]
+        } else {
+            data
+        };
+        if body.len() < 2 {
+            return;
+        }
+        let ch = (self.status_byte & 0xF) as usize;
+        let note = (body[0] & 0x7F) as usize;
+        match self.status_byte & 0xF0 {
+            MIDIMSG_NOTE_ON if body[1] > 0 => {
+                // 鳴っているノートの再ノートオンは最初の時刻を残す
+                if self.noteon_time[ch][note].is_none() {
+                    self.noteon_time[ch][note] = Some(now);
+                }
+            }
+            MIDIMSG_NOTE_ON | MIDIMSG_NOTE_OFF => self.noteon_time[ch][note] = None,
+            MIDIMSG_MODE if body[0] == MIDIMSG_MODE_ALL_SOUND_OFF => {
+                self.noteon_time[ch] = [None; 128];
+            }
+            _ => {}
+        }
+    }
+
+    /// 鳴っているノート（最大時間を指定した場合はそれを超えて鳴っているものだけ）を（チャンネル, ノート）で取り出し、鳴っていない扱いにする
+    fn take_notes(&mut self, now: Instant, max_duration: Option<Duration>) -> Vec<(u8, u8)> {
+        let mut notes = vec![];
+        for ch in 0..16 {
+            for note in 0..128 {
+                if let Some(time) = self.noteon_time[ch][note] {
+                    if max_duration.is_none_or(|max| now.duration_since(time) > max) {
+                        self.noteon_time[ch][note] = None;
+                        notes.push((ch as u8, note as u8));
+                    }
+                }
+            }
+        }
+        notes
+    }
+}
+
+/// 送出したMIDIメッセージから音源側の出力振幅を推定する
+/// MIDIは波形を持たないため、ベロシティ・ボリューム・エクスプレッションを
+/// GM推奨のカーブ（それぞれ40log10(値/127)dB）で振幅に換算し、鳴っているノートのパワー和をとる
+struct MIDIOutputLevelEstimator {
+    /// 入力側のランニングステータス
+    status_byte: u8,
+    /// MIDIチャンネルごとのボリューム
+    volume: [u8; 16],
+    /// MIDIチャンネルごとのエクスプレッション
+    expression: [u8; 16],
+    /// MIDIチャンネル・ノートごとの鳴っているノートのベロシティ（0は消音）
+    velocity: [[u8; 128]; 16],
+}
+
+impl MIDIOutputLevelEstimator {
+    fn new() -> Self {
+        Self {
+            status_byte: 0,
+            volume: [100; 16],
+            expression: [127; 16],
+            velocity: [[0; 128]; 16],
+        }
+    }
+
+    /// 送出したメッセージで状態を更新
+    fn apply(&mut self, data: &[u8]) {
+        if data.is_empty() || data[0] >= 0xF0 {
+            return;
+        }
+        let body = if (data[0] & 0x80) != 0 {
+            self.status_byte = data[0];
+            &data[1..]
+        } else {
+            data
+        };
+        if body.len() < 2 {
+            return;
+        }
+        let ch = (self.status_byte & 0xF) as usize;
+        let note = (body[0] & 0x7F) as usize;
+        match self.status_byte & 0xF0 {
+            MIDIMSG_NOTE_ON => self.velocity[ch][note] = body[1],
+            MIDIMSG_NOTE_OFF => self.velocity[ch][note] = 0,
+            MIDIMSG_MODE => match body[0] {
+                MIDIMSG_CC_VOLUME => self.volume[ch] = body[1],
+                MIDIMSG_CC_EXPRESSION => self.expression[ch] = body[1],
+                MIDIMSG_MODE_ALL_SOUND_OFF => self.velocity[ch] = [0; 128],
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// 推定した出力振幅（フルスケールの1音で1.0）
+    fn level(&self) -> f32 {
+        let gain = |value: u8| (value as f32 / 127.0).powi(2);
+        let mut power = 0.0;
+        for ch in 0..16 {
+            let channel_gain = gain(self.volume[ch]) * gain(self.expression[ch]);
+            for velocity in self.velocity[ch].iter().filter(|v| **v > 0) {
+                let amplitude = gain(*velocity) * channel_gain;
+                power += amplitude * amplitude;
+            }
+        }
+        f32::sqrt(power)
+    }
+}
+
+/// MIDIチャンネルごとの同時発音数を制限する（超えたら最も古いノートを止める）
+struct PolyphonyLimiter {
+    /// MIDIチャンネルごとの最大同時発音数（0で無制限）
+    max_polyphony: usize,
+    /// 入力側のランニングステータス
+    status_byte: u8,
+    /// メッセージの挿入・削除後、ステータスバイトを省略せずに出力するか
+    force_status: bool,
+    /// MIDIチャンネルごとの発音中のノート（古い順）
+    active_notes: [Vec<u8>; 16],
+    /// MIDIチャンネルごとの止めたノート（対応するノートオフは捨てる）
+    stolen_notes: [Vec<u8>; 16],
+}
+
+impl PolyphonyLimiter {
+    fn new(max_polyphony: u8) -> Self {
+        Self {
+            max_polyphony: max_polyphony as usize,
+            status_byte: 0,
+            force_status: false,
+            active_notes: Default::default(),
+            stolen_notes: Default::default(),
+        }
+    }
+
+    /// メッセージを処理し、発音数制限のノートオフを含めた出力メッセージ列を返す
+    fn process(&mut self, mut data: Vec<u8>) -> Vec<Vec<u8>> {
+        // 無制限またはシステムメッセージはそのまま出力
+        if self.max_polyphony == 0 || data.len() < 2 || data[0] >= 0xF0 {
+            return vec![data];
+        }
+        // ランニングステータスの更新
+        if (data[0] & 0x80) != 0 {
+            self.status_byte = data[0];
+            self.force_status = false;
+        } else if self.force_status {
+            data.insert(0, self.status_byte);
+        }
+        let ch = (self.status_byte & 0xF) as usize;
+        let status = self.status_byte & 0xF0;
+        if status != MIDIMSG_NOTE_ON && status != MIDIMSG_NOTE_OFF {
+            return vec![data];
+        }
+        let note = data[data.len() - 2];
+        let velocity = data[data.len() - 1];
+        if status == MIDIMSG_NOTE_OFF || velocity == 0 {
+            if let Some(pos) = self.active_notes[ch].iter().position(|n| *n == note) {
+                self.active_notes[ch].remove(pos);
+            } else if let Some(pos) = self.stolen_notes[ch].iter().position(|n| *n == note) {
+                // 既に止めたノートのノートオフは捨てる
+                self.stolen_notes[ch].remove(pos);
+                self.force_status = true;
+                return vec![];
+            }
+            return vec![data];
+        }
+        // 止めたノートが再び打鍵されたら、以降のノートオフは新しいノートのものとして扱う
+        self.stolen_notes[ch].retain(|n| *n != note);
+        // 上限に達していたら最も古いノートを止めてからノートオン
+        let mut msgs = vec![];
+        while self.active_notes[ch].len() >= self.max_polyphony {
+            let oldest = self.active_notes[ch].remove(0);
+            msgs.push(vec![MIDIMSG_NOTE_OFF | ch as u8, oldest, 0]);
+            self.stolen_notes[ch].push(oldest);
+        }
+        self.active_notes[ch].push(note);
+        if msgs.is_empty() {
+            return vec![data];
+        }
+        if (data[0] & 0x80) == 0 {
+            data.insert(0, self.status_byte);
+        }
+        msgs.push(data);
+        // 以降のランニングステータスはノートオフのステータスと解釈されるため補う
+        self.force_status = true;
+        msgs
+    }
+}
+
+/// 音源の最初のノートオン位置にSRNとラベル・音色を示すマーカーを挿入する
+struct SourceMarkerInserter {
+    /// ランニングステータス
+    status_byte: u8,
+    /// 前回ティック時のノートオンフラグ
+    previous_noteon: u8,
+    /// MIDIチャンネルごとの発音中の音源番号
+    srn_no: [Option<u8>; 16],
+    /// マーカーを出力済みの音源番号
+    marked: Vec<u8>,
+}
+
+impl SourceMarkerInserter {
+    fn new() -> Self {
+        Self {
+            status_byte: 0,
+            previous_noteon: 0,
+            srn_no: [None; 16],
+            marked: vec![],
+        }
+    }
+
+    /// ティック直後のDSPの状態からMIDIチャンネルごとの音源番号を取得
+    fn update(
+        &mut self,
+        dsp: &spc700::mididsp::MIDIDSP,
+        source_params: &BTreeMap<u8, SourceParameter>,
+    ) {
+        let noteon = dsp.read_register(&[0u8], DSP_ADDRESS_NOTEON);
+        // 新たにキーオンされたチャンネルを後から処理して優先する
+        let keyon = noteon & !self.previous_noteon;
+        self.srn_no = [None; 16];
+        for ch in (0..8)
+            .filter(|ch| (noteon >> ch) & 1 != 0)
+            .chain((0..8).filter(|ch| (keyon >> ch) & 1 != 0))
+        {
+            let ch_nibble = (ch as u8) << 4;
+            let srn_no = dsp.read_register(&[0u8], DSP_ADDRESS_V0SRCN | ch_nibble);
+            if let Some(param) = source_params.get(&srn_no) {
+                self.srn_no[(param.channel_routing[ch] & 0xF) as usize] = Some(srn_no);
+            }
+        }
+        self.previous_noteon = noteon;
+    }
+
+    /// 未出力の音源のノートオンであればマーカー文字列を返す
+    fn process(
+        &mut self,
+        data: &[u8],
+        source_params: &BTreeMap<u8, SourceParameter>,
+    ) -> Option<String> {
+        if data.len() < 2 {
+            return None;
+        }
+        // ランニングステータスの更新
+        if (data[0] & 0x80) != 0 {
+            self.status_byte = data[0];
+        }
+        if (self.status_byte & 0xF0) != MIDIMSG_NOTE_ON || data[data.len() - 1] == 0 {
+            return None;
+        }
+        let srn_no = self.srn_no[(self.status_byte & 0xF) as usize]?;
+        if self.marked.contains(&srn_no) {
+            return None;
+        }
+        self.marked.push(srn_no);
+        let param = source_params.get(&srn_no)?;
+        Some(if param.label.is_empty() {
+            format!("SRN {:02X}: {}", srn_no, param.program)
+        } else {
+            format!("SRN {:02X}: {} ({})", srn_no, param.label, param.program)
+        })
+    }
+}
+
+impl Message {
+    /// 音源パラメータを編集するメッセージであれば対象の音源番号を返す
+    fn edited_source(&self) -> Option<u8> {
+        match self {
+            Message::SRNMuteFlagToggled(srn_no, _)
+            | Message::ProgramSelected(srn_no, _, _)
+            | Message::CenterNoteIntChanged(srn_no, _)
+            | Message::CenterNoteFractionChanged(srn_no, _)
+            | Message::NoteOnVelocityChanged(srn_no, _)
+            | Message::VelocityFromKeyOnVolumeFlagToggled(srn_no, _)
+            | Message::PitchBendWidthChanged(srn_no, _)
+            | Message::EnablePitchBendFlagToggled(srn_no, _)
+            | Message::AutoPanFlagToggled(srn_no, _)
+            | Message::FixedPanChanged(srn_no, _)
+            | Message::PanLawChanged(srn_no, _)
+            | Message::AutoVolumeFlagToggled(srn_no, _)
+            | Message::FixedVolumeChanged(srn_no, _)
+            | Message::EnvelopeAsExpressionFlagToggled(srn_no, _)
+            | Message::EchoAsReverbFlagToggled(srn_no, _)
+            | Message::EchoSendScaleChanged(srn_no, _)
+            | Message::FixedReverbSendChanged(srn_no, _)
+            | Message::ChorusSendChanged(srn_no, _)
+            | Message::UpdateParameterAfterNoteOnFlagToggled(srn_no, _)
+            | Message::ChannelRoutingMuteChanged(srn_no, _, _)
+            | Message::ChannelRoutingChanged(srn_no, _, _)
+            | Message::ChannelRoutingReseted(srn_no)
+            | Message::InstrumentNameChanged(srn_no, _)
+            | Message::HideInIndicatorFlagToggled(srn_no, _)
+            | Message::SustainPedalFlagToggled(srn_no, _)
+            | Message::MinNoteChanged(srn_no, _)
+            | Message::MaxNoteChanged(srn_no, _)
+            | Message::LinkDuplicatesFlagToggled(srn_no, _)
+            | Message::SRNKindOverrideSelected(srn_no, _)
+            | Message::SRNCenterNoteOctaveUpClicked(srn_no)
+            | Message::SRNCenterNoteOctaveDownClicked(srn_no)
+            | Message::SRNCenterNoteSnapToSemitoneClicked(srn_no)
+            | Message::SRNCenterNoteSnapToCentClicked(srn_no)
+            | Message::SRNNoteEstimationClicked(srn_no) => Some(*srn_no),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    DialogClosed,
+    IoError(io::ErrorKind),
+}
+
+/// SPCファイルのレイアウトを検証して読み込み、拡張RAMがあればRAMに反映
+fn load_spc_file(data: &[u8]) -> Result<SPCFile, String> {
+    validate_spc_file_layout(data)?;
+    let mut spc_file = parse_spc_file(data).ok_or("failed to parse the header".to_string())?;
+    apply_spc_extra_ram(&mut spc_file, data);
+    validate_spc_ram(&spc_file.ram)?;
+    Ok(spc_file)
+}
+
+/// エミュレーションに渡すRAMイメージの検証
+fn validate_spc_ram(ram: &[u8]) -> Result<(), String> {
+    if ram.len() != SPC_RAM_SIZE {
+        return Err(format!(
+            "RAM image size is invalid ({} bytes, {} bytes required)",
+            ram.len(),
+            SPC_RAM_SIZE
+        ));
+    }
+    Ok(())
+}
+
+/// SPCファイルのレイアウト検証
+fn validate_spc_file_layout(data: &[u8]) -> Result<(), String> {
+    if !data.starts_with(SPC_FILE_SIGNATURE) {
+        return Err("signature not found".to_string());
+    }
+    if data.len() < SPC_FILE_MIN_SIZE {
+        return Err(format!(
+            "file is too short ({} bytes, at least {} bytes required)",
+            data.len(),
+            SPC_FILE_MIN_SIZE
+        ));
+    }
+    Ok(())
+}
+
+/// IPL ROMが有効な場合、ROMの裏にあるRAMの内容を拡張RAMから復元
+fn apply_spc_extra_ram(spc_file: &mut SPCFile, data: &[u8]) {
+    // 拡張RAMを含まないダンプ
+    if data.len() < SPC_FILE_EXTRA_RAM_OFFSET + SPC_EXTRA_RAM_SIZE {
+        return;
+    }
+    // IPL ROMが無効な場合はRAMイメージがそのまま正しい
+    let control = data[SPC_FILE_RAM_OFFSET + SPC_CONTROL_REGISTER_ADDRESS];
+    if (control & 0x80) == 0 {
+        return;
+    }
+    spc_file.ram[SPC_IPL_ROM_ADDRESS..(SPC_IPL_ROM_ADDRESS + SPC_EXTRA_RAM_SIZE)].copy_from_slice(
+        &data[SPC_FILE_EXTRA_RAM_OFFSET..(SPC_FILE_EXTRA_RAM_OFFSET + SPC_EXTRA_RAM_SIZE)],
+    );
+}
+
+/// 入力された浮動小数値を検証し範囲内に丸め込む（NaN・無限大は不正としてNone）
+/// 文字列からの変換はRustの標準パース（ロケール非依存、小数点は'.'）で行われる前提
+fn sanitize_float_input(value: f32, range: std::ops::RangeInclusive<f32>) -> Option<f32> {
+    if !value.is_finite() {
+        eprintln!(
+            "[{}] Ignored invalid numeric input: {}",
+            SPC2MIDI2_TITLE_STR, value
+        );
+        return None;
+    }
+    Some(value.clamp(*range.start(), *range.end()))
+}
+
+/// オーディオ出力のサンプリングレートが扱える範囲か確認（高すぎるレートは警告）
+fn validate_output_sample_rate(sample_rate: u32) -> bool {
+    if sample_rate < MIN_OUTPUT_SAMPLE_RATE || sample_rate > MAX_OUTPUT_SAMPLE_RATE {
+        eprintln!(
+            "[{}] Unsupported output sample rate: {} Hz",
+            SPC2MIDI2_TITLE_STR, sample_rate
+        );
+        return false;
+    }
+    if sample_rate > HIGH_OUTPUT_SAMPLE_RATE {
+        eprintln!(
+            "[{}] WARNING: high output sample rate ({} Hz) increases resampling load and memory usage",
+            SPC2MIDI2_TITLE_STR, sample_rate
+        );
+    }
+    true
+}
+
+/// デバイスの出力設定から指定したサンプリングレートに対応するものを探す
+fn find_output_stream_config(
+    device: &Device,
+    default_config: &SupportedStreamConfig,
+    sample_rate: u32,
+) -> Option<StreamConfig> {
+    device
+        .supported_output_configs()
+        .ok()?
+        .filter(|c| {
+            c.channels() == default_config.channels()
+                && c.sample_format() == default_config.sample_format()
+        })
+        .find_map(|c| c.try_with_sample_rate(sample_rate))
+        .map(Into::<StreamConfig>::into)
+}
+
+/// デバイスの出力設定を選択
+/// 強制するレートがあればそのレートの設定を、なければデフォルト設定を使う
+/// デフォルト設定のレートが扱えない場合は標準的なレートの設定を探す
+fn select_output_stream_config(
+    device: &Device,
+    forced_sample_rate: Option<u32>,
+) -> Option<StreamConfig> {
+    const FALLBACK_SAMPLE_RATES: [u32; 3] = [48000, 44100, 32000];
+    let default_config = device.default_output_config().ok()?;
+
+    if let Some(sample_rate) = forced_sample_rate {
+        if validate_output_sample_rate(sample_rate) {
+            if let Some(config) = find_output_stream_config(device, &default_config, sample_rate) {
+                return Some(config);
+            }
+        }
+        eprintln!(
+            "[{}] Output sample rate {} Hz is not available on this device. Use the default configuration.",
+            SPC2MIDI2_TITLE_STR, sample_rate
+        );
+    }
+
+    if validate_output_sample_rate(default_config.sample_rate()) {
+        return Some(default_config.into());
+    }
+    FALLBACK_SAMPLE_RATES
+        .iter()
+        .find_map(|&sample_rate| find_output_stream_config(device, &default_config, sample_rate))
+}
+
+async fn open_file() -> Result<(PathBuf, LoadedFile), Error> {
+    let picked_file = AsyncFileDialog::new()
+        .set_title("Open a file...")
+        .add_filter("SPC or JSON", &["spc", "SPC", "json"])
+        .pick_file()
+        .await
+        .ok_or(Error::DialogClosed)?;
+
+    load_file(picked_file).await
+}
+
+async fn load_file(path: impl Into<PathBuf>) -> Result<(PathBuf, LoadedFile), Error> {
+    let path = path.into();
+
+    if let Some(extension) = path.extension().and_then(OsStr::to_str) {
+        match extension.to_lowercase().as_str() {
+            "spc" => {
+                let data = std::fs::read(&path).unwrap();
+                return Ok((path, LoadedFile::SPCFile(data.to_vec())));
+            }
+            "json" => {
+                let string = std::fs::read_to_string(&path).unwrap();
+                return Ok((path, LoadedFile::JSONFile(string)));
+            }
+            _ => {
+                return Err(Error::IoError(io::ErrorKind::Unsupported));
+            }
+        }
+    }
+
+    return Err(Error::IoError(io::ErrorKind::Unsupported));
+}
+
+async fn pick_instrument_mapping_file() -> Option<PathBuf> {
+    AsyncFileDialog::new()
+        .set_title("Load an instrument mapping file...")
+        .add_filter("JSON", &["json"])
+        .pick_file()
+        .await
+        .map(|picked_file| picked_file.path().to_path_buf())
+}
+
+async fn save_smf(default_file_name: String, smf: SMF) -> Result<(), Error> {
+    let picked_file = AsyncFileDialog::new()
+        .set_file_name(default_file_name)
+        .set_title("Save to a MIDI file...")
+        .add_filter("SMF", &["mid", "midi", "MID"])
+        .save_file()
+        .await
+        .ok_or(Error::DialogClosed)?;
+
+    let writer = SMFWriter::from_smf(smf);
+    match writer.write_to_file(picked_file.path()) {
+        Ok(()) => Ok(()),
+        _ => Err(Error::DialogClosed),
+    }
+}
+
+async fn save_smf_sections(
+    default_file_name: String,
+    sections: Vec<(String, SMF)>,
+) -> Result<(), Error> {
+    let picked_file = AsyncFileDialog::new()
+        .set_file_name(default_file_name)
+        .set_title("Save to MIDI files (<name>_intro.mid, <name>_loop1.mid, ...)")
+        .add_filter("SMF", &["mid", "midi", "MID"])
+        .save_file()
+        .await
+        .ok_or(Error::DialogClosed)?;
+
+    // 選択したファイル名に区間の接尾辞を付けて保存
+    let path = picked_file.path();
+    let stem = path.file_stem().unwrap().to_str().unwrap().to_owned();
+    let extension = path.extension().and_then(OsStr::to_str).unwrap_or("mid");
+    for (suffix, smf) in sections {
+        let writer = SMFWriter::from_smf(smf);
+        let section_path = path.with_file_name(format!("{}_{}.{}", stem, suffix, extension));
+        if writer.write_to_file(&section_path).is_err() {
+            return Err(Error::DialogClosed);
+        }
+    }
+    Ok(())
+}
+
+async fn save_json(default_file_name: String, json: serde_json::Value) -> Result<(), Error> {
+    let picked_file = AsyncFileDialog::new()
+        .set_file_name(default_file_name)
+        .set_title("Save to a JSON file...")
+        .add_filter("JSON", &["json"])
+        .save_file()
+        .await
+        .ok_or(Error::DialogClosed)?;
+
+    match File::create(picked_file.path()) {
+        Ok(file) => {
+            let writer = BufWriter::new(file);
+            serde_json::to_writer_pretty(writer, &json).expect("Faied to write json");
+            Ok(())
+        }
+        _ => Err(Error::DialogClosed),
+    }
+}
+
+async fn save_report(default_file_name: String, report: String) -> Result<(), Error> {
+    let picked_file = AsyncFileDialog::new()
+        .set_file_name(default_file_name)
+        .set_title("Save to a report file...")
+        .add_filter("Markdown", &["md"])
+        .add_filter("Text", &["txt"])
+        .save_file()
+        .await
+        .ok_or(Error::DialogClosed)?;
+
+    match std::fs::write(picked_file.path(), report) {
+        Ok(()) => Ok(()),
+        _ => Err(Error::DialogClosed),
+    }
+}
+
+async fn save_spc(default_file_name: String, data: Arc<Vec<u8>>) -> Result<(), Error> {
+    let picked_file = AsyncFileDialog::new()
+        .set_file_name(default_file_name)
+        .set_title("Copy the SPC file to...")
+        .add_filter("SPC", &["spc", "SPC"])
+        .save_file()
+        .await
+        .ok_or(Error::DialogClosed)?;
+
+    match std::fs::write(picked_file.path(), data.as_slice()) {
+        Ok(()) => Ok(()),
+        _ => Err(Error::DialogClosed),
+    }
+}
+
+async fn save_wav(default_file_name: String, wav: Vec<u8>) -> Result<(), Error> {
+    let picked_file = AsyncFileDialog::new()
+        .set_file_name(default_file_name)
+        .set_title("Save to a WAV file...")
+        .add_filter("WAV", &["wav", "WAV"])
+        .save_file()
+        .await
+        .ok_or(Error::DialogClosed)?;
+
+    match std::fs::write(picked_file.path(), wav) {
+        Ok(()) => Ok(()),
+        _ => Err(Error::DialogClosed),
+    }
+}
+
+async fn save_ogg(default_file_name: String, ogg: Vec<u8>) -> Result<(), Error> {
+    let picked_file = AsyncFileDialog::new()
+        .set_file_name(default_file_name)
+        .set_title("Save to an OGG file...")
+        .add_filter("OGG", &["ogg", "OGG"])
+        .save_file()
+        .await
+        .ok_or(Error::DialogClosed)?;
+
+    match std::fs::write(picked_file.path(), ogg) {
+        Ok(()) => Ok(()),
+        _ => Err(Error::DialogClosed),
+    }
+}
+
+/// 全音源の再推定でプログラムも推定し直すか確認（Noneはキャンセル）
+async fn confirm_estimate_all_sources() -> Option<bool> {
+    let result = AsyncMessageDialog::new()
+        .set_level(MessageLevel::Warning)
+        .set_title("Estimate All Sources")
+        .set_description(
+            "Re-estimate the center notes of all sources.\n\
+             Also reset the programs to the estimated drum/tonal defaults? \
+             (Manually selected programs will be overwritten)",
+        )
+        .set_buttons(MessageButtons::YesNoCancel)
+        .show()
+        .await;
+    match result {
+        MessageDialogResult::Yes => Some(true),
+        MessageDialogResult::No => Some(false),
+        _ => None,
+    }
+}
+
+/// 長時間の書き出しを始めるか確認
+async fn confirm_long_export(duration_msec: u64, render_sec: f32) -> bool {
+    let result = AsyncMessageDialog::new()
+        .set_level(MessageLevel::Warning)
+        .set_title("Long Export")
+        .set_description(format!(
+            "This export covers {} of the song and may take about {} to render.\n\
+             The progress is shown and the export can be cancelled. Continue?",
+            format_duration_sec(duration_msec as f32 / 1000.0),
+            format_duration_sec(render_sec)
+        ))
+        .set_buttons(MessageButtons::YesNo)
+        .show()
+        .await;
+    matches!(result, MessageDialogResult::Yes)
+}
+
+async fn confirm_reopen_file() -> bool {
+    let result = AsyncMessageDialog::new()
+        .set_level(MessageLevel::Warning)
+        .set_title("Reopen")
+        .set_description(
+            "Reload the SPC file from disk and analyze it again.\n\
+             Unsaved changes to the source parameters will be discarded. Continue?",
+        )
+        .set_buttons(MessageButtons::YesNo)
+        .show()
+        .await;
+    matches!(result, MessageDialogResult::Yes)
+}
+
+async fn show_message_dialog(title: String, description: String) {
+    let _ = AsyncMessageDialog::new()
+        .set_level(MessageLevel::Info)
+        .set_title(title)
+        .set_description(description)
+        .set_buttons(MessageButtons::Ok)
+        .show()
+        .await;
+}
+
+// 設定ファイルのパス
+fn preferences_path() -> Option<PathBuf> {
+    let config_dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    config_dir.map(|dir| dir.join(SPC2MIDI2_TITLE_STR).join(PREFERENCES_FILE_NAME))
+}
+
+// 設定の読み込み（読み込めなければデフォルト値）
+fn load_preferences() -> Preferences {
+    if let Some(path) = preferences_path() {
+        if let Ok(string) = std::fs::read_to_string(&path) {
+            match serde_json::from_str::<Preferences>(&string) {
+                Ok(preferences) => return preferences,
+                Err(e) => {
+                    eprintln!("ERROR: failed to load preferences: {:?}", e);
+                }
+            }
+        }
+    }
+    Preferences::default()
+}
+
+// 設定の保存
+fn save_preferences(preferences: &Preferences) -> Result<(), Error> {
+    let path = preferences_path().ok_or(Error::IoError(io::ErrorKind::NotFound))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| Error::IoError(e.kind()))?;
+    }
+    let file = File::create(&path).map_err(|e| Error::IoError(e.kind()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), preferences)
+        .map_err(|_| Error::IoError(io::ErrorKind::InvalidData))
+}
+
+// 最初に見つかったMIDI出力ポートに接続
+fn connect_default_midi_output() -> (Option<String>, Option<Arc<Mutex<MidiOutputConnection>>>) {
+    if let Ok(midi_out) = MidiOutput::new(SPC2MIDI2_TITLE_STR) {
+        let midi_out_ports = midi_out.ports();
+        if midi_out_ports.len() > 0 {
+            let default_midi_port_name = &midi_out_ports[0];
+            let port_name = Some(midi_out.port_name(default_midi_port_name).unwrap());
+            let midi_out_conn = match midi_out.connect(default_midi_port_name, SPC2MIDI2_TITLE_STR)
+            {
+                Ok(conn) => Some(Arc::new(Mutex::new(conn))),
+                Err(_) => None,
+            };
+            (port_name, midi_out_conn)
+        } else {
+            (None, None)
+        }
+    } else {
+        (None, None)
+    }
+}
+
+// アプリ名の仮想MIDI出力ポートを作成して接続
+#[cfg(unix)]
+fn connect_virtual_midi_output() -> (Option<String>, Option<Arc<Mutex<MidiOutputConnection>>>) {
+    use midir::os::unix::VirtualOutput;
+    if let Ok(midi_out) = MidiOutput::new(SPC2MIDI2_TITLE_STR) {
+        match midi_out.create_virtual(SPC2MIDI2_TITLE_STR) {
+            Ok(conn) => (
+                Some(format!("{} (Virtual)", SPC2MIDI2_TITLE_STR)),
+                Some(Arc::new(Mutex::new(conn))),
+            ),
+            Err(e) => {
+                eprintln!("ERROR: failed to create virtual MIDI port: {}", e);
+                (None, None)
+            }
+        }
+    } else {
+        (None, None)
+    }
+}
+
+// 仮想MIDI出力ポートに対応していないプラットフォーム
+#[cfg(not(unix))]
+fn connect_virtual_midi_output() -> (Option<String>, Option<Arc<Mutex<MidiOutputConnection>>>) {
+    (None, None)
+}
+
+/// いずれかのチャンネルがフルスケールに達しているフレーム数を数える
+fn count_clipped_frames(pcm: &[i32], num_channels: usize) -> usize {
+    pcm.chunks_exact(num_channels)
+        .filter(|frame| {
+            frame
+                .iter()
+                .any(|&smpl| smpl >= i16::MAX as i32 || smpl <= i16::MIN as i32)
+        })
+        .count()
+}
+
+/// PCMを16bitに収める（ソフトリミッタ有効時は閾値を超えた分をなめらかに圧縮）
+fn limit_pcm(pcm: &[i32], soft_limiter: bool) -> Vec<i16> {
+    const FULL_SCALE: f32 = i16::MAX as f32;
+    let threshold = SOFT_LIMITER_THRESHOLD * FULL_SCALE;
+    pcm.iter()
+        .map(|&smpl| {
+            let smpl = smpl as f32;
+            let limited = if soft_limiter && smpl.abs() > threshold {
+                let knee = FULL_SCALE - threshold;
+                smpl.signum() * (threshold + knee * ((smpl.abs() - threshold) / knee).tanh())
+            } else {
+                smpl
+            };
+            limited.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+        })
+        .collect()
+}
+
+/// ステレオ16bit整数PCMのモノラル互換性を測定
+fn measure_mono_compatibility(pcm: &[i16]) -> MonoCompatibility {
+    let (mut lr, mut ll, mut rr) = (0.0f64, 0.0f64, 0.0f64);
+    for frame in pcm.chunks_exact(2) {
+        let (l, r) = (frame[0] as f64, frame[1] as f64);
+        lr += l * r;
+        ll += l * l;
+        rr += r * r;
+    }
+    // 無音の場合は問題なしとする
+    if ll == 0.0 || rr == 0.0 {
+        return MonoCompatibility {
+            correlation: 1.0,
+            mono_level_db: 0.0,
+        };
+    }
+    // モノラル化したパワー((L+R)/2)^2と左右の平均パワーの比
+    let mono_power = (ll + 2.0 * lr + rr) / 4.0;
+    let stereo_power = (ll + rr) / 2.0;
+    MonoCompatibility {
+        correlation: (lr / (ll * rr).sqrt()) as f32,
+        mono_level_db: (10.0 * (mono_power.max(f64::MIN_POSITIVE) / stereo_power).log10()) as f32,
+    }
+}
+
+/// 16bitインターリーブPCMのレート変換
+fn resample_pcm(
+    pcm: &[i16],
+    from_rate: u32,
+    to_rate: u32,
+    num_channels: usize,
+    quality: &WAVResampleQuality,
+) -> Option<Vec<i16>> {
+    const I16_NORMALIZE_VALUE: f32 = 1.0 / 32768.0;
+    let converter = match quality {
+        WAVResampleQuality::Fast => ConverterType::SincFastest,
+        WAVResampleQuality::Good => ConverterType::SincMediumQuality,
+        WAVResampleQuality::Best => ConverterType::SincBestQuality,
+    };
+    let input: Vec<f32> = pcm
+        .iter()
+        .map(|&smpl| smpl as f32 * I16_NORMALIZE_VALUE)
+        .collect();
+    let output = convert(from_rate, to_rate, num_channels, converter, &input).ok()?;
+    Some(
+        output
+            .iter()
+            .map(|&smpl| {
+                (smpl * 32768.0)
+                    .round()
+                    .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+            })
+            .collect(),
+    )
+}
+
+/// SPCのエミュレーション速度を測り、曲の1秒分の処理にかかる実時間(sec)を返す
+fn measure_emulation_sec_per_song_sec(spc_file: &SPCFile, config: &MIDIOutputConfigure) -> f32 {
+    // 計測する曲の時間(msec)
+    const MEASURE_MSEC: u32 = 500;
+    let mut spc: Box<spc700::spc::SPC<spc700::mididsp::MIDIDSP>> = Box::new({
+        let mut spc = SPC::new();
+        spc.initialize(
+            &spc_file.header.spc_register,
+            &spc_file.ram,
+            &spc_file.dsp_register,
+        );
+        spc
+    });
+    let spc_64k_hz_cycle = config.spc_clockup_factor * CLOCK_TICK_CYCLE_64KHZ;
+    let mut cycle_count = 0;
+    let start = Instant::now();
+    for _ in 0..(MEASURE_MSEC * 64) {
+        while cycle_count < spc_64k_hz_cycle {
+            cycle_count += spc.execute_step() as u32;
+        }
+        cycle_count -= spc_64k_hz_cycle;
+        spc.clock_tick_64k_hz();
+    }
+    start.elapsed().as_secs_f32() * 1000.0 / MEASURE_MSEC as f32
+}
+
+/// 秒数を「分:秒」の文字列にする
+fn format_duration_sec(sec: f32) -> String {
+    let sec = sec.max(0.0).round() as u64;
+    format!("{}:{:02}", sec / 60, sec % 60)
+}
+
+/// 16bit PCMのピークレベル(dBFS)（無音の場合は負の無限大）
+fn pcm_peak_dbfs(pcm: &[i16]) -> f32 {
+    let peak = pcm.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0);
+    20.0 * f32::log10(peak as f32 / 32768.0)
+}
+
+/// 16bit整数PCMをWAVフォーマットのバイト列に変換
+fn encode_wav(pcm: &[i16], sampling_rate: u32, num_channels: u16) -> Vec<u8> {
+    const BYTES_PER_SAMPLE: u16 = 2;
+    let data_size = (pcm.len() * BYTES_PER_SAMPLE as usize) as u32;
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+    // RIFFヘッダ
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    // fmtチャンク
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // リニアPCM
+    wav.extend_from_slice(&num_channels.to_le_bytes());
+    wav.extend_from_slice(&sampling_rate.to_le_bytes());
+    wav.extend_from_slice(
+        &(sampling_rate * (num_channels * BYTES_PER_SAMPLE) as u32).to_le_bytes(),
+    );
+    wav.extend_from_slice(&(num_channels * BYTES_PER_SAMPLE).to_le_bytes());
+    wav.extend_from_slice(&(8 * BYTES_PER_SAMPLE).to_le_bytes());
+    // dataチャンク
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for smpl in pcm {
+        wav.extend_from_slice(&smpl.to_le_bytes());
+    }
+    wav
+}
+
+/// SPCのレートで検出したループ（開始サンプル, 周期）を、出力範囲の先頭からの出力レートでのサンプル位置（開始, 長さ）に変換
+/// 開始が出力範囲より前の場合は周期単位で後ろにずらし、ループの終端が出力に収まらない場合はNoneを返す
+fn loop_samples_in_output(
+    spc_loop: (u64, u64),
+    num_skip_samples: u64,
+    sampling_rate: u32,
+    num_output_samples: u64,
+) -> Option<(u64, u64)> {
+    let (mut start, period) = spc_loop;
+    if period == 0 {
+        return None;
+    }
+    if start < num_skip_samples {
+        start += (num_skip_samples - start).div_ceil(period) * period;
+    }
+    let start = start - num_skip_samples;
+    // 端数の誤差が積み重ならないよう、開始と終端をそれぞれ丸めてから長さを求める
+    let convert = |samples: u64| {
+        (samples * sampling_rate as u64 + SPC_SAMPLING_RATE as u64 / 2) / SPC_SAMPLING_RATE as u64
+    };
+    let loop_start = convert(start);
+    let loop_end = convert(start + period);
+    if loop_end > num_output_samples {
+        return None;
+    }
+    Some((loop_start, loop_end - loop_start))
+}
+
+/// PCMをOgg Vorbisにエンコード
+/// ループがあればRPGツクール等のゲームエンジンが参照するLOOPSTART/LOOPLENGTHコメントを付ける
+fn encode_ogg(
+    pcm: &[i16],
+    sampling_rate: u32,
+    num_channels: usize,
+    loop_samples: Option<(u64, u64)>,
+) -> Result<Vec<u8>, String> {
+    const ENCODE_BLOCK_FRAMES: usize = 4096;
+    let mut ogg = Vec::new();
+    let mut builder = VorbisEncoderBuilder::new(
+        NonZero::new(sampling_rate).ok_or("invalid sampling rate")?,
+        NonZero::new(num_channels as u8).ok_or("invalid number of channels")?,
+        &mut ogg,
+    )
+    .map_err(|e| e.to_string())?;
+    if let Some((loop_start, loop_length)) = loop_samples {
+        builder
+            .add_comment_tag("LOOPSTART", loop_start.to_string())
+            .map_err(|e| e.to_string())?;
+        builder
+            .add_comment_tag("LOOPLENGTH", loop_length.to_string())
+            .map_err(|e| e.to_string())?;
+    }
+    let mut encoder = builder.build().map_err(|e| e.to_string())?;
+    // チャンネルごとに分けて一定長ずつエンコード
+    for block in pcm.chunks(ENCODE_BLOCK_FRAMES * num_channels) {
+        let planar: Vec<Vec<f32>> = (0..num_channels)
+            .map(|ch| {
+                block
+                    .iter()
+                    .skip(ch)
+                    .step_by(num_channels)
+                    .map(|smpl| *smpl as f32 / 32768.0)
+                    .collect()
+            })
+            .collect();
+        encoder
+            .encode_audio_block(&planar)
+            .map_err(|e| e.to_string())?;
+    }
+    encoder.finish().map_err(|e| e.to_string())?;
+    Ok(ogg)
+}
+
+// 再生情報の読み取り
+fn read_playback_status(midi_dsp: &spc700::mididsp::MIDIDSP) -> PlaybackStatus {
+    let mut status = PlaybackStatus::new();
+
+    let noteon_flags = midi_dsp.read_register(&[0u8], DSP_ADDRESS_NOTEON);
+    for ch in 0..8 {
+        let ch_nibble = (ch as u8) << 4;
+        status.noteon[ch] = ((noteon_flags >> ch) & 1) != 0;
+        status.srn_no[ch] = midi_dsp.read_register(&[0u8], DSP_ADDRESS_V0SRCN | ch_nibble);
+        let pitch_high = midi_dsp.read_register(&[0u8], DSP_ADDRESS_V0PITCHH | ch_nibble);
+        let pitch_low = midi_dsp.read_register(&[0u8], DSP_ADDRESS_V0PITCHL | ch_nibble);
+        status.pitch[ch] = ((pitch_high as u16) << 8) | (pitch_low as u16);
+        status.envelope[ch] = midi_dsp.read_register(&[0u8], DSP_ADDRESS_V0ENVX | ch_nibble);
+        status.volume[ch][0] = midi_dsp.read_register(&[0u8], DSP_ADDRESS_V0VOLL | ch_nibble) as i8;
+        status.volume[ch][1] = midi_dsp.read_register(&[0u8], DSP_ADDRESS_V0VOLR | ch_nibble) as i8;
+    }
+
+    status
+}
+
+/// ディレクトリエントリから波形をデコードして音源情報を作成
+/// ディレクトリエントリや波形がRAM外を指している場合はNoneを返す
+fn decode_source(
+    ram: &[u8],
+    dir_address: usize,
+    using_channel: [bool; 8],
+    keyon_pitches: Vec<u16>,
+) -> Option<SourceInformation> {
+    let entry = ram.get(dir_address..(dir_address + 4))?;
+    let start_address = make_u16_from_u8(&entry[0..2]) as usize;
+    let loop_address = make_u16_from_u8(&entry[2..4]) as usize;
+    // 終端ブロックまでにRAMの外にはみ出すBRRは無効
+    let mut block_address = start_address;
+    loop {
+        let header = *ram.get(block_address..(block_address + 9))?.first()?;
+        if (header & 0x01) != 0 {
+            break;
+        }
+        block_address += 9;
+    }
+    let mut decoder = Decoder::new();
+    let mut signal = Vec::new();
+    decoder.keyon(ram, dir_address);
+    // 原音ピッチで終端までデコード（終端が見つからない場合はRAM全体分で打ち切る）
+    let max_num_samples = (ram.len() * 16) / 9;
+    loop {
+        let pcm = decoder.process(ram, 0x1000) as f32;
+        signal.push(pcm * PCM_NORMALIZE_CONST);
+        // 最後のブロックはデコードしない（ループを繋ぐため）
+        if decoder.end {
+            break;
+        }
+        if signal.len() >= max_num_samples {
+            return None;
+        }
+    }
+    let loop_start_sample = (loop_address.saturating_sub(start_address) * 16) / 9;
+    let mut info = SourceInformation {
+        signal: signal.clone(),
+        power_spectrum: compute_power_spectrum(&spectrum_analysis_signal(
+            &signal,
+            loop_start_sample,
+        )),
+        dir_address: dir_address,
+        start_address: start_address,
+        end_address: start_address + (signal.len() * 9) / 16,
+        loop_start_sample: loop_start_sample,
+        using_channel: using_channel,
+        keyon_pitches: keyon_pitches,
+        max_pitch_deviation: 0.0,
+        duplicates: vec![],
+        rms: (signal.iter().map(|x| x * x).sum::<f32>() / signal.len() as f32).sqrt(),
+        uses_noise: false,
+        pitch_modulated: false,
+        pitch_confidence: 0.0,
+        detected_drum: false,
+        alternate_dir_addresses: vec![],
+        stereo_pair_partner: None,
+    };
+    info.pitch_confidence = pitch_confidence(&info);
+    Some(info)
+}
+
+/// 曲全体をレンダリングして波形概要とループ区間を作成（キャンセルされた場合はNone）
+fn render_song_overview(
+    spc_file: &SPCFile,
+    duration_msec: u64,
+    cancel: &AtomicBool,
+) -> Option<SongOverview> {
+    let mut spc: Box<spc700::spc::SPC<spc700::sdsp::SDSP>> = Box::new({
+        let mut spc = SPC::new();
+        spc.initialize(
+            &spc_file.header.spc_register,
+            &spc_file.ram,
+            &spc_file.dsp_register,
+        );
+        spc
+    });
+
+    let num_samples = ((duration_msec * SPC_SAMPLING_RATE as u64) / 1000) as usize;
+    let samples_per_peak = (num_samples / SONG_OVERVIEW_NUM_PEAKS).max(1);
+    let mut peaks = Vec::with_capacity(SONG_OVERVIEW_NUM_PEAKS + 1);
+    let mut peak = (0.0f32, 0.0f32);
+    // ループ検出用のキーオン（サンプル位置, チャンネルと音源番号）
+    let mut keyons: Vec<(u64, u32)> = vec![];
+    let mut previous_keyon = 0;
+    let mut cycle_count = 0;
+    let mut sample_count = 0;
+    while sample_count < num_samples {
+        // 1秒ごとにキャンセル確認
+        if sample_count % SPC_SAMPLING_RATE as usize == 0 && cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        cycle_count += spc.execute_step() as u32;
+        // DSPを動かすとキーオンフラグが落ちることがあるので64kHzティック前に調べる
+        let keyon = spc.dsp.read_register(&spc_file.ram, DSP_ADDRESS_KON);
+        for ch in 0..8 {
+            if ((keyon & !previous_keyon) >> ch) & 1 != 0 {
+                let srn_no = spc
+                    .dsp
+                    .read_register(&spc_file.ram, (ch << 4) | DSP_ADDRESS_V0SRCN);
+                keyons.push((sample_count as u64, ((ch as u32) << 8) | srn_no as u32));
+            }
+        }
+        previous_keyon = keyon;
+        if cycle_count >= CLOCK_TICK_CYCLE_64KHZ {
+            cycle_count -= CLOCK_TICK_CYCLE_64KHZ;
+            if let Some(out) = spc.clock_tick_64k_hz() {
+                let mid = (out[0] as f32 + out[1] as f32) * 0.5 * PCM_NORMALIZE_CONST;
+                peak = (peak.0.min(mid), peak.1.max(mid));
+                sample_count += 1;
+                if sample_count % samples_per_peak == 0 {
+                    peaks.push(peak);
+                    peak = (0.0, 0.0);
+                }
+            }
+        }
+    }
+
+    let tolerance = (SPC_SAMPLING_RATE / SONG_OVERVIEW_LOOP_TOLERANCE_DIVISOR) as u64;
+    let loop_region = detect_loop(&keyons, tolerance).map(|(start, period)| {
+        (
+            start as f32 / SPC_SAMPLING_RATE as f32,
+            (start + period) as f32 / SPC_SAMPLING_RATE as f32,
+        )
+    });
+
+    Some(SongOverview {
+        peaks: peaks,
+        duration_sec: duration_msec as f32 / 1000.0,
+        loop_region: loop_region,
+    })
+}
+
+/// SMF出力のオプション
+#[derive(Debug, Clone, Copy)]
+struct SMFExportOptions {
+    /// 出力長を伸縮する目標長さ(msec)（テンポのみを変え、ティック上のノート配置は変わらない）
+    stretch_target_msec: Option<u64>,
+    /// 出力開始時に全チャンネルへ送るマスターリバーブセンド
+    master_reverb_send: u8,
+    /// 出力する時間範囲（開始(msec), 終了(msec)）（プリロール後からの時刻）
+    range_msec: Option<(u64, u64)>,
+    /// 出力を始める前にエミュレーションを空回しする時間(msec)
+    preroll_msec: u64,
+    /// プログラムごとのベロシティ補正率(%)（補正しない場合はNone）
+    velocity_calibration: Option<[u8; 128]>,
+}
+
+/// プリロールを含めたPCMのレンダリング範囲（開始(msec), 終了(msec)）
+/// 出力範囲の時刻はプリロール後からとみなし、プリロール分だけ後ろにずらす
+fn preroll_render_range_msec(
+    range_msec: Option<(u64, u64)>,
+    output_duration_msec: u64,
+    preroll_msec: u64,
+) -> Option<(u64, u64)> {
+    if preroll_msec == 0 {
+        return range_msec;
+    }
+    let (start_msec, end_msec) = range_msec.unwrap_or((0, output_duration_msec));
+    Some((start_msec + preroll_msec, end_msec + preroll_msec))
+}
+
+/// 出力長を目標長さに伸縮したときの4分音符の長さ（マイクロ秒）
+fn stretched_quarter_usec(
+    beats_per_minute: f32,
+    output_duration_msec: u64,
+    stretch_target_msec: Option<u64>,
+) -> u32 {
+    let quarter_usec = (60_000_000.0 / beats_per_minute) as f64;
+    let stretch = match stretch_target_msec {
+        Some(target_msec) if output_duration_msec > 0 => {
+            target_msec as f64 / output_duration_msec as f64
+        }
+        _ => 1.0,
+    };
+    // テンポメタイベントは24bitで表す
+    (quarter_usec * stretch).clamp(1.0, 0xFFFFFF as f64) as u32
+}
+
+/// 開始アドレスまたは波形が一致する音源を検出して重複リストを設定
+fn detect_duplicate_sources(infos: &mut BTreeMap<u8, SourceInformation>) {
+    let keys: Vec<(u8, usize, u64)> = infos
+        .iter()
+        .map(|(srn, info)| {
+            let mut hasher = DefaultHasher::new();
+            for sample in info.signal.iter() {
+                sample.to_bits().hash(&mut hasher);
+            }
+            (*srn, info.start_address, hasher.finish())
+        })
+        .collect();
+    for (srn, start_address, hash) in keys.iter() {
+        if let Some(info) = infos.get_mut(srn) {
+            info.duplicates = keys
+                .iter()
+                .filter(|(other_srn, other_start_address, other_hash)| {
+                    other_srn != srn && (other_start_address == start_address || other_hash == hash)
+                })
+                .map(|(other_srn, _, _)| *other_srn)
+                .collect();
+        }
+    }
+}
+
+/// ボイスの左右ボリュームから片側に振り切っているかを判定（左ならSome(true)、右ならSome(false)）
+fn hard_pan_side(lvol: i8, rvol: i8) -> Option<bool> {
+    let (lvol, rvol) = ((lvol as i32).abs(), (rvol as i32).abs());
+    if lvol > 0 && rvol * HARD_PAN_VOLUME_RATIO <= lvol {
+        Some(true)
+    } else if rvol > 0 && lvol * HARD_PAN_VOLUME_RATIO <= rvol {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// 左右に振り切って同時にキーオンされ、波形の相関が高い音源の組をステレオペアとして記録
+/// stereo_keyons は（左の音源番号, 右の音源番号）ごとの同時キーオン回数
+fn detect_stereo_pairs(
+    infos: &mut BTreeMap<u8, SourceInformation>,
+    stereo_keyons: &BTreeMap<(u8, u8), usize>,
+) {
+    for info in infos.values_mut() {
+        info.stereo_pair_partner = None;
+    }
+    // 同時キーオンの多い組から採用（1つの音源は1組にだけ属する）
+    let mut candidates: Vec<_> = stereo_keyons.iter().collect();
+    candidates.sort_by(|a, b| b.1.cmp(a.1));
+    for (&(left, right), &count) in candidates {
+        let is_pair = match (infos.get(&left), infos.get(&right)) {
+            (Some(left_info), Some(right_info)) => {
+                // それぞれのキーオンの半分以上がペアでの発音で、波形が似ている
+                count >= STEREO_PAIR_MIN_KEYONS
+                    && 2 * count >= left_info.keyon_pitches.len()
+                    && 2 * count >= right_info.keyon_pitches.len()
+                    && left_info.stereo_pair_partner.is_none()
+                    && right_info.stereo_pair_partner.is_none()
+                    && signal_correlation(&left_info.signal, &right_info.signal).abs()
+                        >= STEREO_PAIR_MIN_CORRELATION
+            }
+            _ => false,
+        };
+        if is_pair {
+            infos.get_mut(&left).unwrap().stereo_pair_partner = Some(right);
+            infos.get_mut(&right).unwrap().stereo_pair_partner = Some(left);
+        }
+    }
+}
+
+/// RMSから音源間のラウドネスが揃うように固定ボリュームを設定（ボリューム自動更新の音源は除く）
+fn balance_source_volumes(
+    infos: &BTreeMap<u8, SourceInformation>,
+    params: &mut BTreeMap<u8, SourceParameter>,
+) {
+    // 無音でない音源のRMS(dB)の平均を基準とする
+    let levels: Vec<(u8, f32)> = infos
+        .iter()
+        .filter(|(_, info)| info.rms > 0.0)
+        .map(|(srn, info)| (*srn, 20.0 * info.rms.log10()))
+        .collect();
+    if levels.is_empty() {
+        return;
+    }
+    let reference_db = levels.iter().map(|(_, db)| db).sum::<f32>() / levels.len() as f32;
+    for (srn, db) in levels.iter() {
+        if let Some(param) = params.get_mut(srn) {
+            if param.auto_volume {
+                continue;
+            }
+            let volume = BALANCED_REFERENCE_VOLUME * 10.0f32.powf((reference_db - db) / 20.0);
+            param.fixed_volume = volume.round().clamp(1.0, 127.0) as u8;
+        }
+    }
+}
+
+/// 解析済みの音源情報から全音源のセンターノートを推定し直す
+/// reset_programsが真の場合はプログラムとドラム判定に依存する設定も推定値に戻す（ラベル等は変更しない）
+fn reestimate_source_parameters(
+    infos: &BTreeMap<u8, SourceInformation>,
+    params: &mut BTreeMap<u8, SourceParameter>,
+    a4_pitch_hz: f32,
+    reset_programs: bool,
+) {
+    for (srn, param) in params.iter_mut() {
+        if let Some(info) = infos.get(srn) {
+            let estimated = create_default_source_parameter(
+                info,
+                a4_pitch_hz,
+                &SourceParameterTemplate::default(),
+            );
+            param.center_note = estimated.center_note;
+            if reset_programs {
+                param.program = estimated.program;
+                param.channel_routing = estimated.channel_routing;
+                param.enable_pitch_bend = estimated.enable_pitch_bend;
+                param.pitch_bend_width = estimated.pitch_bend_width;
+                // ドラム判定の上書き指定があればそちらに従う
+                if param.kind_override() != SourceKindOverride::Auto {
+                    let is_drum = param.resolve_drum(false);
+                    apply_drum_classification(param, info, is_drum);
+                }
+            }
+        }
+    }
+}
+
+/// トラックのイベントを取り出し、累計ティックとステータスバイトを補ったイベントに変換
+fn take_absolute_events(track: &mut rimd::Track) -> Vec<(u64, MidiEvent)> {
+    let mut events = vec![];
+    let mut ticks = 0;
+    let mut status_byte = 0;
+    for e in track.events.drain(..) {
+        ticks += e.vtime;
+        let event = match e.event {
+            MidiEvent::Midi(mut msg) => {
+                if !msg.data.is_empty() && msg.data[0] != 0xF0 {
+                    if (msg.data[0] & 0x80) != 0 {
+                        status_byte = msg.data[0];
+                    } else {
+                        msg.data.insert(0, status_byte);
+                    }
+                }
+                MidiEvent::Midi(msg)
+            }
+            event => event,
+        };
+        events.push((ticks, event));
+    }
+    events
+}
+
+/// SMFの全トラックのMIDIメッセージを（先頭からの時刻(usec), メッセージ）の時刻順の列に変換
+/// テンポ変更に従ってティックを時刻に変換する（システムエクスクルーシブは送らない）
+fn smf_to_timed_messages(mut smf: SMF) -> Vec<(u64, Vec<u8>)> {
+    let mut events: Vec<(u64, MidiEvent)> = smf
+        .tracks
+        .iter_mut()
+        .flat_map(|track| take_absolute_events(track))
+        .collect();
+    // 同じティックのイベントはトラック順を保つ
+    events.sort_by_key(|(ticks, _)| *ticks);
+
+    let ticks_per_quarter = smf.division.max(1) as u64;
+    let mut quarter_usec = (60_000_000.0 / DEFAULT_MIDI_BPM) as u64;
+    let mut base_ticks = 0;
+    let mut base_usec = 0;
+    let mut messages = vec![];
+    for (ticks, event) in events {
+        let usec = base_usec + ((ticks - base_ticks) * quarter_usec) / ticks_per_quarter;
+        match event {
+            MidiEvent::Meta(meta)
+                if matches!(meta.command, MetaCommand::TempoSetting) && meta.data.len() == 3 =>
+            {
+                base_ticks = ticks;
+                base_usec = usec;
+                quarter_usec = meta
+                    .data
+                    .iter()
+                    .fold(0, |value, byte| (value << 8) | *byte as u64);
+            }
+            MidiEvent::Midi(msg) if !msg.data.is_empty() && msg.data[0] != 0xF0 => {
+                messages.push((usec, msg.data));
+            }
+            _ => {}
+        }
+    }
+    messages
+}
+
+/// MIDIイベントの複製
+fn clone_midi_event(event: &MidiEvent) -> MidiEvent {
+    match event {
+        MidiEvent::Midi(msg) => MidiEvent::Midi(MidiMessage::from_bytes(msg.data.clone())),
+        MidiEvent::Meta(meta) => MidiEvent::Meta(MetaEvent {
+            command: meta.command,
+            length: meta.length,
+            data: meta.data.clone(),
+        }),
+    }
+}
+
+/// SMFをループ境界で分割し（ファイル名の接尾辞, SMF）のリストを返す（ループが検出できなければ空）
+fn split_smf_by_loop(mut smf: SMF) -> Vec<(String, SMF)> {
+    let tracks: Vec<(Option<String>, Vec<(u64, MidiEvent)>)> = smf
+        .tracks
+        .iter_mut()
+        .map(|track| (track.name.clone(), take_absolute_events(track)))
+        .collect();
+
+    // 全トラックのノートオン（ティック, チャンネルとノート番号）からループを検出
+    let mut noteons: Vec<(u64, u32)> = tracks
+        .iter()
+        .flat_map(|(_, events)| events.iter())
+        .filter_map(|(ticks, event)| match event {
+            MidiEvent::Midi(msg)
+                if msg.data.len() == 3
+                    && (msg.data[0] & 0xF0) == MIDIMSG_NOTE_ON
+                    && msg.data[2] > 0 =>
+            {
+                Some((*ticks, ((msg.data[0] as u32) << 8) | msg.data[1] as u32))
+            }
+            _ => None,
+        })
+        .collect();
+    noteons.sort();
+    let tolerance = (smf.division as u64 / LOOP_DETECTION_TOLERANCE_DIVISOR).max(1);
+    let (loop_start, period) = match detect_loop(&noteons, tolerance) {
+        Some(found) => found,
+        None => return vec![],
+    };
+
+    // 区間（接尾辞, 開始ティック, 終了ティック）
+    let end_ticks = tracks
+        .iter()
+        .filter_map(|(_, events)| events.last().map(|(ticks, _)| *ticks + 1))
+        .max()
+        .unwrap_or(0);
+    let mut sections = vec![];
+    if loop_start > 0 {
+        sections.push(("intro".to_string(), 0, loop_start));
+    }
+    let mut section_start = loop_start;
+    let mut loop_count = 1;
+    while section_start < end_ticks {
+        let section_end = (section_start + period).min(end_ticks);
+        sections.push((format!("loop{}", loop_count), section_start, section_end));
+        section_start = section_end;
+        loop_count += 1;
+    }
+
+    sections
+        .into_iter()
+        .map(|(suffix, start, end)| {
+            let mut section_smf = SMF {
+                format: SMFFormat::MultiTrack,
+                tracks: Vec::new(),
+                division: smf.division,
+            };
+            for (name, events) in tracks.iter() {
+                let mut track = Track {
+                    copyright: None,
+                    name: name.clone(),
+                    events: Vec::new(),
+                };
+                let mut previous_ticks = start;
+                let mut noteon_ch_notes = vec![];
+                let mut state_recorder = Some(ChannelStateRecorder::new());
+                for (ticks, event) in events.iter() {
+                    // 先頭のメタイベント・System Exclusiveは全ての区間に含める
+                    let is_setup = *ticks == 0
+                        && match event {
+                            MidiEvent::Meta(_) => true,
+                            MidiEvent::Midi(msg) => msg.data.first() == Some(&0xF0),
+                        };
+                    if !is_setup && *ticks < start {
+                        // 区間の開始前に送られたチャンネルの状態を記録しておく
+                        if let (Some(recorder), MidiEvent::Midi(msg)) =
+                            (state_recorder.as_mut(), event)
+                        {
+                            recorder.record(&msg.data);
+                        }
+                        continue;
+                    }
+                    if !is_setup && *ticks >= end {
+                        continue;
+                    }
+                    // 区間の最初のイベントの前に開始時点のチャンネルの状態を復元
+                    if !is_setup {
+                        if let Some(recorder) = state_recorder.take() {
+                            for data in recorder.restore_messages() {
+                                track.events.push(TrackEvent {
+                                    vtime: 0,
+                                    event: MidiEvent::Midi(MidiMessage::from_bytes(data)),
+                                });
+                            }
+                        }
+                    }
+                    let ticks = (*ticks).max(start);
+                    if let MidiEvent::Midi(msg) = event {
+                        if msg.data.len() == 3 {
+                            let ch_note = (msg.data[0] & 0xF, msg.data[1]);
+                            match msg.data[0] & 0xF0 {
+                                MIDIMSG_NOTE_ON if msg.data[2] > 0 => noteon_ch_notes.push(ch_note),
+                                MIDIMSG_NOTE_ON | MIDIMSG_NOTE_OFF => {
+                                    noteon_ch_notes.retain(|&x| x != ch_note)
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                    track.events.push(TrackEvent {
+                        vtime: ticks - previous_ticks,
+                        event: clone_midi_event(event),
+                    });
+                    previous_ticks = ticks;
+                }
+                // 区間の終端で鳴っているノートを止める
+                for (index, (ch, note)) in noteon_ch_notes.into_iter().enumerate() {
+                    track.events.push(TrackEvent {
+                        vtime: if index == 0 { end - previous_ticks } else { 0 },
+                        event: MidiEvent::Midi(MidiMessage::from_bytes(
+                            [MIDIMSG_NOTE_OFF | ch, note, 0].to_vec(),
+                        )),
+                    });
+                }
+                if !track.events.is_empty() {
+                    section_smf.tracks.push(track);
+                }
+            }
+            (suffix, section_smf)
+        })
+        .collect()
+}
+
+/// 長さが指定ティック数未満のノートオン・ノートオフの組をトラックから除去
+/// 除去によりランニングステータスが崩れないよう、残すメッセージは全てステータスバイトを付ける
+fn remove_short_notes(track: &mut rimd::Track, min_ticks: u64) {
+    let events = take_absolute_events(track);
+
+    // ノートオンとノートオフの組を探して短いものに除去の印を付ける
+    let mut removed = vec![false; events.len()];
+    let mut noteon_indices: Vec<((u8, u8), usize)> = vec![];
+    for (i, (ticks, event)) in events.iter().enumerate() {
+        if let MidiEvent::Midi(msg) = event {
+            if msg.data.len() != 3 {
+                continue;
+            }
+            let ch_note = (msg.data[0] & 0xF, msg.data[1]);
+            match msg.data[0] & 0xF0 {
+                MIDIMSG_NOTE_ON if msg.data[2] > 0 => {
+                    noteon_indices.push((ch_note, i));
+                }
+                MIDIMSG_NOTE_ON | MIDIMSG_NOTE_OFF => {
+                    // 最も古いノートオンと組にする
+                    if let Some(pos) = noteon_indices.iter().position(|(x, _)| *x == ch_note) {
+                        let (_, noteon_index) = noteon_indices.remove(pos);
+                        if ticks - events[noteon_index].0 < min_ticks {
+                            removed[noteon_index] = true;
+                            removed[i] = true;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // 除去したイベントの時間を次のイベントに繰り越してトラックを再構成
+    let mut previous_ticks = 0;
+    for ((ticks, event), removed) in events.into_iter().zip(removed) {
+        if removed {
+            continue;
+        }
+        track.events.push(TrackEvent {
+            vtime: ticks - previous_ticks,
+            event: event,
+        });
+        previous_ticks = ticks;
+    }
+}
+
+/// 同じティックで鳴っているノートを再発音する場合に、ノートオフを先に送り、
+/// ノートの長さが残る場合はノートオンを1ティック遅らせて再発音が聞こえるようにする
+fn separate_retriggered_notes(track: &mut rimd::Track) {
+    let mut events = take_absolute_events(track);
+
+    // ノートオン・ノートオフの判定（ノートオンかとチャンネル・ノート番号の組）
+    let note_key = |event: &MidiEvent| -> Option<(bool, (u8, u8))> {
+        if let MidiEvent::Midi(msg) = event {
+            if msg.data.len() == 3 {
+                let ch_note = (msg.data[0] & 0xF, msg.data[1]);
+                match msg.data[0] & 0xF0 {
+                    MIDIMSG_NOTE_ON if msg.data[2] > 0 => return Some((true, ch_note)),
+                    MIDIMSG_NOTE_ON | MIDIMSG_NOTE_OFF => return Some((false, ch_note)),
+                    _ => {}
+                }
+            }
+        }
+        None
+    };
+
+    // 同じティックのイベント群ごとに処理
+    // 並べ替えは群の中だけで行い、遅らせるノートオンの位置は記録して最後にまとめてずらす
+    let mut sounding = [[0usize; 128]; 16];
+    // 再発音したノートオンの位置（最初のノートオフ待ち）
+    let mut pending_noteons: BTreeMap<(u8, u8), Vec<usize>> = BTreeMap::new();
+    let mut delayed_noteons = vec![];
+    let mut start = 0;
+    while start < events.len() {
+        let ticks = events[start].0;
+        let end = start
+            + events[start..]
+                .iter()
+                .take_while(|(t, _)| *t == ticks)
+                .count();
+        // 前のティックから鳴っているノートの最初のノートオフがノートオンより後にあれば前に移す
+        let mut moved: Vec<(u8, u8)> = vec![];
+        let mut retriggered: Vec<(u8, u8)> = vec![];
+        for i in start..end {
+            if let Some((false, ch_note)) = note_key(&events[i].1) {
+                let (ch, note) = (ch_note.0 as usize, (ch_note.1 & 0x7F) as usize);
+                if sounding[ch][note] == 0 || moved.contains(&ch_note) {
+                    continue;
+                }
+                moved.push(ch_note);
+                let noteon_pos =
+                    (start..i).find(|&j| note_key(&events[j].1) == Some((true, ch_note)));
+                if let Some(pos) = noteon_pos {
+                    events[pos..=i].rotate_right(1);
+                    retriggered.push(ch_note);
+                }
+            }
+        }
+        // 鳴っているノートを更新し、再発音したノートオンの長さを調べる
+        for i in start..end {
+            match note_key(&events[i].1) {
+                Some((true, ch_note)) => {
+                    sounding[ch_note.0 as usize][(ch_note.1 & 0x7F) as usize] += 1;
+                    // 再発音したノートの最初のノートオンの長さを調べる
+                    if let Some(pos) = retriggered.iter().position(|x| *x == ch_note) {
+                        retriggered.remove(pos);
+                        pending_noteons.entry(ch_note).or_default().push(i);
+                    }
+                }
+                Some((false, ch_note)) => {
+                    let count = &mut sounding[ch_note.0 as usize][(ch_note.1 & 0x7F) as usize];
+                    *count = count.saturating_sub(1);
+                    // 長さが1ティックより長ければノートオンを1ティック遅らせる
+                    for noteon_index in pending_noteons.remove(&ch_note).unwrap_or_default() {
+                        if ticks > events[noteon_index].0 + 1 {
+                            delayed_noteons.push(noteon_index);
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+        start = end;
+    }
+    // ノートオフの無いノートオンも遅らせる
+    delayed_noteons.extend(pending_noteons.into_values().flatten());
+    for noteon_index in delayed_noteons {
+        events[noteon_index].0 += 1;
+    }
+    events.sort_by_key(|(ticks, _)| *ticks);
+
+    let mut previous_ticks = 0;
+    for (ticks, event) in events {
+        track.events.push(TrackEvent {
+            vtime: ticks - previous_ticks,
+            event: event,
+        });
+        previous_ticks = ticks;
+    }
+}
+
+/// 整数部と小数部から基準ノート（上位7bitが整数部、下位9bitが小数部の固定小数）を作成
+fn set_center_note(int: u8, fraction: f32) -> u16 {
+    let int = int.min(127) as u16;
+    // 不正な小数部は0とみなし、整数部へ繰り上がらないよう丸め込む
+    let fraction = if fraction.is_finite() {
+        f32::round(fraction * 512.0).clamp(0.0, 511.0) as u16
+    } else {
+        0
+    };
+    (int << 9) | fraction
+}
+
+/// 実数のノート番号から基準ノートを作成
+fn center_note_from_float(note: f32) -> u16 {
+    // 小数部の丸めによる繰り上がりを整数部に反映してから分解する
+    let fixed = if note.is_finite() {
+        f32::round(note * 512.0).clamp(0.0, u16::MAX as f32) as u16
+    } else {
+        0
+    };
+    set_center_note((fixed >> 9) as u8, (fixed & 0x01FF) as f32 / 512.0)
+}
+
+/// 基準ノートを指定した刻み幅（9bit小数部の固定小数）の最も近い値に丸める
+fn snap_center_note(center_note: u16, step: f32) -> u16 {
+    let snapped = f32::round(f32::round(center_note as f32 / step) * step);
+    // 上限を超える場合は1刻み下に丸める
+    if snapped > u16::MAX as f32 {
+        f32::round(snapped - step) as u16
+    } else {
+        snapped as u16
+    }
+}
+
+/// 音源情報から推定した初期パラメータを作成
+/// 推定する項目（プログラム・基準ノート・チャンネル等）以外はテンプレートの値を使う
+fn create_default_source_parameter(
+    source_info: &SourceInformation,
+    a4_pitch_hz: f32,
+    template: &SourceParameterTemplate,
+) -> SourceParameter {
+    // ドラム音とピッチの推定
+    let (is_drum, center_note) = estimate_drum_and_note(source_info, a4_pitch_hz);
+    SourceParameter {
+        mute: false,
+        program: if is_drum {
+            Program::AcousticBassDrum
+        } else {
+            Program::AcousticGrand
+        },
+        center_note: center_note_from_float(center_note),
+        noteon_velocity: template.noteon_velocity,
+        velocity_from_keyon_volume: template.velocity_from_keyon_volume,
+        // テンプレートで指定が無ければ、解析で観測したピッチ変化を切らずに収める最小の幅（変化が無ければ12半音）
+        pitch_bend_width: if template.pitch_bend_width > 0 {
+            template.pitch_bend_width
+        } else if source_info.max_pitch_deviation > 0.0 {
+            required_pitch_bend_width(source_info)
+        } else {
+            12
+        },
+        envelope_as_expression: template.envelope_as_expression,
+        auto_pan: template.auto_pan,
+        fixed_pan: template.fixed_pan,
+        pan_law: template.pan_law,
+        auto_volume: template.auto_volume,
+        fixed_volume: template.fixed_volume,
+        fixed_reverb_send: template.fixed_reverb_send,
+        chorus_send: template.chorus_send,
+        // ピッチモジュレーションによるピッチ変化はピッチベンドでは再現できないので出力しない
+        enable_pitch_bend: !is_drum && !source_info.pitch_modulated,
+        echo_as_reverb_send: template.echo_as_reverb_send,
+        echo_send_scale_percent: template.echo_send_scale_percent,
+        update_parameter_after_noteon: template.update_parameter_after_noteon,
+        channel_routing: if is_drum {
+            [9; 8]
+        } else {
+            [0, 1, 2, 3, 4, 5, 6, 7]
+        },
+        channel_mute: [false; 8],
+        instrument_name: "".to_string(),
+        label: "".to_string(),
+        hide_in_indicator: false,
+        sustain_pedal: template.sustain_pedal,
+        link_duplicates: template.link_duplicates,
+        merge_stereo_pair: false,
+        pre_merge_state: None,
+        min_note: template.min_note,
+        max_note: template.max_note,
+        force_tonal: false,
+        force_drum: false,
+    }
+}
+
+/// 音源のプログラムを設定
+/// ドラムと音階が切り替わる場合は出力チャンネル（ドラムは9、音階はSPCと同じ）とピッチベンドも切り替える
+fn set_source_program(param: &mut SourceParameter, program: Program, pitch_modulated: bool) {
+    let prev_is_drum = (param.program.clone() as u8) >= 0x80;
+    let curr_is_drum = (program.clone() as u8) >= 0x80;
+    if prev_is_drum != curr_is_drum {
+        param.channel_routing = if curr_is_drum {
+            [9; 8]
+        } else {
+            [0, 1, 2, 3, 4, 5, 6, 7]
+        };
+        // ピッチモジュレーションによるピッチ変化はピッチベンドでは再現できないので出力しない
+        param.enable_pitch_bend = !curr_is_drum && !pitch_modulated;
+    }
+    param.program = program;
+}
+
+/// ドラム判定に依存する設定（プログラム・チャンネル・ピッチベンド）を判定に合わせる
+/// 現在の種類（ドラム・音階）が判定と異なる場合のみ既定のプログラムに切り替える
+fn apply_drum_classification(
+    param: &mut SourceParameter,
+    source_info: &SourceInformation,
+    is_drum: bool,
+) {
+    if ((param.program.clone() as u8) >= 0x80) != is_drum {
+        let program = if is_drum {
+            Program::AcousticBassDrum
+        } else {
+            Program::AcousticGrand
+        };
+        set_source_program(param, program, source_info.pitch_modulated);
+    }
+}
+
+/// DSPレジスタを全て読み出す
+fn read_dsp_registers(dsp: &spc700::sdsp::SDSP, register: &mut [u8; DSP_REGISTER_SIZE]) {
+    for (address, value) in register.iter_mut().enumerate() {
+        *value = dsp.read_register(&[0u8], address as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// テスト用の音源情報（波形・スペクトル・ループ開始位置以外は空）
+    fn test_source_info(
+        signal: Vec<f32>,
+        power_spectrum: Vec<f32>,
+        loop_start_sample: usize,
+    ) -> SourceInformation {
+        SourceInformation {
+            signal: signal,
+            power_spectrum: power_spectrum,
+            dir_address: 0,
+            start_address: 0,
+            end_address: 0,
+            loop_start_sample: loop_start_sample,
+            using_channel: [false; 8],
+            keyon_pitches: vec![],
+            max_pitch_deviation: 0.0,
+            duplicates: vec![],
+            rms: 0.0,
+            uses_noise: false,
+            pitch_modulated: false,
+            pitch_confidence: 0.0,
+            detected_drum: false,
+            alternate_dir_addresses: vec![],
+            stereo_pair_partner: None,
+        }
+    }
+
+    #[test]
+    fn spc_file_open_test() -> Result<(), Box<dyn std::error::Error>> {
+        let test_files = [
+            "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc",
+            "./tests/data/forest_album_230125_spc_supermidipak/03_cowctry_voice.spc",
+        ];
+
+        for file in test_files {
+            let mut app = App::default();
+            let data = Box::new(std::fs::read(&file)?);
+            let _ = app.update(Message::FileOpened(Ok((
+                file.into(),
+                LoadedFile::SPCFile(*data),
+            ))));
+            let _ = app.wait_background_operation();
+
+            assert!(app.spc_file.is_some());
+            assert!(app.spc_file_path == Some(file.into()));
+            // 解析時のドラム判定が音源情報に残っている
+            for (srn_no, info) in app.decoded_source_infos() {
+                let (is_drum, _) = estimate_drum_and_note(&info, app.a4_pitch_hz());
+                assert_eq!(info.detected_drum, is_drum, "SRN {}", srn_no);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parameter_set_test() -> Result<(), Box<dyn std::error::Error>> {
+        let test_files = ["./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc"];
+
+        macro_rules! test_param_field(
+            ($app:expr, $srn_no:expr, $field:tt, $expect:expr) => {
+                {
+                    let params = $app.source_parameter.clone();
+                    let params = params.read().unwrap();
+                    let param = params.get(&$srn_no).unwrap();
+                    assert_eq!(param.$field, $expect);
+                }
+            }
+        );
+
+        for file in test_files {
+            let mut app = App::default();
+            let data = Box::new(std::fs::read(&file)?);
+            let _ = app.update(Message::FileOpened(Ok((
+                file.into(),
+                LoadedFile::SPCFile(*data),
+            ))));
+            let _ = app.wait_background_operation();
+
+            // SRN = 0に対してパラメータ編集し、意図した値が設定されているか確認
+            let _ = app.update(Message::SRNMuteFlagToggled(0, true));
+            test_param_field!(app, 0, mute, true);
+            let _ = app.update(Message::SRNMuteFlagToggled(0, false));
+            test_param_field!(app, 0, mute, false);
+            let _ = app.update(Message::ProgramSelected(0, Program::BrightAcoustic, None));
+            test_param_field!(app, 0, program, Program::BrightAcoustic);
+            let _ = app.update(Message::CenterNoteIntChanged(0, 0));
+            let _ = app.update(Message::CenterNoteFractionChanged(0, 0.0));
+            test_param_field!(app, 0, center_note, 0);
+            let _ = app.update(Message::CenterNoteIntChanged(0, 127));
+            let _ = app.update(Message::CenterNoteFractionChanged(0, 1.0));
+            test_param_field!(app, 0, center_note, 0xFFFF);
+            // 不正な入力は無視され、範囲外の入力は丸め込まれる
+            let _ = app.update(Message::CenterNoteFractionChanged(0, f32::NAN));
+            test_param_field!(app, 0, center_note, 0xFFFF);
+            let _ = app.update(Message::CenterNoteFractionChanged(0, -1.0));
+            test_param_field!(app, 0, center_note, 0xFE00);
+            let _ = app.update(Message::CenterNoteFractionChanged(0, f32::INFINITY));
+            test_param_field!(app, 0, center_note, 0xFE00);
+            let _ = app.update(Message::CenterNoteFractionChanged(0, 1.0));
+            let _ = app.update(Message::SRNCenterNoteSnapToCentClicked(0));
+            test_param_field!(app, 0, center_note, 0xFFFB);
+            let _ = app.update(Message::SRNCenterNoteSnapToSemitoneClicked(0));
+            test_param_field!(app, 0, center_note, 0xFE00);
+            let _ = app.update(Message::NoteOnVelocityChanged(0, 1));
+            test_param_field!(app, 0, noteon_velocity, 1);
+            let _ = app.update(Message::PitchBendWidthChanged(0, 0));
+            test_param_field!(app, 0, pitch_bend_width, 0);
+            let _ = app.update(Message::PitchBendWidthChanged(0, 48));
+            test_param_field!(app, 0, pitch_bend_width, 48);
+            let _ = app.update(Message::EnablePitchBendFlagToggled(0, true));
+            test_param_field!(app, 0, enable_pitch_bend, true);
+            let _ = app.update(Message::EnablePitchBendFlagToggled(0, false));
+            test_param_field!(app, 0, enable_pitch_bend, false);
+            let _ = app.update(Message::AutoPanFlagToggled(0, true));
+            test_param_field!(app, 0, auto_pan, true);
+            let _ = app.update(Message::AutoPanFlagToggled(0, false));
+            test_param_field!(app, 0, auto_pan, false);
+            let _ = app.update(Message::FixedPanChanged(0, 0));
+            test_param_field!(app, 0, fixed_pan, 0);
+            let _ = app.update(Message::FixedPanChanged(0, 127));
+            test_param_field!(app, 0, fixed_pan, 127);
+            let _ = app.update(Message::PanLawChanged(0, PanLaw::ConstantPower3dB));
+            test_param_field!(app, 0, pan_law, PanLaw::ConstantPower3dB);
+            let _ = app.update(Message::PanLawChanged(0, PanLaw::Linear));
+            test_param_field!(app, 0, pan_law, PanLaw::Linear);
+            let _ = app.update(Message::AutoVolumeFlagToggled(0, true));
+            test_param_field!(app, 0, auto_volume, true);
+            let _ = app.update(Message::AutoVolumeFlagToggled(0, false));
+            test_param_field!(app, 0, auto_volume, false);
+            let _ = app.update(Message::FixedVolumeChanged(0, 0));
+            test_param_field!(app, 0, fixed_volume, 0);
+            let _ = app.update(Message::FixedVolumeChanged(0, 127));
+            test_param_field!(app, 0, fixed_volume, 127);
+            let _ = app.update(Message::EnvelopeAsExpressionFlagToggled(0, true));
+            test_param_field!(app, 0, envelope_as_expression, true);
+            let _ = app.update(Message::EnvelopeAsExpressionFlagToggled(0, false));
+            test_param_field!(app, 0, envelope_as_expression, false);
+            let _ = app.update(Message::EchoSendScaleChanged(0, 0));
+            test_param_field!(app, 0, echo_send_scale_percent, 0);
+            let _ = app.update(Message::EchoSendScaleChanged(0, 255));
+            test_param_field!(app, 0, echo_send_scale_percent, MAX_ECHO_SEND_SCALE_PERCENT);
+            let _ = app.update(Message::EchoAsReverbFlagToggled(0, true));
+            test_param_field!(app, 0, echo_as_reverb_send, true);
+            let _ = app.update(Message::EchoAsReverbFlagToggled(0, false));
+            test_param_field!(app, 0, echo_as_reverb_send, false);
+            let _ = app.update(Message::HideInIndicatorFlagToggled(0, true));
+            test_param_field!(app, 0, hide_in_indicator, true);
+            let _ = app.update(Message::HideInIndicatorFlagToggled(0, false));
+            test_param_field!(app, 0, hide_in_indicator, false);
+            let _ = app.update(Message::LabelChanged(0, "Lead".to_string()));
+            test_param_field!(app, 0, label, "Lead".to_string());
+            let _ = app.update(Message::LabelChanged(0, "".to_string()));
+            test_param_field!(app, 0, label, "".to_string());
+            let _ = app.update(Message::LinkDuplicatesFlagToggled(0, true));
+            test_param_field!(app, 0, link_duplicates, true);
+            let _ = app.update(Message::LinkDuplicatesFlagToggled(0, false));
+            test_param_field!(app, 0, link_duplicates, false);
+            // ステレオペアの統合を解除すると統合前のパン・ミュートに戻る
+            let partner = app
+                .source_parameter
+                .read()
+                .unwrap()
+                .keys()
+                .find(|&&srn_no| srn_no != 0)
+                .copied();
+            if let Some(partner) = partner {
+                if let Some(info) = app.source_infos.write().unwrap().get_mut(&0) {
+                    info.stereo_pair_partner = Some(partner);
+                }
+                let _ = app.update(Message::FixedPanChanged(0, 20));
+                let _ = app.update(Message::SRNMuteFlagToggled(partner, true));
+                let _ = app.update(Message::MergeStereoPairFlagToggled(0, true));
+                test_param_field!(app, 0, auto_pan, false);
+                test_param_field!(app, 0, fixed_pan, 64);
+                test_param_field!(app, partner, mute, true);
+                let _ = app.update(Message::MergeStereoPairFlagToggled(0, false));
+                test_param_field!(app, 0, auto_pan, false);
+                test_param_field!(app, 0, fixed_pan, 20);
+                test_param_field!(app, partner, mute, true);
+                test_param_field!(app, 0, merge_stereo_pair, false);
+                let _ = app.update(Message::SRNMuteFlagToggled(partner, false));
+            }
+            let _ = app.update(Message::SRNKindOverrideSelected(
+                0,
+                SourceKindOverride::Drum,
+            ));
+            test_param_field!(app, 0, force_drum, true);
+            test_param_field!(app, 0, channel_routing, [9; 8]);
+            test_param_field!(app, 0, enable_pitch_bend, false);
+            let _ = app.update(Message::SRNKindOverrideSelected(
+                0,
+                SourceKindOverride::Tonal,
+            ));
+            test_param_field!(app, 0, force_drum, false);
+            test_param_field!(app, 0, force_tonal, true);
+            test_param_field!(app, 0, channel_routing, [0, 1, 2, 3, 4, 5, 6, 7]);
+            let _ = app.update(Message::SRNKindOverrideSelected(
+                0,
+                SourceKindOverride::Auto,
+            ));
+            test_param_field!(app, 0, force_tonal, false);
+            let _ = app.update(Message::SustainPedalFlagToggled(0, true));
+            test_param_field!(app, 0, sustain_pedal, true);
+            let _ = app.update(Message::SustainPedalFlagToggled(0, false));
+            test_param_field!(app, 0, sustain_pedal, false);
+            let _ = app.update(Message::MinNoteChanged(0, 36));
+            test_param_field!(app, 0, min_note, 36);
+            let _ = app.update(Message::MaxNoteChanged(0, 24));
+            test_param_field!(app, 0, max_note, 24);
+            test_param_field!(app, 0, min_note, 24);
+            let _ = app.update(Message::MaxNoteChanged(0, 255));
+            test_param_field!(app, 0, max_note, 127);
+            let _ = app.update(Message::MinNoteChanged(0, 0));
+            test_param_field!(app, 0, min_note, 0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn configure_set_test() -> Result<(), Box<dyn std::error::Error>> {
+        let test_files = ["./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc"];
+
+        macro_rules! test_config_field(
+            ($app:expr, $field:tt, $expect:expr) => {
+                {
+                    let config = $app.midi_output_configure.clone();
+                    let config = config.read().unwrap();
+                    assert_eq!(config.$field, $expect);
+                }
+            }
+        );
+
+        for file in test_files {
+            let mut app = App::default();
+            let data = Box::new(std::fs::read(&file)?);
+            let _ = app.update(Message::FileOpened(Ok((
+                file.into(),
+                LoadedFile::SPCFile(*data),
+            ))));
+            let _ = app.wait_background_operation();
+
+            // 意図した値が設定されているか確認
+            let _ = app.update(Message::MIDIOutputTicksPerQuarterChanged(24));
+            test_config_field!(app, ticks_per_quarter, 24);
+            let _ = app.update(Message::MIDIOutputTicksPerQuarterChanged(960));
+            test_config_field!(app, ticks_per_quarter, 960);
+            let _ = app.update(Message::MIDIOutputUpdatePeriodChanged(0));
+            test_config_field!(app, playback_parameter_update_period, 0);
+            let _ = app.update(Message::MIDIOutputUpdatePeriodChanged(255));
+            test_config_field!(app, playback_parameter_update_period, 255);
+            let _ = app.update(Message::MIDIOutputBpmChanged(150.0));
+            test_config_field!(app, beats_per_minute, 150.0);
+            let _ = app.update(Message::MIDIOutputBpmChanged(f32::NAN));
+            test_config_field!(app, beats_per_minute, 150.0);
+            let _ = app.update(Message::MIDIOutputBpmChanged(f32::INFINITY));
+            test_config_field!(app, beats_per_minute, 150.0);
+            let _ = app.update(Message::MIDIOutputBpmChanged(0.0));
+            test_config_field!(app, beats_per_minute, MIN_BEATS_PER_MINUTE as f32);
+            let _ = app.update(Message::MIDIOutputBpmChanged(1.0e9));
+            test_config_field!(app, beats_per_minute, MAX_BEATS_PER_MINUTE as f32);
+            let _ = app.update(Message::MIDIOutputDurationChanged(0));
+            test_config_field!(app, output_duration_msec, MIN_OUTPUT_DURATION_MSEC);
+            let _ = app.update(Message::MIDIOutputDurationChanged(u64::MAX));
+            test_config_field!(app, output_duration_msec, MAX_OUTPUT_DURATION_MSEC);
+            let _ = app.update(Message::WAVBypassEchoChanged(true));
+            test_config_field!(app, wav_bypass_echo, true);
+            let _ = app.update(Message::WAVBypassEchoChanged(false));
+            test_config_field!(app, wav_bypass_echo, false);
+            let _ = app.update(Message::WAVSoftLimiterFlagToggled(true));
+            test_config_field!(app, wav_soft_limiter, true);
+            let _ = app.update(Message::WAVSoftLimiterFlagToggled(false));
+            test_config_field!(app, wav_soft_limiter, false);
+            let _ = app.update(Message::FlattenVelocityFlagToggled(true));
+            test_config_field!(app, flatten_velocity, true);
+            let _ = app.update(Message::FlattenVelocityFlagToggled(false));
+            test_config_field!(app, flatten_velocity, false);
+            let _ = app.update(Message::FlatVelocityChanged(1));
+            test_config_field!(app, flat_velocity, 1);
+            let _ = app.update(Message::FlatVelocityChanged(127));
+            test_config_field!(app, flat_velocity, 127);
+            let _ = app.update(Message::CalibrateVelocityByProgramFlagToggled(true));
+            test_config_field!(app, calibrate_velocity_by_program, true);
+            let _ = app.update(Message::CalibrateVelocityByProgramFlagToggled(false));
+            test_config_field!(app, calibrate_velocity_by_program, false);
+            let _ = app.update(Message::WAVSampleRateChanged(WAVSampleRate::Hz48000));
+            test_config_field!(app, wav_sample_rate, WAVSampleRate::Hz48000);
+            let _ = app.update(Message::WAVResampleQualityChanged(WAVResampleQuality::Best));
+            test_config_field!(app, wav_resample_quality, WAVResampleQuality::Best);
+            let _ = app.update(Message::WAVStereoWidthChanged(0));
+            test_config_field!(app, wav_stereo_width_percent, 0);
+            let _ = app.update(Message::WAVStereoWidthChanged(200));
+            test_config_field!(app, wav_stereo_width_percent, 200);
+            let _ = app.update(Message::TargetPitchBendWidthChanged(1));
+            test_config_field!(app, target_pitch_bend_width, 1);
+            let _ = app.update(Message::TargetPitchBendWidthChanged(48));
+            test_config_field!(app, target_pitch_bend_width, 48);
+            let _ = app.update(Message::ClampPitchBendWidthFlagToggled(true));
+            test_config_field!(app, clamp_pitch_bend_width, true);
+            let _ = app.update(Message::ClampPitchBendWidthFlagToggled(false));
+            test_config_field!(app, clamp_pitch_bend_width, false);
+            let _ = app.update(Message::MIDIOutputSourceMarkersFlagToggled(true));
+            test_config_field!(app, insert_source_markers, true);
+            let _ = app.update(Message::MIDIOutputSourceMarkersFlagToggled(false));
+            test_config_field!(app, insert_source_markers, false);
+            let _ = app.update(Message::MIDIOutputClickTrackFlagToggled(true));
+            test_config_field!(app, insert_click_track, true);
+            let _ = app.update(Message::MIDIOutputClickTrackFlagToggled(false));
+            test_config_field!(app, insert_click_track, false);
+            let _ = app.update(Message::MIDIOutputEmbedSPCMetadataFlagToggled(true));
+            test_config_field!(app, embed_spc_metadata, true);
+            let _ = app.update(Message::MIDIOutputEmbedSPCMetadataFlagToggled(false));
+            test_config_field!(app, embed_spc_metadata, false);
+            let _ = app.update(Message::MIDIOutputEmbedSPCCommentFlagToggled(true));
+            test_config_field!(app, embed_spc_comment_as_lyric, true);
+            let _ = app.update(Message::MIDIOutputEmbedSPCCommentFlagToggled(false));
+            test_config_field!(app, embed_spc_comment_as_lyric, false);
+            let _ = app.update(Message::MIDIOutputMaxPolyphonyChanged(4));
+            test_config_field!(app, max_polyphony_per_channel, 4);
+            let _ = app.update(Message::MIDIOutputMaxPolyphonyChanged(0));
+            test_config_field!(app, max_polyphony_per_channel, 0);
+            let _ = app.update(Message::MIDIOutputMinNoteDurationChanged(1000));
+            test_config_field!(app, min_note_duration_msec, 1000);
+            let _ = app.update(Message::MIDIOutputMinNoteDurationChanged(0));
+            test_config_field!(app, min_note_duration_msec, 0);
+            let _ = app.update(Message::NoteSummaryDurationChanged(1000));
+            test_config_field!(app, note_summary_duration_msec, 1000);
+            let _ = app.update(Message::SplitSMFPerLoopFlagToggled(true));
+            test_config_field!(app, split_smf_per_loop, true);
+            let _ = app.update(Message::SplitSMFPerLoopFlagToggled(false));
+            test_config_field!(app, split_smf_per_loop, false);
+            let _ = app.update(Message::RoutePercussiveNotesFlagToggled(true));
+            test_config_field!(app, route_percussive_notes, true);
+            let _ = app.update(Message::RoutePercussiveNotesFlagToggled(false));
+            test_config_field!(app, route_percussive_notes, false);
+            let _ = app.update(Message::MinSourceKeyOnDurationChanged(50));
+            test_config_field!(app, min_source_keyon_msec, 50);
+            let _ = app.update(Message::ExcludedVoiceFlagToggled(3, true));
+            let _ = app.update(Message::ExcludedVoiceFlagToggled(7, true));
+            test_config_field!(app, excluded_voices, 0x88);
+            let _ = app.update(Message::ExcludedVoiceFlagToggled(7, false));
+            test_config_field!(app, excluded_voices, 0x08);
+            let _ = app.update(Message::ExcludedVoiceFlagToggled(3, false));
+            test_config_field!(app, excluded_voices, 0);
+            assert!(app.summarize_midi_notes().is_some());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn spc_extra_ram_test() -> Result<(), Box<dyn std::error::Error>> {
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let original = std::fs::read(&file)?;
+        let extra_ram: Vec<u8> = (0..SPC_EXTRA_RAM_SIZE as u8).collect();
+
+        // 拡張RAMを書き込んだダンプを作成
+        let mut data = original.clone();
+        data.resize(SPC_FILE_EXTRA_RAM_OFFSET + SPC_EXTRA_RAM_SIZE, 0);
+        data[SPC_FILE_EXTRA_RAM_OFFSET..].copy_from_slice(&extra_ram);
+
+        // IPL ROMが有効な場合は拡張RAMがROM領域の裏に反映される
+        data[SPC_FILE_RAM_OFFSET + SPC_CONTROL_REGISTER_ADDRESS] |= 0x80;
+        let spc_file = load_spc_file(&data).unwrap();
+        assert_eq!(
+            spc_file.ram[SPC_IPL_ROM_ADDRESS..(SPC_IPL_ROM_ADDRESS + SPC_EXTRA_RAM_SIZE)],
+            extra_ram[..]
+        );
+
+        // IPL ROMが無効な場合はRAMイメージのまま
+        data[SPC_FILE_RAM_OFFSET + SPC_CONTROL_REGISTER_ADDRESS] &= !0x80;
+        let spc_file = load_spc_file(&data).unwrap();
+        let ram_offset = SPC_FILE_RAM_OFFSET + SPC_IPL_ROM_ADDRESS;
+        assert_eq!(
+            spc_file.ram[SPC_IPL_ROM_ADDRESS..(SPC_IPL_ROM_ADDRESS + SPC_EXTRA_RAM_SIZE)],
+            data[ram_offset..(ram_offset + SPC_EXTRA_RAM_SIZE)]
+        );
+
+        // 壊れたファイルは読み込まない
+        assert!(load_spc_file(&original[..SPC_FILE_MIN_SIZE - 1]).is_err());
+        assert!(load_spc_file(&original[1..]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsaved_source_parameter_test() -> Result<(), Box<dyn std::error::Error>> {
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let mut app = App::default();
+        let data = Box::new(std::fs::read(&file)?);
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(*data),
+        ))));
+        let _ = app.wait_background_operation();
+        // 読み込み直後は未保存の変更なし
+        assert!(!app.has_unsaved_source_parameter());
+
+        // パラメータを変更すると未保存の変更あり、JSONを保存すると解消
+        let srn_no = *app.source_parameter.read().unwrap().keys().next().unwrap();
+        let _ = app.update(Message::ProgramSelected(srn_no, Program::Timpani, None));
+        assert!(app.has_unsaved_source_parameter());
+        let _ = app.update(Message::JSONSaved(Ok(())));
+        assert!(!app.has_unsaved_source_parameter());
+        Ok(())
+    }
+
+    #[test]
+    fn unanalyzed_sources_test() -> Result<(), Box<dyn std::error::Error>> {
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let mut app = App::default();
+        let data = Box::new(std::fs::read(&file)?);
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(*data),
+        ))));
+        let _ = app.wait_background_operation();
+        let _ = app.update(Message::MIDIOutputDurationChanged(10 * 1000));
+
+        let spc_file = app.spc_file.clone().unwrap();
+        let config = app.midi_output_configure.read().unwrap().clone();
+        let mut params = app.source_parameter.read().unwrap().clone();
+        let mut options = SMFExportOptions {
+            stretch_target_msec: None,
+            master_reverb_send: 0,
+            range_msec: None,
+            preroll_msec: 0,
+            velocity_calibration: None,
+        };
+        let unanalyzed_sources = |params: &BTreeMap<u8, SourceParameter>,
+                                  options: &SMFExportOptions| {
+            App::build_smf(
+                &spc_file,
+                &config,
+                params,
+                options,
+                &AtomicBool::new(false),
+                &AtomicUsize::new(0),
+            )
+            .map(|(_, unanalyzed_sources)| unanalyzed_sources)
+            .unwrap()
+        };
+        // 解析で見つかった音源はすべてパラメータを持つ
+        assert!(unanalyzed_sources(&params, &options).is_empty());
+        // パラメータを1つだけ残すと、他の鳴った音源は解析で見つからなかったものとして報告される
+        let (first_srn, first_param) = params.first_key_value().unwrap();
+        let only_first = BTreeMap::from([(*first_srn, first_param.clone())]);
+        let played = unanalyzed_sources(&only_first, &options);
+        assert!(!played.is_empty());
+        let srn_no = played[0];
+        params.remove(&srn_no);
+        assert_eq!(unanalyzed_sources(&params, &options), vec![srn_no]);
+        // 出力範囲の外で鳴っても報告しない
+        options.range_msec = Some((10 * 1000 - 1, 10 * 1000));
+        assert!(unanalyzed_sources(&params, &options).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn smf_source_marker_test() -> Result<(), Box<dyn std::error::Error>> {
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let mut app = App::default();
+        let data = Box::new(std::fs::read(&file)?);
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(*data),
+        ))));
+        let _ = app.wait_background_operation();
+        let _ = app.update(Message::MIDIOutputDurationChanged(10 * 1000));
+
+        // トラックごとに（累計ティック, イベントの文字列表現, マーカーか）の組を列挙
+        fn collect_events(smf: &SMF) -> Vec<Vec<(u64, String, bool)>> {
+            smf.tracks
+                .iter()
+                .map(|track| {
+                    let mut ticks = 0;
+                    track
+                        .events
+                        .iter()
+                        .map(|e| {
+                            ticks += e.vtime;
+                            let is_marker = match &e.event {
+                                MidiEvent::Meta(meta) => {
+                                    matches!(meta.command, MetaCommand::MarkerText)
+                                }
+                                _ => false,
+                            };
+                            (ticks, format!("{}", e.event), is_marker)
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+
+        let _ = app.update(Message::MIDIOutputSourceMarkersFlagToggled(false));
+        let plain = collect_events(&app.create_smf().unwrap());
+        let _ = app.update(Message::MIDIOutputSourceMarkersFlagToggled(true));
+        let marked = collect_events(&app.create_smf().unwrap());
+
+        assert_eq!(plain.len(), marked.len());
+        let mut num_markers = 0;
+        for (plain_track, marked_track) in plain.iter().zip(marked.iter()) {
+            // マーカーを除くとイベントとそのティックは変わらない
+            let without_markers: Vec<_> = marked_track
+                .iter()
+                .filter(|(_, _, is_marker)| !is_marker)
+                .cloned()
+                .collect();
+            assert_eq!(*plain_track, without_markers);
+            // マーカーは直後のイベント（ノートオン）と同じティックに置かれる
+            for (i, (tick, _, is_marker)) in marked_track.iter().enumerate() {
+                if *is_marker {
+                    num_markers += 1;
+                    assert!(i + 1 < marked_track.len());
+                    assert_eq!(*tick, marked_track[i + 1].0);
+                }
+            }
+        }
+        assert!(num_markers > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn smf_polyphony_limit_timing_test() -> Result<(), Box<dyn std::error::Error>> {
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let mut app = App::default();
+        let data = Box::new(std::fs::read(&file)?);
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(*data),
+        ))));
+        let _ = app.wait_background_operation();
+        let _ = app.update(Message::MIDIOutputDurationChanged(10 * 1000));
+
+        // トラックごとに（累計ティック, チャンネル, ノート番号）のノートオンを列挙
+        fn collect_noteons(smf: &SMF) -> Vec<Vec<(u64, u8, u8)>> {
+            smf.tracks
+                .iter()
+                .map(|track| {
+                    let mut ticks = 0;
+                    let mut status_byte = 0;
+                    let mut noteons = vec![];
+                    for e in &track.events {
+                        ticks += e.vtime;
+                        if let MidiEvent::Midi(msg) = &e.event {
+                            if (msg.data[0] & 0x80) != 0 {
+                                status_byte = msg.data[0];
+                            }
+                            let len = msg.data.len();
+                            if (status_byte & 0xF0) == MIDIMSG_NOTE_ON
+                                && len >= 2
+                                && msg.data[len - 1] != 0
+                            {
+                                noteons.push((ticks, status_byte & 0xF, msg.data[len - 2]));
+                            }
+                        }
+                    }
+                    noteons
+                })
+                .collect()
+        }
+
+        let _ = app.update(Message::MIDIOutputMaxPolyphonyChanged(0));
+        let unlimited = collect_noteons(&app.create_smf().unwrap());
+        let _ = app.update(Message::MIDIOutputMaxPolyphonyChanged(1));
+        let limited = collect_noteons(&app.create_smf().unwrap());
+
+        // 発音数を制限してもノートオンの位置はずれない
+        assert!(unlimited.iter().any(|track| !track.is_empty()));
+        assert_eq!(unlimited, limited);
+
+        Ok(())
+    }
+
+    #[test]
+    fn smf_volume_automation_test() -> Result<(), Box<dyn std::error::Error>> {
+        // ヘッダは既存のSPCファイルのものを流用し、RAMとDSPレジスタは作り直す
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let mut data = std::fs::read(&file)?;
+        data.truncate(SPC_FILE_MIN_SIZE);
+        const PROGRAM_ADDRESS: usize = 0x0400;
+        data[0x25] = (PROGRAM_ADDRESS & 0xFF) as u8; // PC
+        data[0x26] = (PROGRAM_ADDRESS >> 8) as u8;
+        data[0x2A] = 0x00; // PSW
+        data[0x2B] = 0xEF; // SP
+        let ram = &mut data[SPC_FILE_RAM_OFFSET..(SPC_FILE_RAM_OFFSET + 0x10000)];
+        ram.fill(0);
+        // 音源ディレクトリ（$0200）：音源0は$0300から開始し、同じ位置でループ
+        ram[0x0200..0x0204].copy_from_slice(&[0x00, 0x03, 0x00, 0x03]);
+        // BRRブロック（ループ・エンドフラグ付きの矩形波）
+        ram[0x0300..0x0309]
+            .copy_from_slice(&[0xC3, 0x77, 0x77, 0x77, 0x77, 0x99, 0x99, 0x99, 0x99]);
+        // ボイス0をキーオンし、約50msごとに左右ボリュームを127から1まで下げる
+        let program: [u8; 33] = [
+            0x8F, 0x4C, 0xF2, // MOV $F2,#$4C (KON)
+            0x8F, 0x01, 0xF3, // MOV $F3,#$01
+            0x8D, 0x7F, // MOV Y,#$7F
+            0x8F, 0x00, 0xF2, // loop: MOV $F2,#$00 (V0VOLL)
+            0xCB, 0xF3, // MOV $F3,Y
+            0x8F, 0x01, 0xF2, // MOV $F2,#$01 (V0VOLR)
+            0xCB, 0xF3, // MOV $F3,Y
+            0xCD, 0x00, // MOV X,#$00
+            0xE8, 0x20, // d1: MOV A,#$20
+            0x9C, // d2: DEC A
+            0xD0, 0xFD, // BNE d2
+            0x1D, // DEC X
+            0xD0, 0xF8, // BNE d1
+            0xDC, // DEC Y
+            0xD0, 0xE9, // BNE loop
+            0x2F, 0xFE, // BRA *
+        ];
+        ram[PROGRAM_ADDRESS..(PROGRAM_ADDRESS + program.len())].copy_from_slice(&program);
+        let dsp = &mut data[(SPC_FILE_RAM_OFFSET + 0x10000)..SPC_FILE_MIN_SIZE];
+        dsp.fill(0);
+        dsp[0x00] = 0x7F; // V0VOLL
+        dsp[0x01] = 0x7F; // V0VOLR
+        dsp[0x03] = 0x10; // V0PITCHH
+        dsp[0x07] = 0x7F; // V0GAIN（ダイレクト指定）
+        dsp[0x0C] = 0x7F; // MVOLL
+        dsp[0x1C] = 0x7F; // MVOLR
+        dsp[0x5D] = 0x02; // DIR
+        dsp[0x6C] = 0x20; // FLG（エコー書き込み無効）
+
+        let mut app = App::default();
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(data),
+        ))));
+        let _ = app.wait_background_operation();
+        let _ = app.update(Message::MIDIOutputDurationChanged(8 * 1000));
+        {
+            let params = app.source_parameter.read().unwrap();
+            assert!(!params.is_empty() && params.values().all(|param| param.auto_volume));
+        }
+
+        // チャンネルボリューム(CC#7)の値を時刻順に取り出す
+        let smf = app.create_smf().unwrap();
+        let volumes: Vec<u8> = smf
+            .tracks
+            .iter()
+            .flat_map(|track| track.events.iter())
+            .filter_map(|e| match &e.event {
+                MidiEvent::Midi(msg)
+                    if msg.data.len() == 3 && (msg.data[0] & 0xF0) == 0xB0 && msg.data[1] == 7 =>
+                {
+                    Some(msg.data[2])
+                }
+                _ => None,
+            })
+            .collect();
+
+        // フェードアウトに追従して単調に下がる
+        assert!(volumes.len() > 10);
+        assert!(volumes.windows(2).all(|w| w[0] >= w[1]));
+        assert!(volumes.first().unwrap() > volumes.last().unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove_short_notes_test() {
+        let midi = |vtime: u64, data: &[u8]| TrackEvent {
+            vtime: vtime,
+            event: MidiEvent::Midi(MidiMessage::from_bytes(data.to_vec())),
+        };
+        // 長さ10tickのノートの間に、ランニングステータスで長さ2tickのノートを挟む
+        let mut track = Track {
+            copyright: None,
+            name: None,
+            events: vec![
+                midi(0, &[0x90, 60, 100]),
+                midi(4, &[64, 100]),
+                midi(2, &[64, 0]),
+                midi(4, &[60, 0]),
+            ],
+        };
+        remove_short_notes(&mut track, 5);
+
+        // 短いノートのみ除かれ、残りのノートのティックは変わらない
+        let events: Vec<_> = track
+            .events
+            .iter()
+            .map(|e| match &e.event {
+                MidiEvent::Midi(msg) => (e.vtime, msg.data.clone()),
+                _ => panic!("unexpected event"),
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![(0, vec![0x90, 60, 100]), (10, vec![0x90, 60, 0])]
+        );
+    }
+
+    #[test]
+    fn separate_retriggered_notes_test() {
+        let midi = |vtime: u64, data: &[u8]| TrackEvent {
+            vtime: vtime,
+            event: MidiEvent::Midi(MidiMessage::from_bytes(data.to_vec())),
+        };
+        // 高速に再発音する音源：同じティックでノートオンがノートオフより先に来る
+        // 最後の再発音は1ティックしか長さがない
+        let mut track = Track {
+            copyright: None,
+            name: None,
+            events: vec![
+                midi(0, &[0x90, 60, 100]),
+                midi(4, &[60, 100]),
+                midi(0, &[60, 0]),
+                midi(4, &[60, 100]),
+                midi(0, &[60, 0]),
+                midi(1, &[60, 0]),
+                // 長さ0のノートは並べ替えない
+                midi(4, &[0x90, 62, 100]),
+                midi(0, &[62, 0]),
+            ],
+        };
+        separate_retriggered_notes(&mut track);
+
+        let mut ticks = 0;
+        let events: Vec<_> = track
+            .events
+            .iter()
+            .map(|e| {
+                ticks += e.vtime;
+                match &e.event {
+                    MidiEvent::Midi(msg) => (ticks, msg.data.clone()),
+                    _ => panic!("unexpected event"),
+                }
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (0, vec![0x90, 60, 100]),
+                (4, vec![0x90, 60, 0]),
+                (5, vec![0x90, 60, 100]),
+                (8, vec![0x90, 60, 0]),
+                (8, vec![0x90, 60, 100]),
+                (9, vec![0x90, 60, 0]),
+                (13, vec![0x90, 62, 100]),
+                (13, vec![0x90, 62, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn percussive_note_router_test() {
+        let mut router = PercussiveNoteRouter::new();
+        router.keyon_drum_note[2] = Some(PercussiveNoteRouter::GM_DRUM_SNARE);
+        // 判定されたキーオンとそのノートオフ（ランニングステータス）はドラムチャンネルに振り替える
+        let mut noteon = vec![MIDIMSG_NOTE_ON | 2, 60, 100];
+        router.apply(&mut noteon);
+        assert_eq!(noteon, [MIDIMSG_NOTE_ON | 9, 38, 100]);
+        let mut other = vec![64, 100];
+        router.apply(&mut other);
+        assert_eq!(other, [MIDIMSG_NOTE_ON | 2, 64, 100]);
+        let mut noteoff = vec![60, 0];
+        router.apply(&mut noteoff);
+        assert_eq!(noteoff, [MIDIMSG_NOTE_OFF | 9, 38, 0]);
+        let mut noteoff = vec![64, 0];
+        router.apply(&mut noteoff);
+        assert_eq!(noteoff, [MIDIMSG_NOTE_ON | 2, 64, 0]);
+    }
+
+    #[test]
+    fn polyphony_limiter_test() {
+        let mut limiter = PolyphonyLimiter::new(2);
+        // 上限までは素通し
+        assert_eq!(
+            limiter.process(vec![MIDIMSG_NOTE_ON | 1, 60, 100]),
+            [[MIDIMSG_NOTE_ON | 1, 60, 100]]
+        );
+        assert_eq!(limiter.process(vec![64, 100]), [vec![64, 100]]);
+        // 上限を超えると最も古いノートを止めてからノートオン
+        assert_eq!(
+            limiter.process(vec![67, 100]),
+            [
+                [MIDIMSG_NOTE_OFF | 1, 60, 0],
+                [MIDIMSG_NOTE_ON | 1, 67, 100]
+            ]
+        );
+        // 止めたノートのノートオフは捨て、以降はステータスバイトを補う
+        assert!(limiter.process(vec![60, 0]).is_empty());
+        assert_eq!(limiter.process(vec![64, 0]), [[MIDIMSG_NOTE_ON | 1, 64, 0]]);
+        // 他のチャンネルには影響しない
+        assert_eq!(
+            limiter.process(vec![MIDIMSG_NOTE_ON | 2, 60, 100]),
+            [[MIDIMSG_NOTE_ON | 2, 60, 100]]
+        );
+        // 止めたノートを再び打鍵したら、そのノートオフは捨てない
+        let mut limiter = PolyphonyLimiter::new(1);
+        assert_eq!(
+            limiter.process(vec![MIDIMSG_NOTE_ON | 3, 60, 100]),
+            [[MIDIMSG_NOTE_ON | 3, 60, 100]]
+        );
+        assert_eq!(
+            limiter.process(vec![62, 100]),
+            [
+                [MIDIMSG_NOTE_OFF | 3, 60, 0],
+                [MIDIMSG_NOTE_ON | 3, 62, 100]
+            ]
+        );
+        assert_eq!(
+            limiter.process(vec![60, 100]),
+            [
+                [MIDIMSG_NOTE_OFF | 3, 62, 0],
+                [MIDIMSG_NOTE_ON | 3, 60, 100]
+            ]
+        );
+        assert_eq!(limiter.stolen_notes[3], [62]);
+        assert_eq!(limiter.process(vec![60, 0]), [[MIDIMSG_NOTE_ON | 3, 60, 0]]);
+        assert!(limiter.process(vec![62, 0]).is_empty());
+        // 再打鍵後に鳴っていないノートのノートオフはそのまま出力
+        assert_eq!(limiter.process(vec![60, 0]), [[MIDIMSG_NOTE_ON | 3, 60, 0]]);
+    }
+
+    #[test]
+    fn analysis_cache_test() -> Result<(), Box<dyn std::error::Error>> {
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let mut app = App::default();
+        let data = std::fs::read(&file)?;
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(data.clone()),
+        ))));
+        let _ = app.wait_background_operation();
+        let spc_file = app.spc_file.clone().unwrap();
+
+        // 解析結果をキャッシュに詰めてJSONを経由して復元
+        let mut cache = app.create_analysis_cache(spc_content_hash(&data), 10);
+        let analysis = App::analyze_sources(
+            &app.source_analysis_settings(),
+            10,
+            &spc_file.header.spc_register,
+            &spc_file.ram,
+            &spc_file.dsp_register,
+            &AtomicBool::new(false),
+            &AtomicUsize::new(0),
+        )?
+        .unwrap();
+        cache.sources = analysis.cached_sources.clone();
+        cache.source_parameter = analysis.params.clone();
+        app.apply_source_analysis(analysis);
+        let expected: Vec<_> = app
+            .source_infos
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(srn, info)| (*srn, info.signal.clone(), info.keyon_pitches.clone()))
+            .collect();
+        let cache: AnalysisCache = serde_json::from_str(&serde_json::to_string(&cache)?)?;
+        assert!(cache.is_valid_for(&app.create_analysis_cache(spc_content_hash(&data), 10)));
+        assert!(!cache.is_valid_for(&app.create_analysis_cache(spc_content_hash(&data[1..]), 10)));
+        assert!(!cache.is_valid_for(&app.create_analysis_cache(spc_content_hash(&data), 20)));
+        // 解析結果が変わる設定を変えたら使わない
+        app.midi_output_configure.write().unwrap().excluded_voices = 0x01;
+        assert!(!cache.is_valid_for(&app.create_analysis_cache(spc_content_hash(&data), 10)));
+        app.midi_output_configure.write().unwrap().excluded_voices = 0;
+        app.channel_mute_flags.store(0x01, Ordering::Relaxed);
+        assert!(!cache.is_valid_for(&app.create_analysis_cache(spc_content_hash(&data), 10)));
+        app.channel_mute_flags.store(0, Ordering::Relaxed);
+        let template = app.source_parameter_template.read().unwrap().clone();
+        app.source_parameter_template
+            .write()
+            .unwrap()
+            .noteon_velocity = template.noteon_velocity.wrapping_add(1);
+        assert!(!cache.is_valid_for(&app.create_analysis_cache(spc_content_hash(&data), 10)));
+        *app.source_parameter_template.write().unwrap() = template;
+
+        app.restore_analysis(cache, &spc_file.ram);
+        let restored: Vec<_> = app
+            .source_infos
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(srn, info)| (*srn, info.signal.clone(), info.keyon_pitches.clone()))
+            .collect();
+        assert_eq!(restored, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_spc_test() -> Result<(), Box<dyn std::error::Error>> {
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let mut app = App::default();
+        let data = std::fs::read(&file)?;
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(data.clone()),
+        ))));
+        let _ = app.wait_background_operation();
+        let spc_file = app.spc_file.clone().unwrap();
+        let expected: Vec<u8> = app.source_infos.read().unwrap().keys().cloned().collect();
+
+        // 欠けたRAMイメージはエラーになり、解析済みの音源情報は変わらない
+        assert!(App::analyze_sources(
+            &app.source_analysis_settings(),
+            10,
+            &spc_file.header.spc_register,
+            &spc_file.ram[..(SPC_RAM_SIZE / 2)],
+            &spc_file.dsp_register,
+            &AtomicBool::new(false),
+            &AtomicUsize::new(0),
+        )
+        .is_err());
+        let srns: Vec<u8> = app.source_infos.read().unwrap().keys().cloned().collect();
+        assert_eq!(srns, expected);
+
+        // RAM外にはみ出すディレクトリエントリ・波形はデコードしない
+        let mut ram = vec![0u8; SPC_RAM_SIZE];
+        assert!(decode_source(&ram, SPC_RAM_SIZE - 2, [true; 8], vec![]).is_none());
+        ram[0x0200..0x0204].copy_from_slice(&[0xFC, 0xFF, 0xFC, 0xFF]);
+        assert!(decode_source(&ram, 0x0200, [true; 8], vec![]).is_none());
+        // 終端ブロックの前にRAMの終わりに達する波形もデコードしない
+        ram[0x0200..0x0204].copy_from_slice(&[0xE0, 0xFF, 0xE0, 0xFF]);
+        assert!(decode_source(&ram, 0x0200, [true; 8], vec![]).is_none());
+        ram[0xFFE0 + 18] = 0x01;
+        assert!(decode_source(&ram, 0x0200, [true; 8], vec![]).is_some());
+
+        // 壊れたSPCファイルは開かず、SMFも作成しない
+        let mut app = App::default();
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(data[..(SPC_FILE_MIN_SIZE - 1)].to_vec()),
+        ))));
+        let _ = app.wait_background_operation();
+        assert!(app.spc_file.is_none());
+        assert!(app.create_smf().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parameter_import_while_reviewing_test() {
+        let mut app = App::default();
+        let open_json = |app: &mut App, beats_per_minute: f32| {
+            let mut json = app.create_json();
+            json["midi_output_configure"]["beats_per_minute"] = json!(beats_per_minute);
+            let _ = app.update(Message::FileOpened(Ok((
+                "parameters.json".into(),
+                LoadedFile::JSONFile(json.to_string()),
+            ))));
+        };
+        let pending_beats_per_minute = |app: &App| {
+            app.pending_import
+                .as_ref()
+                .map(|json| json.midi_output_configure.beats_per_minute)
+        };
+
+        // 差分確認中は新たな設定ファイルを読み込まない
+        open_json(&mut app, 150.0);
+        assert_eq!(pending_beats_per_minute(&app), Some(150.0));
+        open_json(&mut app, 90.0);
+        assert_eq!(pending_beats_per_minute(&app), Some(150.0));
+
+        // 確認を取り消せば読み込める
+        let diff_window_id = app
+            .windows
+            .iter_mut()
+            .find_map(|(id, window)| {
+                window
+                    .as_any_mut()
+                    .downcast_mut::<ParameterDiffWindow>()
+                    .map(|_| *id)
+            })
+            .unwrap();
+        let _ = app.update(Message::ParameterDiffCanceled(diff_window_id));
+        assert_eq!(pending_beats_per_minute(&app), None);
+        open_json(&mut app, 90.0);
+        assert_eq!(pending_beats_per_minute(&app), Some(90.0));
+    }
+
+    #[test]
+    fn malformed_spc_file_open_test() -> Result<(), Box<dyn std::error::Error>> {
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let data = std::fs::read(&file)?;
+        let dsp_offset = SPC_FILE_RAM_OFFSET + SPC_RAM_SIZE;
+        let dir_offset =
+            SPC_FILE_RAM_OFFSET + ((data[dsp_offset + DSP_ADDRESS_DIR as usize] as usize) << 8);
+
+        // 開いた音源はすべてRAM内に収まっている
+        let open = |data: Vec<u8>| {
+            let mut app = App::default();
+            let _ = app.update(Message::FileOpened(Ok((
+                file.into(),
+                LoadedFile::SPCFile(data),
+            ))));
+            let _ = app.wait_background_operation();
+            assert!(app.spc_file.is_some());
+            assert!(app.spc_file_path.is_some());
+            for info in app.source_infos.read().unwrap().values() {
+                assert!(info.dir_address + 4 <= SPC_RAM_SIZE);
+                assert!(info.end_address <= SPC_RAM_SIZE);
+            }
+        };
+
+        // ディレクトリがRAMの末尾（0xFF00）を指す
+        let mut broken = data.clone();
+        broken[dsp_offset + DSP_ADDRESS_DIR as usize] = 0xFF;
+        open(broken);
+
+        // ディレクトリエントリがRAMの末尾からはみ出す波形を指す
+        let mut broken = data.clone();
+        for entry in broken[dir_offset..(dir_offset + 0x100)].chunks_mut(4) {
+            entry.copy_from_slice(&[0xF7, 0xFF, 0xF7, 0xFF]);
         }
-        if param.envelope_as_expression {
-            flag |= 0x40;
+        open(broken);
+
+        // DSPレジスタが壊れている
+        let mut broken = data.clone();
+        broken[dsp_offset..(dsp_offset + 128)].fill(0xFF);
+        open(broken);
+
+        // 開けないファイルを読んでも開いていたファイルの状態はそのまま
+        let mut app = App::default();
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(data.clone()),
+        ))));
+        let _ = app.wait_background_operation();
+        let expected: Vec<u8> = app.source_infos.read().unwrap().keys().cloned().collect();
+        let _ = app.update(Message::FileOpened(Ok((
+            "broken.spc".into(),
+            LoadedFile::SPCFile(data[..(SPC_FILE_MIN_SIZE - 1)].to_vec()),
+        ))));
+        let _ = app.wait_background_operation();
+        assert!(app.spc_file.is_some());
+        assert_eq!(app.spc_file_path, Some(PathBuf::from(file)));
+        let srns: Vec<u8> = app.source_infos.read().unwrap().keys().cloned().collect();
+        assert_eq!(srns, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reestimate_source_parameters_test() -> Result<(), Box<dyn std::error::Error>> {
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let mut app = App::default();
+        let data = std::fs::read(&file)?;
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(data),
+        ))));
+        let _ = app.wait_background_operation();
+
+        let infos = app.source_infos.read().unwrap().clone();
+        let expected = app.source_parameter.read().unwrap().clone();
+        let mut params = expected.clone();
+        for param in params.values_mut() {
+            param.center_note = 0;
+            param.program = Program::Violin;
+            param.label = "label".to_string();
         }
-        if param.update_parameter_after_noteon {
-            flag |= 0x20;
+
+        // センターノートのみ推定し直す
+        reestimate_source_parameters(&infos, &mut params, DEFAULT_A4_PITCH_HZ, false);
+        for (srn, param) in params.iter() {
+            assert_eq!(param.center_note, expected[srn].center_note);
+            assert_eq!(param.program, Program::Violin);
+            assert_eq!(param.label, "label");
         }
-        spc.dsp.write_register(ram, DSP_ADDRESS_SRN_FLAG, flag);
-        spc.dsp
-            .write_register(ram, DSP_ADDRESS_SRN_PROGRAM, param.program.clone() as u8);
-        spc.dsp
-            .write_register(ram, DSP_ADDRESS_SRN_NOTEON_VELOCITY, param.noteon_velocity);
-        spc.dsp.write_register(
-            ram,
-            DSP_ADDRESS_SRN_CENTER_NOTE_HIGH,
-            ((param.center_note >> 8) & 0xFF) as u8,
+
+        // プログラムも推定し直す
+        reestimate_source_parameters(&infos, &mut params, DEFAULT_A4_PITCH_HZ, true);
+        for (srn, param) in params.iter() {
+            assert_eq!(param.program, expected[srn].program);
+            assert_eq!(param.pitch_bend_width, expected[srn].pitch_bend_width);
+            assert!(param.pitch_bend_width >= required_pitch_bend_width(&infos[srn]));
+            assert_eq!(param.label, "label");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parameter_log_test() -> Result<(), Box<dyn std::error::Error>> {
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let mut app = App::default();
+        let data = std::fs::read(&file)?;
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(data),
+        ))));
+        let _ = app.wait_background_operation();
+        let srn_no = *app.source_parameter.read().unwrap().keys().next().unwrap();
+        let velocity = app.source_parameter.read().unwrap()[&srn_no].noteon_velocity;
+        let new_velocity = if velocity == 1 { 2 } else { 1 };
+
+        // 編集した項目が変更前後の値とともに記録される
+        let _ = app.update(Message::NoteOnVelocityChanged(srn_no, new_velocity));
+        {
+            let log = app.parameter_log.read().unwrap();
+            assert_eq!(log.len(), 1);
+            assert_eq!(log[0].srn_no, srn_no);
+            assert_eq!(log[0].field, "noteon_velocity");
+            assert_eq!(log[0].old, json!(velocity));
+            assert_eq!(log[0].new, json!(new_velocity));
+            assert!(!log[0].applied);
+        }
+
+        // DSPに適用すると適用済みになる
+        let _ = app.update(Message::ReceivedSourceParameterUpdate);
+        {
+            let log = app.parameter_log.read().unwrap();
+            assert!(log.iter().all(|entry| entry.applied));
+        }
+
+        // 値が変わらない編集は記録しない
+        let _ = app.update(Message::NoteOnVelocityChanged(srn_no, new_velocity));
+        assert_eq!(app.parameter_log.read().unwrap().len(), 1);
+
+        // 上限を超えたら古いものから捨てる
+        for i in 0..MAX_PARAMETER_LOG_ENTRIES {
+            let _ = app.update(Message::NoteOnVelocityChanged(srn_no, 10 + (i % 2) as u8));
+        }
+        {
+            let log = app.parameter_log.read().unwrap();
+            assert_eq!(log.len(), MAX_PARAMETER_LOG_ENTRIES);
+            assert_eq!(log[0].old, json!(new_velocity));
+        }
+
+        let _ = app.update(Message::ParameterLogCleared);
+        assert!(app.parameter_log.read().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parameter_diff_test() -> Result<(), Box<dyn std::error::Error>> {
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let mut app = App::default();
+        let data = Box::new(std::fs::read(&file)?);
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(*data),
+        ))));
+        let _ = app.wait_background_operation();
+
+        // 出力設定と音源パラメータを1項目ずつ変えた設定を作る
+        let mut loaded: ExportInformation = serde_json::from_value(app.create_json())?;
+        let srn_no = *loaded.source_parameter.keys().next().unwrap();
+        loaded.midi_output_configure.beats_per_minute += 1.0;
+        if let Some(param) = loaded.source_parameter.get_mut(&srn_no) {
+            param.noteon_velocity = 1;
+        }
+
+        let mut config = app.midi_output_configure.read().unwrap().clone();
+        let mut params = app.source_parameter.read().unwrap().clone();
+        let mut differences = diff_export_information(&config, &params, &loaded);
+        assert_eq!(differences.len(), 2);
+        assert!(differences[0].srn_no.is_none() && differences[0].field == "beats_per_minute");
+        assert!(differences[1].srn_no == Some(srn_no) && differences[1].field == "noteon_velocity");
+
+        // 出力設定の変更は棄却して音源パラメータの変更のみ適用
+        differences[0].accepted = false;
+        let bpm = config.beats_per_minute;
+        apply_parameter_differences(&mut config, &mut params, &loaded, &differences);
+        assert_eq!(config.beats_per_minute, bpm);
+        assert_eq!(params.get(&srn_no).unwrap().noteon_velocity, 1);
+        assert!(diff_export_information(&config, &params, &loaded).len() == 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn click_track_test() {
+        let mut config = MIDIOutputConfigure::new();
+        config.beats_per_minute = 120.0;
+        config.ticks_per_quarter = 480;
+        config.output_duration_msec = 2000;
+        let mut params = BTreeMap::new();
+
+        // 誰も使っていなければドラムチャンネル
+        let track = create_click_track(&config, &params).unwrap();
+        let mut ticks = 0;
+        let noteons: Vec<_> = track
+            .events
+            .iter()
+            .filter_map(|e| {
+                ticks += e.vtime;
+                match &e.event {
+                    MidiEvent::Midi(msg) if msg.data[0] == MIDIMSG_NOTE_ON | 9 => Some(ticks),
+                    _ => None,
+                }
+            })
+            .collect();
+        assert_eq!(noteons, vec![0, 480, 960, 1440]);
+
+        // ドラムチャンネルが使われていれば空いている最後のチャンネル
+        let mut param = create_default_source_parameter(
+            &test_source_info(vec![0.0; 16], vec![1.0; 16], 0),
+            DEFAULT_A4_PITCH_HZ,
+            &SourceParameterTemplate::default(),
         );
-        spc.dsp.write_register(
-            ram,
-            DSP_ADDRESS_SRN_CENTER_NOTE_LOW,
-            ((param.center_note >> 0) & 0xFF) as u8,
+        param.channel_routing = [9, 15, 14, 13, 12, 11, 10, 8];
+        params.insert(0, param);
+        assert_eq!(find_click_channel(&params), Some(7));
+        let track = create_click_track(&config, &params).unwrap();
+        assert!(track.events.iter().any(|e| match &e.event {
+            MidiEvent::Midi(msg) => msg.data[0] == MIDIMSG_PROGRAM_CHANGE | 7,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn source_parameter_template_test() {
+        let info = test_source_info(vec![0.0; 16], vec![1.0; 16], 0);
+        // 工場出荷時のテンプレートではピッチベンド幅を推定する
+        let param = create_default_source_parameter(
+            &info,
+            DEFAULT_A4_PITCH_HZ,
+            &SourceParameterTemplate::default(),
         );
-        spc.dsp.write_register(
-            ram,
-            DSP_ADDRESS_SRN_VOLUME,
-            if param.auto_volume { 0x80 } else { 0x00 } | param.fixed_volume,
+        assert_eq!(param.pitch_bend_width, 12);
+        assert!(!param.envelope_as_expression);
+        // テンプレートの値が推定以外の項目に反映される
+        let template = SourceParameterTemplate {
+            pitch_bend_width: 2,
+            envelope_as_expression: true,
+            ..SourceParameterTemplate::default()
+        };
+        let templated = create_default_source_parameter(&info, DEFAULT_A4_PITCH_HZ, &template);
+        assert_eq!(templated.pitch_bend_width, 2);
+        assert!(templated.envelope_as_expression);
+        assert_eq!(templated.program, param.program);
+        assert_eq!(templated.center_note, param.center_note);
+    }
+
+    #[test]
+    fn source_audibility_test() {
+        let mut param = create_default_source_parameter(
+            &test_source_info(vec![0.0; 16], vec![1.0; 16], 0),
+            DEFAULT_A4_PITCH_HZ,
+            &SourceParameterTemplate::default(),
         );
-        spc.dsp.write_register(
-            ram,
-            DSP_ADDRESS_SRN_PAN,
-            if param.auto_pan { 0x80 } else { 0x00 } | param.fixed_pan,
+        // SRN 3をチャンネル1と2で鳴らしている
+        let mut status = PlaybackStatus::new();
+        status.srn_no[1] = 3;
+        status.noteon[1] = true;
+        status.srn_no[2] = 3;
+        status.envelope[2] = 10;
+        assert_eq!(
+            status.source_audibility(3, &param, 0x00),
+            SourceAudibility::Audible
         );
-        spc.dsp.write_register(
-            ram,
-            DSP_ADDRESS_SRN_PITCHBEND_SENSITIVITY,
-            if param.enable_pitch_bend { 0x80 } else { 0x00 } | param.pitch_bend_width,
+        assert_eq!(
+            status.source_audibility(3, &param, 0x02),
+            SourceAudibility::PartiallyAudible
         );
-        spc.dsp.write_register(
-            ram,
-            DSP_ADDRESS_SRN_REVERB_SEND,
-            if param.echo_as_reverb_send {
-                0x80
-            } else {
-                0x00
-            } | param.fixed_reverb_send,
+        // チャンネル0のソロ
+        assert_eq!(
+            status.source_audibility(3, &param, !0x01),
+            SourceAudibility::Silenced
         );
-        spc.dsp
-            .write_register(ram, DSP_ADDRESS_SRN_CHORUS_SEND, param.chorus_send);
-        for ch in 0..8 {
-            let value = if param.channel_mute[ch] { 0x80 } else { 0x00 }
-                | (ch << 4) as u8
-                | param.channel_routing[ch];
-            spc.dsp
-                .write_register(ram, DSP_ADDRESS_SRN_CHANNEL_ROUTING, value);
+        assert_eq!(
+            status.source_audibility(4, &param, 0x00),
+            SourceAudibility::Idle
+        );
+        param.channel_mute[1] = true;
+        assert_eq!(
+            status.source_audibility(3, &param, 0x04),
+            SourceAudibility::Silenced
+        );
+        param.mute = true;
+        assert_eq!(
+            status.source_audibility(3, &param, 0x00),
+            SourceAudibility::Muted
+        );
+    }
+
+    #[test]
+    fn stereo_pair_detection_test() {
+        assert_eq!(hard_pan_side(127, 0), Some(true));
+        assert_eq!(hard_pan_side(0, -100), Some(false));
+        assert_eq!(hard_pan_side(100, 20), None);
+        assert_eq!(hard_pan_side(0, 0), None);
+
+        let create_info = |phase: f32, num_keyons: usize| {
+            let signal: Vec<f32> = (0..1024)
+                .map(|n| f32::sin(2.0 * std::f32::consts::PI * n as f32 / 64.0 + phase))
+                .collect();
+            let power_spectrum = compute_power_spectrum(&signal);
+            SourceInformation {
+                keyon_pitches: vec![0x1000; num_keyons],
+                ..test_source_info(signal, power_spectrum, 1024)
+            }
+        };
+        let mut infos = BTreeMap::new();
+        infos.insert(0, create_info(0.0, 8));
+        infos.insert(1, create_info(0.1, 8));
+        // 波形が似ていない（位相が90度ずれている）
+        infos.insert(2, create_info(std::f32::consts::FRAC_PI_2, 8));
+        infos.insert(3, create_info(0.0, 8));
+        let mut stereo_keyons = BTreeMap::new();
+        stereo_keyons.insert((0, 1), 8);
+        stereo_keyons.insert((2, 3), 8);
+        // 同時キーオンの少ない組は採用しない
+        stereo_keyons.insert((0, 3), 1);
+        detect_stereo_pairs(&mut infos, &stereo_keyons);
+        assert_eq!(infos[&0].stereo_pair_partner, Some(1));
+        assert_eq!(infos[&1].stereo_pair_partner, Some(0));
+        assert_eq!(infos[&2].stereo_pair_partner, None);
+        assert_eq!(infos[&3].stereo_pair_partner, None);
+    }
+
+    #[test]
+    fn pan_law_test() {
+        for pan_law in PanLaw::ALL {
+            // 両端と中央は変わらない
+            assert_eq!(pan_law.map(0), 0);
+            assert_eq!(pan_law.map(64), 64);
+            assert_eq!(pan_law.map(127), 127);
         }
+        assert_eq!(PanLaw::Linear.map(32), 32);
+        // 同じ左右音量比を表すパン値は-3dBでは中央から離れ、-6dBでは中央に寄る
+        assert!(PanLaw::ConstantPower3dB.map(96) > 96);
+        assert!(PanLaw::ConstantPower3dB.map(32) < 32);
+        assert!(PanLaw::Attenuated6dB.map(96) < 96);
+        assert!(PanLaw::Attenuated6dB.map(32) > 32);
     }
-    // 音源に依存しないパラメータ
-    spc.dsp.write_register(
-        ram,
-        DSP_ADDRESS_PLAYBACK_PARAMETER_UPDATE_PERIOD,
-        config.playback_parameter_update_period,
-    );
-    let mut flag = 0;
-    match config.volume_curve {
-        VolumeCurve::SquareRoot => flag |= 0x00,
-        VolumeCurve::Log => flag |= 0x40,
-        VolumeCurve::Linear => flag |= 0x80,
+
+    #[test]
+    fn limit_pcm_test() {
+        let pcm = [0, 1000, -1000, 40000, -40000, 30000];
+        // 飽和させて折り返さない
+        assert_eq!(
+            limit_pcm(&pcm, false),
+            vec![0, 1000, -1000, 32767, -32768, 30000]
+        );
+        // ソフトリミッタは閾値以下を変えず、閾値を超えた分を圧縮する
+        let limited = limit_pcm(&pcm, true);
+        assert_eq!(&limited[..3], &[0, 1000, -1000]);
+        assert!(limited[3] < 32767 && limited[4] > -32767);
+        assert!(limited[5] < 30000 && limited[5] > 26214);
+        assert_eq!(count_clipped_frames(&pcm, 2), 2);
     }
-    spc.dsp
-        .write_register(ram, DSP_ADDRESS_CONFIGURE_FLAG, flag);
-}
 
-#[derive(Debug, Clone)]
-pub enum Error {
-    DialogClosed,
-    IoError(io::ErrorKind),
-}
+    #[test]
+    fn split_smf_by_loop_test() {
+        fn midi(data: Vec<u8>) -> MidiEvent {
+            MidiEvent::Midi(MidiMessage::from_bytes(data))
+        }
+        let mut events = vec![
+            (0, MidiEvent::Meta(MetaEvent::tempo_setting(500_000))),
+            (0, midi(vec![0xC0, 5])),
+            (0, midi(vec![0xB0, 7, 100])),
+            (0, midi(vec![0xE0, 0, 0x50])),
+        ];
+        // イントロの後に周期100でノートのパターンが繰り返す
+        let mut notes = vec![(0, 10), (20, 11), (40, 12)];
+        for n in 0..3 {
+            let base = 50 + 100 * n;
+            notes.extend([(base, 1), (base + 25, 2), (base + 50, 1), (base + 75, 3)]);
+        }
+        for (ticks, note) in notes {
+            events.push((ticks, midi(vec![0x90, note, 100])));
+            events.push((ticks + 10, midi(vec![0x90, note, 0])));
+        }
+        // 1回目のループの途中でプログラムを切り替える
+        events.push((130, midi(vec![0xC0, 10])));
+        events.sort_by_key(|(ticks, _)| *ticks);
+        let mut previous_ticks = 0;
+        let track = Track {
+            copyright: None,
+            name: None,
+            events: events
+                .into_iter()
+                .map(|(ticks, event)| {
+                    let vtime = ticks - previous_ticks;
+                    previous_ticks = ticks;
+                    TrackEvent {
+                        vtime: vtime,
+                        event: event,
+                    }
+                })
+                .collect(),
+        };
+        let smf = SMF {
+            format: SMFFormat::MultiTrack,
+            tracks: vec![track],
+            division: 48,
+        };
+
+        let sections = split_smf_by_loop(smf);
+        let suffixes: Vec<_> = sections.iter().map(|(suffix, _)| suffix.as_str()).collect();
+        assert_eq!(suffixes, ["intro", "loop1", "loop2", "loop3"]);
+
+        // 区間の先頭（最初のノートオンより前）のMIDIメッセージを取り出す
+        fn leading_messages(smf: &SMF) -> Vec<Vec<u8>> {
+            smf.tracks[0]
+                .events
+                .iter()
+                .filter_map(|e| match &e.event {
+                    MidiEvent::Midi(msg) => Some(msg.data.clone()),
+                    _ => None,
+                })
+                .take_while(|data| (data[0] & 0xF0) != MIDIMSG_NOTE_ON)
+                .collect()
+        }
+        // 各区間の先頭でテンポと開始時点のチャンネルの状態が送られる
+        for (_, section) in sections.iter() {
+            assert!(matches!(
+                &section.tracks[0].events[0].event,
+                MidiEvent::Meta(meta) if matches!(meta.command, MetaCommand::TempoSetting)
+            ));
+        }
+        assert_eq!(
+            leading_messages(&sections[1].1),
+            [vec![0xB0, 7, 100], vec![0xC0, 5], vec![0xE0, 0, 0x50]]
+        );
+        assert_eq!(
+            leading_messages(&sections[2].1),
+            [vec![0xB0, 7, 100], vec![0xC0, 10], vec![0xE0, 0, 0x50]]
+        );
+        // 状態の復元は区間の先頭に置かれる
+        let mut ticks = 0;
+        for e in sections[2].1.tracks[0].events.iter() {
+            ticks += e.vtime;
+            if let MidiEvent::Midi(msg) = &e.event {
+                if (msg.data[0] & 0xF0) != MIDIMSG_NOTE_ON {
+                    assert_eq!(ticks, 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn smf_to_timed_messages_test() {
+        let smf = SMF {
+            format: SMFFormat::MultiTrack,
+            tracks: vec![
+                Track {
+                    copyright: None,
+                    name: None,
+                    events: vec![
+                        TrackEvent {
+                            vtime: 0,
+                            event: MidiEvent::Meta(MetaEvent::tempo_setting(500_000)),
+                        },
+                        // 2拍目からテンポを倍に
+                        TrackEvent {
+                            vtime: 960,
+                            event: MidiEvent::Meta(MetaEvent::tempo_setting(250_000)),
+                        },
+                    ],
+                },
+                Track {
+                    copyright: None,
+                    name: None,
+                    events: vec![
+                        TrackEvent {
+                            vtime: 480,
+                            event: MidiEvent::Midi(MidiMessage::from_bytes(vec![0x90, 60, 100])),
+                        },
+                        // ランニングステータス
+                        TrackEvent {
+                            vtime: 960,
+                            event: MidiEvent::Midi(MidiMessage::from_bytes(vec![60, 0])),
+                        },
+                    ],
+                },
+            ],
+            division: 480,
+        };
+        assert_eq!(
+            smf_to_timed_messages(smf),
+            vec![
+                (500_000, vec![0x90, 60, 100]),
+                (1_250_000, vec![0x90, 60, 0])
+            ]
+        );
+    }
+
+    #[test]
+    fn instrument_mapping_test() {
+        let mapping: InstrumentMapping = serde_json::from_str(
+            r#"{"entries": [
+                {"srn": 3, "program": "Violin", "label": "lead"},
+                {"is_drum": true, "program": "AcousticSnare"},
+                {"is_drum": false, "center_note": 40.0, "program": "FingerElectricBass"},
+                {"is_drum": false, "center_note": 72.0, "program": "Flute"}
+            ]}"#,
+        )
+        .unwrap();
+        // 音源番号の一致を優先
+        assert_eq!(
+            mapping.find(3, true, 40.0).unwrap().program,
+            Program::Violin
+        );
+        // 打楽器の区別とセンターノートの近さで照合
+        assert_eq!(
+            mapping.find(0, true, 60.0).unwrap().program,
+            Program::AcousticSnare
+        );
+        assert_eq!(
+            mapping.find(0, false, 45.0).unwrap().program,
+            Program::FingerElectricBass
+        );
+        assert_eq!(
+            mapping.find(0, false, 70.0).unwrap().program,
+            Program::Flute
+        );
+        // 近い項目が無ければ当てはめない
+        assert!(mapping.find(0, false, 100.0).is_none());
+    }
+
+    #[test]
+    fn instrument_mapping_drum_switch_test() -> Result<(), Box<dyn std::error::Error>> {
+        let file = "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc";
+        let mut app = App::default();
+        let data = std::fs::read(&file)?;
+        let _ = app.update(Message::FileOpened(Ok((
+            file.into(),
+            LoadedFile::SPCFile(data),
+        ))));
+        let _ = app.wait_background_operation();
+        let srn_no = *app.source_parameter.read().unwrap().keys().next().unwrap();
+
+        // 音階からドラムに当てはめるとチャンネルとピッチベンドも切り替える
+        let _ = app.update(Message::ProgramSelected(
+            srn_no,
+            Program::AcousticGrand,
+            None,
+        ));
+        app.instrument_mapping = Some((
+            PathBuf::new(),
+            serde_json::from_str(&format!(
+                r#"{{"entries": [{{"srn": {}, "program": "AcousticSnare"}}]}}"#,
+                srn_no
+            ))?,
+        ));
+        assert_eq!(app.apply_instrument_mapping(), 1);
+        let param = app.source_parameter.read().unwrap()[&srn_no].clone();
+        assert_eq!(param.program, Program::AcousticSnare);
+        assert_eq!(param.channel_routing, [9; 8]);
+        assert!(!param.enable_pitch_bend);
+
+        // ドラムから音階に戻すとSPCと同じチャンネルに戻す
+        let _ = app.update(Message::ProgramSelected(srn_no, Program::Violin, None));
+        let param = app.source_parameter.read().unwrap()[&srn_no].clone();
+        assert_eq!(param.channel_routing, [0, 1, 2, 3, 4, 5, 6, 7]);
+        Ok(())
+    }
+
+    #[test]
+    fn stretched_quarter_usec_test() {
+        assert_eq!(stretched_quarter_usec(120.0, 60_000, None), 500_000);
+        // 2倍の長さに伸ばすとテンポは半分
+        assert_eq!(
+            stretched_quarter_usec(120.0, 60_000, Some(120_000)),
+            1_000_000
+        );
+        assert_eq!(stretched_quarter_usec(120.0, 60_000, Some(30_000)), 250_000);
+        // 出力長が0なら伸縮しない
+        assert_eq!(stretched_quarter_usec(120.0, 0, Some(30_000)), 500_000);
+    }
+
+    #[test]
+    fn fold_note_into_range_test() {
+        // 範囲内はそのまま
+        assert_eq!(fold_note_into_range(60, 0, 127), 60);
+        assert_eq!(fold_note_into_range(60, 48, 72), 60);
+        // 範囲外はオクターブ単位で折り返す
+        assert_eq!(fold_note_into_range(30, 48, 72), 54);
+        assert_eq!(fold_note_into_range(100, 48, 72), 64);
+        assert_eq!(fold_note_into_range(0, 116, 127), 120);
+        assert_eq!(fold_note_into_range(127, 0, 11), 7);
+        // 1オクターブより狭い範囲は端に丸める
+        assert_eq!(fold_note_into_range(30, 60, 64), 60);
+        assert_eq!(fold_note_into_range(100, 60, 64), 64);
+
+        // ノートオフは対応するノートオンと同じノートに書き換える
+        let mut folder = NoteRangeFolder::new();
+        folder.voices.midi_ch[1] = Some(2);
+        folder.voices.noteon = 0x02;
+        folder.voices.keyon = 0x02;
+        folder.note_range[1] = Some((48, 72));
+        let mut noteon = vec![MIDIMSG_NOTE_ON | 2, 84, 100];
+        folder.apply(&mut noteon);
+        assert_eq!(noteon[1], 72);
+        folder.note_range[1] = None;
+        let mut noteoff = vec![84, 0];
+        folder.apply(&mut noteoff);
+        assert_eq!(noteoff[0], 72);
+        let mut noteoff = vec![MIDIMSG_NOTE_OFF | 2, 84, 0];
+        folder.apply(&mut noteoff);
+        assert_eq!(noteoff[1], 84);
+    }
+
+    #[test]
+    fn display_scale_test() {
+        // 等倍の表示スケールでは線幅・フォントサイズはそのまま
+        let scale = DisplayScale::default();
+        assert_eq!(scale.stroke_width(1.0), 1.0);
+        assert_eq!(scale.font_size(16.0), 16.0);
+        assert_eq!(scale.num_points(100.0), 400);
+        // 2倍の表示スケールでは物理ピクセル数分の点を描画し、線幅は物理ピクセル単位に揃える
+        let scale = DisplayScale(2.0);
+        assert_eq!(scale.num_points(100.0), 800);
+        assert_eq!(scale.stroke_width(1.0), 1.0);
+        assert_eq!(scale.stroke_width(0.5), 0.5);
+        assert_eq!(scale.stroke_width(1.2), 1.0);
+        assert_eq!(scale.font_size(16.0), 16.0);
+        // 端数のある表示スケールでは物理ピクセルの整数に丸める
+        let scale = DisplayScale(1.5);
+        assert_eq!(scale.stroke_width(1.0), 2.0 / 1.5);
+        assert_eq!(scale.font_size(15.0), 23.0 / 1.5);
+        assert_eq!(scale.num_points(100.0), 600);
+    }
+
+    #[test]
+    fn indicator_smoothing_test() {
+        // 平滑化しなければ値をそのまま表示する
+        let mut indicator = Indicator {
+            value: 0.0,
+            smoothed_value: 0.0,
+            min: 0.0,
+            max: 127.0,
+            formatter: |value| format!("{}", value),
+            scale: DisplayScale::default(),
+        };
+        indicator.set_value(100.0, 0.0);
+        assert_eq!(indicator.smoothed_value, 100.0);
+        // 表示値は前回と目標の間に収まり、下がるときは上がるときよりゆっくり追従する
+        indicator.set_value(0.0, 0.8);
+        assert_eq!(indicator.value, 0.0);
+        let decayed = indicator.smoothed_value;
+        assert!(decayed > 0.0 && decayed < 100.0);
+        let mut rising = indicator;
+        rising.smoothed_value = 0.0;
+        rising.set_value(100.0, 0.8);
+        assert!(100.0 - rising.smoothed_value < decayed);
+        for _ in 0..1000 {
+            indicator.set_value(0.0, MAX_INDICATOR_SMOOTHING);
+        }
+        assert!(indicator.smoothed_value < 1e-3);
+    }
+
+    #[test]
+    fn channel_state_recorder_test() {
+        let mut recorder = ChannelStateRecorder::new();
+        // プログラムチェンジ・RPN（ピッチベンド幅）・ランニングステータスのボリューム
+        recorder.record(&[MIDIMSG_PROGRAM_CHANGE | 1, 10]);
+        recorder.record(&[MIDIMSG_MODE | 1, 101, 0]);
+        recorder.record(&[100, 0]);
+        recorder.record(&[6, 12]);
+        recorder.record(&[7, 80]);
+        recorder.record(&[MIDIMSG_NOTE_ON | 1, 60, 100]);
+        recorder.record(&[MIDIMSG_PITCH_BEND | 1, 0, 0x50]);
+        // 同じコントロールは最新の値だけが残る
+        recorder.record(&[MIDIMSG_MODE | 1, 7, 90]);
+        recorder.record(&[MIDIMSG_PROGRAM_CHANGE | 1, 20]);
+        assert_eq!(recorder.status_byte, MIDIMSG_PROGRAM_CHANGE | 1);
+        assert_eq!(
+            recorder.restore_messages(),
+            vec![
+                vec![MIDIMSG_MODE | 1, 101, 0],
+                vec![MIDIMSG_MODE | 1, 100, 0],
+                vec![MIDIMSG_MODE | 1, 6, 12],
+                vec![MIDIMSG_MODE | 1, 7, 90],
+                vec![MIDIMSG_PROGRAM_CHANGE | 1, 20],
+                vec![MIDIMSG_PITCH_BEND | 1, 0, 0x50],
+            ]
+        );
+    }
 
-async fn open_file() -> Result<(PathBuf, LoadedFile), Error> {
-    let picked_file = AsyncFileDialog::new()
-        .set_title("Open a file...")
-        .add_filter("SPC or JSON", &["spc", "SPC", "json"])
-        .pick_file()
-        .await
-        .ok_or(Error::DialogClosed)?;
+    #[test]
+    fn audio_health_test() {
+        assert_eq!(audio_health(0), AudioHealth::Good);
+        assert_eq!(audio_health(1), AudioHealth::Degraded);
+        assert_eq!(
+            audio_health(AUDIO_HEALTH_POOR_UNDERRUNS - 1),
+            AudioHealth::Degraded
+        );
+        assert_eq!(audio_health(AUDIO_HEALTH_POOR_UNDERRUNS), AudioHealth::Poor);
+    }
 
-    load_file(picked_file).await
-}
+    #[test]
+    fn voice_channel_map_test() {
+        // 同じMIDIチャンネルに出力するボイスのメッセージはボイス番号順に対応付ける
+        let mut mapper = PanLawMapper::new();
+        mapper.voices.midi_ch = [Some(9), None, None, Some(9), None, None, None, None];
+        mapper.voices.noteon = 0x09;
+        mapper.pan_law[3] = Some(PanLaw::ConstantPower3dB);
+        let mut data = vec![MIDIMSG_MODE | 9, MIDIMSG_CC_PAN, 32];
+        mapper.apply(&mut data);
+        assert_eq!(data[2], 32);
+        let mut data = vec![MIDIMSG_CC_PAN, 32];
+        mapper.apply(&mut data);
+        assert_eq!(data[1], PanLaw::ConstantPower3dB.map(32));
 
-async fn load_file(path: impl Into<PathBuf>) -> Result<(PathBuf, LoadedFile), Error> {
-    let path = path.into();
+        // ノートオンは新たにキーオンされたボイスを優先して対応付ける
+        let mut scaler = KeyOnVelocityScaler::new();
+        scaler.voices.midi_ch = [Some(9), None, None, Some(9), None, None, None, None];
+        scaler.voices.noteon = 0x09;
+        scaler.voices.keyon = 0x08;
+        scaler.velocity = [Some(40), None, None, Some(90), None, None, None, None];
+        let mut data = vec![MIDIMSG_NOTE_ON | 9, 36, 100];
+        scaler.apply(&mut data);
+        assert_eq!(data[2], 90);
+        // パラメータの無い音源のボイスは対応付けない
+        let mut data = vec![MIDIMSG_NOTE_ON | 2, 60, 100];
+        scaler.apply(&mut data);
+        assert_eq!(data[2], 100);
+    }
 
-    if let Some(extension) = path.extension().and_then(OsStr::to_str) {
-        match extension.to_lowercase().as_str() {
-            "spc" => {
-                let data = std::fs::read(&path).unwrap();
-                return Ok((path, LoadedFile::SPCFile(data.to_vec())));
-            }
-            "json" => {
-                let string = std::fs::read_to_string(&path).unwrap();
-                return Ok((path, LoadedFile::JSONFile(string)));
-            }
-            _ => {
-                return Err(Error::IoError(io::ErrorKind::Unsupported));
+    #[test]
+    fn master_reverb_send_test() {
+        // リバーブセンドにはマスターセンドが加算され、CCの範囲に丸め込まれる
+        let mut scaler = EchoSendScaler::new(40);
+        let mut data = vec![MIDIMSG_MODE | 2, MIDIMSG_CC_REVERB_SEND, 30];
+        scaler.apply(&mut data);
+        assert_eq!(data[2], 70);
+        // ランニングステータスのメッセージも対象
+        let mut data = vec![MIDIMSG_CC_REVERB_SEND, 100];
+        scaler.apply(&mut data);
+        assert_eq!(data[1], 127);
+        // リバーブセンド以外のコントロールチェンジは変わらない
+        let mut data = vec![MIDIMSG_MODE | 2, 7, 30];
+        scaler.apply(&mut data);
+        assert_eq!(data[2], 30);
+
+        let event = master_reverb_send_event(3, 200);
+        match event.event {
+            MidiEvent::Midi(msg) => {
+                assert_eq!(
+                    msg.data,
+                    vec![MIDIMSG_MODE | 3, MIDIMSG_CC_REVERB_SEND, 127]
+                );
             }
+            _ => panic!("unexpected event"),
         }
     }
 
-    return Err(Error::IoError(io::ErrorKind::Unsupported));
-}
+    #[test]
+    fn program_velocity_calibrator_test() {
+        let mut percent = [100u8; 128];
+        percent[24] = 50;
+        percent[40] = 200;
+        let mut calibrator = ProgramVelocityCalibrator::new(percent);
+        // プログラムチェンジ前はプログラム0の補正率
+        let mut data = vec![MIDIMSG_NOTE_ON | 1, 60, 100];
+        calibrator.apply(&mut data);
+        assert_eq!(data[2], 100);
+        calibrator.apply(&mut [MIDIMSG_PROGRAM_CHANGE | 1, 24]);
+        let mut data = vec![MIDIMSG_NOTE_ON | 1, 60, 99];
+        calibrator.apply(&mut data);
+        assert_eq!(data[2], 50);
+        // ランニングステータスのノートオンも対象で、上限で丸め込まれる
+        calibrator.apply(&mut [MIDIMSG_PROGRAM_CHANGE | 1, 40]);
+        let mut data = vec![MIDIMSG_NOTE_ON | 1, 60, 100];
+        calibrator.apply(&mut data);
+        assert_eq!(data[2], 127);
+        let mut data = vec![62, 30];
+        calibrator.apply(&mut data);
+        assert_eq!(data[1], 60);
+        // ベロシティ0のノートオンとドラムチャンネルは変わらない
+        let mut data = vec![62, 0];
+        calibrator.apply(&mut data);
+        assert_eq!(data[1], 0);
+        calibrator.apply(&mut [MIDIMSG_PROGRAM_CHANGE | MIDI_DRUM_CHANNEL, 40]);
+        let mut data = vec![MIDIMSG_NOTE_ON | MIDI_DRUM_CHANNEL, 36, 100];
+        calibrator.apply(&mut data);
+        assert_eq!(data[2], 100);
+        assert_eq!(default_program_velocity_calibration().len(), 128);
+    }
 
-async fn save_smf(default_file_name: String, smf: SMF) -> Result<(), Error> {
-    let picked_file = AsyncFileDialog::new()
-        .set_file_name(default_file_name)
-        .set_title("Save to a MIDI file...")
-        .add_filter("SMF", &["mid", "midi", "MID"])
-        .save_file()
-        .await
-        .ok_or(Error::DialogClosed)?;
+    #[test]
+    fn thumbnail_peaks_test() {
+        // 区間ごとの最小値・最大値（割り切れない場合は最後の区間が短くなる）
+        let signal = [0.5, -0.25, 1.0, 0.0, -1.0];
+        assert_eq!(
+            thumbnail_peaks(&signal, 3),
+            vec![(-0.25, 0.5), (0.0, 1.0), (-1.0, 0.0)]
+        );
+        // 区間数より短い信号はサンプルごとになる
+        assert_eq!(thumbnail_peaks(&signal[..2], 64).len(), 2);
+        assert!(thumbnail_peaks(&[], 64).is_empty());
+    }
 
-    let writer = SMFWriter::from_smf(smf);
-    match writer.write_to_file(picked_file.path()) {
-        Ok(()) => Ok(()),
-        _ => Err(Error::DialogClosed),
+    #[test]
+    fn midi_output_level_estimator_test() {
+        let mut estimator = MIDIOutputLevelEstimator::new();
+        assert_eq!(estimator.level(), 0.0);
+        // フルスケールの1音で1.0
+        estimator.apply(&[MIDIMSG_MODE, MIDIMSG_CC_VOLUME, 127]);
+        estimator.apply(&[MIDIMSG_NOTE_ON, 60, 127]);
+        assert!((estimator.level() - 1.0).abs() < 1.0e-6);
+        // ランニングステータスのベロシティ0のノートオンで消音
+        estimator.apply(&[60, 0]);
+        assert_eq!(estimator.level(), 0.0);
+        // エクスプレッション半分で約-12dB
+        estimator.apply(&[MIDIMSG_MODE, MIDIMSG_CC_EXPRESSION, 64]);
+        estimator.apply(&[MIDIMSG_NOTE_ON, 60, 127]);
+        assert!((amplitude_to_meter_db(estimator.level()) + 11.9).abs() < 0.1);
+        assert_eq!(amplitude_to_meter_db(0.0), LEVEL_METER_MIN_DB);
     }
-}
 
-async fn save_json(default_file_name: String, json: serde_json::Value) -> Result<(), Error> {
-    let picked_file = AsyncFileDialog::new()
-        .set_file_name(default_file_name)
-        .set_title("Save to a JSON file...")
-        .add_filter("JSON", &["json"])
-        .save_file()
-        .await
-        .ok_or(Error::DialogClosed)?;
+    #[test]
+    fn held_note_tracker_test() {
+        let mut tracker = HeldNoteTracker::new();
+        let start = Instant::now();
+        tracker.apply(&[MIDIMSG_NOTE_ON | 1, 60, 100], start);
+        tracker.apply(&[64, 100], start);
+        // ランニングステータスのベロシティ0で消音
+        tracker.apply(&[64, 0], start);
+        let later = start + Duration::from_secs(10);
+        assert!(tracker
+            .take_notes(later, Some(Duration::from_secs(20)))
+            .is_empty());
+        assert_eq!(
+            tracker.take_notes(later, Some(Duration::from_secs(5))),
+            vec![(1, 60)]
+        );
+        // 取り出したノートは鳴っていない扱い
+        assert!(tracker.take_notes(later, None).is_empty());
+        tracker.apply(&[MIDIMSG_NOTE_ON | 2, 48, 100], start);
+        tracker.apply(&[MIDIMSG_MODE | 2, MIDIMSG_MODE_ALL_SOUND_OFF, 0], start);
+        assert!(tracker.take_notes(later, None).is_empty());
 
-    match File::create(picked_file.path()) {
-        Ok(file) => {
-            let writer = BufWriter::new(file);
-            serde_json::to_writer_pretty(writer, &json).expect("Faied to write json");
-            Ok(())
-        }
-        _ => Err(Error::DialogClosed),
+        // 割り込んだノートオフの後はランニングステータスにステータスバイトを補う
+        tracker.apply(&[MIDIMSG_NOTE_ON | 3, 60, 100], start);
+        assert_eq!(tracker.restore_status(&[62, 100]), None);
+        assert_eq!(
+            tracker.take_stuck_note_offs(later, Duration::from_secs(5)),
+            [[MIDIMSG_NOTE_OFF | 3, 60, 0]]
+        );
+        assert_eq!(
+            tracker.restore_status(&[62, 100]),
+            Some(vec![MIDIMSG_NOTE_ON | 3, 62, 100])
+        );
+        // 補うのは割り込み直後のメッセージのみ
+        assert_eq!(tracker.restore_status(&[64, 100]), None);
     }
-}
 
-// 再生情報の読み取り
-fn read_playback_status(midi_dsp: &spc700::mididsp::MIDIDSP) -> PlaybackStatus {
-    let mut status = PlaybackStatus::new();
+    #[test]
+    fn format_duration_sec_test() {
+        assert_eq!(format_duration_sec(0.0), "0:00");
+        assert_eq!(format_duration_sec(59.6), "1:00");
+        assert_eq!(format_duration_sec(754.0), "12:34");
+        assert_eq!(format_duration_sec(-1.0), "0:00");
+    }
 
-    let noteon_flags = midi_dsp.read_register(&[0u8], DSP_ADDRESS_NOTEON);
-    for ch in 0..8 {
-        let ch_nibble = (ch as u8) << 4;
-        status.noteon[ch] = ((noteon_flags >> ch) & 1) != 0;
-        status.srn_no[ch] = midi_dsp.read_register(&[0u8], DSP_ADDRESS_V0SRCN | ch_nibble);
-        let pitch_high = midi_dsp.read_register(&[0u8], DSP_ADDRESS_V0PITCHH | ch_nibble);
-        let pitch_low = midi_dsp.read_register(&[0u8], DSP_ADDRESS_V0PITCHL | ch_nibble);
-        status.pitch[ch] = ((pitch_high as u16) << 8) | (pitch_low as u16);
-        status.envelope[ch] = midi_dsp.read_register(&[0u8], DSP_ADDRESS_V0ENVX | ch_nibble);
-        status.volume[ch][0] = midi_dsp.read_register(&[0u8], DSP_ADDRESS_V0VOLL | ch_nibble) as i8;
-        status.volume[ch][1] = midi_dsp.read_register(&[0u8], DSP_ADDRESS_V0VOLR | ch_nibble) as i8;
+    #[test]
+    fn pcm_peak_dbfs_test() {
+        assert_eq!(pcm_peak_dbfs(&[]), f32::NEG_INFINITY);
+        assert_eq!(pcm_peak_dbfs(&[0, 0]), f32::NEG_INFINITY);
+        assert_eq!(pcm_peak_dbfs(&[100, i16::MIN]), 0.0);
+        assert!((pcm_peak_dbfs(&[0, -16384]) + 6.02).abs() < 0.01);
     }
 
-    status
-}
+    #[test]
+    fn preroll_render_range_msec_test() {
+        assert_eq!(preroll_render_range_msec(None, 60000, 0), None);
+        assert_eq!(
+            preroll_render_range_msec(Some((1000, 2000)), 60000, 0),
+            Some((1000, 2000))
+        );
+        assert_eq!(
+            preroll_render_range_msec(None, 60000, 500),
+            Some((500, 60500))
+        );
+        assert_eq!(
+            preroll_render_range_msec(Some((1000, 2000)), 60000, 500),
+            Some((1500, 2500))
+        );
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn loop_samples_in_output_test() {
+        // 同じレートではそのまま
+        assert_eq!(
+            loop_samples_in_output((32000, 64000), 0, 32000, 200000),
+            Some((32000, 64000))
+        );
+        // 44.1kHzでは開始と終端をそれぞれ丸める
+        assert_eq!(
+            loop_samples_in_output((1001, 3001), 0, 44100, 200000),
+            Some((1380, 4135))
+        );
+        // 範囲の開始より前から始まるループは周期単位で後ろにずらす
+        assert_eq!(
+            loop_samples_in_output((1000, 3000), 5000, 32000, 200000),
+            Some((2000, 3000))
+        );
+        // ループの終端が出力に収まらない場合はタグを付けない
+        assert_eq!(loop_samples_in_output((1000, 3000), 0, 32000, 3999), None);
+    }
 
     #[test]
-    fn spc_file_open_test() -> Result<(), Box<dyn std::error::Error>> {
-        let test_files = [
-            "./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc",
-            "./tests/data/forest_album_230125_spc_supermidipak/03_cowctry_voice.spc",
-        ];
+    fn encode_ogg_test() {
+        // 短いステレオの正弦波をエンコード
+        let pcm: Vec<i16> = (0..4000)
+            .flat_map(|i| {
+                let smpl = (8000.0 * (i as f32 * 0.05).sin()) as i16;
+                [smpl, smpl]
+            })
+            .collect();
+        let contains = |data: &[u8], pattern: &[u8]| {
+            data.windows(pattern.len()).any(|window| window == pattern)
+        };
+        // ループがあればコメントにループ位置を書き込む
+        let ogg = encode_ogg(&pcm, 32000, 2, Some((1000, 2500))).unwrap();
+        assert!(ogg.starts_with(b"OggS"));
+        assert!(contains(&ogg, b"LOOPSTART=1000"));
+        assert!(contains(&ogg, b"LOOPLENGTH=2500"));
+        // ループがなければ付けない
+        let ogg = encode_ogg(&pcm, 32000, 2, None).unwrap();
+        assert!(ogg.starts_with(b"OggS"));
+        assert!(!contains(&ogg, b"LOOPSTART"));
+        assert!(!contains(&ogg, b"LOOPLENGTH"));
+        // 不正なチャンネル数はエラー
+        assert!(encode_ogg(&pcm, 32000, 0, None).is_err());
+    }
 
-        for file in test_files {
-            let mut app = App::default();
-            let data = Box::new(std::fs::read(&file)?);
-            let _ = app.update(Message::FileOpened(Ok((
-                file.into(),
-                LoadedFile::SPCFile(*data),
-            ))));
+    #[test]
+    fn pick_spectrum_peaks_test() {
+        // 大きなピーク(3)の裾(4)は極大でないので選ばない
+        let spec = [9.0, 1.0, 5.0, 8.0, 7.0, 2.0, 6.0, 1.0, 3.0, 0.0];
+        assert_eq!(pick_spectrum_peaks(&spec, 3, 1), vec![3, 6, 8]);
+        // 間隔が足りないピークは除く
+        assert_eq!(pick_spectrum_peaks(&spec, 3, 4), vec![3, 8]);
+        assert_eq!(pick_spectrum_peaks(&spec, 1, 1), vec![3]);
+        assert!(pick_spectrum_peaks(&spec, 0, 1).is_empty());
+    }
 
-            assert!(app.spc_file.is_some());
-            assert!(app.spc_file_path == Some(file.into()));
-        }
+    #[test]
+    fn sanitize_smf_text_test() {
+        assert_eq!(sanitize_smf_text(" Intro / Loop "), "Intro / Loop");
+        // 制御文字は除き、ASCII以外は置き換える
+        assert_eq!(sanitize_smf_text("A\tB\r\nC"), "ABC");
+        assert_eq!(sanitize_smf_text("ループ1"), "???1");
+    }
 
-        Ok(())
+    #[test]
+    fn snap_to_brr_block_boundary_test() {
+        // ブロック途中を指すループアドレスは最も近いブロック境界に丸める
+        let loop_start_sample = |offset: usize| (offset * 16) / 9;
+        assert_eq!(snap_to_brr_block_boundary(loop_start_sample(18)), 32);
+        assert_eq!(snap_to_brr_block_boundary(loop_start_sample(22)), 32);
+        assert_eq!(snap_to_brr_block_boundary(loop_start_sample(23)), 48);
+        assert_eq!(snap_to_brr_block_boundary(0), 0);
     }
 
     #[test]
-    fn parameter_set_test() -> Result<(), Box<dyn std::error::Error>> {
-        let test_files = ["./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc"];
+    fn drum_kit_select_messages_test() {
+        // GM1や指定なしではドラムキットを選択しない
+        assert!(drum_kit_select_messages(&MIDISystem::NONE, DrumKit::Jazz).is_empty());
+        assert!(drum_kit_select_messages(&MIDISystem::GMLevel1, DrumKit::Jazz).is_empty());
+        // XGはドラム用のバンク(MSB=127)を選んでからプログラムチェンジ
+        let msgs = drum_kit_select_messages(&MIDISystem::XG, DrumKit::TR808);
+        assert_eq!(msgs.len(), 3);
+        assert_eq!(msgs[0], [0xB9, MIDIMSG_CC_BANK_SELECT_MSB, 0x7F]);
+        assert_eq!(msgs[1], [0xB9, MIDIMSG_CC_BANK_SELECT_LSB, 0x00]);
+        assert_eq!(msgs[2], [0xC9, 25]);
+        let msgs = drum_kit_select_messages(&MIDISystem::GS, DrumKit::Brush);
+        assert_eq!(msgs[0][2], 0x00);
+        assert_eq!(msgs[2][1], 40);
+    }
 
-        macro_rules! test_param_field(
-            ($app:expr, $srn_no:expr, $field:tt, $expect:expr) => {
-                {
-                    let params = $app.source_parameter.clone();
-                    let params = params.read().unwrap();
-                    let param = params.get(&$srn_no).unwrap();
-                    assert_eq!(param.$field, $expect);
-                }
+    #[test]
+    fn set_center_note_test() {
+        // 整数部・小数部の全組み合わせで0〜127.998のノートに収まり、整数部が保たれる
+        for int in 0..=u8::MAX {
+            for step in 0..=1024 {
+                let fraction = step as f32 / 512.0 - 0.5;
+                let center_note = set_center_note(int, fraction);
+                assert_eq!(center_note >> 9, int.min(127) as u16);
             }
-        );
+            for fraction in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+                assert_eq!(set_center_note(int, fraction), (int.min(127) as u16) << 9);
+            }
+        }
+        assert_eq!(set_center_note(127, 1.0), 0xFFFF);
+        // 実数からの変換では小数部の繰り上がりが整数部に反映される
+        assert_eq!(center_note_from_float(60.0), 60 << 9);
+        assert_eq!(center_note_from_float(60.9999), 61 << 9);
+        assert_eq!(center_note_from_float(200.0), 0xFFFF);
+        assert_eq!(center_note_from_float(-1.0), 0);
+        assert_eq!(center_note_from_float(f32::NAN), 0);
+        // 全ての16bit値が実数経由で同じ値に戻る
+        for center_note in 0..=u16::MAX {
+            let note = center_note as f32 / 512.0;
+            assert_eq!(center_note_from_float(note), center_note);
+        }
+    }
 
-        for file in test_files {
-            let mut app = App::default();
-            let data = Box::new(std::fs::read(&file)?);
-            let _ = app.update(Message::FileOpened(Ok((
-                file.into(),
-                LoadedFile::SPCFile(*data),
-            ))));
+    #[test]
+    fn sanitize_float_input_test() {
+        assert_eq!(sanitize_float_input(0.5, 0.0..=1.0), Some(0.5));
+        assert_eq!(sanitize_float_input(-0.5, 0.0..=1.0), Some(0.0));
+        assert_eq!(sanitize_float_input(1.5, 0.0..=1.0), Some(1.0));
+        assert_eq!(sanitize_float_input(f32::NAN, 0.0..=1.0), None);
+        assert_eq!(sanitize_float_input(f32::INFINITY, 0.0..=1.0), None);
+        assert_eq!(sanitize_float_input(f32::NEG_INFINITY, 0.0..=1.0), None);
+    }
 
-            // SRN = 0に対してパラメータ編集し、意図した値が設定されているか確認
-            let _ = app.update(Message::SRNMuteFlagToggled(0, true));
-            test_param_field!(app, 0, mute, true);
-            let _ = app.update(Message::SRNMuteFlagToggled(0, false));
-            test_param_field!(app, 0, mute, false);
-            let _ = app.update(Message::ProgramSelected(0, Program::BrightAcoustic, None));
-            test_param_field!(app, 0, program, Program::BrightAcoustic);
-            let _ = app.update(Message::CenterNoteIntChanged(0, 0));
-            let _ = app.update(Message::CenterNoteFractionChanged(0, 0.0));
-            test_param_field!(app, 0, center_note, 0);
-            let _ = app.update(Message::CenterNoteIntChanged(0, 127));
-            let _ = app.update(Message::CenterNoteFractionChanged(0, 1.0));
-            test_param_field!(app, 0, center_note, 0xFFFF);
-            let _ = app.update(Message::NoteOnVelocityChanged(0, 1));
-            test_param_field!(app, 0, noteon_velocity, 1);
-            let _ = app.update(Message::PitchBendWidthChanged(0, 0));
-            test_param_field!(app, 0, pitch_bend_width, 0);
-            let _ = app.update(Message::PitchBendWidthChanged(0, 48));
-            test_param_field!(app, 0, pitch_bend_width, 48);
-            let _ = app.update(Message::EnablePitchBendFlagToggled(0, true));
-            test_param_field!(app, 0, enable_pitch_bend, true);
-            let _ = app.update(Message::EnablePitchBendFlagToggled(0, false));
-            test_param_field!(app, 0, enable_pitch_bend, false);
-            let _ = app.update(Message::AutoPanFlagToggled(0, true));
-            test_param_field!(app, 0, auto_pan, true);
-            let _ = app.update(Message::AutoPanFlagToggled(0, false));
-            test_param_field!(app, 0, auto_pan, false);
-            let _ = app.update(Message::FixedPanChanged(0, 0));
-            test_param_field!(app, 0, fixed_pan, 0);
-            let _ = app.update(Message::FixedPanChanged(0, 127));
-            test_param_field!(app, 0, fixed_pan, 127);
-            let _ = app.update(Message::AutoVolumeFlagToggled(0, true));
-            test_param_field!(app, 0, auto_volume, true);
-            let _ = app.update(Message::AutoVolumeFlagToggled(0, false));
-            test_param_field!(app, 0, auto_volume, false);
-            let _ = app.update(Message::FixedVolumeChanged(0, 0));
-            test_param_field!(app, 0, fixed_volume, 0);
-            let _ = app.update(Message::FixedVolumeChanged(0, 127));
-            test_param_field!(app, 0, fixed_volume, 127);
-            let _ = app.update(Message::EnvelopeAsExpressionFlagToggled(0, true));
-            test_param_field!(app, 0, envelope_as_expression, true);
-            let _ = app.update(Message::EnvelopeAsExpressionFlagToggled(0, false));
-            test_param_field!(app, 0, envelope_as_expression, false);
-            let _ = app.update(Message::EchoAsReverbFlagToggled(0, true));
-            test_param_field!(app, 0, echo_as_reverb_send, true);
-            let _ = app.update(Message::EchoAsReverbFlagToggled(0, false));
-            test_param_field!(app, 0, echo_as_reverb_send, false);
+    #[test]
+    fn tiny_source_estimation_test() {
+        for len in 0..3 {
+            let signal = vec![0.5; len];
+            let info = test_source_info(signal.clone(), compute_power_spectrum(&signal), len);
+            assert!(info.power_spectrum.is_empty());
+            let (_, center_note) = estimate_drum_and_note(&info, DEFAULT_A4_PITCH_HZ);
+            assert!(center_note.is_finite());
+            assert_eq!(pitch_confidence(&info), 0.0);
+            assert_eq!(estimate_bpm(&signal, 64_000.0), DEFAULT_MIDI_BPM);
         }
-
-        Ok(())
+        assert_eq!(estimate_bpm(&[], 0.0), DEFAULT_MIDI_BPM);
     }
 
     #[test]
-    fn configure_set_test() -> Result<(), Box<dyn std::error::Error>> {
-        let test_files = ["./tests/data/forest_album_230125_spc_supermidipak/02_orphee.spc"];
+    fn sine_source_center_note_test() {
+        // 正弦波の音源からノートを推定する（ループ長からの推定とスペクトルからの推定）
+        let create_info = |frequency: f32, num_samples: usize, loop_start_sample: usize| {
+            let signal: Vec<f32> = (0..num_samples)
+                .map(|n| f32::sin(2.0 * std::f32::consts::PI * frequency * n as f32 / 32000.0))
+                .collect();
+            let power_spectrum = compute_power_spectrum(&signal);
+            test_source_info(signal, power_spectrum, loop_start_sample)
+        };
+        // 500Hz（1周期64サンプル）のノート番号
+        let expected_note = 69.0 + 12.0 * f32::log2(500.0 / DEFAULT_A4_PITCH_HZ);
 
-        macro_rules! test_config_field(
-            ($app:expr, $field:tt, $expect:expr) => {
-                {
-                    let config = $app.midi_output_configure.clone();
-                    let config = config.read().unwrap();
-                    assert_eq!(config.$field, $expect);
+        // ループが1周期分の波形であればループ長から推定する
+        let short_loop = create_info(500.0, 128, 64);
+        let (_, note) = estimate_drum_and_note(&short_loop, DEFAULT_A4_PITCH_HZ);
+        assert!((note - expected_note).abs() < 0.01);
+        assert_eq!(pitch_confidence(&short_loop), 1.0);
+
+        // ループしない長い波形はスペクトルのピークから推定し、ループ長からの推定と一致する
+        let long = create_info(500.0, 4096, 4096);
+        let (_, note) = estimate_drum_and_note(&long, DEFAULT_A4_PITCH_HZ);
+        assert!((note - expected_note).abs() < 0.1);
+        let long = create_info(DEFAULT_A4_PITCH_HZ, 4096, 4096);
+        let (_, note) = estimate_drum_and_note(&long, DEFAULT_A4_PITCH_HZ);
+        assert!((note - 69.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn loop_region_center_note_test() {
+        // 低い音のアタックの後に500Hzでループする音源
+        let signal: Vec<f32> = (0..4096)
+            .map(|n| {
+                let t = n as f32 / 32000.0;
+                if n < 2048 {
+                    f32::sin(2.0 * std::f32::consts::PI * 125.0 * t)
+                } else {
+                    0.5 * f32::sin(2.0 * std::f32::consts::PI * 500.0 * t)
                 }
-            }
-        );
+            })
+            .collect();
+        let create_info =
+            |power_spectrum: Vec<f32>| test_source_info(signal.clone(), power_spectrum, 2048);
+        let expected_note = 69.0 + 12.0 * f32::log2(500.0 / DEFAULT_A4_PITCH_HZ);
 
-        for file in test_files {
-            let mut app = App::default();
-            let data = Box::new(std::fs::read(&file)?);
-            let _ = app.update(Message::FileOpened(Ok((
-                file.into(),
-                LoadedFile::SPCFile(*data),
-            ))));
+        // 全体から計算するとアタックの音高に引っ張られる
+        let whole = create_info(compute_power_spectrum(&signal));
+        let (_, note) = estimate_drum_and_note(&whole, DEFAULT_A4_PITCH_HZ);
+        assert!((note - expected_note).abs() > 1.0);
 
-            // 意図した値が設定されているか確認
-            let _ = app.update(Message::MIDIOutputTicksPerQuarterChanged(24));
-            test_config_field!(app, ticks_per_quarter, 24);
-            let _ = app.update(Message::MIDIOutputTicksPerQuarterChanged(960));
-            test_config_field!(app, ticks_per_quarter, 960);
-            let _ = app.update(Message::MIDIOutputUpdatePeriodChanged(0));
-            test_config_field!(app, playback_parameter_update_period, 0);
-            let _ = app.update(Message::MIDIOutputUpdatePeriodChanged(255));
-            test_config_field!(app, playback_parameter_update_period, 255);
-            let _ = app.update(Message::MIDIOutputDurationChanged(0));
-            test_config_field!(app, output_duration_msec, 0);
-            let _ = app.update(Message::MIDIOutputDurationChanged(u64::MAX));
-            test_config_field!(app, output_duration_msec, u64::MAX);
+        // ループ区間から計算すれば持続部の音高になる
+        let looped = create_info(compute_power_spectrum(&spectrum_analysis_signal(
+            &signal, 2048,
+        )));
+        let (_, note) = estimate_drum_and_note(&looped, DEFAULT_A4_PITCH_HZ);
+        assert!((note - expected_note).abs() < 0.1);
+
+        // ワンショット音源・短いループの切り出し
+        assert_eq!(spectrum_analysis_signal(&signal, 4096), signal);
+        assert_eq!(spectrum_analysis_signal(&signal, 0), signal);
+        assert_eq!(spectrum_analysis_signal(&signal, 4000).len(), 2112);
+    }
+
+    #[test]
+    fn quiet_source_estimation_test() {
+        // 倍音を含む波形を大小2つの音量で作り、同じ判定・ノートになることを確認
+        let create_info = |amplitude: f32| {
+            let signal: Vec<f32> = (0..4096)
+                .map(|n| {
+                    let phase = 2.0 * std::f32::consts::PI * 300.0 * n as f32 / 32000.0;
+                    amplitude * (f32::sin(phase) + 0.5 * f32::sin(2.0 * phase))
+                })
+                .collect();
+            let power_spectrum = compute_power_spectrum(&signal);
+            test_source_info(signal, power_spectrum, 2048)
+        };
+        let loud = create_info(0.8);
+        let quiet = create_info(0.001);
+        let (loud_is_drum, loud_note) = estimate_drum_and_note(&loud, DEFAULT_A4_PITCH_HZ);
+        let (quiet_is_drum, quiet_note) = estimate_drum_and_note(&quiet, DEFAULT_A4_PITCH_HZ);
+        assert_eq!(loud_is_drum, quiet_is_drum);
+        assert!((loud_note - quiet_note).abs() < 0.01);
+        // 推定しても元の信号は変わらない
+        assert!(quiet.signal.iter().all(|x| x.abs() <= 0.0015));
+    }
+
+    #[test]
+    fn detect_loop_test() {
+        // イントロ3イベントの後に周期100で4イベントのパターンが繰り返す（時刻に1の揺れあり）
+        let mut events = vec![(0, 10), (20, 11), (40, 12)];
+        for n in 0..3 {
+            let base = 50 + 100 * n;
+            events.extend([(base, 1), (base + 25, 2), (base + 51, 1), (base + 75, 3)]);
         }
+        assert_eq!(detect_loop(&events, 1), Some((50, 100)));
+        // 1周期分に満たない場合は検出しない
+        assert_eq!(detect_loop(&events[..8], 1), None);
+    }
 
-        Ok(())
+    #[test]
+    fn playback_mode_toggle_test() {
+        // 同じ再生の要求は停止
+        assert_eq!(
+            PlaybackMode::Song.toggled(PlaybackMode::Song),
+            PlaybackMode::None
+        );
+        assert_eq!(
+            PlaybackMode::Preview(3).toggled(PlaybackMode::Preview(3)),
+            PlaybackMode::None
+        );
+        // 停止中は要求された再生を開始
+        assert_eq!(
+            PlaybackMode::None.toggled(PlaybackMode::Song),
+            PlaybackMode::Song
+        );
+        assert_eq!(
+            PlaybackMode::None.toggled(PlaybackMode::Preview(3)),
+            PlaybackMode::Preview(3)
+        );
+        // 曲とプレビュー、異なる音源のプレビューは互いに切り替え
+        assert_eq!(
+            PlaybackMode::Song.toggled(PlaybackMode::Preview(3)),
+            PlaybackMode::Preview(3)
+        );
+        assert_eq!(
+            PlaybackMode::Preview(3).toggled(PlaybackMode::Song),
+            PlaybackMode::Song
+        );
+        assert_eq!(
+            PlaybackMode::Preview(3).toggled(PlaybackMode::Preview(4)),
+            PlaybackMode::Preview(4)
+        );
     }
 }
@@ -3,6 +3,7 @@
 use iced_aw::ICED_AW_FONT_BYTES;
 use spc2midi_tsuu::cli::*;
 use spc2midi_tsuu::App;
+use spc2midi_tsuu::SYMBOL_FONT_BYTES;
 use std::env;
 
 pub fn main() -> iced::Result {
@@ -13,6 +14,7 @@ pub fn main() -> iced::Result {
             .title(App::title)
             .theme(App::theme)
             .font(ICED_AW_FONT_BYTES)
+            .font(SYMBOL_FONT_BYTES)
             .run()
     } else {
         // CLIで実行
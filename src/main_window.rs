@@ -1,17 +1,22 @@
 use crate::types::*;
 use crate::Message;
 use crate::Program;
+use crate::LEVEL_METER_MIN_DB;
+use crate::NOTE_NAMES;
+use crate::SYMBOL_FONT;
+use crate::{SOURCE_GRID_COLUMNS, SOURCE_GRID_ROW_HEIGHT};
 use iced::border::Radius;
 use iced::widget::canvas::{self, Canvas, Event, Frame, Geometry};
 use iced::widget::{
-    button, checkbox, column, pick_list, progress_bar, row, scrollable, space, stack, text,
-    tooltip, Column, Text,
+    button, checkbox, column, pick_list, progress_bar, row, scrollable, slider, space, stack, text,
+    tooltip, vertical_slider, Column, Row, Text,
 };
 use iced::{
     alignment, mouse, Border, Color, Element, Font, Length, Padding, Point, Rectangle, Renderer,
     Size, Theme,
 };
 use iced_aw::menu::{self, Menu};
+use iced_aw::number_input;
 use iced_aw::style::{menu_bar::primary, Status};
 use iced_aw::{menu_bar, menu_items};
 use std::collections::BTreeMap;
@@ -32,10 +37,24 @@ pub struct MainWindow {
     display_source_id_type: Arc<RwLock<DisplaySourceIDType>>,
     pub playback_time_sec: f32,
     pub midi_bit_rate: f32,
+    pub audio_health: AudioHealth,
+    pub recent_underruns: usize,
     pub pitch_indicator: [Indicator; 8],
     pub expression_indicator: [Indicator; 8],
     pub volume_indicator: [[Indicator; 2]; 8],
+    /// SPC（PCM）出力のレベルメーター
+    pub pcm_level_indicator: Indicator,
+    /// MIDI出力（推定）のレベルメーター
+    pub midi_level_indicator: Indicator,
+    pub beat_indicator: BeatIndicator,
+    pub song_overview: Option<Arc<SongOverview>>,
+    pub no_source_detected: bool,
     pub showing_channel_srn_list: [bool; 8],
+    pub operation_progress: Option<f32>,
+    pub add_srn_no: u8,
+    pub layout: MainWindowLayout,
+    /// グリッド表示する音源の（開始アドレス, 波形サムネイル）（表示範囲に入ったものだけ作成）
+    pub thumbnails: BTreeMap<u8, (usize, Arc<Vec<(f32, f32)>>)>,
 }
 
 impl MainWindow {
@@ -62,16 +81,386 @@ impl MainWindow {
             channel_mute_flags: channel_mute_flags,
             playback_time_sec: 0.0f32,
             midi_bit_rate: 0.0f32,
+            audio_health: AudioHealth::Good,
+            recent_underruns: 0,
             expression_indicator: [Indicator::new(0.0, 0.0, 127.0, |value| format!("{:<3}", value));
                 8],
             pitch_indicator: [Indicator::new(0.0, -48.0, 48.0, |value| format!("{:+4.1}", value));
                 8],
             volume_indicator: [[Indicator::new(0.0, -128.0, 127.0, |value| format!("{}", value));
                 2]; 8],
+            pcm_level_indicator: Indicator::new(
+                LEVEL_METER_MIN_DB,
+                LEVEL_METER_MIN_DB,
+                0.0,
+                |value| format!("{:.1}dB", value),
+            ),
+            midi_level_indicator: Indicator::new(
+                LEVEL_METER_MIN_DB,
+                LEVEL_METER_MIN_DB,
+                0.0,
+                |value| format!("{:.1}dB", value),
+            ),
+            beat_indicator: BeatIndicator {
+                playback_time_sec: 0.0,
+                beats_per_minute: DEFAULT_MIDI_BPM,
+                beats_per_bar: 4,
+            },
+            song_overview: None,
+            no_source_detected: false,
             showing_channel_srn_list: [true; 8],
+            operation_progress: None,
+            add_srn_no: 0,
+            layout: MainWindowLayout::Table,
+            thumbnails: BTreeMap::new(),
             display_source_id_type: display_source_id_type,
         }
     }
+
+    /// 表示スケールを全てのインジケータに設定
+    pub fn set_display_scale(&mut self, scale: DisplayScale) {
+        for indicator in self
+            .pitch_indicator
+            .iter_mut()
+            .chain(self.expression_indicator.iter_mut())
+            .chain(self.volume_indicator.iter_mut().flatten())
+            .chain([
+                &mut self.pcm_level_indicator,
+                &mut self.midi_level_indicator,
+            ])
+        {
+            indicator.scale = scale;
+        }
+    }
+
+    /// 音源の識別表示（ラベルがあればラベル、なければ番号）
+    fn source_id_string(
+        &self,
+        srn: u8,
+        info: Option<&SourceInformation>,
+        param: Option<&SourceParameter>,
+    ) -> String {
+        if let Some(param) = param.filter(|param| !param.label.is_empty()) {
+            return param.label.clone();
+        }
+        if let Some(info) = info {
+            match *self.display_source_id_type.read().unwrap() {
+                DisplaySourceIDType::StartAddress => format!("{:04X}", info.start_address),
+                DisplaySourceIDType::SRN => format!("{}", srn),
+            }
+        } else {
+            "".to_string()
+        }
+    }
+
+    /// 聴こえるかどうかで色分けした音源の識別表示（理由をツールチップで表示）
+    fn source_id_with_audibility<'a>(
+        &self,
+        srn: u8,
+        info: Option<&SourceInformation>,
+        param: &SourceParameter,
+        audibility: SourceAudibility,
+        size: f32,
+    ) -> Element<'a, Message> {
+        let palette = self.theme.palette();
+        let (color, description) = match audibility {
+            SourceAudibility::Muted => (palette.warning, "Muted (source mute)"),
+            SourceAudibility::Silenced => (
+                palette.warning,
+                "Silent: every channel playing this source is muted or not soloed",
+            ),
+            SourceAudibility::PartiallyAudible => (
+                Color::from_rgb(0.9, 0.8, 0.4),
+                "Partially audible: some channels playing this source are muted",
+            ),
+            SourceAudibility::Audible => (palette.success, "Audible"),
+            SourceAudibility::Idle => (palette.text, "Not playing"),
+        };
+        tooltip(
+            text(self.source_id_string(srn, info, Some(param)))
+                .size(size)
+                .color(color),
+            text(description),
+            tooltip::Position::Right,
+        )
+        .into()
+    }
+
+    /// ミキサー表示（チャンネルごとに発音中の音源のフェーダー・パン・メーター・ミュート・ソロを並べる）
+    fn mixer_view(&self) -> Element<'_, Message> {
+        let params = self.source_params.read().unwrap();
+        let infos = self.source_infos.read().unwrap();
+        let status = self.playback_status.read().unwrap();
+        let channel_mute_flags = self.channel_mute_flags.load(Ordering::Relaxed);
+        let strips: Vec<Element<'_, Message>> = (0..8)
+            .map(|ch| {
+                let srn = status.srn_no[ch];
+                let param = params.get(&srn);
+                // パン（SPCの値を使う場合は操作不可）
+                let pan: Element<'_, Message> = match param {
+                    Some(param) if !param.auto_pan => {
+                        slider(0..=127, param.fixed_pan, move |pan| {
+                            Message::FixedPanChanged(srn, pan)
+                        })
+                        .width(Length::Fill)
+                        .into()
+                    }
+                    Some(_) => text("Auto").size(12.0).into(),
+                    None => space::vertical().height(12).into(),
+                };
+                // フェーダー（SPCの値を使う場合は操作不可）
+                let fader: Element<'_, Message> = match param {
+                    Some(param) if !param.auto_volume => {
+                        vertical_slider(0..=127, param.fixed_volume, move |volume| {
+                            Message::FixedVolumeChanged(srn, volume)
+                        })
+                        .height(Length::Fill)
+                        .into()
+                    }
+                    Some(_) => text("Auto")
+                        .size(12.0)
+                        .height(Length::Fill)
+                        .align_y(alignment::Alignment::Center)
+                        .into(),
+                    None => space::horizontal().width(20).into(),
+                };
+                column![
+                    text(format!("CH {}", ch)).size(14.0),
+                    button(
+                        text(self.source_id_string(srn, infos.get(&srn), param))
+                            .size(14.0)
+                            .color(if param.is_some_and(|param| param.mute) {
+                                self.theme.palette().warning
+                            } else {
+                                self.theme.palette().text
+                            })
+                    )
+                    .on_press_maybe(param.map(|_| Message::OpenSRNWindow(srn)))
+                    .style(|_, _| button::Style {
+                        background: Some(iced::Background::Color(self.theme.palette().background)),
+                        ..Default::default()
+                    })
+                    .padding(0),
+                    text(format!("{}", if status.noteon[ch] { "♪" } else { "" }))
+                        .font(SYMBOL_FONT)
+                        .height(16),
+                    row![text("Pan").size(12.0), pan]
+                        .spacing(5)
+                        .align_y(alignment::Alignment::Center),
+                    row![
+                        fader,
+                        Canvas::new(self.volume_indicator[ch][0])
+                            .height(Length::Fill)
+                            .width(Length::Fill),
+                        Canvas::new(self.volume_indicator[ch][1])
+                            .height(Length::Fill)
+                            .width(Length::Fill),
+                    ]
+                    .spacing(5)
+                    .height(Length::Fill),
+                    Canvas::new(self.expression_indicator[ch])
+                        .height(20)
+                        .width(Length::Fill),
+                    row![
+                        checkbox((channel_mute_flags >> ch) & 1 != 0)
+                            .label("M")
+                            .on_toggle(move |flag| Message::MuteChannel(ch as u8, flag)),
+                        button("S")
+                            .style(iced::widget::button::success)
+                            .on_press(Message::SoloChannel(ch as u8)),
+                    ]
+                    .spacing(5)
+                    .align_y(alignment::Alignment::Center),
+                ]
+                .spacing(5)
+                .padding(5)
+                .width(Length::FillPortion(1))
+                .height(Length::Fill)
+                .align_x(alignment::Alignment::Center)
+                .into()
+            })
+            .collect();
+        Row::from_vec(strips)
+            .spacing(5)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+
+    /// グリッド表示（音源ごとに波形サムネイルとプログラム・基準ノートを並べ、クリックで音源ウィンドウを開く）
+    fn grid_view(&self) -> Element<'_, Message> {
+        let params = self.source_params.read().unwrap();
+        let infos = self.source_infos.read().unwrap();
+        let status = self.playback_status.read().unwrap();
+        let channel_mute_flags = self.channel_mute_flags.load(Ordering::Relaxed);
+        let mut cells = infos
+            .iter()
+            .map(|(&srn, info)| {
+                let param = params.get(&srn);
+                // サムネイルは表示範囲に入るまで作成されないので空けておく
+                let thumbnail: Element<'_, Message> = match self.thumbnails.get(&srn) {
+                    Some((_, peaks)) => Canvas::new(SourceThumbnail {
+                        peaks: peaks.clone(),
+                    })
+                    .width(Length::Fill)
+                    .height(Length::Fill)
+                    .into(),
+                    None => space::vertical().height(Length::Fill).into(),
+                };
+                let (program, note) = match param {
+                    Some(param) => (
+                        if param.instrument_name != "" {
+                            param.instrument_name.clone()
+                        } else {
+                            param.program.to_string()
+                        },
+                        format!(
+                            "{}{}",
+                            NOTE_NAMES[((param.center_note >> 9) % 12) as usize],
+                            (param.center_note >> 9) as i32 / 12 - 1
+                        ),
+                    ),
+                    None => ("".to_string(), "".to_string()),
+                };
+                let id: Element<'_, Message> = match param {
+                    Some(param) => self.source_id_with_audibility(
+                        srn,
+                        Some(info),
+                        param,
+                        status.source_audibility(srn, param, channel_mute_flags),
+                        12.0,
+                    ),
+                    None => text(self.source_id_string(srn, Some(info), param))
+                        .size(12.0)
+                        .into(),
+                };
+                button(
+                    column![
+                        thumbnail,
+                        id,
+                        text(program)
+                            .size(12.0)
+                            .color(if param.is_some_and(|param| param.mute) {
+                                self.theme.palette().warning
+                            } else {
+                                self.theme.palette().text
+                            }),
+                        text(note).size(12.0),
+                    ]
+                    .spacing(2),
+                )
+                .on_press_maybe(param.map(|_| Message::OpenSRNWindow(srn)))
+                .style(|_, _| button::Style {
+                    background: Some(iced::Background::Color(self.theme.palette().background)),
+                    text_color: self.theme.palette().text,
+                    border: Border::default()
+                        .color(self.theme.palette().primary)
+                        .width(1.0),
+                    ..Default::default()
+                })
+                .padding(4)
+                .width(Length::FillPortion(1))
+                .height(SOURCE_GRID_ROW_HEIGHT - 5.0)
+                .into()
+            })
+            .peekable();
+
+        // 列数ごとに行にまとめる（最終行は空きで埋めて幅を揃える）
+        let mut rows: Vec<Element<'_, Message>> = vec![];
+        while cells.peek().is_some() {
+            let mut row_cells: Vec<Element<'_, Message>> =
+                cells.by_ref().take(SOURCE_GRID_COLUMNS).collect();
+            while row_cells.len() < SOURCE_GRID_COLUMNS {
+                row_cells.push(space::horizontal().width(Length::FillPortion(1)).into());
+            }
+            rows.push(Row::from_vec(row_cells).spacing(5).into());
+        }
+        scrollable(Column::from_vec(rows).spacing(5).width(Length::Fill))
+            .on_scroll(Message::SourceGridScrolled)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+}
+
+/// 同じ波形の音源がある場合のバッジ
+fn duplicate_badge<'a>(
+    info: Option<&SourceInformation>,
+    param: &SourceParameter,
+) -> Element<'a, Message> {
+    match info {
+        Some(info) if !info.duplicates.is_empty() => tooltip(
+            text(if param.link_duplicates { "D*" } else { "D" })
+                .size(10.0)
+                .color(Color::from_rgb(0.6, 0.8, 1.0)),
+            text(format!(
+                "Same waveform as SRN {}{}",
+                info.duplicates
+                    .iter()
+                    .map(|srn| srn.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                if param.link_duplicates {
+                    " (linked)"
+                } else {
+                    ""
+                }
+            )),
+            tooltip::Position::Right,
+        )
+        .into(),
+        _ => space().into(),
+    }
+}
+
+/// ステレオペアの片方である場合のバッジ
+fn stereo_pair_badge<'a>(
+    info: Option<&SourceInformation>,
+    param: &SourceParameter,
+) -> Element<'a, Message> {
+    match info.and_then(|info| info.stereo_pair_partner) {
+        Some(partner) => tooltip(
+            text(if param.merge_stereo_pair { "ST*" } else { "ST" })
+                .size(10.0)
+                .color(Color::from_rgb(0.8, 0.6, 1.0)),
+            text(format!(
+                "Stereo pair with SRN {}{}",
+                partner,
+                if param.merge_stereo_pair {
+                    " (merged)"
+                } else {
+                    ""
+                }
+            )),
+            tooltip::Position::Right,
+        )
+        .into(),
+        None => space().into(),
+    }
+}
+
+/// センターノート推定の信頼度を色分けして表示
+fn pitch_confidence_badge<'a>(info: Option<&SourceInformation>) -> Element<'a, Message> {
+    match info {
+        Some(info) => tooltip(
+            text(format!("{:3.0}", info.pitch_confidence * 100.0))
+                .size(12.0)
+                .color(if info.pitch_confidence >= 0.6 {
+                    Color::from_rgb(0.5, 0.9, 0.5)
+                } else if info.pitch_confidence >= 0.3 {
+                    Color::from_rgb(0.9, 0.8, 0.4)
+                } else {
+                    Color::from_rgb(0.9, 0.4, 0.4)
+                }),
+            text(format!(
+                "Pitch estimation confidence: {:.0}%",
+                info.pitch_confidence * 100.0
+            )),
+            tooltip::Position::Left,
+        )
+        .into(),
+        None => space().into(),
+    }
 }
 
 fn menu_button<'a>(
@@ -131,6 +520,22 @@ impl SPC2MIDI2Window for MainWindow {
                         )
                         .width(Length::Fill)
                         .height(Length::Shrink)),
+                        (menu_button(
+                            text("Reopen (Ctrl+R)")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::ReopenFile,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
+                        (menu_button(
+                            text("Load Instrument Mapping...")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::LoadInstrumentMapping,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
                         (menu_button(
                             text("Save SMF...")
                                 .height(Length::Shrink)
@@ -139,6 +544,30 @@ impl SPC2MIDI2Window for MainWindow {
                         )
                         .width(Length::Fill)
                         .height(Length::Shrink)),
+                        (menu_button(
+                            text("Validate SMF...")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::ValidateSMF,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
+                        (menu_button(
+                            text("Play/Stop Exported SMF")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::SMFPlaybackToggled,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
+                        (menu_button(
+                            text("Show MIDI Note Summary")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::PrintMIDINoteSummary,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
                         (menu_button(
                             text("Save JSON...")
                                 .height(Length::Shrink)
@@ -147,8 +576,40 @@ impl SPC2MIDI2Window for MainWindow {
                         )
                         .width(Length::Fill)
                         .height(Length::Shrink)),
+                        (menu_button(
+                            text("Save WAV...")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::SaveWAV,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
+                        (menu_button(
+                            text("Save OGG with Loop Tags...")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::SaveOGG,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
+                        (menu_button(
+                            text("Save Analysis Report...")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::SaveReport,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
+                        (menu_button(
+                            text("Copy SPC to Output...")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::SaveSPC,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
                     ))
-                    .width(140.0)
+                    .width(200.0)
                 }
             ),
             (
@@ -178,6 +639,38 @@ impl SPC2MIDI2Window for MainWindow {
                         )
                         .width(Length::Fill)
                         .height(Length::Shrink)),
+                        (menu_button(
+                            text("Register Inspector...")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::OpenRegisterInspectorWindow,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
+                        (menu_button(
+                            text("Parameter Change Log...")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::OpenParameterLogWindow,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
+                        (menu_button(
+                            text("Auto-Balance Source Volumes")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::AutoBalanceVolumeRequested,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
+                        (menu_button(
+                            text("Estimate All Sources...")
+                                .height(Length::Shrink)
+                                .align_y(alignment::Vertical::Center),
+                            Message::EstimateAllSourcesRequested,
+                        )
+                        .width(Length::Fill)
+                        .height(Length::Shrink)),
                     ))
                     .width(240.0)
                 }
@@ -203,8 +696,21 @@ impl SPC2MIDI2Window for MainWindow {
 
         let params = self.source_params.read().unwrap();
         let infos = self.source_infos.read().unwrap();
+        let status = self.playback_status.read().unwrap();
+        let channel_mute_flags = self.channel_mute_flags.load(Ordering::Relaxed);
         // 音源リスト
         let mut srn_list = vec![];
+        // 解析で音源が見つからなかったときは理由の候補を表示
+        if self.no_source_detected {
+            srn_list.push(
+                text(
+                    "No sources were detected. The song may start after a long silence \
+                     (increase the Output Duration and re-analyze), or the file may be silent or broken.",
+                )
+                .color(self.theme.palette().warning)
+                .into(),
+            );
+        }
         for spc_ch in 0..8 {
             // spc_chで発音されているSRNを集める
             let mut srns = vec![];
@@ -236,20 +742,30 @@ impl SPC2MIDI2Window for MainWindow {
             if self.showing_channel_srn_list[spc_ch] {
                 for srn in srns {
                     let param = params.get(&srn).unwrap();
+                    // 1音源にまとめたステレオペアは残す側だけ表示
+                    if param.merge_stereo_pair
+                        && infos
+                            .get(&srn)
+                            .and_then(|info| info.stereo_pair_partner)
+                            .is_some_and(|partner| partner < srn)
+                    {
+                        continue;
+                    }
                     srn_list.push(
                         row![
-                            if let Some(info) = infos.get(&srn) {
-                                match *self.display_source_id_type.read().unwrap() {
-                                    DisplaySourceIDType::StartAddress => {
-                                        text(format!("{:04X}", info.start_address))
-                                    }
-                                    DisplaySourceIDType::SRN => text(format!("{}", srn)),
-                                }
-                            } else {
-                                text(format!(""))
-                            }
+                            row![
+                                self.source_id_with_audibility(
+                                    srn,
+                                    infos.get(&srn),
+                                    param,
+                                    status.source_audibility(srn, param, channel_mute_flags),
+                                    16.0,
+                                ),
+                                duplicate_badge(infos.get(&srn), param),
+                                stereo_pair_badge(infos.get(&srn), param),
+                            ]
                             .width(40)
-                            .align_x(alignment::Alignment::Start),
+                            .align_y(alignment::Alignment::Center),
                             pick_list(
                                 Program::ALL.to_vec(),
                                 Some(param.program.clone()),
@@ -282,6 +798,7 @@ impl SPC2MIDI2Window for MainWindow {
                                     .align_y(alignment::Alignment::Center),
                             ]
                             .width(Length::FillPortion(6)),
+                            row![pitch_confidence_badge(infos.get(&srn))].width(24),
                             stack![
                                 progress_bar(0.0..=127.0, param.noteon_velocity as f32).style(
                                     |theme: &Theme| progress_bar::Style {
@@ -343,6 +860,11 @@ impl SPC2MIDI2Window for MainWindow {
             text("C.Note")
                 .width(Length::FillPortion(6))
                 .align_x(alignment::Alignment::Start),
+            tooltip(
+                text("Cf.").width(24),
+                "Confidence of the center note estimation (%)",
+                tooltip::Position::Bottom,
+            ),
             text("Velocity")
                 .width(Length::FillPortion(6))
                 .align_x(alignment::Alignment::Start),
@@ -354,8 +876,6 @@ impl SPC2MIDI2Window for MainWindow {
         .width(Length::Fill)
         .align_y(alignment::Alignment::Center);
 
-        let status = self.playback_status.read().unwrap();
-        let channel_mute_flags = self.channel_mute_flags.load(Ordering::Relaxed);
         let expression_indicator = self.expression_indicator;
         let pitch_indicator = self.pitch_indicator;
         let volume_indicator = self.volume_indicator;
@@ -371,21 +891,15 @@ impl SPC2MIDI2Window for MainWindow {
                         .on_press(Message::SoloChannel(ch as u8))
                         .width(30),
                     text(format!("{}", if status.noteon[ch] { "♪" } else { "" }))
+                        .font(SYMBOL_FONT)
                         .align_y(alignment::Alignment::Center)
                         .height(Length::Fill)
                         .width(10),
-                    if let Some(info) = infos.get(&status.srn_no[ch]) {
-                        match *self.display_source_id_type.read().unwrap() {
-                            DisplaySourceIDType::StartAddress => {
-                                text(format!("{:04X}", info.start_address))
-                            }
-                            DisplaySourceIDType::SRN => {
-                                text(format!("{}", status.srn_no[ch]))
-                            }
-                        }
-                    } else {
-                        text(format!(""))
-                    }
+                    text(self.source_id_string(
+                        status.srn_no[ch],
+                        infos.get(&status.srn_no[ch]),
+                        params.get(&status.srn_no[ch]),
+                    ))
                     .align_y(alignment::Alignment::Center)
                     .height(Length::Fill)
                     .size(14.0)
@@ -488,18 +1002,30 @@ impl SPC2MIDI2Window for MainWindow {
             text("CH")
                 .width(Length::FillPortion(2))
                 .align_x(alignment::Alignment::Start),
-            text("Pitch")
-                .width(Length::FillPortion(6))
-                .align_x(alignment::Alignment::Start),
-            text("Env.")
-                .width(Length::FillPortion(5))
-                .align_x(alignment::Alignment::Start),
-            text("Lvol")
-                .width(Length::FillPortion(4))
-                .align_x(alignment::Alignment::Start),
-            text("Rvol")
-                .width(Length::FillPortion(4))
-                .align_x(alignment::Alignment::Start),
+            indicator_header(
+                "Pitch",
+                "Playback pitch relative to the original pitch of the sample in semitones",
+                &self.pitch_indicator[0],
+                6
+            ),
+            indicator_header(
+                "Env.",
+                "Envelope (ADSR/GAIN) level of the voice",
+                &self.expression_indicator[0],
+                5
+            ),
+            indicator_header(
+                "Lvol",
+                "Left channel volume register (negative values invert the phase)",
+                &self.volume_indicator[0][0],
+                4
+            ),
+            indicator_header(
+                "Rvol",
+                "Right channel volume register (negative values invert the phase)",
+                &self.volume_indicator[0][1],
+                4
+            ),
         ]
         .spacing(10)
         .width(Length::Fill)
@@ -523,9 +1049,27 @@ impl SPC2MIDI2Window for MainWindow {
             checkbox(self.midi_spc_on.clone().load(Ordering::Relaxed))
                 .label("MIDI")
                 .on_toggle(|flag| Message::MIDIMuteFlagToggled(flag)),
+            tooltip(
+                Canvas::new(self.pcm_level_indicator).width(80).height(16),
+                "SPC output peak level (dBFS)",
+                tooltip::Position::Top,
+            ),
+            tooltip(
+                Canvas::new(self.midi_level_indicator).width(80).height(16),
+                "MIDI output level estimated from velocity, volume and expression (dB, GM curve)",
+                tooltip::Position::Top,
+            ),
             text(format!("{:8.02}sec", self.playback_time_sec))
                 .width(90)
                 .align_x(alignment::Alignment::End),
+            tooltip(
+                Canvas::new(self.beat_indicator).width(80).height(16),
+                text(format!(
+                    "Beats at the configured tempo ({:.2} BPM). If they drift from the audible beat, re-estimate or halve/double the BPM",
+                    self.beat_indicator.beats_per_minute
+                )),
+                tooltip::Position::Top,
+            ),
             text(format!("{:8.02}kbps", self.midi_bit_rate / 1000.0))
                 .color(if self.midi_bit_rate > 31_500.0 {
                     self.theme.palette().warning
@@ -534,27 +1078,103 @@ impl SPC2MIDI2Window for MainWindow {
                 })
                 .width(90)
                 .align_x(alignment::Alignment::End),
+            tooltip(
+                text("●").color(match self.audio_health {
+                    AudioHealth::Good => Color::from_rgb(0.5, 0.9, 0.5),
+                    AudioHealth::Degraded => Color::from_rgb(0.9, 0.8, 0.4),
+                    AudioHealth::Poor => Color::from_rgb(0.9, 0.4, 0.4),
+                }),
+                text(format!(
+                    "Audio underruns in the last 10 sec: {}. If this is not green, increase the audio output latency in the device settings",
+                    self.recent_underruns
+                )),
+                tooltip::Position::Top,
+            ),
         ]
         .spacing(10)
         .width(Length::Fill)
         .align_y(alignment::Alignment::Center);
 
-        let r = row![menu_bar, space::horizontal().width(Length::Fill),]
-            .align_y(alignment::Alignment::Center);
+        // バックグラウンド処理中は進捗とキャンセルボタンを表示
+        let r = if let Some(progress) = self.operation_progress {
+            row![
+                menu_bar,
+                space::horizontal().width(Length::Fill),
+                text("Processing...").size(14.0),
+                stack![progress_bar(0.0..=1.0, progress)]
+                    .width(100)
+                    .height(14),
+                button("Cancel").on_press(Message::BackgroundOperationCancelRequested),
+            ]
+            .spacing(10)
+            .align_y(alignment::Alignment::Center)
+        } else {
+            row![
+                menu_bar,
+                space::horizontal().width(Length::Fill),
+                tooltip(
+                    button(match self.layout {
+                        MainWindowLayout::Table => "Mixer",
+                        MainWindowLayout::Mixer => "Grid",
+                        MainWindowLayout::Grid => "Table",
+                    })
+                    .on_press(Message::MainWindowLayoutToggled),
+                    "Switch between the source table, the channel mixer and the thumbnail grid",
+                    tooltip::Position::Bottom,
+                ),
+                tooltip(
+                    number_input(&self.add_srn_no, 0..=255, |srn_no| {
+                        Message::AddSourceNumberChanged(srn_no)
+                    })
+                    .width(60),
+                    "Source number (SRN) not detected by analysis",
+                    tooltip::Position::Bottom,
+                ),
+                button("Add Source").on_press(Message::AddSourceRequested(self.add_srn_no)),
+            ]
+            .spacing(10)
+            .align_y(alignment::Alignment::Center)
+        };
 
-        let c = column![
-            r,
-            srn_index,
-            scrollable(
-                Column::from_vec(srn_list)
-                    .width(Length::Fill)
-                    .height(Length::Fill)
+        // 曲全体の波形（描画が終わるまでは表示しない）
+        let song_overview: Element<'_, Message> = if let Some(overview) = &self.song_overview {
+            tooltip(
+                Canvas::new(SongOverviewView {
+                    overview: overview.clone(),
+                    playback_time_sec: self.playback_time_sec,
+                })
+                .width(Length::Fill)
+                .height(40),
+                "Click to seek. The shaded region is the detected loop",
+                tooltip::Position::Top,
             )
-            .width(Length::Fill)
-            .height(Length::Fill),
-            Column::from_vec(status_list).width(Length::Fill),
-            preview_control,
-        ];
+            .into()
+        } else {
+            Column::new().into()
+        };
+
+        let c = match self.layout {
+            MainWindowLayout::Table => column![
+                r,
+                srn_index,
+                scrollable(
+                    Column::from_vec(srn_list)
+                        .width(Length::Fill)
+                        .height(Length::Fill)
+                )
+                .width(Length::Fill)
+                .height(Length::Fill),
+                Column::from_vec(status_list).width(Length::Fill),
+                song_overview,
+                preview_control,
+            ],
+            MainWindowLayout::Mixer => {
+                column![r, self.mixer_view(), song_overview, preview_control]
+            }
+            MainWindowLayout::Grid => {
+                column![r, self.grid_view(), song_overview, preview_control]
+            }
+        };
 
         c.into()
     }
@@ -564,11 +1184,25 @@ impl Indicator {
     fn new(init_value: f32, min_value: f32, max_value: f32, formatter: fn(f32) -> String) -> Self {
         Self {
             value: init_value,
+            smoothed_value: init_value,
             min: min_value,
             max: max_value,
             formatter: formatter,
+            scale: DisplayScale::default(),
         }
     }
+
+    /// 値を更新し、メーターのバリスティクスで表示値を追従させる
+    /// 振れが大きくなる方向（アタック）は速く、小さくなる方向（ディケイ）はゆっくり追従する
+    pub fn set_value(&mut self, value: f32, smoothing: f32) {
+        let coef = if value.abs() > self.smoothed_value.abs() {
+            smoothing * 0.5
+        } else {
+            smoothing
+        };
+        self.value = value;
+        self.smoothed_value = value + coef * (self.smoothed_value - value);
+    }
 }
 
 impl canvas::Program<Message> for Indicator {
@@ -588,10 +1222,43 @@ impl canvas::Program<Message> for Indicator {
             theme,
             &mut frame,
             &Rectangle::new(Point::new(0.0, 0.0), Size::new(bounds.width, bounds.height)),
-            self.value,
+            self.smoothed_value,
             self.min,
             self.max,
             self.formatter,
+            self.scale,
+        );
+        vec![frame.into_geometry()]
+    }
+
+    fn update(
+        &self,
+        _state: &mut Self::State,
+        _event: &Event,
+        _bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Option<iced_widget::Action<Message>> {
+        None
+    }
+}
+
+impl canvas::Program<Message> for BeatIndicator {
+    type State = Option<()>;
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &Renderer,
+        theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        draw_beat_indicator(
+            theme,
+            &mut frame,
+            &Rectangle::new(Point::new(0.0, 0.0), Size::new(bounds.width, bounds.height)),
+            self,
         );
         vec![frame.into_geometry()]
     }
@@ -607,7 +1274,224 @@ impl canvas::Program<Message> for Indicator {
     }
 }
 
+impl canvas::Program<Message> for SongOverviewView {
+    type State = Option<()>;
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &Renderer,
+        theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        draw_song_overview(
+            theme,
+            &mut frame,
+            &Rectangle::new(Point::new(0.0, 0.0), Size::new(bounds.width, bounds.height)),
+            self,
+        );
+        vec![frame.into_geometry()]
+    }
+
+    fn update(
+        &self,
+        _state: &mut Self::State,
+        event: &Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> Option<iced_widget::Action<Message>> {
+        // クリックした位置の時刻にシーク
+        if let Some(position) = cursor.position_in(bounds) {
+            if let Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) = event {
+                let ratio = (position.x / bounds.width).clamp(0.0, 1.0);
+                return Some(iced_widget::Action::publish(Message::SongSeekRequested(
+                    ratio * self.overview.duration_sec,
+                )));
+            }
+        }
+        None
+    }
+}
+
+/// 曲全体の波形描画（ループ区間を塗り、再生位置に縦線を引く）
+fn draw_song_overview(
+    theme: &Theme,
+    frame: &mut Frame,
+    bounds: &Rectangle,
+    view: &SongOverviewView,
+) {
+    let overview = &view.overview;
+    let center_y = bounds.center().y;
+    let half_height = bounds.height / 2.0;
+    let time_to_x = |time_sec: f32| {
+        bounds.x
+            + bounds.width * (time_sec / overview.duration_sec.max(f32::EPSILON)).clamp(0.0, 1.0)
+    };
+
+    frame.fill_rectangle(
+        Point::new(bounds.x, bounds.y),
+        Size::new(bounds.width, bounds.height),
+        Color::from_rgb8(0, 0, 0),
+    );
+
+    // ループ区間
+    if let Some((loop_start, loop_end)) = overview.loop_region {
+        let start_x = time_to_x(loop_start);
+        frame.fill_rectangle(
+            Point::new(start_x, bounds.y),
+            Size::new(time_to_x(loop_end) - start_x, bounds.height),
+            Color {
+                a: 0.3,
+                ..theme.palette().primary
+            },
+        );
+    }
+
+    // 区間ごとの最小値・最大値をつなぐ矩形
+    const MIN_HEIGHT: f32 = 0.5;
+    let peak_width = bounds.width / overview.peaks.len().max(1) as f32;
+    for (i, (min_val, max_val)) in overview.peaks.iter().enumerate() {
+        frame.fill_rectangle(
+            Point::new(
+                bounds.x + i as f32 * peak_width,
+                center_y - max_val * half_height,
+            ),
+            Size::new(
+                peak_width.max(1.0),
+                ((max_val - min_val) * half_height).max(MIN_HEIGHT),
+            ),
+            Color::from_rgb8(0, 196, 0),
+        );
+    }
+
+    // 再生位置
+    frame.fill_rectangle(
+        Point::new(time_to_x(view.playback_time_sec), bounds.y),
+        Size::new(1.5, bounds.height),
+        theme.palette().danger,
+    );
+}
+
+impl canvas::Program<Message> for SourceThumbnail {
+    type State = Option<()>;
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &Renderer,
+        theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        draw_source_thumbnail(
+            theme,
+            &mut frame,
+            &Rectangle::new(Point::new(0.0, 0.0), Size::new(bounds.width, bounds.height)),
+            &self.peaks,
+        );
+        vec![frame.into_geometry()]
+    }
+}
+
+/// 音源波形のサムネイル描画（区間ごとの最小値・最大値を縦線で結ぶ）
+fn draw_source_thumbnail(
+    theme: &Theme,
+    frame: &mut Frame,
+    bounds: &Rectangle,
+    peaks: &[(f32, f32)],
+) {
+    let center_y = bounds.center().y;
+    let half_height = bounds.height / 2.0;
+
+    frame.fill_rectangle(
+        Point::new(bounds.x, bounds.y),
+        Size::new(bounds.width, bounds.height),
+        Color::from_rgb8(0, 0, 0),
+    );
+
+    const MIN_HEIGHT: f32 = 0.5;
+    let peak_width = bounds.width / peaks.len().max(1) as f32;
+    for (i, (min_val, max_val)) in peaks.iter().enumerate() {
+        frame.fill_rectangle(
+            Point::new(
+                bounds.x + i as f32 * peak_width,
+                center_y - max_val.clamp(-1.0, 1.0) * half_height,
+            ),
+            Size::new(
+                peak_width.max(1.0),
+                ((max_val - min_val).min(2.0) * half_height).max(MIN_HEIGHT),
+            ),
+            theme.palette().success,
+        );
+    }
+}
+
+/// 拍インジケータ描画（小節内の拍を並べ、現在の拍を拍頭で光らせて減衰させる）
+fn draw_beat_indicator(
+    theme: &Theme,
+    frame: &mut Frame,
+    bounds: &Rectangle,
+    indicator: &BeatIndicator,
+) {
+    const CELL_SPACING: f32 = 2.0;
+    let beats = indicator.playback_time_sec * indicator.beats_per_minute / 60.0;
+    let beats_per_bar = indicator.beats_per_bar.max(1);
+    let current_beat = (beats.floor() as u32) % beats_per_bar;
+    // 拍頭で最大、次の拍に向けて減衰
+    let flash = 1.0 - beats.fract();
+    let cell_width =
+        (bounds.width - CELL_SPACING * (beats_per_bar - 1) as f32) / beats_per_bar as f32;
+
+    for beat in 0..beats_per_bar {
+        let position = Point::new(
+            bounds.x + beat as f32 * (cell_width + CELL_SPACING),
+            bounds.y,
+        );
+        let size = Size::new(cell_width, bounds.height);
+        frame.fill_rectangle(
+            position,
+            size,
+            theme.extended_palette().background.strong.color,
+        );
+        if indicator.playback_time_sec > 0.0 && beat == current_beat {
+            // 小節の頭の拍は色を変える
+            let color = if beat == 0 {
+                theme.palette().warning
+            } else {
+                theme.palette().success
+            };
+            frame.fill_rectangle(position, size, Color { a: flash, ..color });
+        }
+    }
+}
+
+/// インジケータの見出し（意味と表示範囲をツールチップで示す）
+fn indicator_header<'a>(
+    label: &'a str,
+    description: &str,
+    indicator: &Indicator,
+    width_portion: u16,
+) -> Element<'a, Message> {
+    tooltip(
+        text(label)
+            .width(Length::FillPortion(width_portion))
+            .align_x(alignment::Alignment::Start),
+        text(format!(
+            "{}\nRange: {} to {} (the green bar fills from the minimum)",
+            description,
+            (indicator.formatter)(indicator.min).trim(),
+            (indicator.formatter)(indicator.max).trim()
+        )),
+        tooltip::Position::Top,
+    )
+    .into()
+}
+
 /// インジケータ描画
+#[allow(clippy::too_many_arguments)]
 fn draw_indicator(
     theme: &Theme,
     frame: &mut Frame,
@@ -616,6 +1500,7 @@ fn draw_indicator(
     min: f32,
     max: f32,
     formatter: fn(f32) -> String,
+    scale: DisplayScale,
 ) {
     let center = bounds.center();
 
@@ -636,7 +1521,7 @@ fn draw_indicator(
 
     frame.fill_text(canvas::Text {
         content: formatter(value),
-        size: iced::Pixels(16.0),
+        size: iced::Pixels(scale.font_size(16.0)),
         position: center,
         color: theme.palette().text,
         align_x: alignment::Horizontal::Center.into(),
@@ -10,7 +10,12 @@ pub struct MIDIOutputConfigurationWindow {
     ticks_per_quarter_box: combo_box::State<u16>,
     volume_curve_box: combo_box::State<VolumeCurve>,
     midi_system_box: combo_box::State<MIDISystem>,
+    drum_kit_box: combo_box::State<DrumKit>,
+    wav_sample_rate_box: combo_box::State<WAVSampleRate>,
+    wav_resample_quality_box: combo_box::State<WAVResampleQuality>,
     midi_output_configure: Arc<RwLock<MIDIOutputConfigure>>,
+    /// 上級者向けの設定を表示するか
+    pub show_advanced_options: bool,
 }
 
 impl VolumeCurve {
@@ -27,6 +32,34 @@ impl std::fmt::Display for VolumeCurve {
     }
 }
 
+impl WAVSampleRate {
+    pub const ALL: [WAVSampleRate; 3] = [Self::Native, Self::Hz44100, Self::Hz48000];
+}
+
+impl std::fmt::Display for WAVSampleRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Native => "32000 Hz (Native)",
+            Self::Hz44100 => "44100 Hz",
+            Self::Hz48000 => "48000 Hz",
+        })
+    }
+}
+
+impl WAVResampleQuality {
+    pub const ALL: [WAVResampleQuality; 3] = [Self::Fast, Self::Good, Self::Best];
+}
+
+impl std::fmt::Display for WAVResampleQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Fast => "Fast",
+            Self::Good => "Good",
+            Self::Best => "Best",
+        })
+    }
+}
+
 impl MIDISystem {
     pub const ALL: [MIDISystem; 5] = [Self::NONE, Self::GMLevel1, Self::GMLevel2, Self::GS, Self::XG];
 }
@@ -43,6 +76,34 @@ impl std::fmt::Display for MIDISystem {
     }
 }
 
+impl DrumKit {
+    pub const ALL: [DrumKit; 8] = [
+        Self::Standard,
+        Self::Room,
+        Self::Power,
+        Self::Electronic,
+        Self::TR808,
+        Self::Jazz,
+        Self::Brush,
+        Self::Orchestra,
+    ];
+}
+
+impl std::fmt::Display for DrumKit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Standard => "Standard",
+            Self::Room => "Room",
+            Self::Power => "Power",
+            Self::Electronic => "Electronic",
+            Self::TR808 => "TR-808",
+            Self::Jazz => "Jazz",
+            Self::Brush => "Brush",
+            Self::Orchestra => "Orchestra",
+        })
+    }
+}
+
 impl SPC2MIDI2Window for MIDIOutputConfigurationWindow {
     fn title(&self) -> String {
         "MIDI Output Configuration".to_string()
@@ -50,7 +111,7 @@ impl SPC2MIDI2Window for MIDIOutputConfigurationWindow {
 
     fn view(&self) -> Element<'_, Message> {
         let midi_output_configure = self.midi_output_configure.read().unwrap();
-        let content = column![
+        let mut content = column![
             row![
                 text("Tempo (BPM)"),
                 number_input(
@@ -122,7 +183,7 @@ impl SPC2MIDI2Window for MIDIOutputConfigurationWindow {
                 text("Song Duration (msec)"),
                 number_input(
                     &midi_output_configure.output_duration_msec,
-                    1000..=(3600 * 1000),
+                    1000..=MAX_OUTPUT_DURATION_MSEC,
                     move |duration| { Message::MIDIOutputDurationChanged(duration) },
                 )
                 .step(100),
@@ -145,6 +206,19 @@ impl SPC2MIDI2Window for MIDIOutputConfigurationWindow {
             .padding(10)
             .align_y(alignment::Alignment::Center)
             .width(Length::Fill),
+            row![
+                text("Drum Kit (GM2/GS/XG)"),
+                combo_box(
+                    &self.drum_kit_box,
+                    "Drum Kit",
+                    Some(&midi_output_configure.drum_kit),
+                    move |kit| { Message::DrumKitChanged(kit) },
+                ),
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
             row![
                 text("SPC700 Clock-Up Factor"),
                 number_input(
@@ -177,11 +251,325 @@ impl SPC2MIDI2Window for MIDIOutputConfigurationWindow {
             .padding(10)
             .align_y(alignment::Alignment::Center)
             .width(Length::Fill),
+            row![
+                tooltip(
+                    text("Insert Source Markers"),
+                    "Add a marker with the SRN and label/program at the first note of each source",
+                    tooltip::Position::Top,
+                ),
+                checkbox(midi_output_configure.insert_source_markers)
+                    .on_toggle(move |flag| Message::MIDIOutputSourceMarkersFlagToggled(flag))
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("Embed SPC Metadata"),
+                    "Add the song title, game, artist and tool version as text events at the start of the SMF",
+                    tooltip::Position::Top,
+                ),
+                checkbox(midi_output_configure.embed_spc_metadata)
+                    .on_toggle(move |flag| Message::MIDIOutputEmbedSPCMetadataFlagToggled(flag))
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("Embed SPC Comment as Lyric"),
+                    "Add the comment of the SPC tag (ID666) as a lyric event at the start of the SMF. Non-ASCII characters are replaced with '?'",
+                    tooltip::Position::Top,
+                ),
+                checkbox(midi_output_configure.embed_spc_comment_as_lyric)
+                    .on_toggle(move |flag| Message::MIDIOutputEmbedSPCCommentFlagToggled(flag))
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("Insert Click Track"),
+                    "Add a click on every beat of the BPM on an unused channel (drum channel if free) to align the SMF in a DAW",
+                    tooltip::Position::Top,
+                ),
+                checkbox(midi_output_configure.insert_click_track)
+                    .on_toggle(move |flag| Message::MIDIOutputClickTrackFlagToggled(flag))
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("Minimum Note Duration (ms)"),
+                    "Drop notes shorter than this in the exported SMF (0: no filtering)",
+                    tooltip::Position::Top,
+                ),
+                number_input(
+                    &midi_output_configure.min_note_duration_msec,
+                    0..=1000,
+                    move |msec| { Message::MIDIOutputMinNoteDurationChanged(msec) },
+                )
+                .step(5),
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("Max Polyphony per Channel"),
+                    "Steal the oldest note when a note-on exceeds this many notes on a channel in the exported SMF (0: unlimited)",
+                    tooltip::Position::Top,
+                ),
+                number_input(
+                    &midi_output_configure.max_polyphony_per_channel,
+                    0..=MAX_POLYPHONY_PER_CHANNEL,
+                    move |polyphony| { Message::MIDIOutputMaxPolyphonyChanged(polyphony) },
+                ),
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("Note Summary Duration (ms)"),
+                    "Length of the song emulated by \"Show MIDI Note Summary\"",
+                    tooltip::Position::Top,
+                ),
+                number_input(
+                    &midi_output_configure.note_summary_duration_msec,
+                    1000..=600000,
+                    move |msec| { Message::NoteSummaryDurationChanged(msec) },
+                )
+                .step(1000),
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("Bypass SPC Echo in WAV"),
+                    "Render WAV without the SPC echo (dry)",
+                    tooltip::Position::Top,
+                ),
+                checkbox(midi_output_configure.wav_bypass_echo)
+                    .on_toggle(move |flag| Message::WAVBypassEchoChanged(flag))
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("Soft Limiter in WAV"),
+                    "Softly compress peaks of the rendered WAV instead of hard clipping",
+                    tooltip::Position::Top,
+                ),
+                checkbox(midi_output_configure.wav_soft_limiter)
+                    .on_toggle(move |flag| Message::WAVSoftLimiterFlagToggled(flag))
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("Flatten Velocity"),
+                    "Output all notes with this velocity regardless of per-source settings",
+                    tooltip::Position::Top,
+                ),
+                checkbox(midi_output_configure.flatten_velocity)
+                    .on_toggle(move |flag| Message::FlattenVelocityFlagToggled(flag)),
+                number_input(
+                    &midi_output_configure.flat_velocity,
+                    if midi_output_configure.flatten_velocity {
+                        1..=127
+                    } else {
+                        midi_output_configure.flat_velocity..=midi_output_configure.flat_velocity
+                    },
+                    move |velocity| { Message::FlatVelocityChanged(velocity) },
+                )
+                .step(1),
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("Calibrate Velocity by Program"),
+                    "Scale note-on velocities in SMF export by the per-program table in the device settings to balance loudness across instruments (ignored when velocity is flattened)",
+                    tooltip::Position::Top,
+                ),
+                checkbox(midi_output_configure.calibrate_velocity_by_program)
+                    .on_toggle(move |flag| Message::CalibrateVelocityByProgramFlagToggled(flag)),
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("WAV Stereo Width (%)"),
+                    "0: mono, 100: original, 200: wide (mid/side processing)",
+                    tooltip::Position::Top,
+                ),
+                number_input(
+                    &midi_output_configure.wav_stereo_width_percent,
+                    0..=MAX_WAV_STEREO_WIDTH_PERCENT,
+                    move |width| { Message::WAVStereoWidthChanged(width) },
+                )
+                .step(10),
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("WAV Sample Rate"),
+                    "Render at the SPC rate (32kHz) and convert to this rate",
+                    tooltip::Position::Top,
+                ),
+                combo_box(
+                    &self.wav_sample_rate_box,
+                    "WAV Sample Rate",
+                    Some(&midi_output_configure.wav_sample_rate),
+                    move |rate| { Message::WAVSampleRateChanged(rate) },
+                ),
+                text("Quality"),
+                combo_box(
+                    &self.wav_resample_quality_box,
+                    "Quality",
+                    Some(&midi_output_configure.wav_resample_quality),
+                    move |quality| { Message::WAVResampleQualityChanged(quality) },
+                ),
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+            row![
+                tooltip(
+                    text("Target Synth Max Pitch Bend Width"),
+                    "Pitch bend range (semitone) supported by the receiving synth",
+                    tooltip::Position::Top,
+                ),
+                number_input(
+                    &midi_output_configure.target_pitch_bend_width,
+                    1..=MAX_PITCH_BEND_WIDTH,
+                    move |width| { Message::TargetPitchBendWidthChanged(width) },
+                )
+                .step(1),
+                checkbox(midi_output_configure.clamp_pitch_bend_width)
+                    .label("Clamp")
+                    .on_toggle(move |flag| Message::ClampPitchBendWidthFlagToggled(flag)),
+            ]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
         ]
         .spacing(10)
         .padding(10)
         .width(Length::Fill)
         .align_x(alignment::Alignment::Center);
+
+        // 上級者向けの設定
+        content = content.push(
+            row![checkbox(self.show_advanced_options)
+                .label("Show Advanced Options")
+                .on_toggle(move |flag| Message::AdvancedOptionsToggled(flag))]
+            .spacing(10)
+            .padding(10)
+            .align_y(alignment::Alignment::Center)
+            .width(Length::Fill),
+        );
+        if self.show_advanced_options {
+            content = content.push(
+                row![
+                    tooltip(
+                        text("Split SMF per Loop"),
+                        "Save <name>_intro.mid, <name>_loop1.mid, ... split at the detected song loop",
+                        tooltip::Position::Top,
+                    ),
+                    checkbox(midi_output_configure.split_smf_per_loop)
+                        .on_toggle(move |flag| Message::SplitSMFPerLoopFlagToggled(flag))
+                ]
+                .spacing(10)
+                .padding(10)
+                .align_y(alignment::Alignment::Center)
+                .width(Length::Fill),
+            );
+            content = content.push(
+                row![
+                    tooltip(
+                        text("Route Percussive Notes to Drums"),
+                        "Send key-ons with noise or a sharp attack and fast decay of tonal sources to the drum channel (heuristic)",
+                        tooltip::Position::Top,
+                    ),
+                    checkbox(midi_output_configure.route_percussive_notes)
+                        .on_toggle(move |flag| Message::RoutePercussiveNotesFlagToggled(flag))
+                ]
+                .spacing(10)
+                .padding(10)
+                .align_y(alignment::Alignment::Center)
+                .width(Length::Fill),
+            );
+            content = content.push(
+                row![
+                    tooltip(
+                        text("Minimum Source Key-On (ms)"),
+                        "Ignore sources whose key-ons never last this long during analysis (0: no gate, applied on reanalysis)",
+                        tooltip::Position::Top,
+                    ),
+                    number_input(
+                        &midi_output_configure.min_source_keyon_msec,
+                        0..=1000,
+                        move |msec| { Message::MinSourceKeyOnDurationChanged(msec) },
+                    )
+                    .step(5),
+                ]
+                .spacing(10)
+                .padding(10)
+                .align_y(alignment::Alignment::Center)
+                .width(Length::Fill),
+            );
+            let excluded_voices = midi_output_configure.excluded_voices;
+            content = content.push(
+                row![
+                    tooltip(
+                        text("Excluded Voices"),
+                        "DSP voices ignored by source analysis and never output as MIDI (e.g. sound effect channels)",
+                        tooltip::Position::Top,
+                    ),
+                    Row::from_vec(
+                        (0..8)
+                            .map(|ch| {
+                                checkbox((excluded_voices >> ch) & 1 != 0)
+                                    .label(format!("{}", ch))
+                                    .on_toggle(move |flag| {
+                                        Message::ExcludedVoiceFlagToggled(ch as u8, flag)
+                                    })
+                                    .into()
+                            })
+                            .collect()
+                    )
+                    .spacing(8),
+                ]
+                .spacing(10)
+                .padding(10)
+                .align_y(alignment::Alignment::Center)
+                .width(Length::Fill),
+            );
+        }
         content.into()
     }
 }
@@ -195,6 +583,10 @@ impl MIDIOutputConfigurationWindow {
             ]),
             volume_curve_box: combo_box::State::new(VolumeCurve::ALL.to_vec()),
             midi_system_box: combo_box::State::new(MIDISystem::ALL.to_vec()),
+            drum_kit_box: combo_box::State::new(DrumKit::ALL.to_vec()),
+            wav_sample_rate_box: combo_box::State::new(WAVSampleRate::ALL.to_vec()),
+            wav_resample_quality_box: combo_box::State::new(WAVResampleQuality::ALL.to_vec()),
+            show_advanced_options: false,
         }
     }
 }
@@ -0,0 +1,101 @@
+use crate::types::*;
+use crate::Message;
+use iced::widget::{button, checkbox, column, row, scrollable, text, Column};
+use iced::{alignment, window, Color, Element, Font, Length};
+
+/// 読み込んだ設定と現在の設定の差分1項目
+#[derive(Debug, Clone)]
+pub struct ParameterDifference {
+    /// 差分のある音源番号（NoneはMIDI出力設定）
+    pub srn_no: Option<u8>,
+    /// フィールド名（空の場合は現在存在しない音源の追加）
+    pub field: String,
+    /// 現在の値
+    pub current: Option<serde_json::Value>,
+    /// 読み込んだ値
+    pub loaded: serde_json::Value,
+    /// 適用するか
+    pub accepted: bool,
+}
+
+#[derive(Debug)]
+pub struct ParameterDiffWindow {
+    window_id: window::Id,
+    /// 差分一覧
+    pub differences: Vec<ParameterDifference>,
+}
+
+impl SPC2MIDI2Window for ParameterDiffWindow {
+    fn title(&self) -> String {
+        "Compare Loaded Parameters".to_string()
+    }
+
+    fn view(&self) -> Element<'_, Message> {
+        let window_id = self.window_id;
+        let mut list = Column::new().spacing(4);
+        for (index, difference) in self.differences.iter().enumerate() {
+            let target = match difference.srn_no {
+                Some(srn_no) => format!("SRN 0x{:02X}", srn_no),
+                None => "Output Config".to_string(),
+            };
+            let field = if difference.field.is_empty() {
+                "(new source)".to_string()
+            } else {
+                difference.field.clone()
+            };
+            let current = match &difference.current {
+                Some(value) => value.to_string(),
+                None => "-".to_string(),
+            };
+            list = list.push(
+                row![
+                    checkbox(difference.accepted).on_toggle(move |flag| {
+                        Message::ParameterDiffEntryToggled(window_id, index, flag)
+                    }),
+                    text(target).width(110),
+                    text(field).width(220),
+                    text(current)
+                        .font(Font::MONOSPACE)
+                        .color(Color::from_rgb(0.8, 0.5, 0.5)),
+                    text("→"),
+                    text(difference.loaded.to_string())
+                        .font(Font::MONOSPACE)
+                        .color(Color::from_rgb(0.5, 0.8, 0.5)),
+                ]
+                .spacing(10)
+                .align_y(alignment::Alignment::Center),
+            );
+        }
+        if self.differences.is_empty() {
+            list = list.push(text("No differences from the current parameters"));
+        }
+
+        column![
+            text(format!(
+                "{} difference(s) found. Select the fields to apply.",
+                self.differences.len()
+            )),
+            scrollable(list).height(Length::Fill).width(Length::Fill),
+            row![
+                button("Apply All").on_press(Message::ParameterDiffApplied(window_id, true)),
+                button("Apply Selected").on_press(Message::ParameterDiffApplied(window_id, false)),
+                button("Cancel").on_press(Message::ParameterDiffCanceled(window_id)),
+            ]
+            .spacing(10)
+            .align_y(alignment::Alignment::Center),
+        ]
+        .spacing(10)
+        .padding(10)
+        .width(Length::Fill)
+        .into()
+    }
+}
+
+impl ParameterDiffWindow {
+    pub fn new(window_id: window::Id, differences: Vec<ParameterDifference>) -> Self {
+        Self {
+            window_id: window_id,
+            differences: differences,
+        }
+    }
+}
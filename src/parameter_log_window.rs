@@ -0,0 +1,89 @@
+use crate::types::*;
+use crate::Message;
+use iced::widget::{button, column, row, scrollable, text, Column};
+use iced::{alignment, Color, Element, Font, Length};
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+
+/// 記録するパラメータ変更の最大数（超えた分は古いものから捨てる）
+pub const MAX_PARAMETER_LOG_ENTRIES: usize = 500;
+
+/// 音源パラメータの変更1項目
+#[derive(Debug, Clone)]
+pub struct ParameterLogEntry {
+    /// 変更した音源番号
+    pub srn_no: u8,
+    /// フィールド名
+    pub field: String,
+    /// 変更前の値
+    pub old: serde_json::Value,
+    /// 変更後の値
+    pub new: serde_json::Value,
+    /// DSPに適用済みか
+    pub applied: bool,
+}
+
+#[derive(Debug)]
+pub struct ParameterLogWindow {
+    /// パラメータ変更の記録（古い順）
+    log: Arc<RwLock<VecDeque<ParameterLogEntry>>>,
+}
+
+impl SPC2MIDI2Window for ParameterLogWindow {
+    fn title(&self) -> String {
+        "Parameter Change Log".to_string()
+    }
+
+    fn view(&self) -> Element<'_, Message> {
+        let log = self.log.read().unwrap();
+        // 新しい変更を上に表示
+        let mut list = Column::new().spacing(4);
+        for entry in log.iter().rev() {
+            list = list.push(
+                row![
+                    text(format!("SRN 0x{:02X}", entry.srn_no)).width(80),
+                    text(entry.field.clone()).width(220),
+                    text(entry.old.to_string())
+                        .font(Font::MONOSPACE)
+                        .color(Color::from_rgb(0.8, 0.5, 0.5)),
+                    text("→"),
+                    text(entry.new.to_string())
+                        .font(Font::MONOSPACE)
+                        .color(Color::from_rgb(0.5, 0.8, 0.5)),
+                    if entry.applied {
+                        text("")
+                    } else {
+                        text("(not applied to DSP)").color(Color::from_rgb(1.0, 0.8, 0.0))
+                    },
+                ]
+                .spacing(10)
+                .align_y(alignment::Alignment::Center),
+            );
+        }
+        if log.is_empty() {
+            list = list.push(text("No parameter changes yet"));
+        }
+
+        column![
+            text(format!(
+                "{} change(s) recorded (up to {})",
+                log.len(),
+                MAX_PARAMETER_LOG_ENTRIES
+            )),
+            scrollable(list).height(Length::Fill).width(Length::Fill),
+            row![button("Clear").on_press(Message::ParameterLogCleared)]
+                .spacing(10)
+                .align_y(alignment::Alignment::Center),
+        ]
+        .spacing(10)
+        .padding(10)
+        .width(Length::Fill)
+        .into()
+    }
+}
+
+impl ParameterLogWindow {
+    pub fn new(log: Arc<RwLock<VecDeque<ParameterLogEntry>>>) -> Self {
+        Self { log: log }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::types::*;
+use crate::Message;
+use iced::widget::{button, column, row, scrollable, text, tooltip, Column, Row};
+use iced::{alignment, window, Color, Element, Font, Length};
+use iced_aw::number_input;
+use spc700::types::*;
+
+/// DSPレジスタ数
+pub const DSP_REGISTER_SIZE: usize = 128;
+/// RAM表示の1ページのサイズ
+const RAM_PAGE_SIZE: usize = 256;
+/// ポーク機能を有効にするか（デバッグビルドのみ）
+const ENABLE_POKE: bool = cfg!(debug_assertions);
+/// ボイスレジスタ名（下位ニブル0x0-0x9）
+const VOICE_REGISTER_NAMES: [&'static str; 10] = [
+    "VOLL", "VOLR", "PITCHL", "PITCHH", "SRCN", "ADSR1", "ADSR2", "GAIN", "ENVX", "OUTX",
+];
+
+#[derive(Debug)]
+pub struct RegisterInspectorWindow {
+    window_id: window::Id,
+    /// DSPレジスタの値
+    pub dsp_register: [u8; DSP_REGISTER_SIZE],
+    /// RAMイメージ（再生中は表示中のページをエミュレータの状態で更新する）
+    ram: Vec<u8>,
+    /// 表示するRAMのページ（上位8bit）
+    pub ram_page: u8,
+    /// ポークするDSPレジスタのアドレス
+    pub poke_address: u8,
+    /// ポークする値
+    pub poke_value: u8,
+}
+
+impl SPC2MIDI2Window for RegisterInspectorWindow {
+    fn title(&self) -> String {
+        "DSP Register / RAM Inspector".to_string()
+    }
+
+    fn view(&self) -> Element<'_, Message> {
+        let window_id = self.window_id;
+        let mut content = column![
+            text("DSP Registers"),
+            self.hex_grid(0, &self.dsp_register, |address| {
+                dsp_register_name(address as u8)
+            }),
+            row![
+                text("RAM Page"),
+                number_input(&self.ram_page, 0..=0xFF, move |page| {
+                    Message::InspectorRAMPageChanged(window_id, page)
+                }),
+                text(format!(
+                    "0x{:04X} - 0x{:04X}",
+                    (self.ram_page as usize) << 8,
+                    ((self.ram_page as usize) << 8) + RAM_PAGE_SIZE - 1
+                )),
+            ]
+            .spacing(10)
+            .align_y(alignment::Alignment::Center),
+            self.hex_grid((self.ram_page as usize) << 8, self.ram_page_data(), |_| {
+                None
+            }),
+        ]
+        .spacing(10)
+        .padding(10)
+        .width(Length::Fill)
+        .align_x(alignment::Alignment::Start);
+
+        if ENABLE_POKE {
+            content = content.push(
+                row![
+                    text("Poke DSP"),
+                    number_input(&self.poke_address, 0..=0x7F, move |address| {
+                        Message::InspectorPokeAddressChanged(window_id, address)
+                    }),
+                    number_input(&self.poke_value, 0..=0xFF, move |value| {
+                        Message::InspectorPokeValueChanged(window_id, value)
+                    }),
+                    button("Write").on_press(Message::InspectorPokeRequested(
+                        self.poke_address,
+                        self.poke_value
+                    )),
+                ]
+                .spacing(10)
+                .align_y(alignment::Alignment::Center),
+            );
+        }
+
+        scrollable(content).into()
+    }
+}
+
+impl RegisterInspectorWindow {
+    pub fn new(window_id: window::Id, ram: &[u8], dsp_register: &[u8]) -> Self {
+        let mut register = [0u8; DSP_REGISTER_SIZE];
+        let len = dsp_register.len().min(DSP_REGISTER_SIZE);
+        register[..len].copy_from_slice(&dsp_register[..len]);
+        Self {
+            window_id: window_id,
+            dsp_register: register,
+            ram: ram.to_vec(),
+            // 初期表示は音源ディレクトリのページ
+            ram_page: register[DSP_ADDRESS_DIR as usize],
+            poke_address: 0,
+            poke_value: 0,
+        }
+    }
+
+    /// 表示中ページのRAMデータ
+    fn ram_page_data(&self) -> &[u8] {
+        let (start, end) = self.ram_page_range(self.ram.len());
+        &self.ram[start..end]
+    }
+
+    /// 表示中ページのRAMデータを更新
+    pub fn update_ram_page(&mut self, ram: &[u8]) {
+        let (start, end) = self.ram_page_range(self.ram.len().min(ram.len()));
+        self.ram[start..end].copy_from_slice(&ram[start..end]);
+    }
+
+    /// 表示中ページのアドレス範囲（RAMサイズに収める）
+    fn ram_page_range(&self, ram_size: usize) -> (usize, usize) {
+        let start = ((self.ram_page as usize) << 8).min(ram_size);
+        let end = (start + RAM_PAGE_SIZE).min(ram_size);
+        (start, end)
+    }
+
+    /// 16バイト単位の16進グリッドを作成
+    fn hex_grid<'a>(
+        &self,
+        base_address: usize,
+        data: &[u8],
+        name: impl Fn(usize) -> Option<String>,
+    ) -> Element<'a, Message> {
+        let mut grid = Column::new().spacing(2);
+        for (line, chunk) in data.chunks(16).enumerate() {
+            let line_address = base_address + line * 16;
+            let mut cells = Row::new().spacing(6).push(
+                text(format!("{:04X}:", line_address))
+                    .font(Font::MONOSPACE)
+                    .color(Color::from_rgb(0.5, 0.5, 0.5)),
+            );
+            for (i, value) in chunk.iter().enumerate() {
+                let cell = text(format!("{:02X}", value)).font(Font::MONOSPACE);
+                cells = if let Some(name) = name(line_address + i - base_address) {
+                    cells.push(tooltip(
+                        cell.color(Color::from_rgb(0.6, 0.8, 1.0)),
+                        text(format!(
+                            "0x{:02X}: {}",
+                            line_address + i - base_address,
+                            name
+                        )),
+                        tooltip::Position::Top,
+                    ))
+                } else {
+                    cells.push(cell)
+                };
+            }
+            grid = grid.push(cells);
+        }
+        grid.into()
+    }
+}
+
+/// DSPレジスタアドレスの名前を取得
+fn dsp_register_name(address: u8) -> Option<String> {
+    let (high, low) = ((address >> 4) as usize, (address & 0xF) as usize);
+    if low < VOICE_REGISTER_NAMES.len() {
+        return Some(format!("V{}{}", high, VOICE_REGISTER_NAMES[low]));
+    }
+    if low == 0xF {
+        return Some(format!("FIR{}", high));
+    }
+    let name = match address {
+        0x0C => "MVOLL",
+        0x1C => "MVOLR",
+        0x2C => "EVOLL",
+        0x3C => "EVOLR",
+        DSP_ADDRESS_KON => "KON",
+        0x5C => "KOF",
+        0x6C => "FLG",
+        0x7C => "ENDX",
+        0x0D => "EFB",
+        0x2D => "PMON",
+        0x3D => "NON",
+        0x4D => "EON",
+        DSP_ADDRESS_DIR => "DIR",
+        0x6D => "ESA",
+        0x7D => "EDL",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
@@ -5,12 +5,29 @@ use std::f32::consts::PI;
 
 /// SPCの出力サンプリングレート
 const SPC_SAMPLING_RATE: f32 = 32000.0;
-/// センターピッチ(A4)
-const A4_PITCH_HZ: f32 = 440.0;
 /// 有効なピッチ候補と認めるスレッショルド
 const PITCH_PEAK_THRESHOLD: f32 = 0.9;
+/// スペクトルを計算する最小のサンプル数（これ未満は空のスペクトルとする）
+const MIN_SPECTRUM_NUM_SAMPLES: usize = 3;
+/// ピッチを推定できない場合のセンターノート
+const DEFAULT_CENTER_NOTE: f32 = 60.0;
+/// ループ区間からスペクトルを計算するときの最小サンプル数（満たない場合はループを繰り返して延ばす）
+const MIN_LOOP_SPECTRUM_NUM_SAMPLES: usize = 2048;
+/// 推定の前に信号のピークを揃える基準値
+const ANALYSIS_REFERENCE_PEAK: f32 = 1.0;
+/// 長調のキープロファイル（Krumhansl-Kessler）
+const MAJOR_KEY_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+/// 短調のキープロファイル（Krumhansl-Kessler）
+const MINOR_KEY_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
 
 fn detect_nonzero_erea(signal: &Vec<f32>) -> (usize, usize) {
+    if signal.is_empty() {
+        return (0, 0);
+    }
     let mut start = 0;
     let mut end = signal.len() - 1;
 
@@ -118,25 +135,25 @@ fn detect_drum(source_info: &SourceInformation) -> bool {
     false
 }
 
-/// センターノートの推定
-fn center_note_estimation(source_info: &SourceInformation) -> f32 {
-    // 対数パワースペクトルのオフセット
-    const LOG_POWER_SPECTRUM_OFFSET_DB: f32 = 120.0;
-
-    // ループ長からの周期推定
+/// ループ部分が1周期分の波形になっているショートループであればそのループ長を返す
+fn short_loop_length(source_info: &SourceInformation) -> Option<usize> {
     let nsmpls = source_info.signal.len();
     if nsmpls > source_info.loop_start_sample {
         // ショートループのサンプル数が小さく、かつ波形全体に対するループが大きければ
         // ループ部分が1周期分の波形になっていると思って推定
         let loop_length = nsmpls - source_info.loop_start_sample;
         if loop_length < (SPC_SAMPLING_RATE / 100.0) as usize && nsmpls < 5 * loop_length {
-            let freq = SPC_SAMPLING_RATE / loop_length as f32;
-            let estimated_note = 12.0 * f32::log2(freq / A4_PITCH_HZ) + 69.0;
-            return estimated_note.clamp(0.0, 127.0);
+            return Some(loop_length);
         }
     }
+    None
+}
+
+/// パワースペクトルからピッチに相当するビンを探索
+fn spectrum_pitch_bin(power_spec: &[f32]) -> usize {
+    // 対数パワースペクトルのオフセット
+    const LOG_POWER_SPECTRUM_OFFSET_DB: f32 = 120.0;
 
-    let power_spec = &source_info.power_spectrum;
     // 対数パワースペクトルに変換
     let log_spec: Vec<f32> = power_spec
         .iter()
@@ -168,19 +185,86 @@ fn center_note_estimation(source_info: &SourceInformation) -> f32 {
 
     // 最初の候補をピッチとする
     // 候補がなければ単純に最大のインデックス
-    let pitch_bin = if peaks.len() > 0 { peaks[0] } else { argmax };
+    if peaks.len() > 0 {
+        peaks[0]
+    } else {
+        argmax
+    }
+}
+
+/// センターノートの推定
+fn center_note_estimation(source_info: &SourceInformation, a4_pitch_hz: f32) -> f32 {
+    // ループ長からの周期推定
+    if let Some(loop_length) = short_loop_length(source_info) {
+        let freq = SPC_SAMPLING_RATE / loop_length as f32;
+        let estimated_note = 12.0 * f32::log2(freq / a4_pitch_hz) + 69.0;
+        return estimated_note.clamp(0.0, 127.0);
+    }
+
+    let power_spec = &source_info.power_spectrum;
+    if power_spec.len() < MIN_SPECTRUM_NUM_SAMPLES {
+        return DEFAULT_CENTER_NOTE;
+    }
+    let pitch_bin = spectrum_pitch_bin(power_spec);
 
     let peak_hz = (pitch_bin as f32 / (2.0 * power_spec.len() as f32)) * SPC_SAMPLING_RATE;
-    let estimated_note = 12.0 * f32::log2(peak_hz / A4_PITCH_HZ) + 69.0;
+    let estimated_note = 12.0 * f32::log2(peak_hz / a4_pitch_hz) + 69.0;
 
     estimated_note.clamp(0.0, 127.0)
 }
 
-/// ドラム音とノート番号の推定
-pub fn estimate_drum_and_note(source_info: &SourceInformation) -> (bool, f32) {
+/// センターノート推定の信頼度（0.0〜1.0）
+/// ピッチとしたビンのパワーがスペクトル平均からどれだけ突出しているかで評価する
+pub fn pitch_confidence(source_info: &SourceInformation) -> f32 {
+    // この突出度(dB)以上で信頼度を最大とする
+    const FULL_CONFIDENCE_PROMINENCE_DB: f32 = 30.0;
+
+    // ループが1周期分の波形であれば周期は確実
+    if short_loop_length(source_info).is_some() {
+        return 1.0;
+    }
+
+    let power_spec = &source_info.power_spectrum;
+    if power_spec.len() < MIN_SPECTRUM_NUM_SAMPLES {
+        return 0.0;
+    }
+    let peak = power_spec[spectrum_pitch_bin(power_spec)];
+    let mean = power_spec.iter().sum::<f32>() / power_spec.len() as f32;
+    if !(peak > 0.0 && mean > 0.0) {
+        return 0.0;
+    }
+    let prominence_db = 10.0 * f32::log10(peak / mean);
+    (prominence_db / FULL_CONFIDENCE_PROMINENCE_DB).clamp(0.0, 1.0)
+}
+
+/// 信号のピークを基準値に揃えた音源情報を作成（音量の小さいリッピングでも判定が変わらないように）
+/// パワースペクトルは振幅の2乗に比例するので倍率の2乗を掛ける
+fn normalize_analysis_level(source_info: &SourceInformation) -> SourceInformation {
+    let peak = source_info
+        .signal
+        .iter()
+        .fold(0.0f32, |peak, x| peak.max(x.abs()));
+    let mut normalized = source_info.clone();
+    if peak > 0.0 {
+        let gain = ANALYSIS_REFERENCE_PEAK / peak;
+        normalized.signal.iter_mut().for_each(|x| *x *= gain);
+        normalized
+            .power_spectrum
+            .iter_mut()
+            .for_each(|p| *p *= gain * gain);
+        normalized.rms *= gain;
+    }
+    normalized
+}
+
+/// ドラム音とノート番号の推定（ノート番号は基準ピッチ(A4)の周波数に合わせる）
+/// 再生・波形表示用の信号はそのままに、ピークを揃えた信号から推定する
+pub fn estimate_drum_and_note(source_info: &SourceInformation, a4_pitch_hz: f32) -> (bool, f32) {
+    let normalized = normalize_analysis_level(source_info);
     (
-        detect_drum(&source_info),
-        center_note_estimation(&source_info),
+        // ノイズ音源は音高を持たないので打楽器として扱う
+        normalized.uses_noise || detect_drum(&normalized),
+        center_note_estimation(&normalized, a4_pitch_hz),
     )
 }
 
@@ -192,7 +276,7 @@ pub fn estimate_bpm(onset_signal: &[f32], sampling_rate: f32) -> f32 {
 
     // フレームに区切り平均をとる
     // （この操作は間引きに相当するので間引く前にLPFをかけるとよいが低速なのでやめる）
-    let frame_size: usize = (sampling_rate * 0.01).round() as usize;
+    let frame_size: usize = ((sampling_rate * 0.01).round() as usize).max(1);
     let onset_envelope: Vec<_> = onset_signal
         .chunks(frame_size)
         .map(|c| c.iter().sum::<f32>() / frame_size as f32)
@@ -208,6 +292,10 @@ pub fn estimate_bpm(onset_signal: &[f32], sampling_rate: f32) -> f32 {
     // 候補ラグ内でのピーク
     let min_lag = ((60.0 * sampling_rate) / (MAX_ESTIMATED_BPM * frame_size as f32)) as usize;
     let max_lag = ((60.0 * sampling_rate) / (MIN_ESTIMATED_BPM * frame_size as f32)) as usize;
+    // 最短の候補ラグに満たない短い信号ではテンポを推定できない
+    if auto_corr.len() <= min_lag {
+        return DEFAULT_MIDI_BPM;
+    }
     let max_lag = max_lag.min(auto_corr.len() - 1);
     let max = auto_corr[min_lag..=max_lag]
         .iter()
@@ -220,7 +308,64 @@ pub fn estimate_bpm(onset_signal: &[f32], sampling_rate: f32) -> f32 {
         }
     }
 
-    unreachable!("Failed to find max peak in tempo estimation!");
+    // 自己相関が求まらない（NaNを含む）場合
+    DEFAULT_MIDI_BPM
+}
+
+/// 超簡易調推定（主音のピッチクラスと長調か否かを返す）
+pub fn estimate_key(pitch_class_histogram: &[f32; 12]) -> Option<(u8, bool)> {
+    if pitch_class_histogram.iter().sum::<f32>() <= 0.0 {
+        return None;
+    }
+
+    // 主音をずらしながらキープロファイルとの相関係数を計算
+    let correlation = |profile: &[f32; 12], tonic: usize| -> f32 {
+        let hist_mean = pitch_class_histogram.iter().sum::<f32>() / 12.0;
+        let prof_mean = profile.iter().sum::<f32>() / 12.0;
+        let (mut cov, mut hist_var, mut prof_var) = (0.0, 0.0, 0.0);
+        for i in 0..12 {
+            let h = pitch_class_histogram[(tonic + i) % 12] - hist_mean;
+            let p = profile[i] - prof_mean;
+            cov += h * p;
+            hist_var += h * h;
+            prof_var += p * p;
+        }
+        if hist_var > 0.0 {
+            cov / (hist_var * prof_var).sqrt()
+        } else {
+            0.0
+        }
+    };
+
+    // 最も相関が高い調を選ぶ
+    let mut key = (0, true);
+    let mut max_corr = f32::MIN;
+    for tonic in 0..12 {
+        for (profile, major) in [(&MAJOR_KEY_PROFILE, true), (&MINOR_KEY_PROFILE, false)] {
+            let corr = correlation(profile, tonic);
+            if corr > max_corr {
+                max_corr = corr;
+                key = (tonic as u8, major);
+            }
+        }
+    }
+
+    Some(key)
+}
+
+/// パワースペクトルを計算する信号の切り出し
+/// ループする音源はアタックの過渡部を避けて音高の安定したループ区間を、ワンショット音源は全体を使う
+pub fn spectrum_analysis_signal(signal: &[f32], loop_start_sample: usize) -> Vec<f32> {
+    if loop_start_sample == 0 || loop_start_sample >= signal.len() {
+        return signal.to_vec();
+    }
+    // ループ区間は周期的に繋がるので、短ければ繰り返して周波数分解能を確保
+    let loop_signal = &signal[loop_start_sample..];
+    let mut analysis_signal = loop_signal.to_vec();
+    while analysis_signal.len() < MIN_LOOP_SPECTRUM_NUM_SAMPLES {
+        analysis_signal.extend_from_slice(loop_signal);
+    }
+    analysis_signal
 }
 
 /// パワースペクトルの計算
@@ -234,6 +379,9 @@ pub fn compute_power_spectrum(signal: &Vec<f32>) -> Vec<f32> {
     };
 
     let m = signal.len();
+    if m < MIN_SPECTRUM_NUM_SAMPLES {
+        return vec![];
+    }
     // 窓との重み付き平均
     let window: Vec<_> = (0..m)
         .map(|i| f32::sin((PI * (i as f32)) / (m - 1) as f32).pow(2.0))
@@ -273,6 +421,22 @@ pub fn compute_power_spectrum(signal: &Vec<f32>) -> Vec<f32> {
     buffer[0..pad_len / 2].to_vec()
 }
 
+/// 2つの信号のラグ0での正規化相互相関（短い方の長さで計算し、無音の場合は0）
+pub fn signal_correlation(a: &[f32], b: &[f32]) -> f32 {
+    let num_samples = a.len().min(b.len());
+    let (mut ab, mut aa, mut bb) = (0.0f32, 0.0f32, 0.0f32);
+    for i in 0..num_samples {
+        ab += a[i] * b[i];
+        aa += a[i] * a[i];
+        bb += b[i] * b[i];
+    }
+    if aa > 0.0 && bb > 0.0 {
+        ab / (aa * bb).sqrt()
+    } else {
+        0.0
+    }
+}
+
 /// 自己相関関数の計算
 fn compute_auto_correlation(signal: &Vec<f32>) -> Vec<f32> {
     // 後半ゼロ埋めした信号
@@ -301,3 +465,63 @@ fn compute_auto_correlation(signal: &Vec<f32>) -> Vec<f32> {
 
     buffer[0..signal.len()].to_vec()
 }
+
+/// スペクトルから大きい順に最大num_peaks個のピーク（極大）のビンを選ぶ
+/// 選んだピーク同士はmin_separation_binsビン以上離れるようにし、1つのピークの裾にラベルが集まらないようにする
+pub fn pick_spectrum_peaks(
+    spec: &[f32],
+    num_peaks: usize,
+    min_separation_bins: usize,
+) -> Vec<usize> {
+    // 直流成分を除いた極大を候補とする
+    let mut candidates: Vec<usize> = (1..spec.len())
+        .filter(|&i| spec[i] >= spec[i - 1] && (i + 1 == spec.len() || spec[i] >= spec[i + 1]))
+        .collect();
+    candidates.sort_by(|&i, &j| spec[j].total_cmp(&spec[i]));
+
+    let mut peaks: Vec<usize> = vec![];
+    for bin in candidates {
+        if peaks.len() >= num_peaks {
+            break;
+        }
+        if peaks
+            .iter()
+            .all(|&peak| peak.abs_diff(bin) >= min_separation_bins.max(1))
+        {
+            peaks.push(bin);
+        }
+    }
+    peaks
+}
+
+/// イベント列（時刻, 種別）から曲のループを検出し（ループ開始時刻, ループ周期）を返す
+/// ある位置以降の全てのイベントが一定のイベント数・一定の時間差で繰り返される位置を探し、
+/// 最も早くループが始まるもの（同じ位置なら周期が短いもの）を選ぶ
+pub fn detect_loop(events: &[(u64, u32)], time_tolerance: u64) -> Option<(u64, u64)> {
+    let num_events = events.len();
+    let mut best: Option<(usize, usize)> = None;
+    for lag in 1..=(num_events / 2) {
+        let period = events[num_events - 1].0 - events[num_events - 1 - lag].0;
+        if period == 0 {
+            continue;
+        }
+        // 末尾から遡って繰り返しが成り立つ先頭位置を探す
+        let mut start = num_events - lag;
+        while start > 0 {
+            let (time, kind) = events[start - 1];
+            let (next_time, next_kind) = events[start - 1 + lag];
+            if kind != next_kind || (next_time - time).abs_diff(period) > time_tolerance {
+                break;
+            }
+            start -= 1;
+        }
+        // 少なくとも1周期分は繰り返しが確認できること
+        if num_events - start < 2 * lag {
+            continue;
+        }
+        if best.map_or(true, |(best_start, _)| start < best_start) {
+            best = Some((start, lag));
+        }
+    }
+    best.map(|(start, lag)| (events[start].0, events[start + lag].0 - events[start].0))
+}
@@ -1,5 +1,6 @@
 use crate::types::*;
 use crate::Message;
+use crate::SYMBOL_FONT;
 use iced::widget::{button, checkbox, column, pick_list, row, text, tooltip, Column};
 use iced::{alignment, Color, Element, Length};
 use std::collections::BTreeMap;
@@ -46,7 +47,7 @@ impl SPC2MIDI2Window for SRNChannelRoutingWindow {
                     .align_y(alignment::Alignment::Center)
                     .height(Length::Fill)
                     .width(Length::FillPortion(1)),
-                    text("→").width(10),
+                    text("→").font(SYMBOL_FONT).width(10),
                     pick_list(
                         output_midi_channel_list.clone(),
                         Some(param.channel_routing[ch]),
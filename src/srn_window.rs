@@ -1,13 +1,16 @@
 use crate::program::*;
+use crate::snap_to_brr_block_boundary;
+use crate::source_estimation::{estimate_drum_and_note, pick_spectrum_peaks};
 use crate::types::*;
 use crate::Message;
 use crate::SPC_SAMPLING_RATE;
+use crate::SYMBOL_FONT;
 use fuzzy_match::fuzzy_match;
 use iced::keyboard::key::Named;
 use iced::widget::canvas::{self, stroke, Cache, Canvas, Event, Frame, Geometry, Path, Stroke};
 use iced::widget::{
-    button, checkbox, column, combo_box, container, row, scrollable, slider, stack, text,
-    text_input, tooltip,
+    button, checkbox, column, combo_box, container, pick_list, radio, row, scrollable, slider,
+    stack, text, text_input, tooltip,
 };
 use iced::window;
 use iced::{
@@ -17,11 +20,56 @@ use iced_aw::number_input;
 use num_traits::pow::Pow;
 use std::cmp;
 use std::collections::BTreeMap;
-use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 
 // 周辺とみなすプログラム数
 const NUM_NEARBY_PROGRAMS: u8 = 11;
+// キーボード演奏のキーと基準（中心ノートのオクターブのC）からの半音数
+const KEYBOARD_NOTE_KEYS: [(&str, u8); 29] = [
+    ("z", 0),
+    ("s", 1),
+    ("x", 2),
+    ("d", 3),
+    ("c", 4),
+    ("v", 5),
+    ("g", 6),
+    ("b", 7),
+    ("h", 8),
+    ("n", 9),
+    ("j", 10),
+    ("m", 11),
+    (",", 12),
+    ("q", 12),
+    ("2", 13),
+    ("w", 14),
+    ("3", 15),
+    ("e", 16),
+    ("r", 17),
+    ("5", 18),
+    ("t", 19),
+    ("6", 20),
+    ("y", 21),
+    ("7", 22),
+    ("u", 23),
+    ("i", 24),
+    ("9", 25),
+    ("o", 26),
+    ("0", 27),
+];
+
+// キーに割り当てた基準からの半音数
+fn keyboard_note_offset(key: &iced::keyboard::Key) -> Option<u8> {
+    if let iced::keyboard::Key::Character(c) = key {
+        let c = c.to_lowercase();
+        KEYBOARD_NOTE_KEYS
+            .iter()
+            .find(|(k, _)| *k == c)
+            .map(|(_, offset)| *offset)
+    } else {
+        None
+    }
+}
 
 #[derive(Debug)]
 pub struct SRNWindow {
@@ -30,14 +78,49 @@ pub struct SRNWindow {
     srn_no: u8,
     source_info: Arc<SourceInformation>,
     source_parameter: Arc<RwLock<BTreeMap<u8, SourceParameter>>>,
+    midi_output_configure: Arc<RwLock<MIDIOutputConfigure>>,
     midi_preview: Arc<AtomicBool>,
     preview_loop: Arc<AtomicBool>,
+    snap_loop_point: Arc<AtomicBool>,
     preview_volume: Arc<AtomicU8>,
+    a4_pitch_hz: Arc<AtomicU32>,
+    spectrum_peak_labels: Arc<AtomicUsize>,
+    spectrum_peak_separation_hz: Arc<AtomicU32>,
+    /// 自動推定したノート番号に相当する周波数
+    estimated_center_note_hz: f32,
+    /// 自動推定したドラム判定
+    detected_drum: bool,
     program_box: combo_box::State<Program>,
     pub program_search_query: Option<String>,
+    /// キーボード演奏モードか
+    pub keyboard_play: bool,
+    /// 曲の再生中にこの音源だけを鳴らしているか
+    pub solo_in_song: bool,
+    /// 表示先ウィンドウの表示スケール
+    display_scale: DisplayScale,
     cache: Cache,
 }
 
+impl PanLaw {
+    pub const ALL: [PanLaw; 3] = [Self::Linear, Self::ConstantPower3dB, Self::Attenuated6dB];
+}
+
+impl std::fmt::Display for PanLaw {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Linear => "Linear",
+            Self::ConstantPower3dB => "-3 dB",
+            Self::Attenuated6dB => "-6 dB",
+        })
+    }
+}
+
+/// パンローによるパン位置の変換のプレビュー
+#[derive(Debug, Clone, Copy)]
+struct PanLawPreview {
+    pan_law: PanLaw,
+}
+
 /// 描画モード
 pub enum DrawMode {
     WaveForm, // 時間波形
@@ -152,10 +235,48 @@ impl SPC2MIDI2Window for SRNWindow {
         let center_note_fraction = (param.center_note & 0x1FF) as f32 / 512.0;
         let match_program = search_bestmatch_program_from_query(self.program_search_query.clone());
         let nearby_programs = create_nearby_programs_list(match_program.clone());
+        // ピッチベンド幅が出力先シンセの最大値を超える・ピッチ変化に足りない場合の警告
+        let pitch_bend_warning = {
+            let config = self.midi_output_configure.read().unwrap();
+            let required = required_pitch_bend_width(&self.source_info);
+            if param.pitch_bend_width > config.target_pitch_bend_width {
+                text(format!(
+                    "Exceeds synth max ±{}",
+                    config.target_pitch_bend_width
+                ))
+            } else if required > param.pitch_bend_width {
+                text(format!("Suggest {} or more", required))
+            } else {
+                text("")
+            }
+            .color(Color::from_rgb(1.0, 0.8, 0.0))
+        };
         let parameter_controller = column![
-            row![checkbox(param.mute)
-                .label("Mute")
-                .on_toggle(|flag| Message::SRNMuteFlagToggled(self.srn_no, flag)),]
+            row![
+                checkbox(param.mute)
+                    .label("Mute")
+                    .on_toggle(|flag| Message::SRNMuteFlagToggled(self.srn_no, flag)),
+                if self.source_info.uses_noise {
+                    tooltip(
+                        text("Noise").color(Color::from_rgb(0.6, 0.8, 1.0)),
+                        "Played with the DSP noise generator (pitch estimation is not meaningful)",
+                        tooltip::Position::Right,
+                    )
+                    .into()
+                } else {
+                    Element::from(text(""))
+                },
+                if self.source_info.pitch_modulated {
+                    tooltip(
+                        text("PMON").color(Color::from_rgb(1.0, 0.8, 0.0)),
+                        "Pitch-modulated by the previous voice (PMON). MIDI conversion of its pitch and timbre is approximate",
+                        tooltip::Position::Right,
+                    )
+                    .into()
+                } else {
+                    Element::from(text(""))
+                },
+            ]
             .spacing(10)
             .width(Length::Fill)
             .align_y(alignment::Alignment::Center),
@@ -167,6 +288,38 @@ impl SPC2MIDI2Window for SRNWindow {
             )
             .on_input(move |name| Message::ProgramSearchboxInputed(window_id, name))
             .on_close(Message::ProgramSearchboxClosed(window_id)),
+            row![
+                tooltip(
+                    text("Type").width(90).align_x(alignment::Alignment::Start),
+                    "Override the drum detection (program, channel routing and pitch bend follow the type)",
+                    tooltip::Position::Top,
+                ),
+                radio(
+                    "Auto",
+                    SourceKindOverride::Auto,
+                    Some(param.kind_override()),
+                    move |kind| Message::SRNKindOverrideSelected(srn_no, kind)
+                ),
+                radio(
+                    "Tonal",
+                    SourceKindOverride::Tonal,
+                    Some(param.kind_override()),
+                    move |kind| Message::SRNKindOverrideSelected(srn_no, kind)
+                ),
+                radio(
+                    "Drum",
+                    SourceKindOverride::Drum,
+                    Some(param.kind_override()),
+                    move |kind| Message::SRNKindOverrideSelected(srn_no, kind)
+                ),
+                text(format!(
+                    "Detected: {}",
+                    if self.detected_drum { "Drum" } else { "Tonal" }
+                )),
+            ]
+            .spacing(10)
+            .width(Length::Fill)
+            .align_y(alignment::Alignment::Center),
             row![
                 text("Center Note")
                     .width(90)
@@ -176,12 +329,12 @@ impl SPC2MIDI2Window for SRNWindow {
                 })
                 .step(1),
                 tooltip(
-                    button("▼").on_press(Message::SRNCenterNoteOctaveDownClicked(self.srn_no)),
+                    button(text("▼").font(SYMBOL_FONT)).on_press(Message::SRNCenterNoteOctaveDownClicked(self.srn_no)),
                     "Note Octave Down",
                     tooltip::Position::Bottom,
                 ),
                 tooltip(
-                    button("▲").on_press(Message::SRNCenterNoteOctaveUpClicked(self.srn_no)),
+                    button(text("▲").font(SYMBOL_FONT)).on_press(Message::SRNCenterNoteOctaveUpClicked(self.srn_no)),
                     "Note Octave Up",
                     tooltip::Position::Bottom,
                 ),
@@ -190,9 +343,20 @@ impl SPC2MIDI2Window for SRNWindow {
                     Message::CenterNoteFractionChanged(srn_no, fraction)
                 },)
                 .step(1.0 / 512.0),
+                tooltip(
+                    button("Snap").on_press(Message::SRNCenterNoteSnapToSemitoneClicked(srn_no)),
+                    "Round to the nearest semitone",
+                    tooltip::Position::Bottom,
+                ),
+                tooltip(
+                    button("Cent").on_press(Message::SRNCenterNoteSnapToCentClicked(srn_no)),
+                    "Round to the nearest cent",
+                    tooltip::Position::Bottom,
+                ),
                 {
                     let note = param.center_note as f32 / 512.0;
-                    text(format!("{:8.2}Hz", note_to_frequency(note))).width(90)
+                    let a4_pitch_hz = f32::from_bits(self.a4_pitch_hz.load(Ordering::Relaxed));
+                    text(format!("{:8.2}Hz", note_to_frequency(note, a4_pitch_hz))).width(90)
                 },
                 button("Reset").on_press(Message::SRNNoteEstimationClicked(self.srn_no)),
             ]
@@ -205,7 +369,37 @@ impl SPC2MIDI2Window for SRNWindow {
                     .align_x(alignment::Alignment::Start),
                 number_input(&param.noteon_velocity, 1..=127, move |velocity| {
                     Message::NoteOnVelocityChanged(srn_no, velocity)
-                },)
+                },),
+                tooltip(
+                    checkbox(param.velocity_from_keyon_volume)
+                        .label("Scale by Key-On Volume")
+                        .on_toggle(move |flag| Message::VelocityFromKeyOnVolumeFlagToggled(
+                            srn_no, flag
+                        )),
+                    "Scale the velocity by the SPC channel volume at key-on (Velocity is the maximum)",
+                    tooltip::Position::Top,
+                ),
+            ]
+            .spacing(10)
+            .width(Length::Fill)
+            .align_y(alignment::Alignment::Center),
+            row![
+                tooltip(
+                    text("Note Range")
+                        .width(90)
+                        .align_x(alignment::Alignment::Start),
+                    "Notes outside this range are shifted by octaves into it (or clamped if the range is narrower than an octave)",
+                    tooltip::Position::Top,
+                ),
+                number_input(&param.min_note, 0..=127, move |note| {
+                    Message::MinNoteChanged(srn_no, note)
+                })
+                .step(1),
+                text("-"),
+                number_input(&param.max_note, 0..=127, move |note| {
+                    Message::MaxNoteChanged(srn_no, note)
+                })
+                .step(1),
             ]
             .spacing(10)
             .width(Length::Fill)
@@ -218,10 +412,24 @@ impl SPC2MIDI2Window for SRNWindow {
                     .label("On")
                     .on_toggle(move |flag| Message::EnablePitchBendFlagToggled(srn_no, flag)),
                 text("Width (semitone)"),
-                number_input(&param.pitch_bend_width, 1..=48, move |width| {
+                number_input(&param.pitch_bend_width, 1..=MAX_PITCH_BEND_WIDTH, move |width| {
                     Message::PitchBendWidthChanged(srn_no, width)
                 },)
                 .step(1),
+                tooltip(
+                    button("Auto").on_press(Message::PitchBendWidthChanged(
+                        srn_no,
+                        required_pitch_bend_width(&self.source_info)
+                    )),
+                    "Set the smallest width that covers the pitch changes detected in analysis",
+                    tooltip::Position::Top,
+                ),
+                tooltip(
+                    text(format!("Detected ±{:.1}", self.source_info.max_pitch_deviation)),
+                    "Maximum pitch change from the key-on pitch detected in analysis",
+                    tooltip::Position::Top,
+                ),
+                pitch_bend_warning,
             ]
             .spacing(10)
             .width(Length::Fill)
@@ -241,6 +449,18 @@ impl SPC2MIDI2Window for SRNWindow {
                     move |pan| { Message::FixedPanChanged(srn_no, pan) }
                 )
                 .step(1),
+                tooltip(
+                    pick_list(PanLaw::ALL, Some(param.pan_law), move |pan_law| {
+                        Message::PanLawChanged(srn_no, pan_law)
+                    }),
+                    "Pan law of the target synth applied to the SPC pan (only when using the SPC value)",
+                    tooltip::Position::Top,
+                ),
+                Canvas::new(PanLawPreview {
+                    pan_law: param.pan_law
+                })
+                .width(Length::Fill)
+                .height(16),
             ]
             .spacing(10)
             .width(Length::Fill)
@@ -272,6 +492,17 @@ impl SPC2MIDI2Window for SRNWindow {
                 checkbox(param.echo_as_reverb_send)
                     .label("Echo as Reverb")
                     .on_toggle(move |flag| Message::EchoAsReverbFlagToggled(srn_no, flag)),
+                tooltip(
+                    slider(
+                        0..=MAX_ECHO_SEND_SCALE_PERCENT,
+                        param.echo_send_scale_percent,
+                        move |percent| Message::EchoSendScaleChanged(srn_no, percent)
+                    )
+                    .width(80),
+                    "Scale of the reverb send converted from echo",
+                    tooltip::Position::Top,
+                ),
+                text(format!("{:>3}%", param.echo_send_scale_percent)).width(40),
                 text("Reverb")
                     .width(60)
                     .align_x(alignment::Alignment::Start),
@@ -330,7 +561,13 @@ impl SPC2MIDI2Window for SRNWindow {
                 text("Name").width(90).align_x(alignment::Alignment::Start),
                 text_input("Instrument Name", &param.instrument_name).on_input_maybe(Some(
                     move |name| Message::InstrumentNameChanged(srn_no, name)
-                ))
+                )),
+                tooltip(
+                    text_input("Label", &param.label)
+                        .on_input(move |label| Message::LabelChanged(srn_no, label)),
+                    "Label shown in the main window instead of the source number",
+                    tooltip::Position::Top,
+                ),
             ]
             .spacing(10)
             .width(Length::Fill)
@@ -351,6 +588,58 @@ impl SPC2MIDI2Window for SRNWindow {
                 checkbox(param.envelope_as_expression)
                     .label("Envelope as Expression")
                     .on_toggle(move |flag| Message::EnvelopeAsExpressionFlagToggled(srn_no, flag)),
+                tooltip(
+                    checkbox(param.hide_in_indicator)
+                        .label("Hide in Channel Status")
+                        .on_toggle(move |flag| Message::HideInIndicatorFlagToggled(srn_no, flag)),
+                    "Do not show activity of this source in the main window indicators",
+                    tooltip::Position::Top,
+                ),
+                tooltip(
+                    checkbox(param.link_duplicates)
+                        .label("Link Duplicates")
+                        .on_toggle_maybe(if self.source_info.duplicates.is_empty() {
+                            None
+                        } else {
+                            Some(move |flag| Message::LinkDuplicatesFlagToggled(srn_no, flag))
+                        }),
+                    if self.source_info.duplicates.is_empty() {
+                        "No other sources share this waveform".to_string()
+                    } else {
+                        format!(
+                            "Apply parameter edits to sources with the same waveform (SRN {})",
+                            self.source_info
+                                .duplicates
+                                .iter()
+                                .map(|srn| srn.to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        )
+                    },
+                    tooltip::Position::Top,
+                ),
+                tooltip(
+                    checkbox(param.merge_stereo_pair)
+                        .label("Merge Stereo Pair")
+                        .on_toggle_maybe(self.source_info.stereo_pair_partner.map(|_| {
+                            move |flag| Message::MergeStereoPairFlagToggled(srn_no, flag)
+                        })),
+                    match self.source_info.stereo_pair_partner {
+                        Some(partner) => format!(
+                            "Output this source and SRN {} (played together panned hard left/right) as one centered source; the higher numbered one is muted",
+                            partner
+                        ),
+                        None => "No stereo pair was detected for this source".to_string(),
+                    },
+                    tooltip::Position::Top,
+                ),
+                tooltip(
+                    checkbox(param.sustain_pedal)
+                        .label("Sustain Pedal")
+                        .on_toggle(move |flag| Message::SustainPedalFlagToggled(srn_no, flag)),
+                    "Hold sustain pedal (CC#64) until the next NoteOn for legato sources (increases note overlap)",
+                    tooltip::Position::Top,
+                ),
             ]
             .spacing(10)
             .width(Length::Fill)
@@ -370,6 +659,13 @@ impl SPC2MIDI2Window for SRNWindow {
             checkbox(self.preview_loop.load(Ordering::Relaxed))
                 .label("Loop")
                 .on_toggle(|flag| Message::SRNPlayLoopFlagToggled(flag)),
+            tooltip(
+                checkbox(self.snap_loop_point.load(Ordering::Relaxed))
+                    .label("Snap Loop")
+                    .on_toggle(|flag| Message::SRNSnapLoopPointFlagToggled(flag)),
+                "Snap loop point to BRR block boundary",
+                tooltip::Position::Top,
+            ),
             text(format!(
                 "Volume {:<3}",
                 self.preview_volume.load(Ordering::Relaxed)
@@ -385,6 +681,20 @@ impl SPC2MIDI2Window for SRNWindow {
             checkbox(self.midi_preview.load(Ordering::Relaxed))
                 .label("MIDI Update Preview")
                 .on_toggle(|flag| Message::SRNMIDIPreviewFlagToggled(flag)),
+            tooltip(
+                checkbox(self.keyboard_play)
+                    .label("Keyboard Play")
+                    .on_toggle(move |flag| Message::SRNKeyboardPlayFlagToggled(srn_no, flag)),
+                "Play the program with the computer keyboard (Z-M: lower octave, Q-I: upper octave)",
+                tooltip::Position::Top,
+            ),
+            tooltip(
+                checkbox(self.solo_in_song)
+                    .label("Solo in Song")
+                    .on_toggle(move |flag| Message::SRNSoloInSongToggled(srn_no, flag)),
+                "While the song plays, mute all voices except those playing this source",
+                tooltip::Position::Top,
+            ),
         ];
         let nearby_programs_popup = container({
             let list = nearby_programs.iter().fold(column![], |col, program| {
@@ -449,24 +759,53 @@ impl SRNWindow {
         srn_no: u8,
         source_info: &SourceInformation,
         source_parameter: Arc<RwLock<BTreeMap<u8, SourceParameter>>>,
+        midi_output_configure: Arc<RwLock<MIDIOutputConfigure>>,
         midi_preview: Arc<AtomicBool>,
         preview_loop: Arc<AtomicBool>,
+        snap_loop_point: Arc<AtomicBool>,
         preview_volume: Arc<AtomicU8>,
+        a4_pitch_hz: Arc<AtomicU32>,
+        spectrum_peak_labels: Arc<AtomicUsize>,
+        spectrum_peak_separation_hz: Arc<AtomicU32>,
     ) -> Self {
+        // 推定は音源ごとに一度だけ行う
+        let a4 = f32::from_bits(a4_pitch_hz.load(Ordering::Relaxed));
+        let (detected_drum, estimated_note) = estimate_drum_and_note(source_info, a4);
+        let estimated_center_note_hz = note_to_frequency(estimated_note, a4);
         Self {
             window_id: window_id,
             title: title,
             srn_no: srn_no,
             source_info: source_info.clone().into(),
             source_parameter: source_parameter,
+            midi_output_configure: midi_output_configure,
             midi_preview: midi_preview,
             preview_loop: preview_loop,
+            snap_loop_point: snap_loop_point,
             preview_volume: preview_volume,
+            a4_pitch_hz: a4_pitch_hz,
+            spectrum_peak_labels: spectrum_peak_labels,
+            spectrum_peak_separation_hz: spectrum_peak_separation_hz,
+            estimated_center_note_hz: estimated_center_note_hz,
+            detected_drum: detected_drum,
             program_box: combo_box::State::new(Program::ALL.to_vec()),
             program_search_query: None,
+            keyboard_play: false,
+            solo_in_song: false,
+            display_scale: DisplayScale::default(),
             cache: Cache::default(),
         }
     }
+
+    pub fn srn_no(&self) -> u8 {
+        self.srn_no
+    }
+
+    /// 表示スケールを設定し、波形・スペクトラムを描き直す
+    pub fn set_display_scale(&mut self, scale: DisplayScale) {
+        self.display_scale = scale;
+        self.cache.clear();
+    }
 }
 
 impl canvas::Program<Message> for SRNWindow {
@@ -493,6 +832,7 @@ impl canvas::Program<Message> for SRNWindow {
                         ),
                         &self.source_info.signal,
                         false,
+                        self.display_scale,
                     );
                     // ループポイント描画
                     draw_loop_point(
@@ -503,6 +843,7 @@ impl canvas::Program<Message> for SRNWindow {
                         ),
                         self.source_info.signal.len(),
                         self.source_info.loop_start_sample,
+                        self.snap_loop_point.load(Ordering::Relaxed),
                     );
                     // 時刻ラベル描画
                     draw_timelabel(
@@ -534,6 +875,18 @@ impl canvas::Program<Message> for SRNWindow {
                             ),
                             &log_spec,
                             (*min, *max),
+                            self.display_scale,
+                        );
+                        // 周波数・dBの目盛り線描画
+                        draw_spectrum_grid(
+                            frame,
+                            &Rectangle::new(
+                                Point::new(0.0, 0.0),
+                                Size::new(bounds.width, bounds.height),
+                            ),
+                            log_spec.len(),
+                            SPC_SAMPLING_RATE as f32,
+                            (*min, *max),
                         );
                         // スペクトラムピークラベル描画
                         draw_spectrum_peak_label(
@@ -544,7 +897,24 @@ impl canvas::Program<Message> for SRNWindow {
                             ),
                             &log_spec,
                             SPC_SAMPLING_RATE as f32,
-                            6,
+                            self.spectrum_peak_labels.load(Ordering::Relaxed),
+                            f32::from_bits(
+                                self.spectrum_peak_separation_hz.load(Ordering::Relaxed),
+                            ),
+                        );
+                        // 推定したノート番号に相当する周波数を描画
+                        draw_center_note_hz(
+                            frame,
+                            &Rectangle::new(
+                                Point::new(0.0, 0.0),
+                                Size::new(bounds.width, bounds.height),
+                            ),
+                            &log_spec,
+                            SPC_SAMPLING_RATE as f32,
+                            self.estimated_center_note_hz,
+                            Color::from_rgb8(240, 160, 80),
+                            "Estimated",
+                            1,
                         );
                         // ノート番号に相当する周波数を描画
                         let params = self.source_parameter.read().unwrap();
@@ -557,7 +927,13 @@ impl canvas::Program<Message> for SRNWindow {
                             ),
                             &log_spec,
                             SPC_SAMPLING_RATE as f32,
-                            note_to_frequency(param.center_note as f32 / 512.0),
+                            note_to_frequency(
+                                param.center_note as f32 / 512.0,
+                                f32::from_bits(self.a4_pitch_hz.load(Ordering::Relaxed)),
+                            ),
+                            Color::from_rgb8(200, 200, 200),
+                            "Current",
+                            0,
                         );
                     }
                 }
@@ -592,6 +968,34 @@ impl canvas::Program<Message> for SRNWindow {
             }
             _ => {}
         }
+        // キーボード演奏
+        if self.keyboard_play {
+            match event {
+                Event::Keyboard(iced::keyboard::Event::KeyPressed { key, repeat, .. }) => {
+                    if let (Some(offset), false) = (keyboard_note_offset(key), *repeat) {
+                        return Some(iced_widget::Action::publish(Message::SRNKeyboardNoteOn(
+                            self.srn_no,
+                            offset,
+                        )));
+                    }
+                }
+                Event::Keyboard(iced::keyboard::Event::KeyReleased { key, .. }) => {
+                    if let Some(offset) = keyboard_note_offset(key) {
+                        return Some(iced_widget::Action::publish(Message::SRNKeyboardNoteOff(
+                            self.srn_no,
+                            offset,
+                        )));
+                    }
+                }
+                // フォーカスを失うとキーを離したことが分からないため全て止める
+                Event::Window(window::Event::Unfocused) => {
+                    return Some(iced_widget::Action::publish(
+                        Message::SRNKeyboardAllNotesOff(self.srn_no),
+                    ));
+                }
+                _ => {}
+            }
+        }
         if let Some(_) = cursor.position_in(bounds) {
             match event {
                 Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
@@ -612,12 +1016,18 @@ impl canvas::Program<Message> for SRNWindow {
 }
 
 /// 波形描画
-fn draw_waveform(frame: &mut Frame, bounds: &Rectangle, pcm: &[f32], amplitude_normalize: bool) {
+fn draw_waveform(
+    frame: &mut Frame,
+    bounds: &Rectangle,
+    pcm: &[f32],
+    amplitude_normalize: bool,
+    scale: DisplayScale,
+) {
     let center = bounds.center();
     let half_height = bounds.height / 2.0;
     let center_left = Point::new(center.x - bounds.width / 2.0, center.y);
 
-    let num_points_to_draw = cmp::min(pcm.len(), 4 * bounds.width as usize); // 描画する点数（それ以外は間引く）
+    let num_points_to_draw = cmp::min(pcm.len(), scale.num_points(bounds.width)); // 描画する点数（それ以外は間引く）
     let sample_stride = pcm.len() as f32 / num_points_to_draw as f32;
     let x_offset_delta = bounds.width / num_points_to_draw as f32;
 
@@ -662,7 +1072,7 @@ fn draw_waveform(frame: &mut Frame, bounds: &Rectangle, pcm: &[f32], amplitude_n
             &path,
             Stroke {
                 style: stroke::Style::Solid(line_color),
-                width: 1.0,
+                width: scale.stroke_width(1.0),
                 ..Stroke::default()
             },
         );
@@ -670,7 +1080,7 @@ fn draw_waveform(frame: &mut Frame, bounds: &Rectangle, pcm: &[f32], amplitude_n
         // ピクセルあたりのサンプル数が多いときは、最小値と最大値をつなぐ矩形のみ描画
         let mut prev_sample = 0;
         for i in 0..num_points_to_draw {
-            const MIN_HEIGHT: f32 = 0.5;
+            let min_height = scale.stroke_width(0.5);
             let current_sample = ((i + 1) as f32 * sample_stride).round() as usize;
             let max_val = pcm[prev_sample..current_sample]
                 .iter()
@@ -683,8 +1093,8 @@ fn draw_waveform(frame: &mut Frame, bounds: &Rectangle, pcm: &[f32], amplitude_n
 
             // 最大と最小の差がない（無音など）ときは高さをクリップ
             let mut height = (max_val - min_val) * pcm_normalizer;
-            if height < MIN_HEIGHT {
-                height = MIN_HEIGHT;
+            if height < min_height {
+                height = min_height;
             }
 
             // 矩形描画
@@ -693,7 +1103,7 @@ fn draw_waveform(frame: &mut Frame, bounds: &Rectangle, pcm: &[f32], amplitude_n
                     center_left.x + i as f32 * x_offset_delta,
                     center.y - max_val * pcm_normalizer,
                 ),
-                Size::new(1.2, height),
+                Size::new(scale.stroke_width(1.2), height),
                 line_color,
             );
             prev_sample = current_sample;
@@ -707,25 +1117,52 @@ fn draw_loop_point(
     bounds: &Rectangle,
     num_samples: usize,
     loop_start_sample: usize,
+    snap_loop_point: bool,
 ) {
-    let path = Path::new(|b| {
-        b.move_to(Point::new(
-            (bounds.width * loop_start_sample as f32) / num_samples as f32,
-            0.0,
-        ));
-        b.line_to(Point::new(
-            (bounds.width * loop_start_sample as f32) / num_samples as f32,
-            bounds.height,
-        ));
+    let draw_line = |frame: &mut Frame, sample: usize, color: Color| {
+        let x = (bounds.width * sample as f32) / num_samples as f32;
+        let path = Path::new(|b| {
+            b.move_to(Point::new(x, 0.0));
+            b.line_to(Point::new(x, bounds.height));
+        });
+        frame.stroke(
+            &path,
+            Stroke {
+                style: stroke::Style::Solid(color),
+                width: 1.5,
+                ..Stroke::default()
+            },
+        );
+    };
+    let snapped_loop_start_sample = snap_to_brr_block_boundary(loop_start_sample);
+    let label = if snap_loop_point {
+        // 丸めで位置が変わる場合は元の位置も薄く描画
+        if snapped_loop_start_sample != loop_start_sample {
+            draw_line(frame, loop_start_sample, Color::from_rgb8(100, 100, 100));
+        }
+        draw_line(
+            frame,
+            snapped_loop_start_sample,
+            Color::from_rgb8(200, 200, 200),
+        );
+        format!(
+            "Loop {} (exact {})",
+            snapped_loop_start_sample, loop_start_sample
+        )
+    } else {
+        draw_line(frame, loop_start_sample, Color::from_rgb8(200, 200, 200));
+        format!(
+            "Loop {} (snapped {})",
+            loop_start_sample, snapped_loop_start_sample
+        )
+    };
+    frame.fill_text(canvas::Text {
+        content: label,
+        position: Point::new(bounds.x + 5.0, bounds.y + 5.0),
+        color: Color::from_rgb8(200, 200, 200),
+        size: iced::Pixels(12.0),
+        ..canvas::Text::default()
     });
-    frame.stroke(
-        &path,
-        Stroke {
-            style: stroke::Style::Solid(Color::from_rgb8(200, 200, 200)),
-            width: 1.5,
-            ..Stroke::default()
-        },
-    );
 }
 
 /// 時刻ラベル描画
@@ -759,12 +1196,18 @@ fn draw_timelabel(frame: &mut Frame, bounds: &Rectangle, sampling_rate: f32, num
 }
 
 /// スペクトラム描画
-fn draw_spectrum(frame: &mut Frame, bounds: &Rectangle, spec: &[f32], db_range: (f32, f32)) {
+fn draw_spectrum(
+    frame: &mut Frame,
+    bounds: &Rectangle,
+    spec: &[f32],
+    db_range: (f32, f32),
+    scale: DisplayScale,
+) {
     const HEIGHT_OFFSET: f32 = 10.0;
     let center = bounds.center();
     let center_left = Point::new(center.x - bounds.width / 2.0, center.y);
 
-    let num_points_to_draw = cmp::min(spec.len(), 4 * bounds.width as usize); // 描画する点数（それ以外は間引く）
+    let num_points_to_draw = cmp::min(spec.len(), scale.num_points(bounds.width)); // 描画する点数（それ以外は間引く）
     let sample_stride = spec.len() as f32 / num_points_to_draw as f32;
 
     assert!(db_range.0 < db_range.1);
@@ -802,12 +1245,110 @@ fn draw_spectrum(frame: &mut Frame, bounds: &Rectangle, spec: &[f32], db_range:
         &path,
         Stroke {
             style: stroke::Style::Solid(Color::from_rgb8(0, 196, 0)),
-            width: 1.0,
+            width: scale.stroke_width(1.0),
             ..Stroke::default()
         },
     );
 }
 
+/// スペクトラムの目盛り線描画（周波数軸は10の冪ごとにラベル、縦軸は10dBごと）
+fn draw_spectrum_grid(
+    frame: &mut Frame,
+    bounds: &Rectangle,
+    num_bins: usize,
+    sampling_rate: f32,
+    db_range: (f32, f32),
+) {
+    // draw_spectrumと同じ座標計算
+    const HEIGHT_OFFSET: f32 = 10.0;
+    const FONT_SIZE: f32 = 12.0;
+    let center = bounds.center();
+    let center_left = Point::new(center.x - bounds.width / 2.0, center.y);
+
+    let normalize = |val: f32, min: f32, max: f32| -> f32 { (val - min) / (max - min) };
+    let compute_x = move |hz: f32| -> f32 {
+        let bin = 2.0 * num_bins as f32 * hz / sampling_rate;
+        center_left.x + bounds.width * normalize(bin.log10(), 0.0, ((num_bins - 1) as f32).log10())
+    };
+    let compute_y = move |p: f32| -> f32 {
+        HEIGHT_OFFSET + bounds.height * (1.0 - normalize(p, db_range.0, db_range.1))
+    };
+    let major_stroke = Stroke {
+        style: stroke::Style::Solid(Color::from_rgba8(255, 255, 255, 0.35)),
+        width: 1.0,
+        ..Stroke::default()
+    };
+    let minor_stroke = Stroke {
+        style: stroke::Style::Solid(Color::from_rgba8(255, 255, 255, 0.12)),
+        width: 1.0,
+        ..Stroke::default()
+    };
+
+    // 周波数の目盛り線（10の冪は太く、その間の倍数は薄く）
+    let min_hz = sampling_rate / (2.0 * num_bins as f32);
+    let max_hz = sampling_rate * (num_bins - 1) as f32 / (2.0 * num_bins as f32);
+    let mut decade_hz = 10.0;
+    while decade_hz <= max_hz {
+        for multiple in 1..10 {
+            let hz = decade_hz * multiple as f32;
+            if hz < min_hz || hz > max_hz {
+                continue;
+            }
+            let x = compute_x(hz);
+            frame.stroke(
+                &Path::line(Point::new(x, 0.0), Point::new(x, bounds.height)),
+                if multiple == 1 {
+                    major_stroke.clone()
+                } else {
+                    minor_stroke.clone()
+                },
+            );
+            if multiple == 1 {
+                frame.fill_text(canvas::Text {
+                    content: if hz >= 1000.0 {
+                        format!("{}k", hz / 1000.0)
+                    } else {
+                        format!("{}", hz)
+                    },
+                    size: iced::Pixels(FONT_SIZE),
+                    position: Point::new(x + 2.0, 0.0),
+                    color: Color::from_rgb8(160, 160, 160),
+                    align_x: alignment::Horizontal::Left.into(),
+                    align_y: alignment::Vertical::Top,
+                    font: Font::DEFAULT,
+                    ..canvas::Text::default()
+                });
+            }
+        }
+        decade_hz *= 10.0;
+    }
+
+    // dBの目盛り線
+    const DB_STEP: f32 = 10.0;
+    let mut db = (db_range.0 / DB_STEP).ceil() * DB_STEP;
+    while db <= db_range.1 {
+        let y = compute_y(db);
+        frame.stroke(
+            &Path::line(
+                Point::new(bounds.x, y),
+                Point::new(bounds.x + bounds.width, y),
+            ),
+            minor_stroke.clone(),
+        );
+        frame.fill_text(canvas::Text {
+            content: format!("{:.0} dB", db),
+            size: iced::Pixels(FONT_SIZE),
+            position: Point::new(bounds.x + bounds.width - 2.0, y),
+            color: Color::from_rgb8(160, 160, 160),
+            align_x: alignment::Horizontal::Right.into(),
+            align_y: alignment::Vertical::Bottom,
+            font: Font::DEFAULT,
+            ..canvas::Text::default()
+        });
+        db += DB_STEP;
+    }
+}
+
 /// スペクトラムピークラベル描画
 fn draw_spectrum_peak_label(
     frame: &mut Frame,
@@ -815,6 +1356,7 @@ fn draw_spectrum_peak_label(
     spec: &[f32],
     sampling_rate: f32,
     num_peaks: usize,
+    min_separation_hz: f32,
 ) {
     let center = bounds.center();
     let center_left = Point::new(center.x - bounds.width / 2.0, center.y);
@@ -827,9 +1369,11 @@ fn draw_spectrum_peak_label(
     let compute_frequency =
         move |s: usize| -> f32 { sampling_rate * (s as f32) / (2.0 * spec.len() as f32) };
 
-    // スペクトルを降順にソートし対応するビンを並べる
-    let mut peak_bins = (0..spec.len()).collect::<Vec<_>>();
-    peak_bins.sort_unstable_by(|&i, &j| spec[j].total_cmp(&spec[i]));
+    // 間隔を空けて大きい順にピークを選ぶ
+    let min_separation_bins =
+        f32::round(2.0 * spec.len() as f32 * min_separation_hz / sampling_rate) as usize;
+    let peak_bins = pick_spectrum_peaks(spec, num_peaks, min_separation_bins);
+    let num_peaks = peak_bins.len();
 
     // ピークの周波数を描画
     const FONT_SIZE: f32 = 16.0;
@@ -850,13 +1394,17 @@ fn draw_spectrum_peak_label(
     }
 }
 
-/// ノート番号に相当する周波数位置の描画
+/// ノート番号に相当する周波数位置の描画（ラベルはlabel_row段目に置く）
+#[allow(clippy::too_many_arguments)]
 fn draw_center_note_hz(
     frame: &mut Frame,
     bounds: &Rectangle,
     spec: &[f32],
     sampling_rate: f32,
     center_note_hz: f32,
+    color: Color,
+    label: &str,
+    label_row: usize,
 ) {
     let center = bounds.center();
     let center_left = Point::new(center.x - bounds.width / 2.0, center.y);
@@ -873,9 +1421,68 @@ fn draw_center_note_hz(
     frame.stroke(
         &path,
         Stroke {
-            style: stroke::Style::Solid(Color::from_rgb8(200, 200, 200)),
+            style: stroke::Style::Solid(color),
             width: 1.5,
             ..Stroke::default()
         },
     );
+
+    const FONT_SIZE: f32 = 14.0;
+    frame.fill_text(canvas::Text {
+        content: format!("{} {:.1}Hz", label, center_note_hz),
+        size: iced::Pixels(FONT_SIZE),
+        position: Point::new(line_x + 4.0, FONT_SIZE * label_row as f32),
+        color: color,
+        align_x: alignment::Horizontal::Left.into(),
+        align_y: alignment::Vertical::Top,
+        font: Font::DEFAULT,
+        ..canvas::Text::default()
+    });
+}
+
+impl canvas::Program<Message> for PanLawPreview {
+    type State = Option<()>;
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &Renderer,
+        theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        const PREVIEW_PANS: [u8; 9] = [0, 16, 32, 48, 64, 80, 96, 112, 127];
+        let mut frame = Frame::new(renderer, bounds.size());
+        frame.fill_rectangle(
+            Point::new(0.0, 0.0),
+            bounds.size(),
+            theme.extended_palette().background.strong.color,
+        );
+        // 変換前（上半分）と変換後（下半分）の位置を左(L)から右(R)に並べて表示
+        let half_height = bounds.height / 2.0;
+        for pan in PREVIEW_PANS {
+            let x = |pan: u8| (pan as f32 / 127.0) * (bounds.width - 1.0);
+            frame.fill_rectangle(
+                Point::new(x(pan), 0.0),
+                Size::new(1.0, half_height),
+                theme.extended_palette().background.weak.color,
+            );
+            frame.fill_rectangle(
+                Point::new(x(self.pan_law.map(pan)), half_height),
+                Size::new(1.0, half_height),
+                theme.palette().success,
+            );
+        }
+        vec![frame.into_geometry()]
+    }
+
+    fn update(
+        &self,
+        _state: &mut Self::State,
+        _event: &Event,
+        _bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Option<iced_widget::Action<Message>> {
+        None
+    }
 }
@@ -3,9 +3,14 @@ use crate::Message;
 use iced::Element;
 use serde::{Deserialize, Serialize};
 use std::any::Any;
+use std::sync::Arc;
 
 /// デフォルトのMIDIファイル出力時間(sec)
 pub const DEFAULT_OUTPUT_DURATION_MSEC: u64 = 60 * 1000;
+/// 最小のMIDIファイル出力時間(msec)
+pub const MIN_OUTPUT_DURATION_MSEC: u64 = 1;
+/// 最大のMIDIファイル出力時間(msec)
+pub const MAX_OUTPUT_DURATION_MSEC: u64 = 3600 * 1000;
 /// デフォルトのMIDI再生パラメータ更新間隔(msec)
 pub const DEFAULT_PLAYBACK_PARAMETER_UPDATE_PERIOD_MSEC: u8 = 5;
 /// デフォルトの出力MIDIのBPM
@@ -20,6 +25,87 @@ pub const MIN_BEATS_PER_MINUTE: u32 = 4;
 pub const MAX_BEATS_PER_MINUTE: u32 = 1920;
 /// BPMの最小解像度
 pub const BPM_RESOLUTION: f32 = 1.0 / 256.0;
+/// デフォルトのオーディオ出力レイテンシ(msec)
+pub const DEFAULT_AUDIO_OUTPUT_LATENCY_MSEC: usize = 200;
+/// デフォルトのMIDIノート一覧の出力時間(msec)
+pub const DEFAULT_NOTE_SUMMARY_DURATION_MSEC: u64 = 10 * 1000;
+/// デフォルトの再生開始時のフェードイン時間(msec)
+pub const DEFAULT_RAMP_IN_MSEC: usize = 300;
+/// デフォルトの再生中の出力デバイス切り替え時のフェードイン時間(msec)
+pub const DEFAULT_DEVICE_SWITCH_FADE_MSEC: usize = 50;
+/// デフォルトの書き出し前に空回しするプリロール時間(msec)
+pub const DEFAULT_EXPORT_PREROLL_MSEC: usize = 0;
+/// 書き出し前に空回しするプリロール時間の上限(msec)
+pub const MAX_EXPORT_PREROLL_MSEC: usize = 30000;
+/// デフォルトの書き出し前に確認する出力時間(sec)
+pub const DEFAULT_LONG_EXPORT_WARNING_SEC: u32 = 600;
+/// 書き出し前に確認する出力時間の上限(sec)
+pub const MAX_LONG_EXPORT_WARNING_SEC: u32 = 3600;
+/// プログラムごとのベロシティ補正の最小倍率(%)
+pub const MIN_PROGRAM_VELOCITY_PERCENT: u8 = 25;
+/// プログラムごとのベロシティ補正の最大倍率(%)
+pub const MAX_PROGRAM_VELOCITY_PERCENT: u8 = 200;
+/// GMのプログラムファミリー（8プログラムごと）のデフォルトのベロシティ補正倍率(%)
+/// 持続して大きく聴こえるオルガン・ブラス・シンセリードは下げ、減衰の速い・小さく聴こえる楽器は上げる
+const DEFAULT_PROGRAM_FAMILY_VELOCITY_PERCENT: [u8; 16] = [
+    100, 105, 85, 100, 95, 105, 100, 85, 95, 115, 85, 100, 100, 100, 100, 100,
+];
+/// デフォルトの鳴りっぱなしとみなすノートの最大発音時間（0で無効、長いドローンやパッドを切らないよう既定では無効）(sec)
+pub const DEFAULT_STUCK_NOTE_TIMEOUT_SEC: u32 = 0;
+/// 鳴りっぱなしとみなすノートの最大発音時間の上限(sec)
+pub const MAX_STUCK_NOTE_TIMEOUT_SEC: u32 = 600;
+/// デフォルトの基準ピッチ(A4)の周波数(Hz)
+pub const DEFAULT_A4_PITCH_HZ: f32 = 440.0;
+/// 基準ピッチ(A4)の最小周波数(Hz)
+pub const MIN_A4_PITCH_HZ: f32 = 400.0;
+/// 基準ピッチ(A4)の最大周波数(Hz)
+pub const MAX_A4_PITCH_HZ: f32 = 480.0;
+/// デフォルトのインジケータの平滑化係数（更新ごとに前回の表示値を残す割合）
+pub const DEFAULT_INDICATOR_SMOOTHING: f32 = 0.6;
+/// インジケータの最大平滑化係数
+pub const MAX_INDICATOR_SMOOTHING: f32 = 0.95;
+/// デフォルトのスペクトルのピークラベル数
+pub const DEFAULT_SPECTRUM_PEAK_LABELS: usize = 6;
+/// 最大のスペクトルのピークラベル数
+pub const MAX_SPECTRUM_PEAK_LABELS: usize = 16;
+/// デフォルトのスペクトルのピーク間の最小間隔(Hz)
+pub const DEFAULT_SPECTRUM_PEAK_SEPARATION_HZ: f32 = 50.0;
+/// 最大のスペクトルのピーク間の最小間隔(Hz)
+pub const MAX_SPECTRUM_PEAK_SEPARATION_HZ: f32 = 2000.0;
+/// オーディオ出力の最小サンプリングレート
+pub const MIN_OUTPUT_SAMPLE_RATE: u32 = 8000;
+/// オーディオ出力の最大サンプリングレート
+pub const MAX_OUTPUT_SAMPLE_RATE: u32 = 192000;
+/// これを超えるオーディオ出力サンプリングレートは警告を出す
+pub const HIGH_OUTPUT_SAMPLE_RATE: u32 = 96000;
+/// デフォルトの強制するオーディオ出力サンプリングレート
+pub const DEFAULT_OUTPUT_SAMPLE_RATE: u32 = 48000;
+/// SMF書き出しを伸縮する目標長さの最小値（秒）
+pub const MIN_STRETCH_TARGET_SEC: u32 = 1;
+/// SMF書き出しを伸縮する目標長さの最大値（秒）
+pub const MAX_STRETCH_TARGET_SEC: u32 = 3600;
+/// デフォルトのSMF書き出しを伸縮する目標長さ（秒）
+pub const DEFAULT_STRETCH_TARGET_SEC: u32 = 60;
+/// 出力範囲に指定できる最大の時刻（秒）
+pub const MAX_EXPORT_RANGE_SEC: f32 = 3600.0;
+/// デフォルトの出力先シンセの最大ピッチベンド幅（半音単位）
+pub const DEFAULT_TARGET_PITCH_BEND_WIDTH: u8 = 24;
+/// 設定可能な最大ピッチベンド幅（半音単位）
+pub const MAX_PITCH_BEND_WIDTH: u8 = 48;
+/// 設定可能なMIDIチャンネルごとの最大同時発音数
+pub const MAX_POLYPHONY_PER_CHANNEL: u8 = 32;
+/// デフォルトの一律ベロシティ
+pub const DEFAULT_FLAT_VELOCITY: u8 = 100;
+/// デフォルトのWAV出力のステレオ幅(%)
+pub const DEFAULT_WAV_STEREO_WIDTH_PERCENT: u16 = 100;
+/// 最大のWAV出力のステレオ幅(%)
+pub const MAX_WAV_STEREO_WIDTH_PERCENT: u16 = 200;
+/// デフォルトのエコーのリバーブセンド変換倍率(%)
+pub const DEFAULT_ECHO_SEND_SCALE_PERCENT: u8 = 100;
+/// 最大のエコーのリバーブセンド変換倍率(%)
+pub const MAX_ECHO_SEND_SCALE_PERCENT: u8 = 200;
+/// デフォルトのマスターリバーブセンド
+pub const DEFAULT_MASTER_REVERB_SEND: u8 = 40;
 
 /// ボリュームカーブ
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,6 +118,29 @@ pub enum VolumeCurve {
     Linear,
 }
 
+/// SPCのパン（左右音量比）をMIDIのパン（CC#10）に変換する際の出力先シンセのパンロー
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum PanLaw {
+    /// 変換しない（MIDIDSPの出力のまま）
+    #[default]
+    Linear,
+    /// 中央で-3dB（サイン・コサイン則）
+    ConstantPower3dB,
+    /// 中央で-6dB（サイン・コサインの2乗則）
+    Attenuated6dB,
+}
+
+/// 音源のドラム判定の上書き指定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKindOverride {
+    /// 解析による判定に従う
+    Auto,
+    /// 音階のある音源として扱う
+    Tonal,
+    /// ドラム音源として扱う
+    Drum,
+}
+
 /// 再生MIDISystem
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MIDISystem {
@@ -47,6 +156,65 @@ pub enum MIDISystem {
     XG,
 }
 
+/// ドラムキット（GM2/GS/XGで共通のプログラム番号で選択する）
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DrumKit {
+    /// スタンダード
+    Standard,
+    /// ルーム
+    Room,
+    /// パワー
+    Power,
+    /// エレクトロニック
+    Electronic,
+    /// TR-808
+    TR808,
+    /// ジャズ
+    Jazz,
+    /// ブラシ
+    Brush,
+    /// オーケストラ
+    Orchestra,
+}
+
+impl DrumKit {
+    /// ドラムチャンネルに送るプログラム番号
+    pub fn program(&self) -> u8 {
+        match self {
+            Self::Standard => 0,
+            Self::Room => 8,
+            Self::Power => 16,
+            Self::Electronic => 24,
+            Self::TR808 => 25,
+            Self::Jazz => 32,
+            Self::Brush => 40,
+            Self::Orchestra => 48,
+        }
+    }
+}
+
+/// WAV出力のサンプリングレート
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WAVSampleRate {
+    /// SPCの出力レート(32kHz)のまま
+    Native,
+    /// 44.1kHz
+    Hz44100,
+    /// 48kHz
+    Hz48000,
+}
+
+/// WAV出力のレート変換品質
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WAVResampleQuality {
+    /// 高速
+    Fast,
+    /// 標準
+    Good,
+    /// 最高品質
+    Best,
+}
+
 /// 波形を区別するIDの表示種別
 #[derive(Debug, Clone)]
 pub enum DisplaySourceIDType {
@@ -56,6 +224,39 @@ pub enum DisplaySourceIDType {
     SRN, 
 }
 
+/// メインウィンドウのレイアウト
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MainWindowLayout {
+    /// 音源の表（デフォルト）
+    Table,
+    /// チャンネルごとのフェーダーを並べたミキサー
+    Mixer,
+    /// 音源の波形サムネイルを並べたグリッド
+    Grid,
+}
+
+/// 再生中のオーディオ出力の状態（直近のアンダーランの頻度による）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioHealth {
+    /// アンダーランなし
+    Good,
+    /// ときどきアンダーランが発生
+    Degraded,
+    /// 頻繁にアンダーランが発生
+    Poor,
+}
+
+/// 再生ストリームの用途
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackMode {
+    /// 停止中
+    None,
+    /// 曲の再生
+    Song,
+    /// 音源のプレビュー再生
+    Preview(u8),
+}
+
 /// 音源情報
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
@@ -64,6 +265,8 @@ pub struct SourceInformation {
     pub signal: Vec<f32>,
     /// パワースペクトル
     pub power_spectrum: Vec<f32>,
+    /// ディレクトリエントリのアドレス
+    pub dir_address: usize,
     /// 開始アドレス
     pub start_address: usize,
     /// 終端アドレス
@@ -72,6 +275,28 @@ pub struct SourceInformation {
     pub loop_start_sample: usize,
     /// チャンネルを使っているか？（8チャンネル分）
     pub using_channel: [bool; 8],
+    /// キーオン時のピッチ（キーオンされた回数分）
+    pub keyon_pitches: Vec<u16>,
+    /// 発音中のキーオン時ピッチからの最大変化量（半音単位）
+    pub max_pitch_deviation: f32,
+    /// 同じ波形を持つ他の音源番号
+    pub duplicates: Vec<u8>,
+    /// デコードした信号のRMS
+    pub rms: f32,
+    /// ノイズ音源として発音されたか
+    pub uses_noise: bool,
+    /// ピッチモジュレーション（前のボイスの出力でピッチを変調）有効で発音されたか
+    pub pitch_modulated: bool,
+    /// センターノート推定の信頼度（0.0〜1.0）
+    pub pitch_confidence: f32,
+    /// 解析でドラム音と判定されたか（種別の上書き指定は含まない）
+    pub detected_drum: bool,
+    /// 曲中のDIR変更で同じ音源番号が指していた、採用しなかった他のディレクトリエントリのアドレス
+    /// （最後に発音されたエントリを採用する）
+    pub alternate_dir_addresses: Vec<usize>,
+    /// 左右に振り切って同時にキーオンされ、相関の高い波形を持つ相手の音源番号
+    /// （2ボイスでステレオ音源を鳴らしている場合の片方）
+    pub stereo_pair_partner: Option<u8>,
 }
 
 /// 1音源のパラメータ
@@ -85,6 +310,9 @@ pub struct SourceParameter {
     pub center_note: u16,
     /// ノートオンベロシティ
     pub noteon_velocity: u8,
+    /// キーオン時のボリュームでベロシティをスケーリングするか（ノートオンベロシティを最大とする）
+    #[serde(default)]
+    pub velocity_from_keyon_volume: bool,
     /// ピッチベンド幅（半音単位）
     pub pitch_bend_width: u8,
     /// エンベロープをエクスプレッションとして出力するか
@@ -93,6 +321,9 @@ pub struct SourceParameter {
     pub auto_pan: bool,
     /// パン値
     pub fixed_pan: u8,
+    /// パンを発音中に更新する際のパンロー
+    #[serde(default)]
+    pub pan_law: PanLaw,
     /// ボリュームを発音中に更新するか
     pub auto_volume: bool,
     /// ボリューム値
@@ -105,6 +336,9 @@ pub struct SourceParameter {
     pub enable_pitch_bend: bool,
     /// エコーをリバーブセンドとして出力するか
     pub echo_as_reverb_send: bool,
+    /// エコーをリバーブセンドとして出力する際の倍率(%)
+    #[serde(default = "default_echo_send_scale_percent")]
+    pub echo_send_scale_percent: u8,
     /// ノートオン後に再生パラメータを更新するか
     pub update_parameter_after_noteon: bool,
     /// 出力チャンネル（SPCの出力チャンネルをインデックス、出力先MIDIチャンネルが値）
@@ -113,6 +347,47 @@ pub struct SourceParameter {
     pub channel_mute: [bool; 8],
     /// 楽器名
     pub instrument_name: String,
+    /// 音源のラベル（空の場合は番号で表示）
+    #[serde(default)]
+    pub label: String,
+    /// メインウィンドウのチャンネルインジケータに表示しないか
+    #[serde(default)]
+    pub hide_in_indicator: bool,
+    /// ノート境界でサステインペダル（CC#64）を出力するか
+    #[serde(default)]
+    pub sustain_pedal: bool,
+    /// 同じ波形の音源にパラメータの編集を反映するか
+    #[serde(default)]
+    pub link_duplicates: bool,
+    /// ステレオペアを1つの音源として出力するか（番号の小さい側を中央に定位させて残し、相手はミュート）
+    #[serde(default)]
+    pub merge_stereo_pair: bool,
+    /// ステレオペアを統合する前のパン・ミュートの設定（統合を解除したときに戻す）
+    #[serde(default)]
+    pub pre_merge_state: Option<StereoPairMergeBackup>,
+    /// 出力するノートの下限（範囲外のノートはオクターブ単位で範囲内に折り返す）
+    #[serde(default)]
+    pub min_note: u8,
+    /// 出力するノートの上限（範囲外のノートはオクターブ単位で範囲内に折り返す）
+    #[serde(default = "default_max_note")]
+    pub max_note: u8,
+    /// ドラム判定によらず音階のある音源として扱うか
+    #[serde(default)]
+    pub force_tonal: bool,
+    /// ドラム判定によらずドラム音源として扱うか
+    #[serde(default)]
+    pub force_drum: bool,
+}
+
+/// ステレオペアを統合する前の音源のパン・ミュートの設定
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StereoPairMergeBackup {
+    /// SPCのパンを使うか
+    pub auto_pan: bool,
+    /// 固定のパン
+    pub fixed_pan: u8,
+    /// ミュート
+    pub mute: bool,
 }
 
 /// MIDI出力設定
@@ -132,10 +407,190 @@ pub struct MIDIOutputConfigure {
     pub volume_curve: VolumeCurve,
     /// ターゲットMIDIシステム
     pub midi_system: MIDISystem,
+    /// ドラムチャンネルで選択するドラムキット（GM2/GS/XGのみ）
+    #[serde(default = "default_drum_kit")]
+    pub drum_kit: DrumKit,
     /// ドラム音色をサンプル単位でトラックに分割するか
     pub split_drum_into_separate_tracks: bool,
     /// 先頭のイベントがない区間を取り除くか
     pub trim_leading_nonevents_period: bool,
+    /// 音源の最初のノートオン位置に音源を示すマーカーを出力するか
+    #[serde(default)]
+    pub insert_source_markers: bool,
+    /// SPCの曲情報と出力ツール情報をテキストイベントとして埋め込むか
+    #[serde(default)]
+    pub embed_spc_metadata: bool,
+    /// SPCのコメント（ID666）を歌詞イベントとして埋め込むか
+    #[serde(default)]
+    pub embed_spc_comment_as_lyric: bool,
+    /// 推定したテンポに合わせたクリックトラックを空いているチャンネルに出力するか
+    #[serde(default)]
+    pub insert_click_track: bool,
+    /// これより短いノートを出力しない（0で無効）(ms)
+    #[serde(default)]
+    pub min_note_duration_msec: u32,
+    /// MIDIチャンネルごとの最大同時発音数（0で無制限）
+    #[serde(default)]
+    pub max_polyphony_per_channel: u8,
+    /// MIDIノート一覧を出力する時間(ms)
+    #[serde(default = "default_note_summary_duration_msec")]
+    pub note_summary_duration_msec: u64,
+    /// SMFをループごとに分けて出力するか
+    #[serde(default)]
+    pub split_smf_per_loop: bool,
+    /// 打楽器的なキーオンを発音ごとにドラムチャンネルに振り替えるか
+    #[serde(default)]
+    pub route_percussive_notes: bool,
+    /// 解析時にこれより短いキーオンしかない音源を検出しない（0で無効）(ms)
+    #[serde(default)]
+    pub min_source_keyon_msec: u32,
+    /// 解析・MIDI出力から除外するDSPボイスのフラグ（ビットが立っているボイスを除外）
+    #[serde(default)]
+    pub excluded_voices: u8,
+    /// WAV出力時にSPCのエコーを除くか
+    #[serde(default)]
+    pub wav_bypass_echo: bool,
+    /// WAV出力時にソフトリミッタでクリップを防ぐか
+    #[serde(default)]
+    pub wav_soft_limiter: bool,
+    /// WAV出力時のステレオ幅(%)（0でモノラル、100で原音のまま）
+    #[serde(default = "default_wav_stereo_width_percent")]
+    pub wav_stereo_width_percent: u16,
+    /// WAV出力のサンプリングレート
+    #[serde(default = "default_wav_sample_rate")]
+    pub wav_sample_rate: WAVSampleRate,
+    /// WAV出力のレート変換品質
+    #[serde(default = "default_wav_resample_quality")]
+    pub wav_resample_quality: WAVResampleQuality,
+    /// 出力先シンセの最大ピッチベンド幅（半音単位）
+    #[serde(default = "default_target_pitch_bend_width")]
+    pub target_pitch_bend_width: u8,
+    /// ピッチベンド幅を出力先シンセの最大値に制限するか
+    #[serde(default)]
+    pub clamp_pitch_bend_width: bool,
+    /// 音源ごとの設定によらず全てのノートを一律のベロシティで出力するか
+    #[serde(default)]
+    pub flatten_velocity: bool,
+    /// 一律に出力する場合のベロシティ
+    #[serde(default = "default_flat_velocity")]
+    pub flat_velocity: u8,
+    /// プログラムごとの音量差を補正するようにベロシティをスケーリングするか（補正表は設定で編集）
+    #[serde(default)]
+    pub calibrate_velocity_by_program: bool,
+}
+
+/// 新しく開いたSPCの音源パラメータの既定値（プログラム・基準ノート等の推定する項目以外）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourceParameterTemplate {
+    /// ノートオンベロシティ
+    pub noteon_velocity: u8,
+    /// キーオン時のボリュームでベロシティをスケーリングするか
+    pub velocity_from_keyon_volume: bool,
+    /// ピッチベンド幅（半音単位、0の場合は解析で観測したピッチ変化から決める）
+    pub pitch_bend_width: u8,
+    /// エンベロープをエクスプレッションとして出力するか
+    pub envelope_as_expression: bool,
+    /// パンを発音中に更新するか
+    pub auto_pan: bool,
+    /// パン値
+    pub fixed_pan: u8,
+    /// パンを発音中に更新する際のパンロー
+    pub pan_law: PanLaw,
+    /// ボリュームを発音中に更新するか
+    pub auto_volume: bool,
+    /// ボリューム値
+    pub fixed_volume: u8,
+    /// リバーブセンド値
+    pub fixed_reverb_send: u8,
+    /// コーラスセンド値
+    pub chorus_send: u8,
+    /// エコーをリバーブセンドとして出力するか
+    pub echo_as_reverb_send: bool,
+    /// エコーをリバーブセンドとして出力する際の倍率(%)
+    pub echo_send_scale_percent: u8,
+    /// ノートオン後に再生パラメータを更新するか
+    pub update_parameter_after_noteon: bool,
+    /// ノート境界でサステインペダル（CC#64）を出力するか
+    pub sustain_pedal: bool,
+    /// 同じ波形の音源にパラメータの編集を反映するか
+    pub link_duplicates: bool,
+    /// 出力するノートの下限
+    pub min_note: u8,
+    /// 出力するノートの上限
+    pub max_note: u8,
+}
+
+/// アプリケーション設定（次回起動時に引き継ぐ）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    /// オーディオ出力レイテンシ(msec)
+    pub audio_output_latency_msec: usize,
+    /// 仮想MIDI出力ポートを作成して出力するか
+    pub use_virtual_midi_port: bool,
+    /// 再生開始時に無音からフェードインするか
+    pub ramp_in_on_play_start: bool,
+    /// 再生開始時のフェードイン時間(msec)
+    pub ramp_in_msec: usize,
+    /// 再生中に出力デバイスを切り替えたときのフェードイン時間(msec)
+    pub device_switch_fade_msec: usize,
+    /// オーディオ出力のサンプリングレートを強制するか
+    pub force_output_sample_rate: bool,
+    /// 強制するオーディオ出力のサンプリングレート
+    pub output_sample_rate: u32,
+    /// ノート推定・周波数表示の基準ピッチ(A4)の周波数(Hz)
+    pub a4_pitch_hz: f32,
+    /// メインウィンドウのインジケータの平滑化係数（0で平滑化しない）
+    pub indicator_smoothing: f32,
+    /// 音源ウィンドウのスペクトルに表示するピークラベル数
+    pub spectrum_peak_labels: usize,
+    /// ピークラベルを付けるピーク間の最小間隔(Hz)
+    pub spectrum_peak_separation_hz: f32,
+    /// 解析結果をSPCファイルの横にキャッシュし、同じ内容のSPCを開いたときは再利用するか
+    pub cache_analysis: bool,
+    /// 波形・スペクトルを必要なときだけデコードし、常駐させないか
+    pub lazy_source_decoding: bool,
+    /// SMF書き出しのテンポを変えて目標の長さに合わせるか
+    pub stretch_smf_export: bool,
+    /// SMF書き出しを伸縮する目標長さ（秒）
+    pub stretch_target_sec: u32,
+    /// SMF・WAVを指定した時間範囲だけ出力するか
+    pub export_range_enabled: bool,
+    /// 出力範囲の開始時刻（秒）
+    pub export_range_start_sec: f32,
+    /// 出力範囲の終了時刻（秒）
+    pub export_range_end_sec: f32,
+    /// 解析後に自動で適用する音色マッピングファイルのパス
+    pub instrument_mapping_path: Option<std::path::PathBuf>,
+    /// 出力開始時に全チャンネルへ送るマスターリバーブセンド（CC#91）
+    /// 音源ごとのリバーブセンド（エコーの変換・固定値）はこの値に加算される
+    pub master_reverb_send: u8,
+    /// 再生中にこの時間(sec)を超えて鳴り続けるノートを止める（0で無効）
+    pub stuck_note_timeout_sec: u32,
+    /// SMF・WAV・OGGの書き出し前に出力せずエミュレーションを進める時間(msec)
+    pub export_preroll_msec: usize,
+    /// 出力時間がこの長さ(sec)を超える書き出しは開始前に確認する（0で確認しない）
+    pub long_export_warning_sec: u32,
+    /// GMプログラムごとのベロシティ補正倍率(%)（128プログラム分）
+    pub program_velocity_calibration: Vec<u8>,
+    /// 新しく開いたSPCの音源パラメータの既定値
+    pub source_parameter_template: SourceParameterTemplate,
+}
+
+/// 現在のミュート・ソロの状態で音源が聴こえるか
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceAudibility {
+    /// 音源自体がミュートされている
+    Muted,
+    /// 発音中の全チャンネルがミュートされている（ソロ中の他チャンネルを含む）
+    Silenced,
+    /// 発音中のチャンネルの一部がミュートされている
+    PartiallyAudible,
+    /// 発音中で聴こえる
+    Audible,
+    /// 発音されていない
+    Idle,
 }
 
 /// 再生中の状態
@@ -153,13 +608,82 @@ pub struct PlaybackStatus {
     pub volume: [[i8; 2]; 8],
 }
 
+// キャンバス描画先ウィンドウの表示スケール（論理ピクセル当たりの物理ピクセル数）
+// キャンバスは論理ピクセルで描画されるため、線幅・フォントサイズを物理ピクセルに揃え、描画点数を物理ピクセル数に合わせる
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayScale(pub f32);
+
+impl Default for DisplayScale {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+impl DisplayScale {
+    /// 物理ピクセルの整数倍（1ピクセル以上）に揃えた線幅
+    pub fn stroke_width(&self, width: f32) -> f32 {
+        (width * self.0).round().max(1.0) / self.0
+    }
+
+    /// 物理ピクセルの整数に揃えたフォントサイズ
+    pub fn font_size(&self, size: f32) -> f32 {
+        (size * self.0).round().max(1.0) / self.0
+    }
+
+    /// 論理ピクセル幅に描画する点数（物理ピクセル当たり4点）
+    pub fn num_points(&self, width: f32) -> usize {
+        (4.0 * width * self.0) as usize
+    }
+}
+
 // インジケータ
 #[derive(Debug, Clone, Copy)]
 pub struct Indicator {
     pub value: f32,
+    /// 表示用に平滑化した値
+    pub smoothed_value: f32,
     pub min: f32,
     pub max: f32,
     pub formatter: fn(f32) -> String,
+    /// 表示先ウィンドウの表示スケール
+    pub scale: DisplayScale,
+}
+
+// 拍インジケータ（推定テンポでの拍位置を表示）
+#[derive(Debug, Clone, Copy)]
+pub struct BeatIndicator {
+    /// 再生時刻(sec)
+    pub playback_time_sec: f32,
+    /// テンポ(BPM)
+    pub beats_per_minute: f32,
+    /// 1小節の拍数
+    pub beats_per_bar: u32,
+}
+
+// 曲全体の波形概要（メインウィンドウのシークバーに表示）
+#[derive(Debug, Clone)]
+pub struct SongOverview {
+    /// 区間ごとの波形の（最小値, 最大値）
+    pub peaks: Vec<(f32, f32)>,
+    /// 曲の長さ(sec)
+    pub duration_sec: f32,
+    /// 検出したループ区間（開始(sec), 終了(sec)）
+    pub loop_region: Option<(f32, f32)>,
+}
+
+// 曲全体の波形と再生位置の表示（クリックでシーク）
+#[derive(Debug, Clone)]
+pub struct SongOverviewView {
+    pub overview: Arc<SongOverview>,
+    /// 再生時刻(sec)
+    pub playback_time_sec: f32,
+}
+
+// 音源波形のサムネイル（メインウィンドウのグリッド表示）
+#[derive(Debug, Clone)]
+pub struct SourceThumbnail {
+    /// 区間ごとの波形の（最小値, 最大値）
+    pub peaks: Arc<Vec<(f32, f32)>>,
 }
 
 pub trait SPC2MIDI2Window: AsAny {
@@ -168,6 +692,7 @@ pub trait SPC2MIDI2Window: AsAny {
 }
 
 pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
@@ -176,6 +701,9 @@ impl<T> AsAny for T
 where
     T: 'static,
 {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
     fn as_any_mut(&mut self) -> &mut dyn Any {
         self
     }
@@ -191,8 +719,199 @@ impl MIDIOutputConfigure {
             spc_clockup_factor: DEFAULT_SPC_CLOCKUP_FACTOR,
             volume_curve: VolumeCurve::SquareRoot,
             midi_system: MIDISystem::NONE,
+            drum_kit: default_drum_kit(),
             split_drum_into_separate_tracks: false,
             trim_leading_nonevents_period: false,
+            insert_source_markers: false,
+            embed_spc_metadata: false,
+            embed_spc_comment_as_lyric: false,
+            calibrate_velocity_by_program: false,
+            insert_click_track: false,
+            min_note_duration_msec: 0,
+            max_polyphony_per_channel: 0,
+            note_summary_duration_msec: DEFAULT_NOTE_SUMMARY_DURATION_MSEC,
+            split_smf_per_loop: false,
+            route_percussive_notes: false,
+            min_source_keyon_msec: 0,
+            excluded_voices: 0,
+            wav_bypass_echo: false,
+            wav_soft_limiter: false,
+            wav_stereo_width_percent: DEFAULT_WAV_STEREO_WIDTH_PERCENT,
+            wav_sample_rate: default_wav_sample_rate(),
+            wav_resample_quality: default_wav_resample_quality(),
+            target_pitch_bend_width: DEFAULT_TARGET_PITCH_BEND_WIDTH,
+            clamp_pitch_bend_width: false,
+            flatten_velocity: false,
+            flat_velocity: DEFAULT_FLAT_VELOCITY,
+        }
+    }
+
+    /// 一律に出力する場合のベロシティ（一律にしない場合はNone）
+    pub fn flattened_velocity(&self) -> Option<u8> {
+        if self.flatten_velocity {
+            Some(self.flat_velocity)
+        } else {
+            None
+        }
+    }
+}
+
+fn default_target_pitch_bend_width() -> u8 {
+    DEFAULT_TARGET_PITCH_BEND_WIDTH
+}
+
+/// デフォルトのプログラムごとのベロシティ補正倍率(%)（128プログラム分）
+pub fn default_program_velocity_calibration() -> Vec<u8> {
+    (0..128)
+        .map(|program| DEFAULT_PROGRAM_FAMILY_VELOCITY_PERCENT[program / 8])
+        .collect()
+}
+
+fn default_flat_velocity() -> u8 {
+    DEFAULT_FLAT_VELOCITY
+}
+
+fn default_wav_stereo_width_percent() -> u16 {
+    DEFAULT_WAV_STEREO_WIDTH_PERCENT
+}
+
+fn default_drum_kit() -> DrumKit {
+    DrumKit::Standard
+}
+
+fn default_wav_sample_rate() -> WAVSampleRate {
+    WAVSampleRate::Hz44100
+}
+
+fn default_wav_resample_quality() -> WAVResampleQuality {
+    WAVResampleQuality::Good
+}
+
+fn default_note_summary_duration_msec() -> u64 {
+    DEFAULT_NOTE_SUMMARY_DURATION_MSEC
+}
+
+fn default_echo_send_scale_percent() -> u8 {
+    DEFAULT_ECHO_SEND_SCALE_PERCENT
+}
+
+fn default_max_note() -> u8 {
+    127
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            audio_output_latency_msec: DEFAULT_AUDIO_OUTPUT_LATENCY_MSEC,
+            use_virtual_midi_port: false,
+            ramp_in_on_play_start: false,
+            ramp_in_msec: DEFAULT_RAMP_IN_MSEC,
+            device_switch_fade_msec: DEFAULT_DEVICE_SWITCH_FADE_MSEC,
+            force_output_sample_rate: false,
+            output_sample_rate: DEFAULT_OUTPUT_SAMPLE_RATE,
+            a4_pitch_hz: DEFAULT_A4_PITCH_HZ,
+            indicator_smoothing: DEFAULT_INDICATOR_SMOOTHING,
+            spectrum_peak_labels: DEFAULT_SPECTRUM_PEAK_LABELS,
+            spectrum_peak_separation_hz: DEFAULT_SPECTRUM_PEAK_SEPARATION_HZ,
+            cache_analysis: false,
+            lazy_source_decoding: false,
+            stretch_smf_export: false,
+            stretch_target_sec: DEFAULT_STRETCH_TARGET_SEC,
+            export_range_enabled: false,
+            export_range_start_sec: 0.0,
+            export_range_end_sec: (DEFAULT_OUTPUT_DURATION_MSEC / 1000) as f32,
+            instrument_mapping_path: None,
+            master_reverb_send: DEFAULT_MASTER_REVERB_SEND,
+            stuck_note_timeout_sec: DEFAULT_STUCK_NOTE_TIMEOUT_SEC,
+            export_preroll_msec: DEFAULT_EXPORT_PREROLL_MSEC,
+            long_export_warning_sec: DEFAULT_LONG_EXPORT_WARNING_SEC,
+            program_velocity_calibration: default_program_velocity_calibration(),
+            source_parameter_template: SourceParameterTemplate::default(),
+        }
+    }
+}
+
+impl SourceParameter {
+    /// ドラム判定の上書き指定
+    pub fn kind_override(&self) -> SourceKindOverride {
+        if self.force_drum {
+            SourceKindOverride::Drum
+        } else if self.force_tonal {
+            SourceKindOverride::Tonal
+        } else {
+            SourceKindOverride::Auto
+        }
+    }
+
+    /// 解析によるドラム判定に上書き指定を反映する
+    pub fn resolve_drum(&self, detected_drum: bool) -> bool {
+        match self.kind_override() {
+            SourceKindOverride::Auto => detected_drum,
+            SourceKindOverride::Tonal => false,
+            SourceKindOverride::Drum => true,
+        }
+    }
+}
+
+impl Default for SourceParameterTemplate {
+    fn default() -> Self {
+        Self {
+            noteon_velocity: 100,
+            velocity_from_keyon_volume: false,
+            pitch_bend_width: 0,
+            envelope_as_expression: false,
+            auto_pan: true,
+            fixed_pan: 64,
+            pan_law: PanLaw::Linear,
+            auto_volume: true,
+            fixed_volume: 100,
+            fixed_reverb_send: 0,
+            chorus_send: 0,
+            echo_as_reverb_send: false,
+            echo_send_scale_percent: DEFAULT_ECHO_SEND_SCALE_PERCENT,
+            update_parameter_after_noteon: true,
+            sustain_pedal: false,
+            link_duplicates: false,
+            min_note: 0,
+            max_note: 127,
+        }
+    }
+}
+
+impl WAVSampleRate {
+    /// サンプリングレート(Hz)
+    pub fn hz(&self) -> u32 {
+        match self {
+            Self::Native => 32000,
+            Self::Hz44100 => 44100,
+            Self::Hz48000 => 48000,
+        }
+    }
+}
+
+impl PanLaw {
+    /// 左右音量比に比例したパン値を、出力先シンセで同じ左右音量比になるパン値に変換
+    pub fn map(&self, pan: u8) -> u8 {
+        let position = (pan.min(127) as f32) / 127.0;
+        // 左右の音量
+        let (left, right) = (1.0 - position, position);
+        let mapped = match self {
+            Self::Linear => return pan,
+            Self::ConstantPower3dB => right.atan2(left),
+            Self::Attenuated6dB => right.sqrt().atan2(left.sqrt()),
+        } * 2.0
+            / std::f32::consts::PI;
+        (mapped * 127.0).round().clamp(0.0, 127.0) as u8
+    }
+}
+
+impl PlaybackMode {
+    /// 再生要求を受けた後のモード（同じ再生の要求であれば停止、それ以外は要求された再生に切り替え）
+    pub fn toggled(self, request: PlaybackMode) -> PlaybackMode {
+        if self == request {
+            PlaybackMode::None
+        } else {
+            request
         }
     }
 }
@@ -207,9 +926,46 @@ impl PlaybackStatus {
             volume: [[0, 0]; 8],
         }
     }
+
+    /// 音源を鳴らしているチャンネルのミュート状態から、音源が聴こえるかを判定
+    /// 音源とチャンネルの対応は発音ごとに変わるので、現在その音源を鳴らしているチャンネルで判定する
+    pub fn source_audibility(
+        &self,
+        srn_no: u8,
+        param: &SourceParameter,
+        channel_mute_flags: u8,
+    ) -> SourceAudibility {
+        if param.mute {
+            return SourceAudibility::Muted;
+        }
+        let mut num_sounding = 0;
+        let mut num_muted = 0;
+        for ch in 0..8 {
+            if self.srn_no[ch] == srn_no && (self.noteon[ch] || self.envelope[ch] > 0) {
+                num_sounding += 1;
+                if (channel_mute_flags >> ch) & 1 != 0 || param.channel_mute[ch] {
+                    num_muted += 1;
+                }
+            }
+        }
+        if num_sounding == 0 {
+            SourceAudibility::Idle
+        } else if num_muted == num_sounding {
+            SourceAudibility::Silenced
+        } else if num_muted > 0 {
+            SourceAudibility::PartiallyAudible
+        } else {
+            SourceAudibility::Audible
+        }
+    }
+}
+
+/// 発音中のピッチ変化をカバーするのに必要なピッチベンド幅（半音単位）
+pub fn required_pitch_bend_width(info: &SourceInformation) -> u8 {
+    (info.max_pitch_deviation.ceil() as u8).clamp(1, MAX_PITCH_BEND_WIDTH)
 }
 
-/// 小数点を含むノート番号を周波数に変換
-pub fn note_to_frequency(note: f32) -> f32 {
-    440.0 * 2.0f32.powf((note - 69.0) / 12.0)
+/// 小数点を含むノート番号を基準ピッチ(A4)の周波数に合わせて周波数に変換
+pub fn note_to_frequency(note: f32, a4_pitch_hz: f32) -> f32 {
+    a4_pitch_hz * 2.0f32.powf((note - 69.0) / 12.0)
 }